@@ -1,33 +1,45 @@
 use std::env;
-use std::f32::consts::TAU;
-use std::io::Write;
+use std::f32::consts::{PI, TAU};
+use std::io::{Seek, SeekFrom, Write};
 use std::process;
 use std::vec::Vec;
 
-static SUPPORTED_SAMPLE_RATES: [u32; 3] = [
-    16_000, // 16 kHz is commonly used for speech and telephony applications
+static SUPPORTED_SAMPLE_RATES: [u32; 10] = [
+    8_000,   // 8 kHz is the narrowband telephony rate (G.711)
+    11_025,  // 11.025 kHz is a quarter of the CD rate, common in legacy multimedia
+    16_000,  // 16 kHz is commonly used for speech and telephony applications
+    22_050,  // 22.05 kHz is half the CD rate, common in games and legacy multimedia
+    32_000,  // 32 kHz is used by DAT long-play, NICAM and digital broadcast
     44_100, // 44.1 kHz is the standard sample rate for audio CDs and is widely used in music production
     48_000, // 48 kHz is commonly used in professional audio and video production, as well as in some high-quality consumer audio formats
+    88_200, // 88.2 kHz is double the CD rate, used for high-resolution production
+    96_000, // 96 kHz is the common high-resolution studio and DVD-Audio rate
+    192_000, // 192 kHz is the highest common high-resolution rate
 ];
 
 /// Audio sample width.
 ///
 /// Stored in number of bytes per sample.
 #[repr(u8)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SampleWidth {
+    /// 8 bit audio, stored unsigned (offset binary) as WAV requires
+    Width1Byte = 1,
     /// 16 bit audio
     Width2Byte = 2,
     /// 24 bit audio
     Width3Byte = 3,
     /// 32 bit audio
     Width4Byte = 4,
+    /// 64 bit audio, only available as IEEE float
+    Width8Byte = 8,
 }
 
 impl SampleWidth {
-    /// Parse from string (16, 24, 32)
+    /// Parse from string (8, 16, 24, 32)
     fn from_str(s: &str) -> Option<Self> {
         match s {
+            "8" => Some(SampleWidth::Width1Byte),
             "16" => Some(SampleWidth::Width2Byte),
             "24" => Some(SampleWidth::Width3Byte),
             "32" => Some(SampleWidth::Width4Byte),
@@ -36,18 +48,62 @@ impl SampleWidth {
     }
 
     /// Get string representation
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
+            SampleWidth::Width1Byte => "8",
             SampleWidth::Width2Byte => "16",
             SampleWidth::Width3Byte => "24",
             SampleWidth::Width4Byte => "32",
+            SampleWidth::Width8Byte => "64",
+        }
+    }
+}
+
+/// Encoding of each sample in the output.
+#[derive(Clone, Copy, PartialEq)]
+enum SampleFormat {
+    /// Two's complement integer PCM (unsigned for 8-bit)
+    Pcm,
+    /// IEEE 754 floating point with a nominal range of ±1.0
+    Float,
+    /// ITU-T G.711 A-law, 8-bit companded from 16-bit linear codes
+    ALaw,
+    /// ITU-T G.711 μ-law, 8-bit companded from 16-bit linear codes
+    MuLaw,
+}
+
+impl SampleFormat {
+    /// WAVE format tag, also the first field of the extensible sub-format GUID.
+    fn wav_format_tag(self) -> u16 {
+        match self {
+            SampleFormat::Pcm => 0x0001,
+            SampleFormat::Float => 0x0003,
+            SampleFormat::ALaw => 0x0006,
+            SampleFormat::MuLaw => 0x0007,
+        }
+    }
+
+    /// Sun AU encoding field for samples of this format and width.
+    fn au_encoding(self, sample_width: SampleWidth) -> u32 {
+        match (self, sample_width) {
+            (SampleFormat::MuLaw, _) => 1,
+            (SampleFormat::ALaw, _) => 27,
+            (SampleFormat::Float, SampleWidth::Width8Byte) => 7,
+            (SampleFormat::Float, _) => 6,
+            (SampleFormat::Pcm, width) => width as u32 + 1,
+        }
+    }
+
+    /// Width of the linear codes the samples are quantized to before encoding.
+    fn quantize_width(self, sample_width: SampleWidth) -> SampleWidth {
+        match self {
+            SampleFormat::ALaw | SampleFormat::MuLaw => SampleWidth::Width2Byte,
+            _ => sample_width,
         }
     }
 }
 
 // https://ccrma.stanford.edu/courses/422-winter-2014/projects/WaveFormat/
-#[repr(C, packed)]
-#[allow(dead_code)]
 struct WavHeader {
     chunk_id: [u8; 4],      // 0
     chunk_size: u32,        //4
@@ -82,6 +138,144 @@ impl WavHeader {
             subchunk_2_size: 0,
         }
     }
+
+    /// Serialize field by field in little-endian order, independent of the
+    /// host's layout and byte order.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(44);
+        bytes.extend_from_slice(&self.chunk_id);
+        bytes.extend_from_slice(&self.chunk_size.to_le_bytes());
+        bytes.extend_from_slice(&self.format);
+        bytes.extend_from_slice(&self.subchunk_1_id);
+        bytes.extend_from_slice(&self.subchunk_1_size.to_le_bytes());
+        bytes.extend_from_slice(&self.audio_format.to_le_bytes());
+        bytes.extend_from_slice(&self.num_channels.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.block_align.to_le_bytes());
+        bytes.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(&self.subchunk_2_id);
+        bytes.extend_from_slice(&self.subchunk_2_size.to_le_bytes());
+        bytes
+    }
+}
+
+// fmt chunk of a WAVE_FORMAT_EXTENSIBLE file, used for more than two
+// channels so players get an explicit speaker assignment.
+struct WavExtensibleHeader {
+    chunk_id: [u8; 4],          // 0
+    chunk_size: u32,            // 4
+    format: [u8; 4],            // 8
+    subchunk_1_id: [u8; 4],     // 12
+    subchunk_1_size: u32,       // 16
+    audio_format: u16,          // 20
+    num_channels: u16,          // 22
+    sample_rate: u32,           // 24
+    byte_rate: u32,             // 28
+    block_align: u16,           // 32
+    bits_per_sample: u16,       // 34
+    extension_size: u16,        // 36
+    valid_bits_per_sample: u16, // 38
+    channel_mask: u32,          // 40
+    sub_format: [u8; 16],       // 44
+    subchunk_2_id: [u8; 4],     // 60
+    subchunk_2_size: u32,       // 64
+}
+
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// KSDATAFORMAT_SUBTYPE_PCM: 00000001-0000-0010-8000-00aa00389b71
+const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+impl WavExtensibleHeader {
+    pub fn new() -> Self {
+        Self {
+            chunk_id: *b"RIFF",
+            chunk_size: 0,
+            format: *b"WAVE",
+            subchunk_1_id: *b"fmt ",
+            subchunk_1_size: 40,
+            audio_format: WAVE_FORMAT_EXTENSIBLE,
+            num_channels: 1,
+            sample_rate: 44_100,
+            byte_rate: 176_400,
+            block_align: 2,
+            bits_per_sample: 16,
+            extension_size: 22,
+            valid_bits_per_sample: 16,
+            channel_mask: 0,
+            sub_format: KSDATAFORMAT_SUBTYPE_PCM,
+            subchunk_2_id: *b"data",
+            subchunk_2_size: 0,
+        }
+    }
+
+    /// Serialize field by field in little-endian order.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(68);
+        bytes.extend_from_slice(&self.chunk_id);
+        bytes.extend_from_slice(&self.chunk_size.to_le_bytes());
+        bytes.extend_from_slice(&self.format);
+        bytes.extend_from_slice(&self.subchunk_1_id);
+        bytes.extend_from_slice(&self.subchunk_1_size.to_le_bytes());
+        bytes.extend_from_slice(&self.audio_format.to_le_bytes());
+        bytes.extend_from_slice(&self.num_channels.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.block_align.to_le_bytes());
+        bytes.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(&self.extension_size.to_le_bytes());
+        bytes.extend_from_slice(&self.valid_bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(&self.channel_mask.to_le_bytes());
+        bytes.extend_from_slice(&self.sub_format);
+        bytes.extend_from_slice(&self.subchunk_2_id);
+        bytes.extend_from_slice(&self.subchunk_2_size.to_le_bytes());
+        bytes
+    }
+}
+
+const MAX_CHANNELS: u8 = 32;
+
+/// Conventional layout name for a channel count.
+fn channel_layout_name(channels: u8) -> &'static str {
+    match channels {
+        1 => "mono",
+        2 => "stereo",
+        3 => "3.0",
+        4 => "quad",
+        5 => "5.0",
+        6 => "5.1",
+        7 => "6.1",
+        8 => "7.1",
+        _ => "discrete",
+    }
+}
+
+/// WAVE_FORMAT_EXTENSIBLE speaker mask for the conventional layout of
+/// `channels` (L, R, C, LFE, surrounds). Larger counts are left unassigned.
+fn channel_mask(channels: u8) -> u32 {
+    const FL: u32 = 0x1;
+    const FR: u32 = 0x2;
+    const FC: u32 = 0x4;
+    const LFE: u32 = 0x8;
+    const BL: u32 = 0x10;
+    const BR: u32 = 0x20;
+    const BC: u32 = 0x100;
+    const SL: u32 = 0x200;
+    const SR: u32 = 0x400;
+    match channels {
+        1 => FC,
+        2 => FL | FR,
+        3 => FL | FR | FC,
+        4 => FL | FR | BL | BR,
+        5 => FL | FR | FC | BL | BR,
+        6 => FL | FR | FC | LFE | BL | BR,
+        7 => FL | FR | FC | LFE | BC | SL | SR,
+        8 => FL | FR | FC | LFE | BL | BR | SL | SR,
+        _ => 0,
+    }
 }
 
 // Get the maximum absolute value for a given sample width.
@@ -90,428 +284,12752 @@ impl WavHeader {
 |----------|-----------------------------|------------------|
 |  Format  |   Integer Type              |   Max Positive   |
 |----------|-----------------------------|------------------|
+|   8-bit  |  uint8_t (offset by 128)    |          127     |
 |  16-bit  |  int16_t                    |        32767     |
+|  12-bit  |  int16_t (in 12 bits)       |        2047      |
+|  20-bit  |  int32_t (in 20 bits)       |      524,287     |
 |  24-bit  |  int32_t (in 24 bits)       |     8,388,607    |
 |  32-bit  |  int32_t                    |  2,147,483,647   |
 |----------|-----------------------------|------------------|
 */
-fn get_range(sample_width: SampleWidth) -> f32 {
-    match sample_width {
-        SampleWidth::Width2Byte => 32767.0,
-        SampleWidth::Width3Byte => 8388607.0,
-        SampleWidth::Width4Byte => 2147483647.0,
-    }
+fn get_range(bits: u32) -> f64 {
+    ((1u64 << (bits - 1)) - 1) as f64
 }
 
+#[derive(Clone)]
 struct Config {
     frequency: f32,
+    /// Per-channel frequencies; empty when every channel uses `frequency`
+    channel_frequencies: Vec<f32>,
+    /// Start phase of sine and wavetable signals in degrees
+    phase_deg: f32,
+    /// Phase step between adjacent channels in degrees
+    phase_offset_deg: f32,
+    channel_specs: Vec<ChannelSpec>,
+    /// Constant-power pan position, -1.0 (left) to 1.0 (right)
+    pan: Option<f32>,
+    mid_side: Option<MidSide>,
     sample_rate: u32,
     channels: u8,
     sample_width: SampleWidth,
+    sample_format: SampleFormat,
+    data_bits: Option<u32>,
+    container_bits: Option<u32>,
+    justify: Justify,
     duration_ms: f32,
+    /// Exact sample count when the duration was given in samples or cycles
+    duration_samples: Option<usize>,
+    loopable: bool,
     output_format: OutputFormat,
+    layout: Layout,
+    endian: Endian,
+    packing: Packing,
+    slot_bits: u32,
+    q_format: Option<QFormat>,
+    element_type: ElementType,
+    radix: Radix,
+    split_channels: bool,
+    /// File for raw and WAV output instead of stdout
+    outfile: Option<String>,
+    /// Write WAV output as RF64 even below 4 GB
+    rf64: bool,
+    /// Broadcast Wave `bext` chunk for WAV output
+    bext: Option<Bext>,
+    /// Sampler loop as start and end frame (exclusive), written as a WAV `smpl` chunk
+    loop_points: Option<(usize, usize)>,
+    /// RIFF `LIST`/`INFO` tags for WAV output
+    info: Option<Info>,
+    /// Mark segment boundaries with WAV cue points
+    cues: bool,
+    /// Wrap base64 output in a WAV file
+    base64_wav: bool,
+    /// Prefix base64 output with a `data:` URI scheme and media type
+    data_uri: bool,
+    /// Load address of the first byte in firmware image output
+    base_address: Option<u32>,
+    /// Data bytes per Intel HEX or S-record record
+    record_length: Option<usize>,
+    /// Word width of memory initialization files (default: the sample slot)
+    word_bits: Option<u32>,
+    /// Ogg Vorbis quality, -1 to 10 (default: 3)
+    quality: Option<f32>,
+    /// MP3 bit rate in kbit/s (default: 128)
+    bitrate: Option<u32>,
+    /// Array identifier for C and Rust output instead of the generated one
+    symbol_name: Option<String>,
+    /// Declare C arrays `static` and Rust arrays as `static` items
+    static_storage: bool,
+    /// Declare C arrays `constexpr` (C++ or C23)
+    constexpr: bool,
+    /// C++ namespace or Rust module path around the array
+    namespace: Option<String>,
+    /// Alignment in bytes of C and assembler arrays
+    align: Option<usize>,
+    /// Linker section of C and assembler arrays
+    section: Option<String>,
+    attribute_style: AttributeStyle,
+    /// Largest C array in bytes; longer tables are split into several
+    split_bytes: Option<usize>,
+    /// Emit a descriptor with the format of C and Rust arrays
+    descriptor: bool,
+    /// Command line that produced this configuration, for file metadata
+    command_line: String,
     analyze_only: bool,
+    waveform: Waveform,
+    mls_order: u8,
+    mls_seed: u32,
+    pulse_interval_ms: f32,
+    level_dbfs: f32,
+    tones: Vec<Tone>,
+    bit_pattern: BitPattern,
+    staircase_steps: u32,
+    staircase_dwell: usize,
+    id_tone_ms: f32,
+    id_gap_ms: f32,
+    burst: Option<Burst>,
+    crossfade: Option<Crossfade>,
+    seed: u64,
+    noise_band: Option<(f32, f32)>,
+    tail_ms: f32,
+    pad_to: Option<usize>,
+    pad_truncate: bool,
+    offset_binary: bool,
+    snr_db: Option<f32>,
+    pluck_decay: f32,
+    wavetable_path: Option<String>,
+    wavetable: Vec<f32>,
+    expression_text: String,
+    expression: Option<Expr>,
+    gain_db: f32,
+    /// Extra gain per channel in dB; channels past the list are unchanged
+    channel_gains_db: Vec<f32>,
+    channel_delays: Vec<ChannelDelay>,
+    amplitude: f32,
+    adsr: Option<Adsr>,
+    decay_tau_ms: Option<f32>,
+    envelope: Option<GainEnvelope>,
+    fade_in_ms: f32,
+    fade_out_ms: f32,
+    fade_curve: FadeCurve,
+    dc_offset: f32,
+    dither: Dither,
+    noise_shaping: NoiseShaping,
+    normalize: Option<Normalize>,
+    target_lufs: Option<f32>,
+    limiter: Limiter,
+    limit_ceiling_db: f32,
+    strict: bool,
+    rounding: Rounding,
+    full_scale: FullScale,
+    /// Reference pitch and temperament for note-name frequencies
+    tuning: Tuning,
+    /// Rate the sources are synthesized at before resampling to `sample_rate`
+    internal_rate: Option<u32>,
+    /// Sample clock error in parts per million; positive is a fast clock
+    drift_ppm: f64,
 }
 
-#[derive(Clone, Copy)]
-enum OutputFormat {
-    Hex,
-    CArray,
-    RustArray,
-    RawBytes,
-    Info,
-    WavFile,
+/// Measurements gathered while quantizing, for the analysis report.
+#[derive(Default)]
+struct RenderStats {
+    /// Samples that exceeded full scale and were saturated
+    clipped_samples: usize,
+    /// Largest absolute sample before saturation, relative to full scale
+    peak: f32,
 }
 
-impl OutputFormat {
+/// Per-channel sample values for the data output formats, independent of
+/// byte order, packing and container width.
+struct ChannelValues {
+    /// Staged samples before quantization; code-exact waveforms give their
+    /// codes scaled to full scale
+    floats: Vec<Vec<f32>>,
+    /// Signed integer codes at the output bit depth; `None` for float output
+    codes: Option<Vec<Vec<i32>>>,
+}
+
+/// Overload protection applied to the gained signal before quantization.
+#[derive(Clone, Copy, PartialEq)]
+enum Limiter {
+    None,
+    /// Memoryless soft clipper: linear up to the knee, tanh-shaped above it
+    SoftClip,
+    /// Lookahead peak limiter: gain ramps down ahead of peaks so the output
+    /// never exceeds the ceiling, then recovers with an exponential release
+    Lookahead,
+}
+
+// Soft clipper knee (-6 dBFS); the curve approaches full scale asymptotically.
+const SOFT_CLIP_KNEE: f32 = 0.5;
+const LIMITER_LOOKAHEAD_MS: f32 = 5.0;
+const LIMITER_RELEASE_MS: f32 = 50.0;
+
+impl Limiter {
     fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
-            "hex" => Some(OutputFormat::Hex),
-            "carray" | "c" => Some(OutputFormat::CArray),
-            "rustarray" | "rust" => Some(OutputFormat::RustArray),
-            "raw" | "bytes" => Some(OutputFormat::RawBytes),
-            "info" => Some(OutputFormat::Info),
-            "wav" => Some(OutputFormat::WavFile),
+            "none" | "off" => Some(Limiter::None),
+            "soft" | "softclip" | "soft-clip" => Some(Limiter::SoftClip),
+            "lookahead" | "limit" => Some(Limiter::Lookahead),
             _ => None,
         }
     }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Limiter::None => "none",
+            Limiter::SoftClip => "soft clip",
+            Limiter::Lookahead => "lookahead limiter",
+        }
+    }
 }
 
-fn print_usage() {
-    println!("Usage: singen [OPTIONS]");
-    println!();
-    println!("Options:");
-    println!("  -f, --frequency FREQ     Sine wave frequency in Hz (default: 440.0)");
-    println!("  -r, --rate RATE          Sample rate in Hz (default: 16000)");
-    println!("                           Supported: 16000, 44100, 48000");
-    println!("  -c, --channels CH        Number of channels (1=mono, 2=stereo, default: 2)");
-    println!("  -b, --bits BITS          Bit depth: 16, 24, or 32 (default: 16)");
-    println!("  -d, --duration MS        Duration in milliseconds (default: 1.0)");
-    println!("  -o, --output FORMAT      Output format:");
-    println!("                           hex      - Hexadecimal values (default)");
-    println!("                           carray   - C-style array declaration");
-    println!("                           rustarray - Rust array declaration");
-    println!("                           raw      - Raw binary bytes (stdout)");
-    println!("                           wav      - Windows audio file format (stdout)");
-    println!("                           info     - Only show buffer info, no data");
-    println!("  -a, --analyze            Analyze only (don't generate data)");
-    println!("  -h, --help               Show this help message");
-    println!();
-    println!("Examples:");
-    println!("  singen -f 1000 -r 48000 -b 16 -d 10 -o carray");
-    println!("  singen --frequency 440 --rate 44100 --channels 1 --bits 24");
-    println!("  singen -r 16000 -d 1 -o rustarray -p");
+/// Soft-clip one sample: identity below the knee, then a tanh segment that
+/// is continuous in value and slope and never reaches full scale.
+fn soft_clip(x: f32) -> f32 {
+    let magnitude = x.abs();
+    if magnitude <= SOFT_CLIP_KNEE {
+        x
+    } else {
+        let headroom = 1.0 - SOFT_CLIP_KNEE;
+        let shaped = SOFT_CLIP_KNEE + headroom * ((magnitude - SOFT_CLIP_KNEE) / headroom).tanh();
+        shaped.copysign(x)
+    }
 }
 
-fn parse_args() -> Config {
-    let args: Vec<String> = env::args().collect();
-    let mut config = Config {
-        frequency: 440.0,
-        sample_rate: 16_000,
-        channels: 2,
-        sample_width: SampleWidth::Width2Byte,
-        duration_ms: 1.0,
-        output_format: OutputFormat::Hex,
-        analyze_only: false,
-    };
+/// Lookahead-limit `channels` in place to `ceiling` (linear). Channels share
+/// one gain envelope so the stereo image does not shift under limiting.
+fn lookahead_limit(channels: &mut [Vec<f32>], sample_rate: f32, ceiling: f32) {
+    let frames = channels.first().map_or(0, Vec::len);
+    let lookahead = (LIMITER_LOOKAHEAD_MS / 1000.0 * sample_rate).max(1.0);
+    let release = (-1.0 / (LIMITER_RELEASE_MS / 1000.0 * sample_rate)).exp();
 
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "-h" | "--help" => {
-                print_usage();
-                process::exit(0);
-            }
-            "-f" | "--frequency" => {
-                i += 1;
-                if i < args.len() {
-                    config.frequency = args[i].parse().unwrap_or_else(|_| {
-                        eprintln!("Error: Invalid frequency value");
-                        process::exit(1);
-                    });
-                }
-            }
-            "-r" | "--rate" => {
-                i += 1;
-                if i < args.len() {
-                    config.sample_rate = args[i].parse().unwrap_or_else(|_| {
-                        eprintln!("Error: Invalid sample rate");
-                        process::exit(1);
-                    });
-                    if !SUPPORTED_SAMPLE_RATES.contains(&config.sample_rate) {
-                        eprintln!(
-                            "Warning: {} Hz is not in the standard supported rates list",
-                            config.sample_rate
-                        );
-                    }
-                }
-            }
-            "-c" | "--channels" => {
-                i += 1;
-                if i < args.len() {
-                    let ch = args[i].parse().unwrap_or_else(|_| {
-                        eprintln!("Error: Invalid channel count");
-                        process::exit(1);
-                    });
-                    if ch != 1 && ch != 2 {
-                        eprintln!("Error: Channel count must be 1 or 2");
-                        process::exit(1);
-                    }
-                    config.channels = ch;
-                }
-            }
-            "-b" | "--bits" => {
-                i += 1;
-                if i < args.len() {
-                    config.sample_width = SampleWidth::from_str(&args[i]).unwrap_or_else(|| {
-                        eprintln!("Error: Invalid bit depth. Must be 16, 24, or 32");
-                        process::exit(1);
-                    });
-                }
-            }
-            "-d" | "--duration" => {
-                i += 1;
-                if i < args.len() {
-                    config.duration_ms = args[i].parse().unwrap_or_else(|_| {
-                        eprintln!("Error: Invalid duration");
-                        process::exit(1);
-                    });
-                }
-            }
-            "-o" | "--output" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_format = OutputFormat::from_str(&args[i]).unwrap_or_else(|| {
-                        eprintln!("Error: Invalid output format");
-                        process::exit(1);
-                    });
-                }
-            }
-            "-a" | "--analyze" => {
-                config.analyze_only = true;
-                config.output_format = OutputFormat::Info;
-            }
-            _ => {
-                eprintln!("Error: Unknown option: {}", args[i]);
-                print_usage();
-                process::exit(1);
-            }
+    // Gain each frame needs on its own.
+    let mut gains: Vec<f32> = (0..frames)
+        .map(|i| {
+            let peak = channels
+                .iter()
+                .fold(0.0f32, |peak, ch| peak.max(ch[i].abs()));
+            if peak > ceiling { ceiling / peak } else { 1.0 }
+        })
+        .collect();
+
+    // Backward pass: ramp the gain down linearly over the lookahead window
+    // so it has reached the required value by the time each peak arrives.
+    let attack_step = 1.0 / lookahead;
+    for i in (0..frames.saturating_sub(1)).rev() {
+        gains[i] = gains[i].min(gains[i + 1] + attack_step);
+    }
+
+    // Forward pass: exponential release back towards unity gain.
+    for i in 1..frames {
+        let released = 1.0 - (1.0 - gains[i - 1]) * release;
+        gains[i] = gains[i].min(released);
+    }
+
+    for channel in channels.iter_mut() {
+        for (sample, gain) in channel.iter_mut().zip(&gains) {
+            *sample *= gain;
         }
-        i += 1;
     }
+}
 
-    config
+/// How scaled samples are rounded to integer codes.
+#[derive(Clone, Copy, PartialEq)]
+enum Rounding {
+    /// Round half away from zero
+    Nearest,
+    /// Round half to even (banker's rounding)
+    HalfEven,
+    /// Truncate toward zero
+    Truncate,
 }
 
-/// Generate a linear chirp from `f0` Hz to `f1` Hz over `duration_secs`.
-/// Returns a vector of floating‑point samples in the range [-1.0, 1.0].
-fn generate_linear_chirp(
-    f0: f32,            // start frequency (Hz)
-    f1: f32,            // end frequency (Hz)
-    sample_rate: f32,   // samples per second
-    duration_secs: f32, // total duration in seconds
-) -> Vec<f32> {
-    let dt = 1.0 / sample_rate;
-    let num_samples = (duration_secs * sample_rate).round() as usize;
-    let mut samples = Vec::with_capacity(num_samples);
-    let mut phase = 0.0;
+impl Rounding {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "nearest" | "round" => Some(Rounding::Nearest),
+            "even" | "half-even" => Some(Rounding::HalfEven),
+            "truncate" | "trunc" => Some(Rounding::Truncate),
+            _ => None,
+        }
+    }
 
-    for i in 0..num_samples {
-        let t = i as f32 * dt;
-        // Instantaneous frequency at time t (linear interpolation)
-        let freq = f0 + (f1 - f0) * (t / duration_secs);
-        // Phase increment for this sample
-        phase += TAU * freq * dt;
-        // Keep phase in [-π, π] range to avoid floating-point drift (optional)
-        phase = phase.rem_euclid(TAU);
-        samples.push(phase.sin());
+    fn to_str(self) -> &'static str {
+        match self {
+            Rounding::Nearest => "nearest (half away from zero)",
+            Rounding::HalfEven => "half to even",
+            Rounding::Truncate => "truncate",
+        }
     }
 
-    samples
+    fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Nearest => value.round(),
+            Rounding::HalfEven => value.round_ties_even(),
+            Rounding::Truncate => value.trunc(),
+        }
+    }
 }
 
-fn float_samples_to_bytes(samples: &[f32], channels: u8, sample_width: SampleWidth) -> Vec<u8> {
-    let max_val = get_range(sample_width);
-    let mut buffer = Vec::with_capacity(samples.len() * channels as usize * sample_width as usize);
+/// Mapping of the float range [-1.0, 1.0] onto integer codes.
+#[derive(Clone, Copy, PartialEq)]
+enum FullScale {
+    /// ±1.0 maps to ±(2^(N-1) - 1); the most negative code is never produced
+    Symmetric,
+    /// Scale by 2^(N-1) so -1.0 reaches the most negative code; +1.0
+    /// saturates one code short of 2^(N-1)
+    Asymmetric,
+}
 
-    for &sample in samples {
-        let scaled = (sample * max_val).round() as i32;
-        let bytes = scaled.to_le_bytes();
-        for _ in 0..channels {
-            for b in &bytes[0..sample_width as usize] {
-                buffer.push(*b);
-            }
+impl FullScale {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "symmetric" | "sym" => Some(FullScale::Symmetric),
+            "asymmetric" | "asym" => Some(FullScale::Asymmetric),
+            _ => None,
         }
     }
-    buffer
-}
 
-fn print_buffer_info(config: &Config, total_samples: usize, total_bytes: usize) {
-    println!("Sine Wave Generator - Configuration");
-    println!("=====================================");
-    println!("Frequency:      {} Hz", config.frequency);
-    println!("Sample Rate:    {} Hz", config.sample_rate);
-    println!(
-        "Channels:       {} ({})",
-        config.channels,
-        if config.channels == 1 {
-            "mono"
-        } else {
-            "stereo"
+    fn to_str(self) -> &'static str {
+        match self {
+            FullScale::Symmetric => "symmetric",
+            FullScale::Asymmetric => "asymmetric",
         }
-    );
-    println!("Bit Depth:      {}-bit", config.sample_width.to_str());
-    println!("Duration:       {} ms", config.duration_ms);
-    println!();
-    println!("Buffer Analysis:");
-    println!("  Samples:      {}", total_samples);
-    println!("  Total bytes:  {}", total_bytes);
+    }
 
-    // Calculate frequency info
-    let period_samples = config.sample_rate as f32 / config.frequency;
-    println!("\nFrequency Analysis:");
-    println!("  Period:       {:.2} samples", period_samples);
-    println!(
-        "  Full cycles:  {:.2}",
-        total_samples as f32 / period_samples
-    );
+    /// Multiplier from float samples to `bits`-wide codes.
+    fn scale(self, bits: u32) -> f64 {
+        match self {
+            FullScale::Symmetric => get_range(bits),
+            FullScale::Asymmetric => (1u64 << (bits - 1)) as f64,
+        }
+    }
 }
 
-fn print_buffer_hex(buffer: &[u8], bytes_per_line: usize) {
-    let mut i: usize = 0;
-    print!("[");
-    while i < buffer.len() {
-        if i > 0 {
-            print!("\n ");
+/// Dither added at the target bit depth before rounding.
+#[derive(Clone, Copy, PartialEq)]
+enum Dither {
+    None,
+    /// Rectangular PDF, ±0.5 LSB
+    Rectangular,
+    /// Triangular PDF, ±1 LSB (sum of two independent rectangular sources)
+    Triangular,
+}
+
+impl Dither {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "none" | "off" => Some(Dither::None),
+            "rect" | "rpdf" | "rectangular" => Some(Dither::Rectangular),
+            "tpdf" | "triangular" => Some(Dither::Triangular),
+            _ => None,
         }
-        for j in 0..bytes_per_line {
-            if i + j < buffer.len() {
-                print!("0x{:02X}", buffer[i + j]);
-                if i + j + 1 < buffer.len() && j < bytes_per_line - 1 {
-                    print!(", ");
-                }
-            }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Dither::None => "none",
+            Dither::Rectangular => "rectangular (RPDF)",
+            Dither::Triangular => "triangular (TPDF)",
         }
-        i += bytes_per_line;
     }
-    println!("]");
-}
 
-fn print_c_array(buffer: &[u8], config: &Config) {
-    let name = format!(
-        "sine_{}hz_{}ms_{}bit_{}ch",
-        config.sample_rate,
-        config.duration_ms as u32,
-        config.sample_width.to_str(),
-        config.channels
-    );
+    /// Next dither value in LSBs.
+    fn sample(self, rng: &mut Rng) -> f32 {
+        match self {
+            Dither::None => 0.0,
+            Dither::Rectangular => 0.5 * rng.next_bipolar(),
+            Dither::Triangular => 0.5 * (rng.next_bipolar() + rng.next_bipolar()),
+        }
+    }
+}
 
-    println!(
-        "// Sine wave: {} Hz, {} ms, {}-bit, {} channel{}",
-        config.frequency,
-        config.duration_ms,
-        config.sample_width.to_str(),
-        config.channels,
-        if config.channels > 1 { "s" } else { "" }
-    );
-    println!("// Sample rate: {} Hz", config.sample_rate);
-    println!("// Total bytes: {}", buffer.len());
-    println!(
-        "const uint8_t {}[{}] = {{",
-        name.to_uppercase(),
-        buffer.len()
-    );
+/// Error-feedback filter that pushes quantization noise out of the most
+/// audible band.
+#[derive(Clone, Copy, PartialEq)]
+enum NoiseShaping {
+    None,
+    /// Second-order highpass shaping, E(z) (1 - z^-1)^2
+    SecondOrder,
+    /// Wannamaker's 9-tap F-weighted shaper, designed for 44.1 kHz
+    FWeighted,
+}
 
-    for (i, chunk) in buffer.chunks(16).enumerate() {
-        print!("    ");
-        for (j, byte) in chunk.iter().enumerate() {
-            print!("0x{:02X}", byte);
-            if i * 16 + j < buffer.len() - 1 {
-                print!(", ");
+static NOISE_SHAPING_SECOND_ORDER: [f64; 2] = [2.0, -1.0];
+static NOISE_SHAPING_F_WEIGHTED: [f64; 9] = [
+    2.412, -3.370, 3.937, -4.174, 3.353, -2.205, 1.281, -0.569, 0.0847,
+];
+// Bound on the fed-back error so clipped samples cannot drive the loop unstable.
+const NOISE_SHAPING_MAX_ERROR: f64 = 2.0;
+
+impl NoiseShaping {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "none" | "off" => Some(NoiseShaping::None),
+            "2nd" | "second-order" => Some(NoiseShaping::SecondOrder),
+            "f-weighted" | "fweighted" => Some(NoiseShaping::FWeighted),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            NoiseShaping::None => "none",
+            NoiseShaping::SecondOrder => "2nd order",
+            NoiseShaping::FWeighted => "F-weighted (9 tap)",
+        }
+    }
+
+    /// Feedback coefficients applied to past quantization errors, newest first.
+    fn coefficients(self) -> &'static [f64] {
+        match self {
+            NoiseShaping::None => &[],
+            NoiseShaping::SecondOrder => &NOISE_SHAPING_SECOND_ORDER,
+            NoiseShaping::FWeighted => &NOISE_SHAPING_F_WEIGHTED,
+        }
+    }
+}
+
+// Dither draws from its own stream so enabling it does not change noise renders.
+const DITHER_SEED_SALT: u64 = 0x5EED_D17E;
+
+/// Level measure used by `--normalize`.
+#[derive(Clone, Copy)]
+enum LevelMeasure {
+    /// Largest absolute sample value
+    Peak,
+    /// Root mean square over all channels; a full-scale sine measures -3.01 dBFS
+    Rms,
+}
+
+/// Normalization of the processed signal to a target level.
+#[derive(Clone, Copy)]
+struct Normalize {
+    measure: LevelMeasure,
+    target_dbfs: f32,
+}
+
+impl Normalize {
+    /// Parse `peak:LEVEL` or `rms:LEVEL`, LEVEL in dBFS with an optional unit.
+    fn from_str(s: &str) -> Option<Self> {
+        let (measure, level) = s.split_once(':')?;
+        let measure = match measure.trim().to_lowercase().as_str() {
+            "peak" => LevelMeasure::Peak,
+            "rms" => LevelMeasure::Rms,
+            _ => return None,
+        };
+        let level = level.trim().to_lowercase();
+        let level = level
+            .strip_suffix("dbfs")
+            .or_else(|| level.strip_suffix("db"))
+            .unwrap_or(&level);
+        let target_dbfs: f32 = level.trim().parse().ok()?;
+        if !(target_dbfs.is_finite() && target_dbfs <= 0.0) {
+            return None;
+        }
+        Some(Normalize {
+            measure,
+            target_dbfs,
+        })
+    }
+
+    fn describe(self) -> String {
+        let measure = match self.measure {
+            LevelMeasure::Peak => "peak",
+            LevelMeasure::Rms => "RMS",
+        };
+        format!("{} {} dBFS", measure, self.target_dbfs)
+    }
+}
+
+/// Measure `channels` with `measure`, as a linear fraction of full scale.
+fn measure_level(channels: &[Vec<f32>], measure: LevelMeasure) -> f32 {
+    match measure {
+        LevelMeasure::Peak => channels
+            .iter()
+            .flatten()
+            .fold(0.0f32, |peak, &sample| peak.max(sample.abs())),
+        LevelMeasure::Rms => {
+            let count: usize = channels.iter().map(Vec::len).sum();
+            if count == 0 {
+                return 0.0;
+            }
+            let sum: f64 = channels
+                .iter()
+                .flatten()
+                .map(|&sample| sample as f64 * sample as f64)
+                .sum();
+            (sum / count as f64).sqrt() as f32
+        }
+    }
+}
+
+/// Scale every sample by `gain`.
+fn scale_channels(channels: &mut [Vec<f32>], gain: f32) {
+    for sample in channels.iter_mut().flatten() {
+        *sample *= gain;
+    }
+}
+
+/// Shape of fade-in and fade-out ramps.
+#[derive(Clone, Copy)]
+enum FadeCurve {
+    Linear,
+    /// Raised-cosine (half Hann) ramp
+    Cosine,
+    /// Exponential ramp, perceptually even in level
+    Exponential,
+}
+
+// Steepness of the exponential fade; the ramp covers about 43 dB of its
+// range linearly in level before the final step to silence.
+const FADE_EXP_STEEPNESS: f32 = 5.0;
+
+impl FadeCurve {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "linear" | "lin" => Some(FadeCurve::Linear),
+            "cosine" | "cos" => Some(FadeCurve::Cosine),
+            "exponential" | "exp" => Some(FadeCurve::Exponential),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            FadeCurve::Linear => "linear",
+            FadeCurve::Cosine => "cosine",
+            FadeCurve::Exponential => "exponential",
+        }
+    }
+
+    /// Gain for a fade-in `progress` from 0.0 (silent) to 1.0 (full level).
+    fn gain(self, progress: f32) -> f32 {
+        let p = progress.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => p,
+            FadeCurve::Cosine => 0.5 - 0.5 * (PI * p).cos(),
+            FadeCurve::Exponential => {
+                ((FADE_EXP_STEEPNESS * p).exp() - 1.0) / (FADE_EXP_STEEPNESS.exp() - 1.0)
             }
         }
-        if i * 16 < buffer.len() {
-            println!();
+    }
+}
+
+/// Linear attack/decay/sustain/release envelope. The release occupies the
+/// final `release_ms` of the signal.
+#[derive(Clone, Copy)]
+struct Adsr {
+    attack_ms: f32,
+    decay_ms: f32,
+    /// Sustain level as a linear fraction of full level
+    sustain: f32,
+    release_ms: f32,
+}
+
+impl Adsr {
+    /// Parse `attack_ms:decay_ms:sustain:release_ms`; times also take units (`0.5s`).
+    fn from_str(s: &str) -> Option<Self> {
+        let fields: Vec<&str> = s.split(':').map(str::trim).collect();
+        let [attack, decay, sustain, release] = fields[..] else {
+            return None;
+        };
+        let (attack_ms, decay_ms, release_ms) =
+            (parse_ms(attack)?, parse_ms(decay)?, parse_ms(release)?);
+        let sustain: f32 = sustain.parse().ok()?;
+        if attack_ms < 0.0 || decay_ms < 0.0 || release_ms < 0.0 || !(0.0..=1.0).contains(&sustain)
+        {
+            return None;
+        }
+        Some(Adsr {
+            attack_ms,
+            decay_ms,
+            sustain,
+            release_ms,
+        })
+    }
+
+    /// Envelope level before the release phase.
+    fn held_level(&self, t_ms: f32) -> f32 {
+        if t_ms < self.attack_ms {
+            t_ms / self.attack_ms
+        } else if t_ms < self.attack_ms + self.decay_ms {
+            1.0 - (1.0 - self.sustain) * (t_ms - self.attack_ms) / self.decay_ms
+        } else {
+            self.sustain
+        }
+    }
+
+    /// Envelope gain `t_ms` into a signal lasting `duration_ms`. If the signal
+    /// is shorter than the envelope, the release starts from wherever the
+    /// attack or decay had reached.
+    fn gain_at(&self, t_ms: f32, duration_ms: f32) -> f32 {
+        let release_start = (duration_ms - self.release_ms).max(0.0);
+        if t_ms < release_start {
+            self.held_level(t_ms)
+        } else {
+            let progress = (t_ms - release_start) / self.release_ms;
+            self.held_level(release_start) * (1.0 - progress).max(0.0)
         }
     }
-    println!("}};");
 }
 
-fn print_rust_array(buffer: &[u8], config: &Config) {
-    let name = format!(
-        "SINE_{}HZ_{}MS_{}BIT_{}CH",
-        config.sample_rate,
-        config.duration_ms as u32,
-        config.sample_width.to_str(),
-        config.channels
-    );
+/// Gain automation from a breakpoint file, interpolated linearly in dB.
+/// Before the first and after the last breakpoint the end gains are held.
+#[derive(Clone)]
+struct GainEnvelope {
+    path: String,
+    /// `(time_ms, gain_db)` pairs in ascending time order
+    points: Vec<(f32, f32)>,
+}
 
-    println!(
-        "// Sine wave: {} Hz, {} ms, {}-bit, {} channel{}",
-        config.frequency,
-        config.duration_ms,
-        config.sample_width.to_str(),
-        config.channels,
-        if config.channels > 1 { "s" } else { "" }
-    );
-    println!("// Sample rate: {} Hz", config.sample_rate);
-    println!("// Total bytes: {}", buffer.len());
-    println!("pub const {}: [u8; {}] = [", name, buffer.len());
+impl GainEnvelope {
+    /// Load breakpoints from a `.json` file (an array of `[time_ms, gain_db]`
+    /// pairs or of `{"time_ms": .., "gain_db": ..}` objects) or from CSV text
+    /// with one `time_ms,gain_db` pair per line.
+    fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let is_json = std::path::Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let points = if is_json {
+            parse_envelope_json(&text)?
+        } else {
+            parse_envelope_csv(&text)?
+        };
 
-    for (i, chunk) in buffer.chunks(16).enumerate() {
-        print!("    ");
-        for (j, byte) in chunk.iter().enumerate() {
-            print!("0x{:02X}", byte);
-            if i * 16 + j < buffer.len() - 1 {
-                print!(", ");
+        if points.is_empty() {
+            return Err("no breakpoints".to_string());
+        }
+        for &(time_ms, gain_db) in &points {
+            if !time_ms.is_finite() || time_ms < 0.0 || !gain_db.is_finite() {
+                return Err(format!("invalid breakpoint {} ms, {} dB", time_ms, gain_db));
             }
         }
-        if i * 16 < buffer.len() {
-            println!();
+        if points.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+            return Err("breakpoint times must be in ascending order".to_string());
         }
+        Ok(GainEnvelope {
+            path: path.to_string(),
+            points,
+        })
+    }
+
+    /// Interpolated gain in dB `t_ms` after the start of the signal.
+    fn gain_db_at(&self, t_ms: f32) -> f32 {
+        let next = self.points.partition_point(|&(time_ms, _)| time_ms <= t_ms);
+        if next == 0 {
+            return self.points[0].1;
+        }
+        if next == self.points.len() {
+            return self.points[next - 1].1;
+        }
+        let (t0, g0) = self.points[next - 1];
+        let (t1, g1) = self.points[next];
+        g0 + (g1 - g0) * (t_ms - t0) / (t1 - t0)
     }
-    println!("];");
 }
 
-fn print_raw_bytes(buffer: &[u8]) {
-    use std::io::{self, Write};
-    let stdout = io::stdout();
-    let mut handle = stdout.lock();
-    handle.write_all(buffer).unwrap();
+/// Parse `time_ms,gain_db` lines. Blank lines, `#` comments and a
+/// non-numeric header line are skipped.
+fn parse_envelope_csv(text: &str) -> Result<Vec<(f32, f32)>, String> {
+    let mut points = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|field| !field.is_empty())
+            .collect();
+        let parsed = match fields[..] {
+            [time, gain] => time.parse().ok().zip(gain.parse().ok()),
+            _ => None,
+        };
+        match parsed {
+            Some(point) => points.push(point),
+            None if points.is_empty() && index == 0 => continue,
+            None => return Err(format!("line {}: expected time_ms,gain_db", index + 1)),
+        }
+    }
+    Ok(points)
 }
 
-fn create_wav_file_array(
-    buffer: &[u8],
-    sample_rate: u32,
-    channels: u16,
-    sample_width: SampleWidth,
-) -> Vec<u8> {
-    let wav_header_len = std::mem::size_of::<WavHeader>();
-    let buffer_len = buffer.len();
+/// Parse a JSON array of `[time_ms, gain_db]` pairs or of objects with
+/// `time_ms` and `gain_db` members.
+fn parse_envelope_json(text: &str) -> Result<Vec<(f32, f32)>, String> {
+    let mut parser = JsonParser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return Err("unexpected trailing characters after JSON value".to_string());
+    }
 
-    let mut wav_hdr = WavHeader::new();
-    wav_hdr.chunk_size = (36 + buffer_len) as u32; // 4 + (24) + 8 + buffer_len
-    wav_hdr.num_channels = channels;
-    wav_hdr.sample_rate = sample_rate;
-    wav_hdr.byte_rate = sample_rate as u32 * channels as u32 * sample_width as u32;
-    wav_hdr.block_align = channels * sample_width as u16; // fixed formula
-    wav_hdr.bits_per_sample = sample_width as u16 * 8;
-    wav_hdr.subchunk_2_size = buffer_len as u32;
+    let JsonValue::Array(entries) = value else {
+        return Err("expected a JSON array of breakpoints".to_string());
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let point = match entry {
+                JsonValue::Array(pair) => match pair[..] {
+                    [JsonValue::Number(time), JsonValue::Number(gain)] => Some((time, gain)),
+                    _ => None,
+                },
+                JsonValue::Object(members) => {
+                    let number = |key: &str| {
+                        members.iter().find_map(|(name, value)| match value {
+                            JsonValue::Number(n) if name == key => Some(*n),
+                            _ => None,
+                        })
+                    };
+                    number("time_ms").zip(number("gain_db"))
+                }
+                _ => None,
+            };
+            point
+                .map(|(time, gain)| (time as f32, gain as f32))
+                .ok_or_else(|| "breakpoint must be [time_ms, gain_db] or an object".to_string())
+        })
+        .collect()
+}
 
-    let mut file = Vec::with_capacity(wav_header_len + buffer_len);
-    let ptr = &wav_hdr as *const WavHeader as *const u8;
-    // SAFETY: WavHeader is repr(C, packed) so it has no padding.
-    file.write_all(unsafe { std::slice::from_raw_parts(ptr, wav_header_len) })
-        .unwrap();
-    file.write_all(buffer).unwrap();
-    file
+/// Subset of JSON values needed for breakpoint files.
+enum JsonValue {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
 }
 
-fn main() {
-    let config = parse_args();
+/// Minimal recursive descent JSON reader.
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
 
-    let total_samples =
-        ((config.duration_ms * config.sample_rate as f32) / 1000.0).round() as usize;
-    let total_bytes = total_samples * (config.sample_width as u8 * config.channels) as usize;
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
 
-    let float_samples = generate_linear_chirp(
-        config.frequency,
-        config.frequency,
-        config.sample_rate as f32,
-        config.duration_ms / 1000.0,
-    );
-    let buffer = float_samples_to_bytes(&float_samples, config.channels, config.sample_width);
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}', found end of input", expected)),
+        }
+    }
 
-    match config.output_format {
-        OutputFormat::Info => {
-            print_buffer_info(&config, total_samples, total_bytes);
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('[') => {
+                self.chars.next();
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&']') {
+                    self.chars.next();
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(JsonValue::Array(items)),
+                        _ => return Err("expected ',' or ']' in array".to_string()),
+                    }
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&'}') {
+                    self.chars.next();
+                    return Ok(JsonValue::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let JsonValue::String(name) = self.parse_value()? else {
+                        return Err("expected a string member name".to_string());
+                    };
+                    self.expect(':')?;
+                    members.push((name, self.parse_value()?));
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(JsonValue::Object(members)),
+                        _ => return Err("expected ',' or '}' in object".to_string()),
+                    }
+                }
+            }
+            Some('"') => {
+                self.chars.next();
+                let mut text = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('"') => return Ok(JsonValue::String(text)),
+                        Some('\\') => match self.chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(c) => text.push(c),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !c.is_ascii_alphabetic() {
+                        break;
+                    }
+                    word.push(c);
+                    self.chars.next();
+                }
+                match word.as_str() {
+                    "null" => Ok(JsonValue::Null),
+                    "true" | "false" => Ok(JsonValue::Bool),
+                    _ => Err(format!("unexpected '{}'", word)),
+                }
+            }
+            Some(_) => {
+                let mut number = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                        break;
+                    }
+                    number.push(c);
+                    self.chars.next();
+                }
+                number
+                    .parse()
+                    .map(JsonValue::Number)
+                    .map_err(|_| format!("invalid number '{}'", number))
+            }
+            None => Err("unexpected end of input".to_string()),
         }
-        OutputFormat::Hex => {
-            print_buffer_info(&config, total_samples, total_bytes);
-            println!("\nBuffer data (hexadecimal):");
-            print_buffer_hex(&buffer, 16);
+    }
+}
+
+/// On/off gating cadence applied to the generated signal.
+#[derive(Clone, Copy)]
+struct Burst {
+    on_ms: f32,
+    off_ms: f32,
+    /// Raised-cosine rise and fall time at each burst edge
+    edge_ms: f32,
+}
+
+impl Burst {
+    /// Parse an `on:off` cadence, each a time as for `parse_ms`.
+    fn from_str(s: &str) -> Option<Self> {
+        let (on, off) = s.split_once(':')?;
+        let on_ms = parse_ms(on)?;
+        let off_ms = parse_ms(off)?;
+        if on_ms <= 0.0 || off_ms < 0.0 {
+            return None;
         }
-        OutputFormat::CArray => {
-            print_buffer_info(&config, total_samples, total_bytes);
-            println!("\nC array declaration:");
-            print_c_array(&buffer, &config);
+        Some(Burst {
+            on_ms,
+            off_ms,
+            edge_ms: 0.0,
+        })
+    }
+
+    /// Gain of the burst envelope `t_ms` after the start of the signal.
+    fn gain_at(&self, t_ms: f32) -> f32 {
+        let t = t_ms % (self.on_ms + self.off_ms);
+        if t >= self.on_ms {
+            0.0
+        } else if t < self.edge_ms {
+            0.5 - 0.5 * (PI * t / self.edge_ms).cos()
+        } else if self.on_ms - t < self.edge_ms {
+            0.5 - 0.5 * (PI * (self.on_ms - t) / self.edge_ms).cos()
+        } else {
+            1.0
         }
-        OutputFormat::RustArray => {
-            print_buffer_info(&config, total_samples, total_bytes);
-            println!("\nRust array declaration:");
-            print_rust_array(&buffer, &config);
+    }
+}
+
+/// Second source that the generated signal crossfades into.
+#[derive(Clone, Copy)]
+struct Crossfade {
+    waveform: Waveform,
+    /// Frequency of the second source; `None` reuses `-f`
+    frequency: Option<f32>,
+    /// Length of the equal-power crossfade, centred in the signal
+    length_ms: f32,
+}
+
+impl Crossfade {
+    /// Parse a `WAVEFORM[:FREQ]` source spec.
+    fn from_str(s: &str, tuning: &Tuning) -> Option<Self> {
+        let (waveform, frequency) = match s.split_once(':') {
+            Some((waveform, frequency)) => (waveform, Some(parse_hz(frequency, tuning)?)),
+            None => (s, None),
+        };
+        let waveform = Waveform::from_str(waveform.trim())?;
+        if waveform.is_code_exact() || frequency.is_some_and(|f: f32| !(f.is_finite() && f > 0.0)) {
+            return None;
+        }
+        Some(Crossfade {
+            waveform,
+            frequency,
+            length_ms: 0.0,
+        })
+    }
+
+    fn describe(self, config: &Config) -> String {
+        let source = if self.waveform.uses_frequency() {
+            format!(
+                "{} {} Hz",
+                self.waveform.to_str(),
+                self.frequency.unwrap_or(config.frequency)
+            )
+        } else {
+            self.waveform.to_str().to_string()
+        };
+        format!("into {} over {} ms", source, self.length_ms)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Hex,
+    CArray,
+    /// C array in AVR flash (`PROGMEM`) for Arduino sketches
+    Progmem,
+    RustArray,
+    /// Python `bytes` literal, or a list of ints or floats
+    PythonArray,
+    /// JavaScript module exporting a typed array
+    JsArray,
+    /// TypeScript module exporting a typed array
+    TsArray,
+    /// Go byte slice, or a slice of ints or floats
+    GoArray,
+    RawBytes,
+    /// Raw IEEE f32 little-endian samples, before quantization
+    RawFloat,
+    /// `index,time_s,channel,value[,code]` rows
+    Csv,
+    /// Configuration and per-channel sample arrays as one JSON object
+    Json,
+    /// NumPy `.npy` array shaped frames x channels
+    Npy,
+    /// MATLAB/Octave script defining the sample rate and a frames x channels matrix
+    Matlab,
+    /// Raw or WAV-wrapped bytes as one line of base64 text
+    Base64,
+    /// Intel HEX records loading the bytes at `--base-address`
+    IntelHex,
+    /// Motorola S-records with 2, 3 or 4 address bytes (S19/S28/S37), or the
+    /// smallest that fits when `None`
+    SRecord(Option<usize>),
+    /// Verilog `$readmemh` file, one hex word per line
+    ReadMemH,
+    /// VHDL package with a constant `std_logic_vector` ROM array
+    Vhdl,
+    /// Xilinx coefficient file for Block Memory Generator
+    Coe,
+    /// Intel (Altera) memory initialization file for Quartus
+    Mif,
+    /// GNU assembler source with a global table in a read-only section
+    Asm,
+    Info,
+    WavFile,
+    AiffFile,
+    FlacFile,
+    OggFile,
+    Mp3File,
+    AuFile,
+    CafFile,
+}
+
+impl OutputFormat {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "hex" => Some(OutputFormat::Hex),
+            "carray" | "c" => Some(OutputFormat::CArray),
+            "progmem" | "arduino" => Some(OutputFormat::Progmem),
+            "rustarray" | "rust" => Some(OutputFormat::RustArray),
+            "python" | "py" => Some(OutputFormat::PythonArray),
+            "js" | "javascript" => Some(OutputFormat::JsArray),
+            "ts" | "typescript" => Some(OutputFormat::TsArray),
+            "go" | "golang" => Some(OutputFormat::GoArray),
+            "raw" | "bytes" => Some(OutputFormat::RawBytes),
+            "f32" | "raw-f32" | "rawf32" => Some(OutputFormat::RawFloat),
+            "csv" => Some(OutputFormat::Csv),
+            "json" => Some(OutputFormat::Json),
+            "npy" | "numpy" => Some(OutputFormat::Npy),
+            "matlab" | "m" | "octave" => Some(OutputFormat::Matlab),
+            "base64" | "b64" => Some(OutputFormat::Base64),
+            "ihex" | "intel-hex" => Some(OutputFormat::IntelHex),
+            "srec" | "mot" => Some(OutputFormat::SRecord(None)),
+            "s19" => Some(OutputFormat::SRecord(Some(2))),
+            "s28" => Some(OutputFormat::SRecord(Some(3))),
+            "s37" => Some(OutputFormat::SRecord(Some(4))),
+            "mem" | "readmemh" => Some(OutputFormat::ReadMemH),
+            "vhdl" | "vhd" => Some(OutputFormat::Vhdl),
+            "coe" => Some(OutputFormat::Coe),
+            "mif" => Some(OutputFormat::Mif),
+            "asm" | "gas" => Some(OutputFormat::Asm),
+            "info" => Some(OutputFormat::Info),
+            "wav" => Some(OutputFormat::WavFile),
+            "aiff" | "aif" => Some(OutputFormat::AiffFile),
+            "flac" => Some(OutputFormat::FlacFile),
+            "ogg" | "vorbis" => Some(OutputFormat::OggFile),
+            "mp3" => Some(OutputFormat::Mp3File),
+            "au" | "snd" => Some(OutputFormat::AuFile),
+            "caf" => Some(OutputFormat::CafFile),
+            _ => None,
+        }
+    }
+
+    /// Audio file containers, which carry their own header and fix the
+    /// sample layout.
+    fn is_audio_file(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::WavFile
+                | OutputFormat::AiffFile
+                | OutputFormat::FlacFile
+                | OutputFormat::OggFile
+                | OutputFormat::Mp3File
+                | OutputFormat::AuFile
+                | OutputFormat::CafFile
+        )
+    }
+
+    /// Data formats that list sample values rather than encoded bytes.
+    fn uses_values(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Csv | OutputFormat::Json | OutputFormat::Npy | OutputFormat::Matlab
+        )
+    }
+
+    /// Formats written as bytes to stdout or `--outfile`.
+    fn is_binary(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::RawBytes
+                | OutputFormat::RawFloat
+                | OutputFormat::Npy
+                | OutputFormat::IntelHex
+                | OutputFormat::SRecord(_)
+                | OutputFormat::Asm
+        ) || self.is_audio_file()
+            || self.uses_words()
+    }
+
+    /// FPGA memory initialization formats, which list `--word-bits` words.
+    fn uses_words(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::ReadMemH | OutputFormat::Vhdl | OutputFormat::Coe | OutputFormat::Mif
+        )
+    }
+
+    /// Name used in error messages about audio file output.
+    fn file_type(self) -> &'static str {
+        match self {
+            OutputFormat::AiffFile => "AIFF",
+            OutputFormat::FlacFile => "FLAC",
+            OutputFormat::OggFile => "Ogg Vorbis",
+            OutputFormat::Mp3File => "MP3",
+            OutputFormat::AuFile => "AU",
+            OutputFormat::CafFile => "CAF",
+            _ => "WAV",
         }
-        OutputFormat::RawBytes => {
-            print_raw_bytes(&buffer);
+    }
+}
+
+/// Arrangement of multichannel samples in the output buffer.
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    /// One frame after another: L0 R0 L1 R1 ...
+    Interleaved,
+    /// Each channel's samples in one block: L0 L1 ... R0 R1 ...
+    Planar,
+}
+
+impl Layout {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "interleaved" => Some(Layout::Interleaved),
+            "planar" => Some(Layout::Planar),
+            _ => None,
         }
-        OutputFormat::WavFile => {
-            let file = create_wav_file_array(
-                &buffer,
-                config.sample_rate,
-                config.channels as u16,
-                config.sample_width,
-            );
-            print_raw_bytes(file.as_ref());
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Layout::Interleaved => "interleaved",
+            Layout::Planar => "planar",
         }
     }
 }
+
+/// How samples are placed in the output words (I2S/TDM slots).
+#[derive(Clone, Copy, PartialEq)]
+enum Packing {
+    /// Sample-width bytes per sample, no padding
+    Packed,
+    /// Sample in the upper bits of the slot, low bits zero
+    LeftJustified,
+    /// Sample in the lower bits of the slot, sign-extended (ALSA S24_LE for 24 in 32)
+    RightJustified,
+    /// Philips I2S: left-justified, with the serial stream one bit clock late
+    I2s,
+}
+
+impl Packing {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "packed" => Some(Packing::Packed),
+            "left" | "lj" | "left-justified" => Some(Packing::LeftJustified),
+            "right" | "rj" | "right-justified" => Some(Packing::RightJustified),
+            "i2s" | "standard" => Some(Packing::I2s),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Packing::Packed => "packed",
+            Packing::LeftJustified => "left-justified",
+            Packing::RightJustified => "right-justified",
+            Packing::I2s => "I2S standard",
+        }
+    }
+}
+
+/// Parse a number with an optional SI prefix and an optional trailing unit,
+/// e.g. `2.5k`, `2.5kHz` or `48k`. The unit is matched case-insensitively;
+/// the prefix is case-sensitive so that `m` (milli) and `M` (mega) differ.
+/// NaN and infinities are rejected.
+fn parse_si(s: &str, unit: &str) -> Option<f64> {
+    let s = s.trim();
+    let s = match s.len().checked_sub(unit.len()) {
+        Some(split) if s.is_char_boundary(split) && s[split..].eq_ignore_ascii_case(unit) => {
+            &s[..split]
+        }
+        _ => s,
+    }
+    .trim_end();
+    if let Ok(value) = s.parse::<f64>() {
+        return value.is_finite().then_some(value);
+    }
+    let prefix = s.chars().last()?;
+    let scale = match prefix {
+        'G' => 1e9,
+        'M' => 1e6,
+        'k' | 'K' => 1e3,
+        'm' => 1e-3,
+        'u' | 'µ' => 1e-6,
+        _ => return None,
+    };
+    let number: f64 = s[..s.len() - prefix.len_utf8()].trim_end().parse().ok()?;
+    Some(number * scale).filter(|value| value.is_finite())
+}
+
+/// Default reference pitch of A4 (MIDI note 69) for note-name input.
+const A4_FREQUENCY: f64 = 440.0;
+const A4_MIDI_NOTE: f64 = 69.0;
+
+/// Semitones above C for the natural note letters C D E F G A B.
+static NOTE_SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// 5-limit just intonation ratios for each semitone above the root.
+static JUST_RATIOS: [f64; 12] = [
+    1.0,
+    16.0 / 15.0,
+    9.0 / 8.0,
+    6.0 / 5.0,
+    5.0 / 4.0,
+    4.0 / 3.0,
+    45.0 / 32.0,
+    3.0 / 2.0,
+    8.0 / 5.0,
+    5.0 / 3.0,
+    9.0 / 5.0,
+    15.0 / 8.0,
+];
+
+/// How note names map to pitches.
+#[derive(Clone, Copy, PartialEq)]
+enum Temperament {
+    /// 12-tone equal temperament
+    Equal,
+    /// Just intonation on a root pitch class (0 = C); the root itself is
+    /// tuned in equal temperament from A4
+    Just(i32),
+}
+
+impl Temperament {
+    /// Parse `equal`, `just` (on C) or `just:ROOT` such as `just:D` or `just:Eb`.
+    fn from_str(s: &str) -> Option<Self> {
+        match s.split_once(':') {
+            None if s == "equal" || s == "et" || s == "12tet" => Some(Temperament::Equal),
+            None if s == "just" => Some(Temperament::Just(0)),
+            Some(("just", root)) => {
+                let root = midi_note(&format!("{}4", root.trim()))?;
+                (root.fract() == 0.0).then_some(Temperament::Just((root as i32).rem_euclid(12)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reference pitch and temperament for note-name input.
+#[derive(Clone, Copy)]
+struct Tuning {
+    a4_hz: f64,
+    temperament: Temperament,
+}
+
+impl Tuning {
+    /// Frequency of a (possibly fractional) MIDI note. In just intonation the
+    /// fractional part is applied as equal-tempered cents above the degree.
+    fn frequency(&self, note: f64) -> f64 {
+        let equal = |note: f64| self.a4_hz * 2f64.powf((note - A4_MIDI_NOTE) / 12.0);
+        match self.temperament {
+            Temperament::Equal => equal(note),
+            Temperament::Just(root) => {
+                let whole = note.floor();
+                let interval = whole as i32 - root;
+                let root_note = root + 12 * interval.div_euclid(12);
+                equal(f64::from(root_note))
+                    * JUST_RATIOS[interval.rem_euclid(12) as usize]
+                    * 2f64.powf((note - whole) / 12.0)
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.temperament {
+            Temperament::Equal => format!("A4 = {} Hz, equal temperament", self.a4_hz),
+            Temperament::Just(root) => {
+                let names = [
+                    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+                ];
+                format!(
+                    "A4 = {} Hz, just intonation on {}",
+                    self.a4_hz, names[root as usize]
+                )
+            }
+        }
+    }
+}
+
+/// Read `--a4` and `--temperament` ahead of the other options, so they apply
+/// to note names wherever those appear on the command line.
+fn parse_tuning(args: &[String]) -> Tuning {
+    let mut tuning = Tuning {
+        a4_hz: A4_FREQUENCY,
+        temperament: Temperament::Equal,
+    };
+    for pair in args.windows(2) {
+        match pair[0].as_str() {
+            "--a4" => {
+                tuning.a4_hz = match parse_si(&pair[1], "hz") {
+                    Some(hz) if hz > 0.0 => hz,
+                    _ => {
+                        eprintln!("Error: A4 reference must be a positive frequency in Hz");
+                        process::exit(1);
+                    }
+                };
+            }
+            "--temperament" => {
+                tuning.temperament =
+                    Temperament::from_str(&pair[1].to_lowercase()).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid temperament, expected equal, just or just:ROOT");
+                        process::exit(1);
+                    });
+            }
+            _ => {}
+        }
+    }
+    tuning
+}
+
+/// Parse a frequency in Hz: `1000`, `1k`, `2.5kHz`, or a note (`A4`, `C#5`,
+/// `midi:69`) converted with `tuning`.
+fn parse_hz(s: &str, tuning: &Tuning) -> Option<f32> {
+    if let Some(hz) = parse_si(s, "hz") {
+        return Some(hz as f32);
+    }
+    Some(tuning.frequency(midi_note(s)?) as f32)
+}
+
+/// MIDI note number of `midi:N` or a scientific pitch name such as `A4`,
+/// `C#5`, `Bb3` or `C-1` (MIDI 0).
+fn midi_note(s: &str) -> Option<f64> {
+    let s = s.trim();
+    if let Some(number) = s.strip_prefix("midi:").or_else(|| s.strip_prefix("MIDI:")) {
+        return number
+            .trim()
+            .parse()
+            .ok()
+            .filter(|note: &f64| note.is_finite());
+    }
+    let mut chars = s.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let mut semitone = NOTE_SEMITONES["CDEFGAB".find(letter)?];
+    let rest = chars.as_str();
+    let octave = rest.trim_start_matches(['#', '♯', 'b', '♭']);
+    for accidental in rest[..rest.len() - octave.len()].chars() {
+        semitone += if matches!(accidental, '#' | '♯') {
+            1
+        } else {
+            -1
+        };
+    }
+    let octave: i32 = octave.parse().ok()?;
+    Some(f64::from((octave + 1) * 12 + semitone))
+}
+
+/// Parse a whole count with an optional SI prefix (`48k`, `1.5M`), rejecting
+/// fractions and negative values.
+fn parse_count(s: &str, unit: &str) -> Option<u64> {
+    let value = parse_si(s, unit)?;
+    let whole = value.round();
+    (whole >= 0.0 && whole <= u64::MAX as f64 && (value - whole).abs() < 1e-6)
+        .then_some(whole as u64)
+}
+
+/// Parse a time in milliseconds. A bare number is milliseconds; anything
+/// else must end in seconds, optionally prefixed (`250ms`, `1.5s`, `20us`).
+/// NaN and infinities are rejected.
+fn parse_ms(s: &str) -> Option<f32> {
+    let s = s.trim();
+    if let Ok(ms) = s.parse::<f32>() {
+        return ms.is_finite().then_some(ms);
+    }
+    if !s.ends_with(['s', 'S']) {
+        return None;
+    }
+    parse_si(s, "s")
+        .map(|secs| (secs * 1000.0) as f32)
+        .filter(|ms| ms.is_finite())
+}
+
+/// Signal length as given to `-d`, before it is resolved against the rate.
+#[derive(Clone, Copy)]
+enum DurationSpec {
+    Ms(f32),
+    Samples(usize),
+    /// Whole or fractional periods of the first channel's frequency
+    Cycles(f32),
+}
+
+impl DurationSpec {
+    /// Parse `500`, `500ms`, `2s`, `4800samples`, `48ksamples` or `10cycles`.
+    fn from_str(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        let number = |units: &[&str]| {
+            units
+                .iter()
+                .find(|unit| lower.ends_with(*unit))
+                .map(|unit| &s[..s.len() - unit.len()])
+        };
+        if let Some(samples) = number(&["samples", "sample", "smp"]) {
+            return parse_count(samples, "").map(|samples| DurationSpec::Samples(samples as usize));
+        }
+        let length = |value: f32| (value.is_finite() && value >= 0.0).then_some(value);
+        if let Some(cycles) = number(&["cycles", "cycle", "cyc"]) {
+            return parse_si(cycles, "")
+                .and_then(|cycles| length(cycles as f32))
+                .map(DurationSpec::Cycles);
+        }
+        if let Some(secs) = number(&["sec"]) {
+            return parse_si(secs, "")
+                .and_then(|secs| length((secs * 1000.0) as f32))
+                .map(DurationSpec::Ms);
+        }
+        parse_ms(s).and_then(length).map(DurationSpec::Ms)
+    }
+}
+
+/// Position of reduced-resolution (12/20-bit) codes within their container word.
+#[derive(Clone, Copy, PartialEq)]
+enum Justify {
+    /// Code in the upper bits, low bits zero
+    Left,
+    /// Code in the lower bits, sign-extended
+    Right,
+}
+
+impl Justify {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "left" | "msb" => Some(Justify::Left),
+            "right" | "lsb" => Some(Justify::Right),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Justify::Left => "left-justified",
+            Justify::Right => "right-justified",
+        }
+    }
+}
+
+/// Fixed-point element type for typed C and Rust arrays, as used by CMSIS-DSP.
+#[derive(Clone, Copy, PartialEq)]
+enum QFormat {
+    /// 1.15 fixed point, q15_t / i16
+    Q15,
+    /// 1.31 fixed point, q31_t / i32
+    Q31,
+}
+
+impl QFormat {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "q15" => Some(QFormat::Q15),
+            "q31" => Some(QFormat::Q31),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            QFormat::Q15 => "q15",
+            QFormat::Q31 => "q31",
+        }
+    }
+
+    fn sample_width(self) -> SampleWidth {
+        match self {
+            QFormat::Q15 => SampleWidth::Width2Byte,
+            QFormat::Q31 => SampleWidth::Width4Byte,
+        }
+    }
+
+    fn c_type(self) -> &'static str {
+        match self {
+            QFormat::Q15 => "q15_t",
+            QFormat::Q31 => "q31_t",
+        }
+    }
+
+    fn rust_type(self) -> &'static str {
+        match self {
+            QFormat::Q15 => "i16",
+            QFormat::Q31 => "i32",
+        }
+    }
+
+    /// Generated comment describing how samples map to codes.
+    fn saturation_comment(self, comment: &str) -> String {
+        let bits = self.sample_width() as u32 * 8;
+        let (min, max) = code_limits(bits);
+        format!(
+            "{} {}: value = round(x * 2^{}), saturated to [{}, {}]; +1.0 saturates to 0x{:X}",
+            comment,
+            self.to_str().to_uppercase(),
+            bits - 1,
+            min,
+            max,
+            max
+        )
+    }
+}
+
+/// Element type of generated C and Rust arrays.
+#[derive(Clone, Copy, PartialEq)]
+enum ElementType {
+    /// Flat byte dump of the output buffer
+    UInt8,
+    /// One 16-bit sample per element
+    Int16,
+    /// One 32-bit sample per element (24-bit samples are sign-extended)
+    Int32,
+    /// One 32-bit float sample per element, taken before quantization
+    Float32,
+}
+
+impl ElementType {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "uint8" | "uint8_t" | "u8" => Some(ElementType::UInt8),
+            "int16" | "int16_t" | "i16" => Some(ElementType::Int16),
+            "int32" | "int32_t" | "i32" => Some(ElementType::Int32),
+            "float" | "f32" => Some(ElementType::Float32),
+            _ => None,
+        }
+    }
+
+    fn c_type(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => "uint8_t",
+            ElementType::Int16 => "int16_t",
+            ElementType::Int32 => "int32_t",
+            ElementType::Float32 => "float",
+        }
+    }
+
+    fn rust_type(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => "u8",
+            ElementType::Int16 => "i16",
+            ElementType::Int32 => "i32",
+            ElementType::Float32 => "f32",
+        }
+    }
+
+    /// Go element type of a slice of this type.
+    fn go_type(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => "byte",
+            ElementType::Int16 => "int16",
+            ElementType::Int32 => "int32",
+            ElementType::Float32 => "float32",
+        }
+    }
+
+    /// `<avr/pgmspace.h>` macro reading one element of this type from flash.
+    fn pgm_read(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => "pgm_read_byte",
+            ElementType::Int16 => "pgm_read_word",
+            ElementType::Int32 => "pgm_read_dword",
+            ElementType::Float32 => "pgm_read_float",
+        }
+    }
+
+    /// GNU assembler data directive for one element of this type. `.long`
+    /// rather than `.word`, which is 16 bits on x86 and 32 bits on ARM.
+    fn asm_directive(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => ".byte",
+            ElementType::Int16 => ".short",
+            ElementType::Int32 => ".long",
+            ElementType::Float32 => ".float",
+        }
+    }
+
+    /// JavaScript typed array holding elements of this type.
+    fn js_type(self) -> &'static str {
+        match self {
+            ElementType::UInt8 => "Uint8Array",
+            ElementType::Int16 => "Int16Array",
+            ElementType::Int32 => "Int32Array",
+            ElementType::Float32 => "Float32Array",
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            ElementType::UInt8 => 8,
+            ElementType::Int16 => 16,
+            ElementType::Int32 | ElementType::Float32 => 32,
+        }
+    }
+}
+
+/// Number base of typed array values.
+#[derive(Clone, Copy, PartialEq)]
+enum Radix {
+    Decimal,
+    Hex,
+}
+
+impl Radix {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "dec" | "decimal" => Some(Radix::Decimal),
+            "hex" | "hexadecimal" => Some(Radix::Hex),
+            _ => None,
+        }
+    }
+}
+
+/// Compiler syntax for alignment and section attributes on C arrays.
+#[derive(Clone, Copy, PartialEq)]
+enum AttributeStyle {
+    /// `__attribute__((aligned(N), section("...")))`, also understood by Clang
+    Gcc,
+    /// `__declspec(align(N))` and `__declspec(allocate("..."))`
+    Msvc,
+}
+
+impl AttributeStyle {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "gcc" | "clang" => Some(AttributeStyle::Gcc),
+            "msvc" => Some(AttributeStyle::Msvc),
+            _ => None,
+        }
+    }
+}
+
+/// Byte order of each multi-byte sample.
+#[derive(Clone, Copy, PartialEq)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "little" | "le" => Some(Endian::Little),
+            "big" | "be" => Some(Endian::Big),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Endian::Little => "little-endian",
+            Endian::Big => "big-endian",
+        }
+    }
+}
+
+/// Kind of signal to synthesize.
+#[derive(Clone, Copy, PartialEq)]
+enum Waveform {
+    /// Pure sine tone
+    Sine,
+    /// Maximum length sequence (pseudo-random ±full-scale)
+    Mls,
+    /// Repeating asymmetric positive-going pulse for polarity checks
+    Polarity,
+    /// EBU GLITS stereo line-up ident
+    Glits,
+    /// EBU BLITS surround/stereo line-up ident
+    Blits,
+    /// Sum of the sine components in `Config::tones`
+    Multitone,
+    /// Dunn J-test jitter signal (bit-exact integer codes)
+    JTest,
+    /// Fixed digital bit patterns from `Config::bit_pattern`
+    BitPattern,
+    /// Evenly spaced code levels held for a fixed dwell each
+    Staircase,
+    /// Full-scale ramp from the lowest to the highest code over the duration
+    Ramp,
+    /// Uniform white noise, optionally band-limited
+    Noise,
+    /// Karplus-Strong plucked string at the configured frequency
+    Pluck,
+    /// User-supplied single-cycle wavetable looped at the configured frequency
+    Wavetable,
+    /// Per-sample math expression from `Config::expression`
+    Expression,
+    /// Tone stepped through each channel in turn for wiring checks
+    ChannelId,
+    /// Pink (-3 dB/octave) noise, optionally band-limited
+    PinkNoise,
+    /// Pink noise at 0 dBFS RMS stepped through each channel in turn, for
+    /// speaker level calibration; the level setting sets its RMS level
+    CalNoise,
+}
+
+impl Waveform {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "sine" | "sin" => Some(Waveform::Sine),
+            "mls" => Some(Waveform::Mls),
+            "polarity" | "pulse" => Some(Waveform::Polarity),
+            "glits" => Some(Waveform::Glits),
+            "blits" => Some(Waveform::Blits),
+            "jtest" | "j-test" => Some(Waveform::JTest),
+            "pattern" | "bits" => Some(Waveform::BitPattern),
+            "staircase" | "stairs" => Some(Waveform::Staircase),
+            "ramp" => Some(Waveform::Ramp),
+            "noise" | "white" => Some(Waveform::Noise),
+            "pluck" | "karplus" => Some(Waveform::Pluck),
+            "wavetable" | "table" => Some(Waveform::Wavetable),
+            "expr" => Some(Waveform::Expression),
+            "chanid" | "channel-id" => Some(Waveform::ChannelId),
+            "pink" | "pinknoise" => Some(Waveform::PinkNoise),
+            "calnoise" => Some(Waveform::CalNoise),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Waveform::Sine => "sine",
+            Waveform::Mls => "mls",
+            Waveform::Polarity => "polarity",
+            Waveform::Glits => "glits",
+            Waveform::Blits => "blits",
+            Waveform::Multitone => "multitone",
+            Waveform::JTest => "jtest",
+            Waveform::BitPattern => "pattern",
+            Waveform::Staircase => "staircase",
+            Waveform::Ramp => "ramp",
+            Waveform::Noise => "noise",
+            Waveform::Pluck => "pluck",
+            Waveform::Wavetable => "wavetable",
+            Waveform::Expression => "expr",
+            Waveform::ChannelId => "chanid",
+            Waveform::PinkNoise => "pink",
+            Waveform::CalNoise => "calnoise",
+        }
+    }
+
+    /// Whether the `-f` frequency is meaningful for this waveform.
+    fn uses_frequency(self) -> bool {
+        matches!(
+            self,
+            Waveform::Sine
+                | Waveform::Pluck
+                | Waveform::Wavetable
+                | Waveform::Expression
+                | Waveform::ChannelId
+        )
+    }
+
+    /// Whether the start phase and `--phase-offset` apply to this waveform.
+    fn uses_phase(self) -> bool {
+        matches!(self, Waveform::Sine | Waveform::Wavetable)
+    }
+
+    /// Whether the waveform is built from exact integer codes rather than
+    /// quantized float samples. Level and gain do not apply to these.
+    fn is_code_exact(self) -> bool {
+        matches!(
+            self,
+            Waveform::JTest | Waveform::BitPattern | Waveform::Staircase | Waveform::Ramp
+        )
+    }
+}
+
+/// Per-sample bit patterns for exercising digital audio links.
+#[derive(Clone, Copy)]
+enum BitPattern {
+    /// The same code in every sample
+    Fixed(u32),
+    /// 0xAA.. and 0x55.. on alternate samples
+    Alternating,
+    /// A single set bit moving from LSB to MSB
+    WalkingOnes,
+    /// A single cleared bit moving from LSB to MSB
+    WalkingZeros,
+}
+
+impl BitPattern {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "alternating" | "alt" => Some(BitPattern::Alternating),
+            "walking-ones" | "walk1" => Some(BitPattern::WalkingOnes),
+            "walking-zeros" | "walk0" => Some(BitPattern::WalkingZeros),
+            other => {
+                let hex = other.strip_prefix("0x").unwrap_or(other);
+                u32::from_str_radix(hex, 16).ok().map(BitPattern::Fixed)
+            }
+        }
+    }
+
+    fn describe(self) -> String {
+        match self {
+            BitPattern::Fixed(value) => format!("0x{:X}", value),
+            BitPattern::Alternating => "alternating".to_string(),
+            BitPattern::WalkingOnes => "walking ones".to_string(),
+            BitPattern::WalkingZeros => "walking zeros".to_string(),
+        }
+    }
+}
+
+/// One sine component of a multitone signal.
+#[derive(Clone, Copy)]
+struct Tone {
+    frequency: f32,
+    /// Linear amplitude relative to full scale
+    amplitude: f32,
+}
+
+/// Complete signal spec for one channel, given with `--ch`.
+#[derive(Clone, Copy)]
+struct ChannelSpec {
+    /// Zero-based channel index
+    channel: usize,
+    waveform: Waveform,
+    frequency: Option<f32>,
+    /// Channel level in dBFS, replacing any preset level
+    level_dbfs: Option<f32>,
+    phase_deg: Option<f32>,
+}
+
+impl ChannelSpec {
+    /// Parse `CH:WAVEFORM[:FREQ][:LEVELdB][:PHASEdeg]`, CH counting from 1.
+    /// The optional fields are told apart by their units.
+    fn from_str(s: &str, tuning: &Tuning) -> Option<Self> {
+        let mut fields = s.split(':').map(str::trim);
+        let channel: usize = fields.next()?.parse().ok()?;
+        let waveform = Waveform::from_str(fields.next()?)?;
+        if channel == 0 || waveform.is_code_exact() {
+            return None;
+        }
+        let mut spec = ChannelSpec {
+            channel: channel - 1,
+            waveform,
+            frequency: None,
+            level_dbfs: None,
+            phase_deg: None,
+        };
+        for original in fields {
+            let field = original.to_lowercase();
+            if let Some(level) = field
+                .strip_suffix("dbfs")
+                .or_else(|| field.strip_suffix("db"))
+            {
+                spec.level_dbfs = Some(level.trim().parse().ok()?);
+            } else if let Some(phase) = field.strip_suffix("deg") {
+                spec.phase_deg = Some(phase.trim().parse().ok()?);
+            } else {
+                let frequency = parse_hz(original, tuning)?;
+                if !(frequency.is_finite() && frequency > 0.0) {
+                    return None;
+                }
+                spec.frequency = Some(frequency);
+            }
+        }
+        Some(spec)
+    }
+
+    fn describe(self, config: &Config) -> String {
+        let mut text = self.waveform.to_str().to_string();
+        if self.waveform.uses_frequency() {
+            text += &format!(" {} Hz", self.frequency.unwrap_or(config.frequency));
+        }
+        if let Some(level) = self.level_dbfs {
+            text += &format!(", {} dBFS", level);
+        }
+        if let Some(phase) = self.phase_deg {
+            text += &format!(", {} deg", phase);
+        }
+        text
+    }
+}
+
+/// Stereo mid/side matrixing applied to the rendered channels.
+#[derive(Clone, Copy)]
+enum MidSide {
+    /// L/R in, M/S out: M = (L + R) / 2, S = (L - R) / 2
+    Encode,
+    /// M/S in, L/R out: L = M + S, R = M - S
+    Decode,
+}
+
+impl MidSide {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "encode" | "ms" => Some(MidSide::Encode),
+            "decode" | "lr" => Some(MidSide::Decode),
+            _ => None,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            MidSide::Encode => "L/R to M/S",
+            MidSide::Decode => "M/S to L/R",
+        }
+    }
+
+    fn apply(self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let (a, b) = (*l, *r);
+            (*l, *r) = match self {
+                MidSide::Encode => (0.5 * (a + b), 0.5 * (a - b)),
+                MidSide::Decode => (a + b, a - b),
+            };
+        }
+    }
+}
+
+/// Delay of one channel, from `--ch-delay`.
+#[derive(Clone, Copy)]
+enum ChannelDelay {
+    Samples(usize),
+    Ms(f32),
+}
+
+impl ChannelDelay {
+    /// Parse a sample count (`48`, `1k`), or a time with a seconds unit
+    /// (`1ms`, `250us`).
+    fn from_str(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.ends_with(['s', 'S']) {
+            let ms = parse_ms(s)?;
+            return (ms >= 0.0).then_some(ChannelDelay::Ms(ms));
+        }
+        Some(ChannelDelay::Samples(parse_count(s, "")? as usize))
+    }
+
+    fn samples(self, sample_rate: u32) -> usize {
+        match self {
+            ChannelDelay::Samples(samples) => samples,
+            ChannelDelay::Ms(ms) => ((ms * sample_rate as f32) / 1000.0).round() as usize,
+        }
+    }
+}
+
+/// Broadcast Wave (EBU Tech 3285) `bext` fields. Unset text fields are
+/// filled in when the file is written: the signal description, `singen`,
+/// and the current UTC date and time.
+#[derive(Clone, Default)]
+struct Bext {
+    description: Option<String>,
+    originator: Option<String>,
+    date: Option<String>,
+    time: Option<String>,
+    /// Timecode of the first sample in seconds since midnight
+    time_reference_secs: f64,
+}
+
+/// RIFF `INFO` tags. The title defaults to the signal description and the
+/// comment to the command line, so the settings travel with the file.
+#[derive(Clone, Default)]
+struct Info {
+    title: Option<String>,
+    comment: Option<String>,
+}
+
+/// Check `s` against a fixed pattern where `9` stands for any digit.
+fn matches_digit_pattern(s: &str, pattern: &str) -> bool {
+    s.len() == pattern.len()
+        && s.chars()
+            .zip(pattern.chars())
+            .all(|(c, p)| if p == '9' { c.is_ascii_digit() } else { c == p })
+}
+
+/// Parse `HH:MM:SS` or `HH:MM:SS.sss` into seconds since midnight.
+fn parse_timecode(s: &str) -> Option<f64> {
+    let mut fields = s.trim().split(':');
+    let hours: u32 = fields.next()?.parse().ok()?;
+    let minutes: u32 = fields.next()?.parse().ok()?;
+    let seconds: f64 = fields.next()?.parse().ok()?;
+    if fields.next().is_some() || hours >= 24 || minutes >= 60 || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(f64::from(hours * 3600 + minutes * 60) + seconds)
+}
+
+/// Current UTC date and time as `YYYY-MM-DD` and `HH:MM:SS`.
+fn utc_date_time() -> (String, String) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, time) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01 (proleptic Gregorian)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60),
+    )
+}
+
+/// Named signal presets. A preset overwrites the relevant configuration
+/// fields; options given after it on the command line still take effect.
+#[derive(Clone, Copy)]
+enum Preset {
+    /// EBU R68 alignment: 1 kHz at -18 dBFS
+    Ebu18,
+    /// SMPTE RP155 reference: 1 kHz at -20 dBFS
+    Smpte20,
+    /// AES17 997 Hz measurement tone at one of the common measurement levels
+    Aes17 { level_dbfs: f32 },
+    /// SMPTE RP120 IMD: 60 Hz and 7 kHz at 4:1
+    ImdSmpte,
+    /// CCIF (ITU-R) IMD: 19 kHz and 20 kHz at 1:1
+    ImdCcif,
+    /// Speaker calibration: 500 Hz-2 kHz pink noise at an RMS reference
+    /// level, stepped through each channel
+    SurroundCal { level_dbfs: f32 },
+}
+
+// IMD two-tone signals. Amplitudes sum to 1.0 so the composite peak sits
+// exactly at the configured level.
+static IMD_SMPTE_TONES: [Tone; 2] = [
+    Tone {
+        frequency: 60.0,
+        amplitude: 0.8,
+    },
+    Tone {
+        frequency: 7000.0,
+        amplitude: 0.2,
+    },
+];
+static IMD_CCIF_TONES: [Tone; 2] = [
+    Tone {
+        frequency: 19000.0,
+        amplitude: 0.5,
+    },
+    Tone {
+        frequency: 20000.0,
+        amplitude: 0.5,
+    },
+];
+
+// AES17 recommends 997 Hz rather than 1 kHz: it is prime relative to the
+// common sample rates, so the tone does not repeat over a short, identical
+// set of sample codes and quantization error is spread over all codes.
+const AES17_FREQUENCY: f32 = 997.0;
+// Common AES17 measurement levels: full scale, the -20 dBFS reference level
+// and the -60 dBFS level used for dynamic range (noise in presence of signal).
+static AES17_LEVELS_DBFS: [f32; 3] = [0.0, -20.0, -60.0];
+
+// Monitor calibration (SMPTE RP200 style): band-limited pink noise at
+// -20 dBFS RMS, a few seconds per channel.
+const SURROUND_CAL_LEVEL_DBFS: f32 = -20.0;
+const SURROUND_CAL_BAND: (f32, f32) = (500.0, 2000.0);
+const SURROUND_CAL_DWELL_MS: f32 = 5000.0;
+const SURROUND_CAL_GAP_MS: f32 = 1000.0;
+
+impl Preset {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "ebu-18" | "ebu" => Some(Preset::Ebu18),
+            "smpte-20" | "smpte" => Some(Preset::Smpte20),
+            "aes17" => Some(Preset::Aes17 { level_dbfs: -20.0 }),
+            "imd-smpte" => Some(Preset::ImdSmpte),
+            "imd-ccif" => Some(Preset::ImdCcif),
+            "surround-cal" => Some(Preset::SurroundCal {
+                level_dbfs: SURROUND_CAL_LEVEL_DBFS,
+            }),
+            other => {
+                if let Some(level) = other.strip_prefix("surround-cal-") {
+                    let level: f32 = level.parse().ok().filter(|level: &f32| level.is_finite())?;
+                    return Some(Preset::SurroundCal {
+                        level_dbfs: -level.abs(),
+                    });
+                }
+                let level: f32 = other.strip_prefix("aes17-")?.parse().ok()?;
+                let level_dbfs = -level.abs();
+                AES17_LEVELS_DBFS
+                    .contains(&level_dbfs)
+                    .then_some(Preset::Aes17 { level_dbfs })
+            }
+        }
+    }
+
+    fn apply(self, config: &mut Config) {
+        config.waveform = Waveform::Sine;
+        match self {
+            Preset::Ebu18 => {
+                config.frequency = 1000.0;
+                config.level_dbfs = -18.0;
+            }
+            Preset::Smpte20 => {
+                config.frequency = 1000.0;
+                config.level_dbfs = -20.0;
+            }
+            Preset::Aes17 { level_dbfs } => {
+                config.frequency = AES17_FREQUENCY;
+                config.level_dbfs = level_dbfs;
+            }
+            Preset::ImdSmpte => {
+                config.waveform = Waveform::Multitone;
+                config.tones = IMD_SMPTE_TONES.to_vec();
+                config.level_dbfs = 0.0;
+            }
+            Preset::ImdCcif => {
+                config.waveform = Waveform::Multitone;
+                config.tones = IMD_CCIF_TONES.to_vec();
+                config.level_dbfs = 0.0;
+            }
+            Preset::SurroundCal { level_dbfs } => {
+                config.waveform = Waveform::CalNoise;
+                config.noise_band = Some(SURROUND_CAL_BAND);
+                config.id_tone_ms = SURROUND_CAL_DWELL_MS;
+                config.id_gap_ms = SURROUND_CAL_GAP_MS;
+                config.level_dbfs = level_dbfs;
+            }
+        }
+    }
+}
+
+// Feedback masks of maximal-length Galois LFSRs, indexed by order - MLS_MIN_ORDER.
+// Each mask yields a period of exactly 2^order - 1 states for any nonzero seed.
+const MLS_MIN_ORDER: u8 = 2;
+const MLS_MAX_ORDER: u8 = 24;
+static MLS_TAPS: [u32; 23] = [
+    0x3, 0x6, 0xC, 0x14, 0x30, 0x60, 0xB8, 0x110, 0x240, 0x500, 0xE08, 0x1C80, 0x3802, 0x6000,
+    0xD008, 0x12000, 0x20400, 0x72000, 0x90000, 0x140000, 0x300000, 0x420000, 0xE10000,
+];
+
+fn print_usage() {
+    println!("Usage: singen [OPTIONS]");
+    println!();
+    println!("Options:");
+    println!("  -f, --frequency FREQ     Sine wave frequency in Hz (default: 440.0); a comma");
+    println!("                           list such as 440,1000 sets one per channel");
+    println!("                           SI prefixes work here and for rates, times and");
+    println!("                           counts: 1k, 2.5kHz, 48k, 1.5s, 250us, 48ksamples");
+    println!("                           Frequencies also take notes (equal temperament,");
+    println!("                           A4 = 440 Hz): A4, C#5, Bb3, midi:69");
+    println!("      --a4 HZ              Reference pitch of A4 for note names (default: 440)");
+    println!("      --temperament T      Tuning of note names: equal (default), or just");
+    println!("                           intonation on a root, just:C, just:Eb, ...");
+    println!("      --pan P              Constant-power pan of the signal between the left");
+    println!("                           and right channels, -1.0 to 1.0; others are muted");
+    println!(
+        "      --mid-side MODE      Stereo matrix: encode (L/R to M/S) or decode (M/S to L/R)"
+    );
+    println!("      --phase DEG          Start phase of the oscillator, e.g. 90 for a cosine");
+    println!("                           (sine and wavetable, default: 0)");
+    println!("      --phase-offset DEG   Phase of each channel relative to the previous one");
+    println!("                           (sine and wavetable, default: 0)");
+    println!("      --frequency-left F   Frequency of the left channel");
+    println!("      --frequency-right F  Frequency of the right channel");
+    println!("  -r, --rate RATE          Sample rate in Hz (default: 16000)");
+    println!("                           Any positive rate; standard: 8000, 11025, 16000,");
+    println!("                           22050, 32000, 44100, 48000, 88200, 96000, 192000");
+    println!("      --internal-rate RATE Synthesize at RATE, then resample to the output rate");
+    println!("                           with a windowed-sinc filter (band-limits the signal)");
+    println!("      --drift-ppm PPM      Render as if the sample clock were PPM parts per");
+    println!("                           million fast (negative: slow), via resampling");
+    println!("  -c, --channels CH        Number of channels, 1-32 (1=mono, 2=stereo, 6=5.1,");
+    println!("                           8=7.1, default: 2)");
+    println!("  -b, --bits BITS          Bit depth: 8, 12, 16, 20, 24, 32, f32, f64, alaw");
+    println!("                           or ulaw (default: 16)");
+    println!("                           8-bit samples are unsigned, centred on 0x80;");
+    println!("                           f32 writes 32-bit IEEE float samples, f64");
+    println!("                           64-bit ones (raw, hex and array output only);");
+    println!("                           alaw and ulaw write 8-bit G.711 A-law and μ-law");
+    println!("  -d, --duration DUR       Duration: milliseconds by default, or with a unit");
+    println!("                           500ms, 2s, 4800samples, 10cycles (default: 1.0)");
+    println!("  -o, --output FORMAT      Output format:");
+    println!("                           hex      - Hexadecimal values (default)");
+    println!("                           carray   - C-style array declaration");
+    println!("                           progmem  - C array in AVR flash for Arduino (PROGMEM,");
+    println!("                                      avr/pgmspace.h), with flash size checks");
+    println!("                           rustarray - Rust array declaration");
+    println!("                           python   - Python bytes literal, or a list of values");
+    println!("                                      with --element-type/--q-format");
+    println!(
+        "                           js, ts   - JavaScript/TypeScript module exporting a typed"
+    );
+    println!(
+        "                                      array (Uint8Array, Int16Array, ...) and the rate"
+    );
+    println!("                           go       - Go []byte declaration (typed slice with");
+    println!("                                      --element-type/--q-format)");
+    println!(
+        "                           base64   - Raw bytes (or a WAV file with --base64-wav) as"
+    );
+    println!("                                      one line of base64 text");
+    println!("                           ihex     - Intel HEX records at --base-address, for");
+    println!("                                      flashing tables into MCU memory");
+    println!(
+        "                           srec     - Motorola S-records at --base-address; s19, s28"
+    );
+    println!("                                      or s37 force 16-, 24- or 32-bit addresses");
+    println!("                           mem      - Verilog $readmemh file, one hex word per line");
+    println!("                           vhdl     - VHDL package with a constant std_logic_vector");
+    println!("                                      ROM array");
+    println!("                           coe      - Xilinx coefficient file for Block Memory");
+    println!("                                      Generator IP");
+    println!("                           mif      - Intel/Altera memory initialization file for");
+    println!("                                      Quartus");
+    println!(
+        "                           asm      - GNU assembler .byte table in a .rodata section"
+    );
+    println!("                                      (.short/.long/.float with --element-type)");
+    println!("                           raw      - Raw binary bytes (stdout or --outfile)");
+    println!(
+        "                           f32      - Raw f32 little-endian samples before quantization,"
+    );
+    println!("                                      ignoring -b (stdout or --outfile)");
+    println!(
+        "                           csv      - index,time_s,channel,value rows, plus the signed"
+    );
+    println!("                                      integer code for integer bit depths");
+    println!("                           json     - Settings, command line and per-channel sample");
+    println!("                                      arrays (integer codes or floats)");
+    println!(
+        "                           npy      - NumPy array, frames x channels: float32, or int16/"
+    );
+    println!("                                      int32 codes (stdout or --outfile)");
+    println!("                           matlab   - MATLAB/Octave .m script: fs and a frames x");
+    println!("                                      channels matrix");
+    println!(
+        "                           wav      - Windows audio file format (stdout or --outfile)"
+    );
+    println!("                           aiff     - AIFF, big-endian PCM (stdout or --outfile)");
+    println!(
+        "                           flac     - Lossless FLAC, 8/16/24-bit (stdout or --outfile)"
+    );
+    println!("                           ogg      - Ogg Vorbis at --quality (stdout or --outfile)");
+    println!(
+        "                           mp3      - MPEG-1 Layer III at --bitrate, mono or stereo,"
+    );
+    println!("                                      32/44.1/48 kHz (stdout or --outfile)");
+    println!("                           au       - Sun AU/.snd: linear PCM, float, μ-law or");
+    println!("                                      A-law, big-endian (stdout or --outfile)");
+    println!("                           caf      - Apple Core Audio Format: linear PCM, float,");
+    println!(
+        "                                      μ-law or A-law, no 4 GB limit (stdout or --outfile)"
+    );
+    println!("                           info     - Only show buffer info, no data");
+    println!("      --layout LAYOUT      Sample order for raw, hex and array output:");
+    println!("                           interleaved (default) or planar (channel blocks)");
+    println!("      --endian ORDER       Byte order for raw, hex and array output:");
+    println!("                           little (default) or big");
+    println!("      --packing MODE       Slot layout for raw, hex and array output:");
+    println!("                           packed (sample bytes only, default), left");
+    println!("                           (left-justified), right (right-justified,");
+    println!("                           sign-extended) or i2s (left-justified, one bit");
+    println!("                           clock late)");
+    println!("      --slot-bits N        Slot width for --packing: 16, 24 or 32 (default: 32)");
+    println!("      --q-format Q         Emit array output (C, Rust, Python, JS/TS, Go) as");
+    println!("                           CMSIS-DSP q15_t or q31_t (i16/i32) values: q15 or q31");
+    println!("      --element-type T     Array element: uint8_t/u8 (byte dump, default),");
+    println!("                           int16_t/i16, int32_t/i32 (one sample per");
+    println!("                           element) or float/f32 (float literals of the");
+    println!("                           samples before quantization, any bit depth)");
+    println!("      --radix R            Typed array values in dec (default) or hex");
+    println!("      --name NAME          Identifier of carray, progmem and rustarray output");
+    println!("                           (split channels append _ch1, _ch2, ...)");
+    println!("      --static             Declare the array static (C) or as a static item (Rust)");
+    println!("      --constexpr          Declare carray/progmem output constexpr (C++, C23)");
+    println!("      --namespace NS       Wrap the array in a C++ namespace or Rust module;");
+    println!("                           a::b nests");
+    println!("      --align N            Align carray, progmem and asm arrays to N bytes");
+    println!("                           (a power of two), e.g. for DMA buffers");
+    println!("      --section NAME       Place carray and asm arrays in linker section NAME");
+    println!("      --attribute-style S  C attribute syntax for --align/--section: gcc");
+    println!("                           (default, also Clang) or msvc");
+    println!("      --split-bytes N      Emit carray/progmem tables over N bytes as NAME_0,");
+    println!("                           NAME_1, ... with NAME_CHUNKS and NAME_CHUNK_LENS");
+    println!("                           index tables (e.g. 32k for AVR, 64k per flash bank)");
+    println!("      --descriptor         Add a NAME_DESC descriptor (sample rate, channels,");
+    println!("                           bits, frames, data and length) to carray, progmem");
+    println!("                           and rustarray output");
+    println!("      --offset-binary      Write integer samples unsigned, offset by half the");
+    println!("                           code range (0x8000 is silence at 16 bits)");
+    println!("      --container BITS     Word size for 12- and 20-bit samples: 16, 24 or 32");
+    println!("                           (default: 16 for 12-bit, 24 for 20-bit)");
+    println!("      --justify SIDE       12/20-bit codes in the container: right");
+    println!("                           (sign-extended, default) or left (low bits zero)");
+    println!("      --split-channels     Emit one mono buffer or array per channel; raw and");
+    println!("                           WAV output is written to <stem>_chN.<ext> files");
+    println!("  -O, --outfile PATH       Write raw, npy, memory image, asm or audio file output");
+    println!("                           to PATH instead of stdout; carray and progmem");
+    println!("                           output is written as PATH.h and PATH.c");
+    println!("      --rf64               Write WAV output as RF64 (64-bit sizes in a ds64 chunk);");
+    println!("                           automatic when the file would exceed 4 GB");
+    println!("      --bext               Add a Broadcast Wave bext chunk to WAV output");
+    println!("      --bext-description T Description (default: the signal settings)");
+    println!("      --bext-originator T  Originator (default: singen)");
+    println!("      --bext-date DATE     Origination date, YYYY-MM-DD (default: today, UTC)");
+    println!("      --bext-time TIME     Origination time, HH:MM:SS (default: now, UTC)");
+    println!("      --bext-timecode TC   Timecode of the first sample, HH:MM:SS[.sss]; each");
+    println!("                           --bext-* option implies --bext");
+    println!("      --loop-points S:E    Add a smpl chunk looping the signal from S to E");
+    println!("                           (durations as for -d; E is exclusive and defaults");
+    println!("                           to the end of the signal), e.g. 0:1cycles");
+    println!("      --info               Add LIST/INFO tags to WAV output: title, comment");
+    println!("                           (the command line), software and date");
+    println!("      --info-title TEXT    INFO title (default: the signal description)");
+    println!("      --info-comment TEXT  INFO comment (default: the command line)");
+    println!("      --cues               Add labelled WAV cue points at segment boundaries:");
+    println!("                           channel slots, ident cycles, bursts, crossfade, tail");
+    println!("      --base64-wav         Encode a complete WAV file in base64 output");
+    println!("      --data-uri           Prefix base64 output with data:audio/wav;base64, or");
+    println!("                           data:application/octet-stream;base64,");
+    println!("      --base-address ADDR  Load address of the first byte in ihex and srec");
+    println!("                           output, decimal or 0x hex (default: 0)");
+    println!("      --record-length N    Data bytes per ihex (1-255) or srec (1-250) record");
+    println!("                           (default: 16)");
+    println!("      --word-bits N        Word width of mem, vhdl, coe and mif output, 1-64");
+    println!("                           bits (default: the sample width); words take");
+    println!("                           whole bytes in output order, masked to N bits");
+    println!("      --quality Q          Ogg Vorbis quality, -1 to 10 (default: 3); higher");
+    println!("                           keeps coding noise further below the signal");
+    println!("      --bitrate KBPS       MP3 bit rate: 32, 40, 48, 56, 64, 80, 96, 112, 128,");
+    println!("                           160, 192, 224, 256 or 320 kbit/s (default: 128)");
+    println!("  -w, --waveform TYPE      Signal to generate:");
+    println!("                           sine     - Sine tone (default)");
+    println!("                           mls      - Maximum length sequence");
+    println!("                           polarity - Repeating positive-going polarity test pulse");
+    println!("                           glits    - EBU GLITS stereo ident (1 kHz, -18 dBFS)");
+    println!(
+        "                           blits    - EBU BLITS ident sequence (-18 dBFS, 2 kHz at -24)"
+    );
+    println!("                           jtest    - J-test: fs/4 square + LSB toggle at fs/192");
+    println!("                           pattern  - Digital bit pattern (see --pattern)");
+    println!("                           staircase - Evenly spaced DAC code levels");
+    println!("                           ramp     - Full-scale ramp over the duration");
+    println!("                           noise    - White noise (see --band, --seed)");
+    println!("                           pluck    - Karplus-Strong plucked string");
+    println!("                           wavetable - Loop a single-cycle table (see --table)");
+    println!("                           expr     - Math expression (see --expr)");
+    println!("                           chanid   - Tone stepped through each channel in turn");
+    println!("                           pink     - Pink noise (see --band, --seed)");
+    println!("                           calnoise - Pink noise at 0 dBFS RMS through each channel");
+    println!("      --mls-order N        MLS order, period is 2^N-1 samples (2-24, default: 16)");
+    println!("      --mls-seed SEED      Nonzero MLS register seed (default: 1)");
+    println!("      --pulse-interval MS  Polarity pulse repetition interval (default: 500)");
+    println!("      --pattern PAT        Bit pattern: alternating, walking-ones, walking-zeros");
+    println!("                           or a hex code such as 0xAAAA (default: alternating)");
+    println!("      --steps N            Staircase level count (default: 16)");
+    println!("      --dwell SAMPLES      Samples held per staircase step (default: 64)");
+    println!("      --id-tone MS         Channel ID tone or calibration noise dwell per channel");
+    println!("                           (default: 1000)");
+    println!("      --id-gap MS          Silence after each channel's turn (default: 500)");
+    println!("      --burst ON:OFF       Gate the signal into bursts, e.g. 200:800 (ms) or 1s:4s");
+    println!("      --burst-edge MS      Raised-cosine edge time for bursts (default: 0)");
+    println!("      --crossfade-to W[:F] Crossfade into a second waveform, e.g. noise or sine:880");
+    println!(
+        "      --crossfade MS       Equal-power crossfade length, centred (default: duration)"
+    );
+    println!("      --ch SPEC            Independent signal for one channel, repeatable:");
+    println!("                           CH:WAVEFORM[:FREQ][:LEVELdB][:PHASEdeg], CH from 1,");
+    println!("                           e.g. --ch 1:sine:1000:-20dB --ch 2:noise:-20dB");
+    println!("      --band LOW:HIGH      Band-limit noise to LOW..HIGH Hz");
+    println!("      --seed N             Random seed for noise (default: 1)");
+    println!("      --snr-db DB          Mix in white noise at DB below the signal RMS");
+    println!("      --pluck-decay G      Plucked string loop gain, 0-1 (default: 0.996)");
+    println!("      --table PATH         Single-cycle wavetable: .wav, .csv/.txt or raw f32 LE");
+    println!("      --expr EXPR          Per-sample expression, e.g. \"sin(2*pi*f*t)\"");
+    println!("                           Variables: t (s), f (Hz), fs (Hz), n (index)");
+    println!("                           Functions: sin cos tan abs sqrt exp ln log10 floor");
+    println!("                           ceil sign min max pow; constants pi, tau, e");
+    println!("      --tail MS            Append MS of silence after the signal (default: 0)");
+    println!("      --loopable           Snap frequencies so the signal holds whole cycles");
+    println!("                           and loops without a click (adjustment is reported)");
+    println!("      --pad-to FRAMES      Zero-pad the buffer to a multiple of FRAMES frames");
+    println!("      --pad-truncate       Truncate to the multiple below instead of padding");
+    println!("  -g, --gain-db DB         Gain in dB applied before quantization (default: 0)");
+    println!("      --ch-gain G1,G2,..   Extra gain per channel in dB, e.g. 0,-6");
+    println!("      --ch-delay D1,D2,..  Delay per channel in samples, or a time with a unit,");
+    println!("                           e.g. 0,48 or 0,1.5ms; all channels grow by the max");
+    println!("      --amplitude A        Linear amplitude multiplier (default: 1.0)");
+    println!("      --adsr A:D:S:R       Envelope: attack, decay, release in ms; sustain 0-1");
+    println!("      --envelope PATH      Gain automation from time_ms,gain_db breakpoints");
+    println!("                           (.csv/.txt lines or a .json array), linear in dB");
+    println!("      --decay-tau MS       Exponential decay, amplitude falls to 1/e every MS");
+    println!("      --fade-in MS         Fade-in time (default: 0)");
+    println!("      --fade-out MS        Fade-out time (default: 0)");
+    println!(
+        "      --fade-curve CURVE   Fade shape: linear, cosine, exponential (default: cosine)"
+    );
+    println!(
+        "      --dc-offset F        DC offset as a fraction of full scale, -1 to 1 (default: 0)"
+    );
+    println!(
+        "      --dither TYPE        Dither at the target depth: none, rect, tpdf (default: none)"
+    );
+    println!("      --noise-shaping TYPE Shape quantization noise: none, 2nd, f-weighted");
+    println!("                           (default: none)");
+    println!("      --normalize M:LEVEL  Normalize to a target, e.g. peak:-1dBFS or rms:-20dBFS");
+    println!("                           (--gain-db and --amplitude still apply on top)");
+    println!("      --target-lufs L      Scale to an integrated loudness (ITU-R BS.1770) in LUFS");
+    println!(
+        "      --limiter TYPE       Overload protection: none, soft, lookahead (default: none)"
+    );
+    println!("      --limit-ceiling DB   Lookahead limiter ceiling in dBFS (default: -0.1)");
+    println!(
+        "      --rounding MODE      Quantizer rounding: nearest, even, truncate (default: nearest)"
+    );
+    println!("      --full-scale MAP     symmetric (+/-32767) or asymmetric (-32768..32767)");
+    println!("                           mapping of +/-1.0 to codes (default: symmetric)");
+    println!("  -P, --preset NAME        Apply a named preset:");
+    println!("                           ebu-18   - 1 kHz line-up tone at -18 dBFS");
+    println!("                           smpte-20 - 1 kHz line-up tone at -20 dBFS");
+    println!("                           aes17    - AES17 997 Hz tone at -20 dBFS");
+    println!("                           aes17-0, aes17-20, aes17-60 - 997 Hz at 0/-20/-60 dBFS");
+    println!("                           imd-smpte - SMPTE IMD: 60 Hz + 7 kHz at 4:1");
+    println!("                           imd-ccif - CCIF IMD: 19 kHz + 20 kHz at 1:1");
+    println!("                           surround-cal - 500-2000 Hz pink noise at -20 dBFS RMS,");
+    println!("                           5 s per channel (surround-cal-N for -N dBFS)");
+    println!("      --strict             Exit with an error if any sample clips");
+    println!("  -a, --analyze            Analyze only (don't generate data)");
+    println!("  -h, --help               Show this help message");
+    println!();
+    println!("Examples:");
+    println!("  singen -f 1000 -r 48000 -b 16 -d 10 -o carray");
+    println!("  singen --frequency 440 --rate 44100 --channels 1 --bits 24");
+    println!("  singen -r 16000 -d 1 -o rustarray -p");
+}
+
+/// C, C++ and Rust identifier: a letter or `_`, then letters, digits and `_`.
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Address in decimal or `0x` hex.
+fn parse_address(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// The invocation as one line, quoting arguments that hold whitespace.
+fn command_line(args: &[String]) -> String {
+    let quoted = args.iter().skip(1).map(|arg| {
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            format!("\"{}\"", arg)
+        } else {
+            arg.clone()
+        }
+    });
+    std::iter::once("singen".to_string())
+        .chain(quoted)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_args() -> Config {
+    let args: Vec<String> = env::args().collect();
+    config_from_args(&args)
+}
+
+/// Build the configuration from a full argument list, program name first.
+fn config_from_args(args: &[String]) -> Config {
+    let mut config = Config {
+        frequency: 440.0,
+        channel_frequencies: Vec::new(),
+        phase_deg: 0.0,
+        phase_offset_deg: 0.0,
+        channel_specs: Vec::new(),
+        pan: None,
+        mid_side: None,
+        sample_rate: 16_000,
+        channels: 2,
+        sample_width: SampleWidth::Width2Byte,
+        sample_format: SampleFormat::Pcm,
+        data_bits: None,
+        container_bits: None,
+        justify: Justify::Right,
+        duration_ms: 1.0,
+        duration_samples: None,
+        loopable: false,
+        output_format: OutputFormat::Hex,
+        layout: Layout::Interleaved,
+        endian: Endian::Little,
+        packing: Packing::Packed,
+        slot_bits: 32,
+        q_format: None,
+        element_type: ElementType::UInt8,
+        radix: Radix::Decimal,
+        split_channels: false,
+        outfile: None,
+        rf64: false,
+        bext: None,
+        loop_points: None,
+        info: None,
+        cues: false,
+        base64_wav: false,
+        data_uri: false,
+        base_address: None,
+        record_length: None,
+        word_bits: None,
+        quality: None,
+        bitrate: None,
+        symbol_name: None,
+        static_storage: false,
+        constexpr: false,
+        namespace: None,
+        align: None,
+        section: None,
+        attribute_style: AttributeStyle::Gcc,
+        split_bytes: None,
+        descriptor: false,
+        command_line: command_line(args),
+        analyze_only: false,
+        waveform: Waveform::Sine,
+        mls_order: 16,
+        mls_seed: 1,
+        pulse_interval_ms: 500.0,
+        level_dbfs: 0.0,
+        tones: Vec::new(),
+        bit_pattern: BitPattern::Alternating,
+        staircase_steps: 16,
+        staircase_dwell: 64,
+        id_tone_ms: 1000.0,
+        id_gap_ms: 500.0,
+        burst: None,
+        crossfade: None,
+        seed: 1,
+        noise_band: None,
+        tail_ms: 0.0,
+        pad_to: None,
+        pad_truncate: false,
+        offset_binary: false,
+        snr_db: None,
+        pluck_decay: 0.996,
+        wavetable_path: None,
+        wavetable: Vec::new(),
+        expression_text: String::new(),
+        expression: None,
+        gain_db: 0.0,
+        channel_gains_db: Vec::new(),
+        channel_delays: Vec::new(),
+        amplitude: 1.0,
+        adsr: None,
+        decay_tau_ms: None,
+        envelope: None,
+        fade_in_ms: 0.0,
+        fade_out_ms: 0.0,
+        fade_curve: FadeCurve::Cosine,
+        dc_offset: 0.0,
+        dither: Dither::None,
+        noise_shaping: NoiseShaping::None,
+        normalize: None,
+        target_lufs: None,
+        limiter: Limiter::None,
+        limit_ceiling_db: -0.1,
+        strict: false,
+        rounding: Rounding::Nearest,
+        full_scale: FullScale::Symmetric,
+        tuning: parse_tuning(args),
+        internal_rate: None,
+        drift_ppm: 0.0,
+    };
+    let mut burst_edge_ms = 0.0;
+    let mut crossfade_ms = None;
+    let mut duration_spec = DurationSpec::Ms(1.0);
+    let mut loop_spec = None;
+    let mut frequency_left = None;
+    let mut frequency_right = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-h" | "--help" => {
+                print_usage();
+                process::exit(0);
+            }
+            "-f" | "--frequency" => {
+                i += 1;
+                if i < args.len() {
+                    let frequencies: Vec<f32> = args[i]
+                        .split(',')
+                        .map(|frequency| {
+                            parse_hz(frequency, &config.tuning)
+                                .filter(|&frequency| frequency.is_finite() && frequency > 0.0)
+                        })
+                        .collect::<Option<_>>()
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: Invalid frequency value");
+                            process::exit(1);
+                        });
+                    config.frequency = frequencies[0];
+                    config.channel_frequencies = if frequencies.len() > 1 {
+                        frequencies
+                    } else {
+                        Vec::new()
+                    };
+                }
+            }
+            "--phase" => {
+                i += 1;
+                if i < args.len() {
+                    config.phase_deg = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid start phase");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--phase-offset" => {
+                i += 1;
+                if i < args.len() {
+                    config.phase_offset_deg = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid phase offset");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--pan" => {
+                i += 1;
+                if i < args.len() {
+                    let pan: f32 = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid pan position");
+                        process::exit(1);
+                    });
+                    if !(-1.0..=1.0).contains(&pan) {
+                        eprintln!("Error: Pan position must be between -1.0 and 1.0");
+                        process::exit(1);
+                    }
+                    config.pan = Some(pan);
+                }
+            }
+            "--mid-side" => {
+                i += 1;
+                if i < args.len() {
+                    config.mid_side = Some(MidSide::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid mid/side mode, expected encode or decode");
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--frequency-left" | "--frequency-right" => {
+                let option = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let frequency = parse_hz(&args[i], &config.tuning)
+                        .filter(|&frequency| frequency.is_finite() && frequency > 0.0)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: Invalid frequency value");
+                            process::exit(1);
+                        });
+                    if option == "--frequency-left" {
+                        frequency_left = Some(frequency);
+                    } else {
+                        frequency_right = Some(frequency);
+                    }
+                }
+            }
+            "-r" | "--rate" => {
+                i += 1;
+                if i < args.len() {
+                    config.sample_rate = match parse_count(&args[i], "hz") {
+                        Some(rate) if rate > 0 && rate <= u32::MAX as u64 => rate as u32,
+                        _ => {
+                            eprintln!("Error: Sample rate must be a positive integer in Hz");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "-c" | "--channels" => {
+                i += 1;
+                if i < args.len() {
+                    let ch = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid channel count");
+                        process::exit(1);
+                    });
+                    if ch == 0 || ch > MAX_CHANNELS {
+                        eprintln!(
+                            "Error: Channel count must be between 1 and {}",
+                            MAX_CHANNELS
+                        );
+                        process::exit(1);
+                    }
+                    config.channels = ch;
+                }
+            }
+            "-b" | "--bits" => {
+                i += 1;
+                if i < args.len() {
+                    (config.sample_width, config.sample_format) = match args[i]
+                        .to_lowercase()
+                        .as_str()
+                    {
+                        "f32" | "32f" | "float" => (SampleWidth::Width4Byte, SampleFormat::Float),
+                        "f64" | "64f" | "double" => (SampleWidth::Width8Byte, SampleFormat::Float),
+                        "alaw" | "a-law" => (SampleWidth::Width1Byte, SampleFormat::ALaw),
+                        "ulaw" | "mulaw" | "u-law" | "μ-law" => {
+                            (SampleWidth::Width1Byte, SampleFormat::MuLaw)
+                        }
+                        "12" => (SampleWidth::Width2Byte, SampleFormat::Pcm),
+                        "20" => (SampleWidth::Width3Byte, SampleFormat::Pcm),
+                        bits => match SampleWidth::from_str(bits) {
+                            Some(width) => (width, SampleFormat::Pcm),
+                            None => {
+                                eprintln!(
+                                    "Error: Invalid bit depth. Must be 8, 12, 16, 20, 24, 32, f32, f64, alaw or ulaw"
+                                );
+                                process::exit(1);
+                            }
+                        },
+                    };
+                    config.data_bits = match args[i].as_str() {
+                        "12" => Some(12),
+                        "20" => Some(20),
+                        _ => None,
+                    };
+                }
+            }
+            "-d" | "--duration" => {
+                i += 1;
+                if i < args.len() {
+                    duration_spec = DurationSpec::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid duration, expected e.g. 500ms, 2s, 4800samples or 10cycles");
+                        process::exit(1);
+                    });
+                }
+            }
+            "-o" | "--output" => {
+                i += 1;
+                if i < args.len() {
+                    config.output_format = OutputFormat::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid output format");
+                        process::exit(1);
+                    });
+                }
+            }
+            "-w" | "--waveform" => {
+                i += 1;
+                if i < args.len() {
+                    config.waveform = Waveform::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid waveform");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--mls-order" => {
+                i += 1;
+                if i < args.len() {
+                    let order = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid MLS order");
+                        process::exit(1);
+                    });
+                    if !(MLS_MIN_ORDER..=MLS_MAX_ORDER).contains(&order) {
+                        eprintln!(
+                            "Error: MLS order must be between {} and {}",
+                            MLS_MIN_ORDER, MLS_MAX_ORDER
+                        );
+                        process::exit(1);
+                    }
+                    config.mls_order = order;
+                }
+            }
+            "--mls-seed" => {
+                i += 1;
+                if i < args.len() {
+                    config.mls_seed = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid MLS seed");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--pulse-interval" => {
+                i += 1;
+                if i < args.len() {
+                    config.pulse_interval_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid pulse interval");
+                        process::exit(1);
+                    });
+                    if config.pulse_interval_ms <= 0.0 {
+                        eprintln!("Error: Pulse interval must be positive");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--pattern" => {
+                i += 1;
+                if i < args.len() {
+                    config.bit_pattern = BitPattern::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid bit pattern");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--steps" => {
+                i += 1;
+                if i < args.len() {
+                    config.staircase_steps = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid step count");
+                        process::exit(1);
+                    });
+                    if config.staircase_steps < 2 {
+                        eprintln!("Error: Staircase needs at least 2 steps");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--dwell" => {
+                i += 1;
+                if i < args.len() {
+                    config.staircase_dwell = parse_count(&args[i], "")
+                        .map(|dwell| dwell as usize)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: Invalid dwell");
+                            process::exit(1);
+                        });
+                    if config.staircase_dwell == 0 {
+                        eprintln!("Error: Dwell must be at least one sample");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--id-tone" | "--id-gap" => {
+                let option = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let time_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid channel ID timing");
+                        process::exit(1);
+                    });
+                    if option == "--id-tone" {
+                        if time_ms <= 0.0 {
+                            eprintln!("Error: Channel ID tone time must be positive");
+                            process::exit(1);
+                        }
+                        config.id_tone_ms = time_ms;
+                    } else {
+                        if time_ms < 0.0 {
+                            eprintln!("Error: Channel ID gap cannot be negative");
+                            process::exit(1);
+                        }
+                        config.id_gap_ms = time_ms;
+                    }
+                }
+            }
+            "--burst" => {
+                i += 1;
+                if i < args.len() {
+                    config.burst = Some(Burst::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid burst cadence, expected ON:OFF, e.g. 200:800 or 10ms:5ms");
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--burst-edge" => {
+                i += 1;
+                if i < args.len() {
+                    burst_edge_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid burst edge time");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--crossfade-to" => {
+                i += 1;
+                if i < args.len() {
+                    config.crossfade = Some(
+                        Crossfade::from_str(&args[i], &config.tuning).unwrap_or_else(|| {
+                            eprintln!("Error: Invalid crossfade source, expected WAVEFORM[:FREQ]");
+                            process::exit(1);
+                        }),
+                    );
+                }
+            }
+            "--crossfade" => {
+                i += 1;
+                if i < args.len() {
+                    let length_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid crossfade time");
+                        process::exit(1);
+                    });
+                    if length_ms < 0.0 {
+                        eprintln!("Error: Crossfade time cannot be negative");
+                        process::exit(1);
+                    }
+                    crossfade_ms = Some(length_ms);
+                }
+            }
+            "--ch" => {
+                i += 1;
+                if i < args.len() {
+                    let spec = ChannelSpec::from_str(&args[i], &config.tuning).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid channel spec, expected CH:WAVEFORM[:FREQ][:LEVELdB][:PHASEdeg]");
+                        process::exit(1);
+                    });
+                    config
+                        .channel_specs
+                        .retain(|other| other.channel != spec.channel);
+                    config.channel_specs.push(spec);
+                }
+            }
+            "--band" => {
+                i += 1;
+                if i < args.len() {
+                    let band = args[i].split_once(':').and_then(|(low, high)| {
+                        Some((
+                            parse_hz(low, &config.tuning)?,
+                            parse_hz(high, &config.tuning)?,
+                        ))
+                    });
+                    config.noise_band = match band {
+                        Some((low, high)) if low > 0.0 && high > low => Some((low, high)),
+                        _ => {
+                            eprintln!("Error: Invalid band, expected LOW:HIGH in Hz");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--seed" => {
+                i += 1;
+                if i < args.len() {
+                    config.seed = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid seed");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--snr-db" => {
+                i += 1;
+                if i < args.len() {
+                    config.snr_db = match args[i].parse::<f32>() {
+                        Ok(snr_db) if snr_db.is_finite() => Some(snr_db),
+                        _ => {
+                            eprintln!("Error: Invalid SNR");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--pluck-decay" => {
+                i += 1;
+                if i < args.len() {
+                    config.pluck_decay = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid pluck decay");
+                        process::exit(1);
+                    });
+                    if !(0.0..=1.0).contains(&config.pluck_decay) {
+                        eprintln!("Error: Pluck decay must be between 0 and 1");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--table" => {
+                i += 1;
+                if i < args.len() {
+                    config.wavetable_path = Some(args[i].clone());
+                }
+            }
+            "--expr" => {
+                i += 1;
+                if i < args.len() {
+                    config.expression = Some(Expr::parse(&args[i]).unwrap_or_else(|e| {
+                        eprintln!("Error: Invalid expression: {}", e);
+                        process::exit(1);
+                    }));
+                    config.expression_text = args[i].clone();
+                    config.waveform = Waveform::Expression;
+                }
+            }
+            "--internal-rate" => {
+                i += 1;
+                if i < args.len() {
+                    config.internal_rate = match parse_count(&args[i], "hz") {
+                        Some(rate) if rate > 0 && rate <= u32::MAX as u64 => Some(rate as u32),
+                        _ => {
+                            eprintln!("Error: Internal rate must be a positive integer in Hz");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--drift-ppm" => {
+                i += 1;
+                if i < args.len() {
+                    config.drift_ppm = match args[i].parse::<f64>() {
+                        Ok(ppm) if ppm.is_finite() && ppm.abs() < 1e6 => ppm,
+                        _ => {
+                            eprintln!(
+                                "Error: Invalid clock drift, expected parts per million between -1000000 and 1000000"
+                            );
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--tail" => {
+                i += 1;
+                if i < args.len() {
+                    config.tail_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid tail duration");
+                        process::exit(1);
+                    });
+                    if config.tail_ms < 0.0 {
+                        eprintln!("Error: Tail duration cannot be negative");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--loopable" => {
+                config.loopable = true;
+            }
+            "--pad-to" => {
+                i += 1;
+                if i < args.len() {
+                    let frames = parse_count(&args[i], "")
+                        .map(|frames| frames as usize)
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: Invalid frame count for --pad-to");
+                            process::exit(1);
+                        });
+                    if frames == 0 {
+                        eprintln!("Error: --pad-to needs at least 1 frame");
+                        process::exit(1);
+                    }
+                    config.pad_to = Some(frames);
+                }
+            }
+            "--pad-truncate" => {
+                config.pad_truncate = true;
+            }
+            "-g" | "--gain-db" => {
+                i += 1;
+                if i < args.len() {
+                    config.gain_db = match args[i].parse::<f32>() {
+                        Ok(gain_db) if gain_db.is_finite() => gain_db,
+                        _ => {
+                            eprintln!("Error: Invalid gain");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--ch-gain" => {
+                i += 1;
+                if i < args.len() {
+                    config.channel_gains_db = args[i]
+                        .split(',')
+                        .map(|gain| gain.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .unwrap_or_else(|_| {
+                            eprintln!("Error: Invalid channel gains, expected e.g. 0,-6");
+                            process::exit(1);
+                        });
+                }
+            }
+            "--ch-delay" => {
+                i += 1;
+                if i < args.len() {
+                    config.channel_delays = args[i]
+                        .split(',')
+                        .map(ChannelDelay::from_str)
+                        .collect::<Option<_>>()
+                        .unwrap_or_else(|| {
+                            eprintln!("Error: Invalid channel delays, expected e.g. 0,48 or 0,1ms");
+                            process::exit(1);
+                        });
+                }
+            }
+            "--amplitude" => {
+                i += 1;
+                if i < args.len() {
+                    config.amplitude = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid amplitude");
+                        process::exit(1);
+                    });
+                    if !(config.amplitude.is_finite() && config.amplitude >= 0.0) {
+                        eprintln!("Error: Amplitude must be a finite value of 0 or more");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--adsr" => {
+                i += 1;
+                if i < args.len() {
+                    config.adsr = Some(Adsr::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid envelope, expected ATTACK:DECAY:SUSTAIN:RELEASE");
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--decay-tau" => {
+                i += 1;
+                if i < args.len() {
+                    let tau_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid decay time constant");
+                        process::exit(1);
+                    });
+                    if tau_ms <= 0.0 {
+                        eprintln!("Error: Decay time constant must be positive");
+                        process::exit(1);
+                    }
+                    config.decay_tau_ms = Some(tau_ms);
+                }
+            }
+            "--envelope" => {
+                i += 1;
+                if i < args.len() {
+                    config.envelope = Some(GainEnvelope::load(&args[i]).unwrap_or_else(|e| {
+                        eprintln!("Error: Cannot load envelope '{}': {}", args[i], e);
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--fade-in" | "--fade-out" => {
+                let option = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let fade_ms = parse_ms(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid fade time");
+                        process::exit(1);
+                    });
+                    if fade_ms < 0.0 {
+                        eprintln!("Error: Fade time cannot be negative");
+                        process::exit(1);
+                    }
+                    if option == "--fade-in" {
+                        config.fade_in_ms = fade_ms;
+                    } else {
+                        config.fade_out_ms = fade_ms;
+                    }
+                }
+            }
+            "--fade-curve" => {
+                i += 1;
+                if i < args.len() {
+                    config.fade_curve = FadeCurve::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid fade curve");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--dc-offset" => {
+                i += 1;
+                if i < args.len() {
+                    config.dc_offset = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid DC offset");
+                        process::exit(1);
+                    });
+                    if !(-1.0..=1.0).contains(&config.dc_offset) {
+                        eprintln!("Error: DC offset must be between -1 and 1");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--dither" => {
+                i += 1;
+                if i < args.len() {
+                    config.dither = Dither::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid dither type");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--noise-shaping" => {
+                i += 1;
+                if i < args.len() {
+                    config.noise_shaping = NoiseShaping::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid noise shaping type");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--normalize" => {
+                i += 1;
+                if i < args.len() {
+                    config.normalize = Some(Normalize::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid normalization, expected peak:LEVEL or rms:LEVEL");
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--target-lufs" => {
+                i += 1;
+                if i < args.len() {
+                    let target: f32 = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid loudness target");
+                        process::exit(1);
+                    });
+                    if target >= 0.0 || target < LOUDNESS_ABSOLUTE_GATE as f32 {
+                        eprintln!("Error: Loudness target must be between -70 and 0 LUFS");
+                        process::exit(1);
+                    }
+                    config.target_lufs = Some(target);
+                }
+            }
+            "--limiter" => {
+                i += 1;
+                if i < args.len() {
+                    config.limiter = Limiter::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid limiter type");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--limit-ceiling" => {
+                i += 1;
+                if i < args.len() {
+                    config.limit_ceiling_db = args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid limiter ceiling");
+                        process::exit(1);
+                    });
+                    if config.limit_ceiling_db > 0.0 {
+                        eprintln!("Error: Limiter ceiling cannot exceed 0 dBFS");
+                        process::exit(1);
+                    }
+                }
+            }
+            "--rounding" => {
+                i += 1;
+                if i < args.len() {
+                    config.rounding = Rounding::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid rounding mode");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--full-scale" => {
+                i += 1;
+                if i < args.len() {
+                    config.full_scale = FullScale::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid full-scale mapping");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--layout" => {
+                i += 1;
+                if i < args.len() {
+                    config.layout = Layout::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid layout, expected interleaved or planar");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--endian" => {
+                i += 1;
+                if i < args.len() {
+                    config.endian = Endian::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid byte order, expected little or big");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--packing" => {
+                i += 1;
+                if i < args.len() {
+                    config.packing = Packing::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid packing, expected packed, left, right or i2s");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--slot-bits" => {
+                i += 1;
+                if i < args.len() {
+                    config.slot_bits = match args[i].parse() {
+                        Ok(bits @ (16 | 24 | 32)) => bits,
+                        _ => {
+                            eprintln!("Error: Slot width must be 16, 24 or 32 bits");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--q-format" => {
+                i += 1;
+                if i < args.len() {
+                    let q_format = QFormat::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid fixed-point format, expected q15 or q31");
+                        process::exit(1);
+                    });
+                    config.sample_width = q_format.sample_width();
+                    config.sample_format = SampleFormat::Pcm;
+                    config.data_bits = None;
+                    config.full_scale = FullScale::Asymmetric;
+                    config.q_format = Some(q_format);
+                }
+            }
+            "--element-type" => {
+                i += 1;
+                if i < args.len() {
+                    config.element_type = ElementType::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!(
+                            "Error: Invalid element type, expected uint8_t, int16_t, int32_t or float"
+                        );
+                        process::exit(1);
+                    });
+                }
+            }
+            "--radix" => {
+                i += 1;
+                if i < args.len() {
+                    config.radix = Radix::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid radix, expected dec or hex");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--offset-binary" | "--unsigned" => {
+                config.offset_binary = true;
+            }
+            "--container" => {
+                i += 1;
+                if i < args.len() {
+                    config.container_bits = match args[i].parse() {
+                        Ok(bits @ (16 | 24 | 32)) => Some(bits),
+                        _ => {
+                            eprintln!("Error: Container width must be 16, 24 or 32 bits");
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--justify" => {
+                i += 1;
+                if i < args.len() {
+                    config.justify = Justify::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Invalid justification, expected left or right");
+                        process::exit(1);
+                    });
+                }
+            }
+            "--split-channels" => {
+                config.split_channels = true;
+            }
+            "--rf64" => {
+                config.rf64 = true;
+            }
+            "--bext" => {
+                config.bext.get_or_insert_with(Bext::default);
+            }
+            "--info" => {
+                config.info.get_or_insert_with(Info::default);
+            }
+            "--info-title" | "--info-comment" => {
+                let option = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let info = config.info.get_or_insert_with(Info::default);
+                    if option == "--info-title" {
+                        info.title = Some(args[i].clone());
+                    } else {
+                        info.comment = Some(args[i].clone());
+                    }
+                }
+            }
+            "--cues" => {
+                config.cues = true;
+            }
+            "--base64-wav" => {
+                config.base64_wav = true;
+            }
+            "--data-uri" => {
+                config.data_uri = true;
+            }
+            "--base-address" => {
+                i += 1;
+                if i < args.len() {
+                    config.base_address = Some(parse_address(&args[i]).unwrap_or_else(|| {
+                        eprintln!(
+                            "Error: Invalid base address '{}', use decimal or 0x hex",
+                            args[i]
+                        );
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--word-bits" => {
+                i += 1;
+                if i < args.len() {
+                    config.word_bits = match args[i].parse() {
+                        Ok(bits @ 1..=64) => Some(bits),
+                        _ => {
+                            eprintln!("Error: Invalid word width '{}', use 1 to 64 bits", args[i]);
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--quality" => {
+                i += 1;
+                if i < args.len() {
+                    config.quality = match args[i].parse::<f32>() {
+                        Ok(quality) if (-1.0..=10.0).contains(&quality) => Some(quality),
+                        _ => {
+                            eprintln!("Error: Invalid quality '{}', use -1 to 10", args[i]);
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--bitrate" => {
+                i += 1;
+                if i < args.len() {
+                    config.bitrate = match args[i].parse::<u32>() {
+                        Ok(bitrate) if MP3_BITRATES.contains(&bitrate) => Some(bitrate),
+                        _ => {
+                            eprintln!(
+                                "Error: Invalid bit rate '{}', use 32 to 320 kbit/s in MPEG-1 steps",
+                                args[i]
+                            );
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--name" => {
+                i += 1;
+                if i < args.len() {
+                    if !is_identifier(&args[i]) {
+                        eprintln!(
+                            "Error: Invalid name '{}', use letters, digits and _",
+                            args[i]
+                        );
+                        process::exit(1);
+                    }
+                    config.symbol_name = Some(args[i].clone());
+                }
+            }
+            "--static" => {
+                config.static_storage = true;
+            }
+            "--constexpr" => {
+                config.constexpr = true;
+            }
+            "--namespace" => {
+                i += 1;
+                if i < args.len() {
+                    if !args[i].split("::").all(is_identifier) {
+                        eprintln!(
+                            "Error: Invalid namespace '{}', use identifiers joined by ::",
+                            args[i]
+                        );
+                        process::exit(1);
+                    }
+                    config.namespace = Some(args[i].clone());
+                }
+            }
+            "--align" => {
+                i += 1;
+                if i < args.len() {
+                    config.align = match args[i].parse::<usize>() {
+                        Ok(align) if align.is_power_of_two() => Some(align),
+                        _ => {
+                            eprintln!("Error: Invalid alignment '{}', use a power of two", args[i]);
+                            process::exit(1);
+                        }
+                    };
+                }
+            }
+            "--section" => {
+                i += 1;
+                if i < args.len() {
+                    let valid = !args[i].is_empty()
+                        && args[i]
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "._$".contains(c));
+                    if !valid {
+                        eprintln!("Error: Invalid section name '{}'", args[i]);
+                        process::exit(1);
+                    }
+                    config.section = Some(args[i].clone());
+                }
+            }
+            "--attribute-style" => {
+                i += 1;
+                if i < args.len() {
+                    config.attribute_style =
+                        AttributeStyle::from_str(&args[i]).unwrap_or_else(|| {
+                            eprintln!("Error: Invalid attribute style, expected gcc or msvc");
+                            process::exit(1);
+                        });
+                }
+            }
+            "--split-bytes" => {
+                i += 1;
+                if i < args.len() {
+                    config.split_bytes =
+                        match parse_count(&args[i], "b").map(|bytes| bytes as usize) {
+                            Some(bytes) if bytes > 0 => Some(bytes),
+                            _ => {
+                                eprintln!("Error: Invalid split size '{}'", args[i]);
+                                process::exit(1);
+                            }
+                        };
+                }
+            }
+            "--descriptor" => {
+                config.descriptor = true;
+            }
+            "--record-length" => {
+                i += 1;
+                if i < args.len() {
+                    config.record_length = Some(args[i].parse().unwrap_or_else(|_| {
+                        eprintln!("Error: Invalid record length '{}'", args[i]);
+                        process::exit(1);
+                    }));
+                }
+            }
+            "--loop-points" => {
+                i += 1;
+                if i < args.len() {
+                    loop_spec = args[i]
+                        .split_once(':')
+                        .and_then(|(start, end)| {
+                            let start = DurationSpec::from_str(start)?;
+                            let end = if end.trim().is_empty() {
+                                None
+                            } else {
+                                Some(DurationSpec::from_str(end)?)
+                            };
+                            Some((start, end))
+                        })
+                        .or_else(|| {
+                            eprintln!(
+                                "Error: Invalid loop points: {} (expected start:end)",
+                                args[i]
+                            );
+                            process::exit(1);
+                        });
+                }
+            }
+            "--bext-description" | "--bext-originator" | "--bext-date" | "--bext-time" => {
+                let option = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let value = args[i].clone();
+                    let bext = config.bext.get_or_insert_with(Bext::default);
+                    match option.as_str() {
+                        "--bext-description" if value.len() <= 256 => {
+                            bext.description = Some(value)
+                        }
+                        "--bext-originator" if value.len() <= 32 => bext.originator = Some(value),
+                        "--bext-date" if matches_digit_pattern(&value, "9999-99-99") => {
+                            bext.date = Some(value)
+                        }
+                        "--bext-time" if matches_digit_pattern(&value, "99:99:99") => {
+                            bext.time = Some(value)
+                        }
+                        _ => {
+                            eprintln!(
+                                "Error: Invalid value for {}; descriptions take up to 256 characters, originators 32, dates YYYY-MM-DD and times HH:MM:SS",
+                                option
+                            );
+                            process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--bext-timecode" => {
+                i += 1;
+                if i < args.len() {
+                    match parse_timecode(&args[i]) {
+                        Some(secs) => {
+                            config
+                                .bext
+                                .get_or_insert_with(Bext::default)
+                                .time_reference_secs = secs
+                        }
+                        None => {
+                            eprintln!(
+                                "Error: Invalid value for --bext-timecode; expected HH:MM:SS[.sss]"
+                            );
+                            process::exit(1);
+                        }
+                    }
+                }
+            }
+            "-O" | "--outfile" => {
+                i += 1;
+                if i < args.len() {
+                    config.outfile = Some(args[i].clone());
+                }
+            }
+            "-P" | "--preset" => {
+                i += 1;
+                if i < args.len() {
+                    let preset = Preset::from_str(&args[i]).unwrap_or_else(|| {
+                        eprintln!("Error: Unknown preset");
+                        process::exit(1);
+                    });
+                    preset.apply(&mut config);
+                }
+            }
+            "--a4" | "--temperament" => {
+                // Already read by parse_tuning
+                i += 1;
+            }
+            "--strict" => {
+                config.strict = true;
+            }
+            "-a" | "--analyze" => {
+                config.analyze_only = true;
+                config.output_format = OutputFormat::Info;
+            }
+            _ => {
+                eprintln!("Error: Unknown option: {}", args[i]);
+                print_usage();
+                process::exit(1);
+            }
+        }
+        i += 1;
+    }
+
+    if frequency_left.is_some() || frequency_right.is_some() {
+        if config.channels < 2 {
+            eprintln!("Error: --frequency-left/--frequency-right need at least 2 channels");
+            process::exit(1);
+        }
+        let mut frequencies = config.channel_frequencies.clone();
+        frequencies.resize(2.max(frequencies.len()), config.frequency);
+        if let Some(left) = frequency_left {
+            frequencies[0] = left;
+        }
+        if let Some(right) = frequency_right {
+            frequencies[1] = right;
+        }
+        config.frequency = frequencies[0];
+        config.channel_frequencies = frequencies;
+    }
+
+    match duration_spec {
+        DurationSpec::Ms(ms) => config.duration_ms = ms,
+        DurationSpec::Samples(samples) => config.duration_samples = Some(samples),
+        DurationSpec::Cycles(cycles) => {
+            let samples = spec_samples(duration_spec, &config).filter(|_| cycles > 0.0);
+            if samples.is_none() {
+                eprintln!(
+                    "Error: A duration in cycles needs a positive count and a tonal waveform at a positive frequency"
+                );
+                process::exit(1);
+            }
+            config.duration_samples = samples;
+        }
+    }
+    if let Some(samples) = config.duration_samples {
+        config.duration_ms = (samples as f64 * 1000.0 / config.sample_rate as f64) as f32;
+    }
+
+    if let Some((start, end)) = loop_spec {
+        let start = spec_samples(start, &config);
+        let end = end.map_or(Some(signal_samples(&config)), |end| {
+            spec_samples(end, &config)
+        });
+        match (start, end) {
+            (Some(start), Some(end)) if start < end && end <= signal_samples(&config) => {
+                config.loop_points = Some((start, end));
+            }
+            _ => {
+                eprintln!(
+                    "Error: Loop points must satisfy start < end <= signal length ({} samples); cycles need a tonal waveform",
+                    signal_samples(&config)
+                );
+                process::exit(1);
+            }
+        }
+        if container_format(&config) != OutputFormat::WavFile {
+            eprintln!("Error: --loop-points needs WAV output");
+            process::exit(1);
+        }
+    }
+
+    if (config.adsr.is_some()
+        || config.decay_tau_ms.is_some()
+        || config.envelope.is_some()
+        || config.fade_in_ms > 0.0
+        || config.fade_out_ms > 0.0
+        || config.normalize.is_some()
+        || config.target_lufs.is_some())
+        && config.waveform.is_code_exact()
+    {
+        eprintln!("Error: Envelopes cannot be applied to code-exact waveforms");
+        process::exit(1);
+    }
+
+    if let Some(burst) = config.burst.as_mut() {
+        if burst_edge_ms < 0.0 || 2.0 * burst_edge_ms > burst.on_ms {
+            eprintln!("Error: Burst edge time must be between 0 and half the on time");
+            process::exit(1);
+        }
+        burst.edge_ms = burst_edge_ms;
+        if config.waveform.is_code_exact() {
+            eprintln!("Error: Bursts cannot be applied to code-exact waveforms");
+            process::exit(1);
+        }
+    }
+
+    if let Some(crossfade) = config.crossfade.as_mut() {
+        if config.waveform.is_code_exact() {
+            eprintln!("Error: Code-exact waveforms cannot be crossfaded");
+            process::exit(1);
+        }
+        crossfade.length_ms = crossfade_ms.unwrap_or(config.duration_ms);
+        if crossfade.length_ms > config.duration_ms {
+            eprintln!("Error: Crossfade time cannot exceed the duration");
+            process::exit(1);
+        }
+    }
+    let crossfade_waveform = config.crossfade.map(|crossfade| crossfade.waveform);
+
+    let nyquist = config.sample_rate as f32 / 2.0;
+    let mut frequencies: Vec<f32> = Vec::new();
+    if config.waveform.uses_frequency() {
+        frequencies.push(config.frequency);
+        frequencies.extend(&config.channel_frequencies);
+    }
+    if config.waveform == Waveform::Multitone {
+        frequencies.extend(config.tones.iter().map(|tone| tone.frequency));
+    }
+    frequencies.extend(
+        config
+            .channel_specs
+            .iter()
+            .filter_map(|spec| spec.frequency),
+    );
+    if let Some(&frequency) = frequencies.iter().find(|&&frequency| frequency >= nyquist) {
+        eprintln!(
+            "Warning: {} Hz is at or above Nyquist ({} Hz) and will alias",
+            frequency, nyquist
+        );
+    }
+
+    if let Some((_, high)) = config.noise_band
+        && high >= nyquist
+    {
+        eprintln!("Error: Band upper edge must be below Nyquist");
+        process::exit(1);
+    }
+
+    if config.channel_frequencies.len() > config.channels as usize {
+        eprintln!(
+            "Error: {} frequencies given for {} channels",
+            config.channel_frequencies.len(),
+            config.channels
+        );
+        process::exit(1);
+    }
+
+    if let Some(spec) = config
+        .channel_specs
+        .iter()
+        .find(|spec| spec.channel >= config.channels as usize)
+    {
+        eprintln!(
+            "Error: Channel spec for channel {} but only {} channels",
+            spec.channel + 1,
+            config.channels
+        );
+        process::exit(1);
+    }
+    if !config.channel_specs.is_empty() && config.waveform.is_code_exact() {
+        eprintln!("Error: Channel specs cannot be combined with code-exact waveforms");
+        process::exit(1);
+    }
+    let spec_waveform = |waveform| {
+        config
+            .channel_specs
+            .iter()
+            .any(|spec| spec.waveform == waveform)
+    };
+    let spec_expression = spec_waveform(Waveform::Expression);
+    let spec_wavetable = spec_waveform(Waveform::Wavetable);
+
+    if config.pan.is_some() {
+        if config.channels < 2 {
+            eprintln!("Error: --pan needs at least 2 channels");
+            process::exit(1);
+        }
+        if config.waveform.is_code_exact() {
+            eprintln!("Error: --pan cannot be applied to code-exact waveforms");
+            process::exit(1);
+        }
+    }
+
+    if config.waveform == Waveform::Glits && config.channels != 2 {
+        eprintln!("Error: GLITS is a two-channel signal, use -c 2");
+        process::exit(1);
+    }
+
+    if config.mid_side.is_some() {
+        if config.channels != 2 {
+            eprintln!("Error: --mid-side needs exactly 2 channels");
+            process::exit(1);
+        }
+        if config.waveform.is_code_exact() {
+            eprintln!("Error: --mid-side cannot be applied to code-exact waveforms");
+            process::exit(1);
+        }
+    }
+
+    if config.phase_offset_deg != 0.0 && !config.waveform.uses_phase() {
+        eprintln!("Error: --phase-offset applies only to sine and wavetable signals");
+        process::exit(1);
+    }
+
+    if config.channel_gains_db.len() > config.channels as usize {
+        eprintln!(
+            "Error: {} channel gains given for {} channels",
+            config.channel_gains_db.len(),
+            config.channels
+        );
+        process::exit(1);
+    }
+    if config.channel_delays.len() > config.channels as usize {
+        eprintln!(
+            "Error: {} channel delays given for {} channels",
+            config.channel_delays.len(),
+            config.channels
+        );
+        process::exit(1);
+    }
+    if !config.channel_gains_db.is_empty() && config.waveform.is_code_exact() {
+        eprintln!("Error: --ch-gain cannot be applied to code-exact waveforms");
+        process::exit(1);
+    }
+
+    if (config.base64_wav || config.data_uri) && config.output_format != OutputFormat::Base64 {
+        eprintln!("Error: --base64-wav and --data-uri need base64 output");
+        process::exit(1);
+    }
+    let max_record_length = match config.output_format {
+        OutputFormat::IntelHex => Some(255),
+        // Count byte covers up to 4 address bytes and the checksum
+        OutputFormat::SRecord(_) => Some(250),
+        _ => None,
+    };
+    if (config.base_address.is_some() || config.record_length.is_some())
+        && max_record_length.is_none()
+    {
+        eprintln!("Error: --base-address and --record-length need ihex or srec output");
+        process::exit(1);
+    }
+    if config.word_bits.is_some() && !config.output_format.uses_words() {
+        eprintln!("Error: --word-bits needs mem, vhdl, coe or mif output");
+        process::exit(1);
+    }
+    if let (Some(len), Some(max)) = (config.record_length, max_record_length)
+        && !(1..=max).contains(&len)
+    {
+        eprintln!("Error: Record length must be 1 to {} bytes", max);
+        process::exit(1);
+    }
+
+    if config.layout == Layout::Planar && container_format(&config).is_audio_file() {
+        eprintln!(
+            "Error: {} output is always interleaved, --layout planar is not supported",
+            container_format(&config).file_type()
+        );
+        process::exit(1);
+    }
+
+    if config.endian == Endian::Big && container_format(&config) == OutputFormat::WavFile {
+        eprintln!("Error: WAV output is always little-endian, --endian big is not supported");
+        process::exit(1);
+    }
+
+    if let Some(q_format) = config.q_format {
+        if !matches!(
+            config.output_format,
+            OutputFormat::CArray
+                | OutputFormat::RustArray
+                | OutputFormat::PythonArray
+                | OutputFormat::JsArray
+                | OutputFormat::TsArray
+                | OutputFormat::GoArray
+                | OutputFormat::Asm
+        ) {
+            eprintln!(
+                "Error: --q-format needs carray, rustarray, python, js, ts, go or asm output"
+            );
+            process::exit(1);
+        }
+        if config.sample_width != q_format.sample_width()
+            || config.sample_format != SampleFormat::Pcm
+            || config.endian != Endian::Little
+            || config.full_scale != FullScale::Asymmetric
+        {
+            eprintln!(
+                "Error: --q-format {} fixes the sample format, drop --bits, --endian and --full-scale",
+                q_format.to_str()
+            );
+            process::exit(1);
+        }
+    }
+
+    if config.output_format == OutputFormat::RawFloat {
+        // Raw float carries the staged samples before any quantization.
+        config.sample_width = SampleWidth::Width4Byte;
+        config.sample_format = SampleFormat::Float;
+        config.data_bits = None;
+        config.endian = Endian::Little;
+    }
+
+    if config.element_type != ElementType::UInt8 {
+        if !matches!(
+            config.output_format,
+            OutputFormat::CArray
+                | OutputFormat::Progmem
+                | OutputFormat::RustArray
+                | OutputFormat::PythonArray
+                | OutputFormat::JsArray
+                | OutputFormat::TsArray
+                | OutputFormat::GoArray
+                | OutputFormat::Asm
+        ) || config.q_format.is_some()
+        {
+            eprintln!(
+                "Error: --element-type needs carray, progmem, rustarray, python, js, ts, go or asm output without --q-format"
+            );
+            process::exit(1);
+        }
+        if config.element_type == ElementType::Float32 {
+            // Float literals carry the staged samples before any quantization.
+            config.sample_width = SampleWidth::Width4Byte;
+            config.sample_format = SampleFormat::Float;
+            config.data_bits = None;
+        }
+        let sample_bits = bytes_per_sample(&config) as u32 * 8;
+        let fits = match config.element_type {
+            ElementType::Float32 => true,
+            ElementType::Int32 => {
+                config.sample_format == SampleFormat::Pcm
+                    && (sample_bits == 24 || sample_bits == 32)
+            }
+            element_type => {
+                config.sample_format == SampleFormat::Pcm && sample_bits == element_type.bits()
+            }
+        };
+        if !fits {
+            eprintln!(
+                "Error: {} elements need {}-bit integer samples",
+                config.element_type.c_type(),
+                config.element_type.bits()
+            );
+            process::exit(1);
+        }
+    }
+
+    if let Some(container) = config.container_bits {
+        let Some(data_bits) = config.data_bits else {
+            eprintln!("Error: --container applies to 12- and 20-bit samples");
+            process::exit(1);
+        };
+        if container < data_bits {
+            eprintln!(
+                "Error: {}-bit samples do not fit in a {}-bit container",
+                data_bits, container
+            );
+            process::exit(1);
+        }
+        config.sample_width = match container {
+            16 => SampleWidth::Width2Byte,
+            24 => SampleWidth::Width3Byte,
+            _ => SampleWidth::Width4Byte,
+        };
+    }
+    if config.data_bits.is_some() && container_format(&config).is_audio_file() {
+        eprintln!(
+            "Error: {} output needs 8, 16, 24 or 32-bit samples, use raw or array output",
+            container_format(&config).file_type()
+        );
+        process::exit(1);
+    }
+
+    if matches!(
+        config.output_format,
+        OutputFormat::AiffFile
+            | OutputFormat::FlacFile
+            | OutputFormat::OggFile
+            | OutputFormat::Mp3File
+    ) && config.sample_format != SampleFormat::Pcm
+    {
+        eprintln!(
+            "Error: {} output needs integer PCM samples",
+            config.output_format.file_type()
+        );
+        process::exit(1);
+    }
+
+    if config.output_format == OutputFormat::FlacFile {
+        if !cfg!(feature = "flac") {
+            eprintln!("Error: FLAC output needs singen built with the flac feature");
+            process::exit(1);
+        }
+        if config.sample_width == SampleWidth::Width4Byte {
+            eprintln!("Error: FLAC output needs 8, 16 or 24-bit samples");
+            process::exit(1);
+        }
+        if config.channels > 8 || config.sample_rate >= 1 << 20 {
+            eprintln!("Error: FLAC output supports up to 8 channels and rates below 1048576 Hz");
+            process::exit(1);
+        }
+    }
+
+    if config.output_format == OutputFormat::OggFile && !cfg!(feature = "ogg") {
+        eprintln!("Error: Ogg Vorbis output needs singen built with the ogg feature");
+        process::exit(1);
+    }
+    if config.quality.is_some() && config.output_format != OutputFormat::OggFile {
+        eprintln!("Error: --quality needs ogg output");
+        process::exit(1);
+    }
+
+    if config.output_format == OutputFormat::Mp3File {
+        if !cfg!(feature = "mp3") {
+            eprintln!("Error: MP3 output needs singen built with the mp3 feature");
+            process::exit(1);
+        }
+        if config.channels > 2 && !config.split_channels {
+            eprintln!("Error: MP3 output supports 1 or 2 channels, or --split-channels");
+            process::exit(1);
+        }
+        if ![32_000, 44_100, 48_000].contains(&config.sample_rate) {
+            eprintln!("Error: MP3 output needs a 32000, 44100 or 48000 Hz sample rate");
+            process::exit(1);
+        }
+    }
+    if config.bitrate.is_some() && config.output_format != OutputFormat::Mp3File {
+        eprintln!("Error: --bitrate needs mp3 output");
+        process::exit(1);
+    }
+
+    if config.offset_binary {
+        if config.sample_format != SampleFormat::Pcm {
+            eprintln!("Error: --offset-binary needs integer PCM samples");
+            process::exit(1);
+        }
+        if config.sample_width != SampleWidth::Width1Byte
+            && container_format(&config) == OutputFormat::WavFile
+        {
+            eprintln!("Error: WAV stores samples above 8 bits as two's complement");
+            process::exit(1);
+        }
+        if matches!(
+            config.output_format,
+            OutputFormat::AiffFile
+                | OutputFormat::FlacFile
+                | OutputFormat::OggFile
+                | OutputFormat::Mp3File
+                | OutputFormat::AuFile
+                | OutputFormat::CafFile
+        ) {
+            eprintln!(
+                "Error: {} stores all samples as two's complement",
+                config.output_format.file_type()
+            );
+            process::exit(1);
+        }
+        if config.q_format.is_some() || config.element_type != ElementType::UInt8 {
+            eprintln!("Error: --offset-binary cannot be combined with signed typed arrays");
+            process::exit(1);
+        }
+    }
+
+    if config.packing != Packing::Packed {
+        if config.sample_width == SampleWidth::Width1Byte
+            || config.sample_format != SampleFormat::Pcm
+        {
+            eprintln!("Error: --packing needs 16-, 24- or 32-bit integer samples");
+            process::exit(1);
+        }
+        if config.slot_bits < config.sample_width as u32 * 8 {
+            eprintln!(
+                "Error: {}-bit samples do not fit in {}-bit slots",
+                config.sample_width.to_str(),
+                config.slot_bits
+            );
+            process::exit(1);
+        }
+        if container_format(&config).is_audio_file() {
+            eprintln!(
+                "Error: {} output stores samples packed, use raw or array output",
+                container_format(&config).file_type()
+            );
+            process::exit(1);
+        }
+        if config.packing == Packing::I2s
+            && (config.layout == Layout::Planar || config.split_channels)
+        {
+            eprintln!("Error: --packing i2s needs interleaved frames");
+            process::exit(1);
+        }
+    }
+
+    if config.sample_width == SampleWidth::Width8Byte
+        && container_format(&config).is_audio_file()
+        && !matches!(
+            container_format(&config),
+            OutputFormat::AuFile | OutputFormat::CafFile
+        )
+    {
+        eprintln!(
+            "Error: 64-bit float samples are only available for raw, hex, array, AU and CAF output"
+        );
+        process::exit(1);
+    }
+
+    if config.rf64 && container_format(&config) != OutputFormat::WavFile {
+        eprintln!("Error: --rf64 needs WAV output");
+        process::exit(1);
+    }
+
+    if config.bext.is_some() && container_format(&config) != OutputFormat::WavFile {
+        eprintln!("Error: --bext needs WAV output");
+        process::exit(1);
+    }
+
+    if (config.info.is_some() || config.cues) && container_format(&config) != OutputFormat::WavFile
+    {
+        eprintln!("Error: --info and --cues need WAV output");
+        process::exit(1);
+    }
+    if let Some(info) = config.info.as_mut() {
+        info.comment
+            .get_or_insert_with(|| config.command_line.clone());
+    }
+
+    let c_output = matches!(
+        config.output_format,
+        OutputFormat::CArray | OutputFormat::Progmem
+    );
+    if (config.symbol_name.is_some() || config.static_storage || config.namespace.is_some())
+        && !c_output
+        && config.output_format != OutputFormat::RustArray
+    {
+        eprintln!(
+            "Error: --name, --static and --namespace need carray, progmem or rustarray output"
+        );
+        process::exit(1);
+    }
+    if config.descriptor && !c_output && config.output_format != OutputFormat::RustArray {
+        eprintln!("Error: --descriptor needs carray, progmem or rustarray output");
+        process::exit(1);
+    }
+    if config.constexpr && !c_output {
+        eprintln!("Error: --constexpr needs carray or progmem output");
+        process::exit(1);
+    }
+    if (config.align.is_some() || config.section.is_some())
+        && !c_output
+        && config.output_format != OutputFormat::Asm
+    {
+        eprintln!("Error: --align and --section need carray, progmem or asm output");
+        process::exit(1);
+    }
+    if config.section.is_some() && config.output_format == OutputFormat::Progmem {
+        eprintln!("Error: PROGMEM places the array in its own section, --section is not supported");
+        process::exit(1);
+    }
+    if let Some(bytes) = config.split_bytes {
+        if !c_output {
+            eprintln!("Error: --split-bytes needs carray or progmem output");
+            process::exit(1);
+        }
+        let element_bytes = match config.q_format {
+            Some(q_format) => q_format.sample_width() as usize,
+            None => config.element_type.bits() as usize / 8,
+        };
+        if bytes < element_bytes {
+            eprintln!(
+                "Error: --split-bytes {} is smaller than one {}-byte element",
+                bytes, element_bytes
+            );
+            process::exit(1);
+        }
+    }
+    if config.attribute_style != AttributeStyle::Gcc && !c_output {
+        eprintln!("Error: --attribute-style needs carray or progmem output");
+        process::exit(1);
+    }
+    if (config.static_storage || config.constexpr) && c_output && config.outfile.is_some() {
+        eprintln!("Error: --static and --constexpr arrays cannot be shared through a .h/.c pair");
+        process::exit(1);
+    }
+
+    let binary_output = config.output_format.is_binary();
+    if config.outfile.is_some() && !binary_output && !c_output {
+        eprintln!(
+            "Error: --outfile is only available for raw, npy, memory image, asm, audio file, carray and progmem output"
+        );
+        process::exit(1);
+    }
+
+    if config.split_channels && binary_output && config.outfile.is_none() {
+        eprintln!(
+            "Error: --split-channels needs a file output path for raw, npy, memory image, asm and audio file output"
+        );
+        process::exit(1);
+    }
+
+    if config.sample_format != SampleFormat::Pcm && config.waveform.is_code_exact() {
+        eprintln!("Error: Code-exact waveforms need a linear integer bit depth");
+        process::exit(1);
+    }
+
+    if (config.internal_rate.is_some() || config.drift_ppm != 0.0)
+        && config.waveform.is_code_exact()
+    {
+        eprintln!(
+            "Error: --internal-rate and --drift-ppm cannot be applied to code-exact waveforms"
+        );
+        process::exit(1);
+    }
+
+    if config.snr_db.is_some() && config.waveform.is_code_exact() {
+        eprintln!("Error: --snr-db cannot be applied to code-exact waveforms");
+        process::exit(1);
+    }
+
+    let pluck_out_of_range =
+        |frequency: f32| frequency <= 0.0 || frequency > config.sample_rate as f32 / 4.0;
+    if (config.waveform == Waveform::Pluck
+        && (0..config.channels)
+            .any(|ch| pluck_out_of_range(channel_frequency(&config, ch as usize))))
+        || config.channel_specs.iter().any(|spec| {
+            spec.waveform == Waveform::Pluck
+                && pluck_out_of_range(spec.frequency.unwrap_or(config.frequency))
+        })
+    {
+        eprintln!("Error: Plucked string frequency must be between 0 and fs/4");
+        process::exit(1);
+    }
+
+    if (config.waveform == Waveform::Expression
+        || crossfade_waveform == Some(Waveform::Expression)
+        || spec_expression)
+        && config.expression.is_none()
+    {
+        eprintln!("Error: Expression waveform requires --expr EXPR");
+        process::exit(1);
+    }
+
+    if config.waveform == Waveform::Wavetable
+        || crossfade_waveform == Some(Waveform::Wavetable)
+        || spec_wavetable
+    {
+        let path = config.wavetable_path.as_deref().unwrap_or_else(|| {
+            eprintln!("Error: Wavetable waveform requires --table PATH");
+            process::exit(1);
+        });
+        config.wavetable = load_wavetable(path).unwrap_or_else(|e| {
+            eprintln!("Error: Cannot load wavetable '{}': {}", path, e);
+            process::exit(1);
+        });
+        if config.wavetable.is_empty() {
+            eprintln!("Error: Wavetable '{}' contains no samples", path);
+            process::exit(1);
+        }
+    }
+
+    if let BitPattern::Fixed(value) = config.bit_pattern {
+        let bits = code_bits(&config);
+        if bits < 32 && value >> bits != 0 {
+            eprintln!(
+                "Error: Bit pattern 0x{:X} does not fit in {} bits",
+                value, bits
+            );
+            process::exit(1);
+        }
+    }
+
+    if config.waveform == Waveform::Mls && config.mls_seed & ((1u32 << config.mls_order) - 1) == 0 {
+        eprintln!("Error: MLS seed must have at least one bit set within the register order");
+        process::exit(1);
+    }
+
+    if config.loopable {
+        let samples = signal_samples(&config);
+        if !(config.waveform.uses_frequency()
+            || config.waveform == Waveform::Multitone
+            || !config.channel_specs.is_empty())
+            || samples == 0
+        {
+            eprintln!("Error: --loopable needs a tonal waveform and a non-zero duration");
+            process::exit(1);
+        }
+        let mut reported = Vec::new();
+        let mut snap = |frequency: f32| {
+            let (snapped, cycles) = loop_frequency(frequency, samples, config.sample_rate);
+            if snapped != frequency && !reported.contains(&frequency) {
+                eprintln!(
+                    "Note: {} Hz adjusted to {} Hz for {} whole cycles in {} samples",
+                    frequency, snapped, cycles, samples
+                );
+                reported.push(frequency);
+            }
+            snapped
+        };
+        config.frequency = snap(config.frequency);
+        for frequency in config.channel_frequencies.iter_mut() {
+            *frequency = snap(*frequency);
+        }
+        for tone in config.tones.iter_mut() {
+            tone.frequency = snap(tone.frequency);
+        }
+        for spec in config.channel_specs.iter_mut() {
+            spec.frequency = spec.frequency.map(&mut snap);
+        }
+    }
+
+    config
+}
+
+/// Nearest frequency to `frequency` that fits a whole number of cycles (at
+/// least one) into `samples`, so the buffer loops without a discontinuity.
+fn loop_frequency(frequency: f32, samples: usize, sample_rate: u32) -> (f32, u64) {
+    let cycles = (frequency as f64 * samples as f64 / sample_rate as f64)
+        .round()
+        .max(1.0);
+    (
+        (cycles * sample_rate as f64 / samples as f64) as f32,
+        cycles as u64,
+    )
+}
+
+/// Generate a linear chirp from `f0` Hz to `f1` Hz over `duration_secs`.
+/// Returns a vector of floating‑point samples in the range [-1.0, 1.0].
+fn generate_linear_chirp(
+    f0: f32,            // start frequency (Hz)
+    f1: f32,            // end frequency (Hz)
+    sample_rate: f32,   // samples per second
+    num_samples: usize, // total duration in samples
+    start_phase: f32,   // initial phase (radians)
+) -> Vec<f32> {
+    let dt = 1.0 / sample_rate;
+    let duration_secs = num_samples as f32 * dt;
+    let mut samples = Vec::with_capacity(num_samples);
+    let mut phase = start_phase;
+
+    for i in 0..num_samples {
+        // The first sample sits exactly on the start phase.
+        samples.push(phase.sin());
+        let t = i as f32 * dt;
+        // Instantaneous frequency at time t (linear interpolation)
+        let freq = f0 + (f1 - f0) * (t / duration_secs);
+        // Phase increment for this sample
+        phase += TAU * freq * dt;
+        // Keep phase in [-π, π] range to avoid floating-point drift (optional)
+        phase = phase.rem_euclid(TAU);
+    }
+
+    samples
+}
+
+/// Generate `num_samples` of a maximum length sequence of the given `order`.
+/// The sequence repeats every 2^order - 1 samples; each sample is ±1.0.
+fn generate_mls(order: u8, seed: u32, num_samples: usize) -> Vec<f32> {
+    let mask = MLS_TAPS[(order - MLS_MIN_ORDER) as usize];
+    let mut state = seed & ((1u32 << order) - 1);
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for _ in 0..num_samples {
+        let bit = state & 1;
+        state >>= 1;
+        if bit != 0 {
+            state ^= mask;
+        }
+        samples.push(if bit != 0 { 1.0 } else { -1.0 });
+    }
+
+    samples
+}
+
+// Shape of the polarity test pulse: a fast raised-cosine rise followed by a
+// slower raised-cosine fall, so the transient is positive-going and clearly
+// asymmetric in time (an inverted connection cannot look like the original).
+const POLARITY_RISE_MS: f32 = 0.25;
+const POLARITY_FALL_MS: f32 = 1.0;
+
+/// Generate `num_samples` of the polarity test signal: one positive-going pulse
+/// at the start of every `interval_secs`, silence in between.
+fn generate_polarity_pulse(sample_rate: f32, interval_secs: f32, num_samples: usize) -> Vec<f32> {
+    let interval = ((interval_secs * sample_rate).round() as usize).max(1);
+    let rise = (POLARITY_RISE_MS / 1000.0 * sample_rate).max(1.0);
+    let fall = (POLARITY_FALL_MS / 1000.0 * sample_rate).max(1.0);
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let t = (i % interval) as f32;
+        let value = if t < rise {
+            0.5 - 0.5 * (PI * t / rise).cos()
+        } else if t < rise + fall {
+            0.5 + 0.5 * (PI * (t - rise) / fall).cos()
+        } else {
+            0.0
+        };
+        samples.push(value);
+    }
+
+    samples
+}
+
+// Broadcast line-up ident signals (EBU Tech 3304). Both run at the EBU
+// alignment level of -18 dBFS, except for BLITS's 2 kHz section.
+const IDENT_LEVEL_DBFS: f32 = -18.0;
+
+// GLITS: a two-channel 1 kHz tone with a 4 s cadence. The left leg is
+// interrupted once for 250 ms at the start of the cycle; the right leg is
+// interrupted twice for 250 ms, 500 ms and 1000 ms into the cycle.
+const GLITS_FREQUENCY: f32 = 1000.0;
+const GLITS_CYCLE_MS: f32 = 4000.0;
+static GLITS_BREAKS_MS: [&[(f32, f32)]; 2] = [&[(0.0, 250.0)], &[(500.0, 750.0), (1000.0, 1250.0)]];
+
+// BLITS: each channel of the L, R, C, LFE, Ls, Rs layout is identified in
+// turn by a 600 ms tone with 200 ms gaps, followed by 2 kHz on all channels
+// and finally a 1 kHz stereo (L/R only) section. Slots for channels that are
+// not present stay silent so the cadence is identical at any channel count.
+const BLITS_IDENT_TONE_MS: f32 = 600.0;
+const BLITS_IDENT_GAP_MS: f32 = 200.0;
+static BLITS_IDENT_FREQUENCIES: [f32; 6] = [880.0, 880.0, 1320.0, 82.5, 660.0, 660.0];
+const BLITS_ALL_FREQUENCY: f32 = 2000.0;
+const BLITS_ALL_LEVEL_DBFS: f32 = -24.0;
+const BLITS_ALL_MS: f32 = 2000.0;
+const BLITS_STEREO_FREQUENCY: f32 = 1000.0;
+const BLITS_STEREO_MS: f32 = 3000.0;
+const BLITS_SECTION_GAP_MS: f32 = 200.0;
+const BLITS_CYCLE_MS: f32 = 6.0 * (BLITS_IDENT_TONE_MS + BLITS_IDENT_GAP_MS)
+    + BLITS_ALL_MS
+    + BLITS_SECTION_GAP_MS
+    + BLITS_STEREO_MS
+    + BLITS_SECTION_GAP_MS;
+
+/// Convert a level in dBFS to a linear amplitude.
+fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Convert a linear amplitude to dB (negative infinity for silence).
+fn linear_to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+/// Total linear gain applied before quantization: the preset level, the
+/// `--gain-db` gain and the `--amplitude` multiplier combined.
+fn output_gain(config: &Config) -> f32 {
+    db_to_linear(config.level_dbfs + config.gain_db) * config.amplitude
+}
+
+/// Render a gated tone. `schedule` maps a time in milliseconds to the
+/// frequency sounding at that instant, or `None` for silence.
+fn generate_scheduled_tone(
+    sample_rate: f32,
+    num_samples: usize,
+    amplitude: f32,
+    schedule: impl Fn(f32) -> Option<f32>,
+) -> Vec<f32> {
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let t_secs = i as f64 / sample_rate as f64;
+        let value = match schedule((t_secs * 1000.0) as f32) {
+            Some(freq) => {
+                let phase = (freq as f64 * t_secs).fract() as f32 * TAU;
+                amplitude * phase.sin()
+            }
+            None => 0.0,
+        };
+        samples.push(value);
+    }
+
+    samples
+}
+
+/// Generate the sum of `tones`, each with its own phase accumulator.
+fn generate_multitone(tones: &[Tone], sample_rate: f32, num_samples: usize) -> Vec<f32> {
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let t_secs = i as f64 / sample_rate as f64;
+        let value = tones
+            .iter()
+            .map(|tone| {
+                let phase = (tone.frequency as f64 * t_secs).fract() as f32 * TAU;
+                tone.amplitude * phase.sin()
+            })
+            .sum();
+        samples.push(value);
+    }
+
+    samples
+}
+
+/// Generate a Karplus-Strong plucked string: a seeded noise burst circulating
+/// in a delay line with a two-point averaging filter and loop gain `decay`.
+///
+/// The loop delay is the integer delay line, half a sample from the averaging
+/// filter, and a first-order allpass that supplies the fractional remainder,
+/// so the fundamental lands on `frequency` rather than on fs/(N + 0.5).
+fn generate_pluck(
+    frequency: f32,
+    sample_rate: f32,
+    decay: f32,
+    seed: u64,
+    num_samples: usize,
+) -> Vec<f32> {
+    let period = sample_rate / frequency;
+    // Keep the allpass delay in [0.1, 1.1) where its phase delay is flat enough.
+    let length = ((period - 0.5 - 0.1).floor() as usize).max(1);
+    let fraction = period - 0.5 - length as f32;
+    let allpass_coeff = (1.0 - fraction) / (1.0 + fraction);
+
+    let mut delay_line = generate_white_noise(seed, length);
+    let mut pos = 0;
+    let mut previous = 0.0;
+    let (mut allpass_x1, mut allpass_y1) = (0.0, 0.0);
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for _ in 0..num_samples {
+        let current = delay_line[pos];
+        let averaged = 0.5 * (current + previous);
+        previous = current;
+        let tuned = allpass_coeff * averaged + allpass_x1 - allpass_coeff * allpass_y1;
+        allpass_x1 = averaged;
+        allpass_y1 = tuned;
+        delay_line[pos] = decay * tuned;
+        pos = (pos + 1) % length;
+        samples.push(current);
+    }
+
+    samples
+}
+
+/// Load a single-cycle wavetable. The format is chosen by extension: `.wav`
+/// (first channel of PCM or float data), `.csv`/`.txt` (numbers separated by
+/// commas or whitespace), anything else is read as raw little-endian f32.
+fn load_wavetable(path: &str) -> Result<Vec<f32>, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let extension = std::path::Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "wav" | "wave" => read_wav_first_channel(&data),
+        "csv" | "txt" => {
+            let text = String::from_utf8(data).map_err(|_| "not valid UTF-8 text".to_string())?;
+            text.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .map(|field| {
+                    field
+                        .parse::<f32>()
+                        .map_err(|_| format!("invalid number '{}'", field))
+                })
+                .collect()
+        }
+        _ => {
+            if data.len() % 4 != 0 {
+                return Err("raw f32 data length is not a multiple of 4 bytes".to_string());
+            }
+            Ok(data
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+    }
+}
+
+/// Decode the first channel of a PCM (8/16/24/32-bit) or IEEE float WAV file
+/// into samples in [-1.0, 1.0].
+fn read_wav_first_channel(data: &[u8]) -> Result<Vec<f32>, String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("not a RIFF/WAVE file".to_string());
+    }
+
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let body = &data[pos + 8..(pos + 8 + size).min(data.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut audio_format = u16::from_le_bytes([body[0], body[1]]);
+                // The extensible sub-format GUID starts with the plain format tag.
+                if audio_format == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    audio_format = u16::from_le_bytes([body[24], body[25]]);
+                }
+                let channels = u16::from_le_bytes([body[2], body[3]]) as usize;
+                let bits = u16::from_le_bytes([body[14], body[15]]) as usize;
+                format = Some((audio_format, channels.max(1), bits));
+            }
+            b"data" => {
+                let (audio_format, channels, bits) =
+                    format.ok_or_else(|| "data chunk before fmt chunk".to_string())?;
+                let width = bits / 8;
+                if width == 0 {
+                    return Err(format!("unsupported bit depth {}", bits));
+                }
+                let frame = width * channels;
+                return body
+                    .chunks_exact(frame)
+                    .map(|f| decode_wav_sample(&f[..width], audio_format, bits))
+                    .collect();
+            }
+            _ => {}
+        }
+        // Chunks are word aligned.
+        pos += 8 + size + (size & 1);
+    }
+
+    Err("no data chunk found".to_string())
+}
+
+fn decode_wav_sample(bytes: &[u8], audio_format: u16, bits: usize) -> Result<f32, String> {
+    match (audio_format, bits) {
+        (1, 8) => Ok((bytes[0] as f32 - 128.0) / 128.0),
+        (1, 16) => Ok(i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0),
+        (1, 24) => {
+            let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+            Ok(value as f32 / 8388608.0)
+        }
+        (1, 32) => {
+            let value = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            Ok(value as f32 / 2147483648.0)
+        }
+        (3, 32) => Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        _ => Err(format!(
+            "unsupported WAV encoding (format {}, {} bits)",
+            audio_format, bits
+        )),
+    }
+}
+
+/// Loop `table` as one cycle at `frequency`, linearly interpolating between
+/// table entries.
+fn generate_wavetable(
+    table: &[f32],
+    frequency: f32,
+    sample_rate: f32,
+    start_phase: f32,
+    num_samples: usize,
+) -> Vec<f32> {
+    let len = table.len();
+    let step = len as f64 * frequency as f64 / sample_rate as f64;
+    let offset = (start_phase as f64 / std::f64::consts::TAU).rem_euclid(1.0) * len as f64;
+    let mut samples = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let pos = (offset + i as f64 * step) % len as f64;
+        let index = pos as usize;
+        let frac = (pos - index as f64) as f32;
+        let a = table[index];
+        let b = table[(index + 1) % len];
+        samples.push(a + (b - a) * frac);
+    }
+
+    samples
+}
+
+/// Variables available to `--expr` expressions.
+#[derive(Clone, Copy)]
+enum ExprVar {
+    /// Time in seconds
+    Time,
+    /// Configured frequency in Hz
+    Frequency,
+    /// Sample rate in Hz
+    SampleRate,
+    /// Sample index
+    Index,
+}
+
+/// Parsed `--expr` expression tree.
+#[derive(Clone)]
+enum Expr {
+    Number(f64),
+    Var(ExprVar),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(&'static str, Vec<Expr>),
+}
+
+// Functions callable from expressions, with their argument counts.
+static EXPR_FUNCTIONS: [(&str, usize); 14] = [
+    ("sin", 1),
+    ("cos", 1),
+    ("tan", 1),
+    ("abs", 1),
+    ("sqrt", 1),
+    ("exp", 1),
+    ("ln", 1),
+    ("log10", 1),
+    ("floor", 1),
+    ("ceil", 1),
+    ("sign", 1),
+    ("min", 2),
+    ("max", 2),
+    ("pow", 2),
+];
+
+/// Recursive-descent parser over the expression text. Grammar:
+///   expr   := term (('+' | '-') term)*
+///   term   := unary (('*' | '/' | '%') unary)*
+///   unary  := '-' unary | power
+///   power  := atom ('^' unary)?
+///   atom   := number | name | name '(' expr (',' expr)* ')' | '(' expr ')'
+struct ExprParser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+}
+
+impl ExprParser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                return Some(c);
+            }
+            self.chars.next();
+        }
+        None
+    }
+
+    fn position(&mut self) -> usize {
+        self.peek();
+        self.chars.peek().map_or(self.text.len(), |&(pos, _)| pos)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.chars.next();
+            Ok(())
+        } else {
+            Err(format!(
+                "expected '{}' at position {}",
+                expected,
+                self.position()
+            ))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.chars.next();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let start = self.position();
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut end = start;
+                while let Some(&(pos, c)) = self.chars.peek() {
+                    let exponent_sign = (c == '-' || c == '+')
+                        && matches!(self.text[..pos].chars().last(), Some('e' | 'E'));
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign) {
+                        break;
+                    }
+                    end = pos + c.len_utf8();
+                    self.chars.next();
+                }
+                self.text[start..end]
+                    .parse()
+                    .map(Expr::Number)
+                    .map_err(|_| format!("invalid number at position {}", start))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut end = start;
+                while let Some(&(pos, c)) = self.chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = pos + c.len_utf8();
+                    self.chars.next();
+                }
+                let name = &self.text[start..end];
+                if self.peek() == Some('(') {
+                    return self.call(name, start);
+                }
+                match name {
+                    "t" => Ok(Expr::Var(ExprVar::Time)),
+                    "f" => Ok(Expr::Var(ExprVar::Frequency)),
+                    "fs" => Ok(Expr::Var(ExprVar::SampleRate)),
+                    "n" => Ok(Expr::Var(ExprVar::Index)),
+                    "pi" => Ok(Expr::Number(std::f64::consts::PI)),
+                    "tau" => Ok(Expr::Number(std::f64::consts::TAU)),
+                    "e" => Ok(Expr::Number(std::f64::consts::E)),
+                    _ => Err(format!("unknown variable '{}' at position {}", name, start)),
+                }
+            }
+            Some(c) => Err(format!("unexpected '{}' at position {}", c, start)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: &str, start: usize) -> Result<Expr, String> {
+        let &(function, arity) = EXPR_FUNCTIONS
+            .iter()
+            .find(|(function, _)| *function == name)
+            .ok_or_else(|| format!("unknown function '{}' at position {}", name, start))?;
+        self.expect('(')?;
+        let mut args = vec![self.expr()?];
+        while self.peek() == Some(',') {
+            self.chars.next();
+            args.push(self.expr()?);
+        }
+        self.expect(')')?;
+        if args.len() != arity {
+            return Err(format!(
+                "{}() takes {} argument{}, got {}",
+                function,
+                arity,
+                if arity > 1 { "s" } else { "" },
+                args.len()
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+}
+
+impl Expr {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = ExprParser {
+            chars: text.char_indices().peekable(),
+            text,
+        };
+        let expr = parser.expr()?;
+        if parser.peek().is_some() {
+            let pos = parser.position();
+            return Err(format!("unexpected '{}' at position {}", &text[pos..], pos));
+        }
+        Ok(expr)
+    }
+
+    /// Evaluate with `vars` = [t, f, fs, n].
+    fn eval(&self, vars: &[f64; 4]) -> f64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Var(var) => vars[*var as usize],
+            Expr::Neg(inner) => -inner.eval(vars),
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.eval(vars), rhs.eval(vars));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    '%' => a.rem_euclid(b),
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let a = args[0].eval(vars);
+                let b = args.get(1).map_or(0.0, |arg| arg.eval(vars));
+                match *function {
+                    "sin" => a.sin(),
+                    "cos" => a.cos(),
+                    "tan" => a.tan(),
+                    "abs" => a.abs(),
+                    "sqrt" => a.sqrt(),
+                    "exp" => a.exp(),
+                    "ln" => a.ln(),
+                    "log10" => a.log10(),
+                    "floor" => a.floor(),
+                    "ceil" => a.ceil(),
+                    "sign" => a.signum(),
+                    "min" => a.min(b),
+                    "max" => a.max(b),
+                    _ => a.powf(b),
+                }
+            }
+        }
+    }
+}
+
+/// Evaluate `expr` once per sample. Non-finite results are rendered as silence.
+fn generate_expression(
+    expr: &Expr,
+    frequency: f32,
+    sample_rate: f32,
+    num_samples: usize,
+) -> Vec<f32> {
+    (0..num_samples)
+        .map(|i| {
+            let vars = [
+                i as f64 / sample_rate as f64,
+                frequency as f64,
+                sample_rate as f64,
+                i as f64,
+            ];
+            let value = expr.eval(&vars);
+            if value.is_finite() { value as f32 } else { 0.0 }
+        })
+        .collect()
+}
+
+/// Generate the two GLITS legs, left then right.
+fn generate_glits(sample_rate: f32, num_samples: usize) -> Vec<Vec<f32>> {
+    let amplitude = db_to_linear(IDENT_LEVEL_DBFS);
+    GLITS_BREAKS_MS
+        .iter()
+        .map(|breaks| {
+            generate_scheduled_tone(sample_rate, num_samples, amplitude, |t| {
+                let t = t % GLITS_CYCLE_MS;
+                if breaks.iter().any(|&(start, end)| t >= start && t < end) {
+                    None
+                } else {
+                    Some(GLITS_FREQUENCY)
+                }
+            })
+        })
+        .collect()
+}
+
+/// Generate a channel identification sequence: a tone in each channel in
+/// turn, `tone_ms` long and followed by `gap_ms` of silence, at that
+/// channel's entry in `frequencies`. The sequence repeats once every channel
+/// has sounded.
+fn generate_channel_id(
+    sample_rate: f32,
+    frequencies: &[f32],
+    tone_ms: f32,
+    gap_ms: f32,
+    num_samples: usize,
+) -> Vec<Vec<f32>> {
+    let slot_ms = tone_ms + gap_ms;
+    let cycle_ms = slot_ms * frequencies.len() as f32;
+    frequencies
+        .iter()
+        .enumerate()
+        .map(|(ch, &frequency)| {
+            let start = ch as f32 * slot_ms;
+            generate_scheduled_tone(sample_rate, num_samples, 1.0, |t| {
+                let t = t % cycle_ms;
+                (t >= start && t < start + tone_ms).then_some(frequency)
+            })
+        })
+        .collect()
+}
+
+/// Generate BLITS for `channels` channels, in L, R, C, LFE, Ls, Rs order.
+fn generate_blits(sample_rate: f32, channels: u8, num_samples: usize) -> Vec<Vec<f32>> {
+    let amplitude = db_to_linear(IDENT_LEVEL_DBFS);
+    let slot_ms = BLITS_IDENT_TONE_MS + BLITS_IDENT_GAP_MS;
+    let all_start = BLITS_IDENT_FREQUENCIES.len() as f32 * slot_ms;
+    let stereo_start = all_start + BLITS_ALL_MS + BLITS_SECTION_GAP_MS;
+    let in_all = |i: usize| {
+        let t = (i as f64 * 1000.0 / sample_rate as f64) as f32 % BLITS_CYCLE_MS;
+        t >= all_start && t < all_start + BLITS_ALL_MS
+    };
+
+    (0..channels as usize)
+        .map(|ch| {
+            let mut samples = generate_scheduled_tone(sample_rate, num_samples, amplitude, |t| {
+                let t = t % BLITS_CYCLE_MS;
+                if t < all_start {
+                    let slot = (t / slot_ms) as usize;
+                    let in_tone = t - slot as f32 * slot_ms < BLITS_IDENT_TONE_MS;
+                    (slot == ch && in_tone).then(|| BLITS_IDENT_FREQUENCIES[ch])
+                } else if t < all_start + BLITS_ALL_MS {
+                    Some(BLITS_ALL_FREQUENCY)
+                } else if t >= stereo_start && t < stereo_start + BLITS_STEREO_MS && ch < 2 {
+                    Some(BLITS_STEREO_FREQUENCY)
+                } else {
+                    None
+                }
+            });
+            // The 2 kHz section sits below the alignment level
+            let all_gain = db_to_linear(BLITS_ALL_LEVEL_DBFS - IDENT_LEVEL_DBFS);
+            for (i, sample) in samples.iter_mut().enumerate() {
+                if in_all(i) {
+                    *sample *= all_gain;
+                }
+            }
+            samples
+        })
+        .collect()
+}
+
+// J-test (Julian Dunn): a square wave at fs/4 whose codes are ±1/2 full
+// scale (fundamental at about -3 dBFS), with the LSB pulled down by one code
+// for alternate 96-sample halves, i.e. a 1 LSB square wave at fs/192.
+const JTEST_LSB_PERIOD: usize = 192;
+
+/// Generate `num_samples` of J-test codes for a `bits`-wide sample.
+fn generate_jtest(bits: u32, num_samples: usize) -> Vec<i32> {
+    let half_scale = 1i32 << (bits - 2);
+    let mut codes = Vec::with_capacity(num_samples);
+
+    for i in 0..num_samples {
+        let square = if (i / 2) % 2 == 0 {
+            half_scale
+        } else {
+            -half_scale
+        };
+        let lsb = if i % JTEST_LSB_PERIOD < JTEST_LSB_PERIOD / 2 {
+            0
+        } else {
+            -1
+        };
+        codes.push(square + lsb);
+    }
+
+    codes
+}
+
+/// Generate `num_samples` of `pattern` for a `bits`-wide sample.
+fn generate_bit_pattern(pattern: BitPattern, bits: u32, num_samples: usize) -> Vec<i32> {
+    let mask = if bits >= 32 {
+        u32::MAX
+    } else {
+        (1u32 << bits) - 1
+    };
+    (0..num_samples)
+        .map(|i| {
+            let code = match pattern {
+                BitPattern::Fixed(value) => value,
+                BitPattern::Alternating => {
+                    if i % 2 == 0 {
+                        0xAAAA_AAAA
+                    } else {
+                        0x5555_5555
+                    }
+                }
+                BitPattern::WalkingOnes => 1u32 << (i as u32 % bits),
+                BitPattern::WalkingZeros => !(1u32 << (i as u32 % bits)),
+            };
+            (code & mask) as i32
+        })
+        .collect()
+}
+
+/// Lowest and highest two's complement codes for a `bits`-wide sample.
+fn code_limits(bits: u32) -> (i64, i64) {
+    let max = (1i64 << (bits - 1)) - 1;
+    (-max - 1, max)
+}
+
+/// Generate a staircase of `steps` evenly spaced codes from the lowest to the
+/// highest code, each held for `dwell` samples, repeating until `num_samples`.
+fn generate_staircase(bits: u32, steps: u32, dwell: usize, num_samples: usize) -> Vec<i32> {
+    let (min, max) = code_limits(bits);
+    let span = max - min;
+    let last = steps as i64 - 1;
+    (0..num_samples)
+        .map(|i| {
+            let step = (i / dwell) as i64 % steps as i64;
+            // Round to the nearest code so the spacing error is at most half a code.
+            (min + (step * span + last / 2) / last) as i32
+        })
+        .collect()
+}
+
+/// Generate a ramp from the lowest to the highest code across `num_samples`.
+fn generate_ramp(bits: u32, num_samples: usize) -> Vec<i32> {
+    let (min, max) = code_limits(bits);
+    let span = (max - min) as i128;
+    let last = num_samples.saturating_sub(1).max(1) as i128;
+    (0..num_samples)
+        .map(|i| (min as i128 + (i as i128 * span + last / 2) / last) as i32)
+        .collect()
+}
+
+/// Generate one stream of integer codes per channel for code-exact waveforms.
+fn generate_channel_codes(config: &Config) -> Vec<Vec<i32>> {
+    let num_samples = signal_samples(config);
+    let bits = code_bits(config);
+    let codes = match config.waveform {
+        Waveform::JTest => generate_jtest(bits, num_samples),
+        Waveform::BitPattern => {
+            let codes = generate_bit_pattern(config.bit_pattern, bits, num_samples);
+            // Patterns are literal wire bits, so cancel the offset-binary output offset.
+            if uses_offset_binary(config) {
+                let sign_bit = (1i64 << (bits - 1)) as i32;
+                codes.into_iter().map(|code| code ^ sign_bit).collect()
+            } else {
+                codes
+            }
+        }
+        Waveform::Staircase => generate_staircase(
+            bits,
+            config.staircase_steps,
+            config.staircase_dwell,
+            num_samples,
+        ),
+        Waveform::Ramp => generate_ramp(bits, num_samples),
+        _ => unreachable!("float waveforms are rendered by generate_channels"),
+    };
+    vec![codes; config.channels as usize]
+}
+
+/// Small deterministic PRNG (xorshift64*) so renders are reproducible per seed.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Mix the seed so that small seeds still start from a well-spread state;
+        // xorshift must never be seeded with zero.
+        let state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ 0xD1B5_4A32_D192_ED03;
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform sample in [-1.0, 1.0).
+    fn next_bipolar(&mut self) -> f32 {
+        // Top 24 bits give an exactly representable f32 in [0, 1).
+        let unit = (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32;
+        unit * 2.0 - 1.0
+    }
+}
+
+/// Generate `num_samples` of uniform white noise in [-1.0, 1.0).
+fn generate_white_noise(seed: u64, num_samples: usize) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    (0..num_samples).map(|_| rng.next_bipolar()).collect()
+}
+
+/// Salt for the `--snr-db` noise streams, kept apart from the noise waveform's stream.
+const SNR_NOISE_SEED_SALT: u64 = 0x5A4E_4F15;
+
+/// Add independent white noise to each channel, scaled so its RMS sits
+/// `snr_db` below `signal_rms`.
+fn add_noise_at_snr(channels: &mut [Vec<f32>], signal_rms: f32, snr_db: f32, seed: u64) {
+    let noise: Vec<Vec<f32>> = channels
+        .iter()
+        .enumerate()
+        .map(|(ch, channel)| {
+            generate_white_noise(seed ^ SNR_NOISE_SEED_SALT ^ ch as u64, channel.len())
+        })
+        .collect();
+    let noise_rms = measure_level(&noise, LevelMeasure::Rms);
+    if noise_rms == 0.0 {
+        return;
+    }
+    let gain = signal_rms / noise_rms / db_to_linear(snr_db);
+    for (channel, noise) in channels.iter_mut().zip(&noise) {
+        for (sample, n) in channel.iter_mut().zip(noise) {
+            *sample += n * gain;
+        }
+    }
+}
+
+/// Generate `num_samples` of pink noise by filtering seeded white noise
+/// (Paul Kellet's refined pinking filter, within 0.05 dB above 9 Hz).
+fn generate_pink_noise(seed: u64, num_samples: usize) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let mut b = [0.0f32; 7];
+    (0..num_samples)
+        .map(|_| {
+            let white = rng.next_bipolar();
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.969 * b[2] + white * 0.153852;
+            b[3] = 0.8665 * b[3] + white * 0.3104856;
+            b[4] = 0.55 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+            let pink = b.iter().sum::<f32>() + white * 0.5362;
+            b[6] = white * 0.115926;
+            pink
+        })
+        .collect()
+}
+
+/// Generate the calibration noise sequence: independent pink noise per
+/// channel, band-limited by `--band` and scaled to 0 dBFS RMS, sounding in
+/// one channel at a time for `id_tone_ms` with `id_gap_ms` of silence after.
+fn generate_cal_noise(config: &Config, num_samples: usize) -> Vec<Vec<f32>> {
+    let slot_ms = config.id_tone_ms + config.id_gap_ms;
+    let cycle_ms = slot_ms * config.channels as f32;
+    let ms_per_sample = 1000.0 / config.sample_rate as f64;
+
+    (0..config.channels as usize)
+        .map(|ch| {
+            let mut samples = generate_pink_noise(config.seed.wrapping_add(ch as u64), num_samples);
+            if let Some((low, high)) = config.noise_band {
+                band_limit(&mut samples, config.sample_rate as f32, low, high);
+            }
+            let start = ch as f32 * slot_ms;
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let t = (i as f64 * ms_per_sample) as f32 % cycle_ms;
+                if t < start || t >= start + config.id_tone_ms {
+                    *sample = 0.0;
+                }
+            }
+            // Calibrate on the active sections only so each burst hits the level.
+            let active = samples.iter().filter(|&&sample| sample != 0.0).count();
+            let power: f64 = samples.iter().map(|&x| x as f64 * x as f64).sum();
+            if active > 0 && power > 0.0 {
+                let gain = (active as f64 / power).sqrt() as f32;
+                samples.iter_mut().for_each(|sample| *sample *= gain);
+            }
+            samples
+        })
+        .collect()
+}
+
+/// Second-order IIR section (RBJ audio EQ cookbook), direct form I.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn from_coefficients(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = std::f64::consts::TAU * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        Biquad::from_coefficients(
+            [(1.0 - cos_w0) / 2.0, 1.0 - cos_w0, (1.0 - cos_w0) / 2.0],
+            [1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha],
+        )
+    }
+
+    fn highpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = std::f64::consts::TAU * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        Biquad::from_coefficients(
+            [(1.0 + cos_w0) / 2.0, -(1.0 + cos_w0), (1.0 + cos_w0) / 2.0],
+            [1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha],
+        )
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// ITU-R BS.1770-4 K-weighting, designed for any sample rate with the
+// analogue prototype parameters used by libebur128: a +4 dB high shelf
+// modelling the head, followed by the RLB high-pass.
+const K_SHELF_FREQUENCY: f64 = 1681.974450955533;
+const K_SHELF_GAIN_DB: f64 = 3.999843853973347;
+const K_SHELF_Q: f64 = 0.7071752369554196;
+const K_HIGHPASS_FREQUENCY: f64 = 38.13547087602444;
+const K_HIGHPASS_Q: f64 = 0.5003270373238773;
+
+// BS.1770 gating: 400 ms blocks at 75% overlap, an absolute gate at
+// -70 LUFS and a relative gate 10 LU below the absolute-gated loudness.
+const LOUDNESS_BLOCK_MS: f64 = 400.0;
+const LOUDNESS_BLOCK_STEP_MS: f64 = 100.0;
+const LOUDNESS_ABSOLUTE_GATE: f64 = -70.0;
+const LOUDNESS_RELATIVE_GATE: f64 = -10.0;
+
+// Channel weights in L, R, C, LFE, Ls, Rs order; the LFE is excluded.
+static LOUDNESS_CHANNEL_WEIGHTS: [f64; 6] = [1.0, 1.0, 1.0, 0.0, 1.41, 1.41];
+
+impl Biquad {
+    /// The two K-weighting stages for `sample_rate`.
+    fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+        let k = (std::f64::consts::PI * K_SHELF_FREQUENCY / sample_rate).tan();
+        let vh = 10.0f64.powf(K_SHELF_GAIN_DB / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let shelf = Biquad::from_coefficients(
+            [
+                vh + vb * k / K_SHELF_Q + k * k,
+                2.0 * (k * k - vh),
+                vh - vb * k / K_SHELF_Q + k * k,
+            ],
+            [
+                1.0 + k / K_SHELF_Q + k * k,
+                2.0 * (k * k - 1.0),
+                1.0 - k / K_SHELF_Q + k * k,
+            ],
+        );
+
+        let k = (std::f64::consts::PI * K_HIGHPASS_FREQUENCY / sample_rate).tan();
+        let highpass = Biquad::from_coefficients(
+            [1.0, -2.0, 1.0],
+            [
+                1.0,
+                2.0 * (k * k - 1.0) / (1.0 + k / K_HIGHPASS_Q + k * k),
+                (1.0 - k / K_HIGHPASS_Q + k * k) / (1.0 + k / K_HIGHPASS_Q + k * k),
+            ],
+        );
+        [shelf, highpass]
+    }
+}
+
+/// Integrated loudness of `channels` in LUFS per ITU-R BS.1770-4. Signals
+/// shorter than one 400 ms block are measured ungated over their full length.
+/// Returns negative infinity for silence.
+fn integrated_loudness(channels: &[Vec<f32>], sample_rate: u32) -> f64 {
+    let frames = channels.first().map_or(0, Vec::len);
+    let rate = sample_rate as f64;
+
+    // Per-channel K-weighted, squared samples.
+    let weighted: Vec<Vec<f64>> = channels
+        .iter()
+        .map(|channel| {
+            let mut filters = Biquad::k_weighting(rate);
+            channel
+                .iter()
+                .map(|&sample| {
+                    let y = filters
+                        .iter_mut()
+                        .fold(sample as f64, |x, filter| filter.process(x));
+                    y * y
+                })
+                .collect()
+        })
+        .collect();
+
+    let block = ((LOUDNESS_BLOCK_MS / 1000.0 * rate).round() as usize).min(frames);
+    let step = ((LOUDNESS_BLOCK_STEP_MS / 1000.0 * rate).round() as usize).max(1);
+    if block == 0 {
+        return f64::NEG_INFINITY;
+    }
+
+    // Weighted mean square power of each block.
+    let mut powers = Vec::new();
+    let mut start = 0;
+    while start + block <= frames {
+        let power: f64 = weighted
+            .iter()
+            .enumerate()
+            .map(|(ch, squares)| {
+                let weight = LOUDNESS_CHANNEL_WEIGHTS.get(ch).copied().unwrap_or(1.0);
+                weight * squares[start..start + block].iter().sum::<f64>() / block as f64
+            })
+            .sum();
+        powers.push(power);
+        start += step;
+    }
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = powers
+            .iter()
+            .copied()
+            .filter(|&power| loudness(power) > threshold)
+            .collect();
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+
+    let Some(absolute) = gated_mean(LOUDNESS_ABSOLUTE_GATE) else {
+        return f64::NEG_INFINITY;
+    };
+    let relative_threshold = loudness(absolute) + LOUDNESS_RELATIVE_GATE;
+    gated_mean(relative_threshold).map_or(f64::NEG_INFINITY, loudness)
+}
+
+// Q values of the two sections of a 4th-order Butterworth response.
+static BUTTERWORTH_4_Q: [f64; 2] = [0.541_196_100_146_197, 1.306_562_964_876_377];
+
+/// Band-limit `samples` in place with 4th-order Butterworth high-pass and
+/// low-pass filters at `low` and `high` Hz.
+fn band_limit(samples: &mut [f32], sample_rate: f32, low: f32, high: f32) {
+    let mut sections: Vec<Biquad> = BUTTERWORTH_4_Q
+        .iter()
+        .flat_map(|&q| {
+            [
+                Biquad::highpass(sample_rate as f64, low as f64, q),
+                Biquad::lowpass(sample_rate as f64, high as f64, q),
+            ]
+        })
+        .collect();
+    for sample in samples.iter_mut() {
+        *sample = sections
+            .iter_mut()
+            .fold(*sample as f64, |x, section| section.process(x)) as f32;
+    }
+}
+
+/// Number of samples covered by the configured duration.
+fn signal_samples(config: &Config) -> usize {
+    config
+        .duration_samples
+        .unwrap_or(((config.duration_ms * config.sample_rate as f32) / 1000.0).round() as usize)
+}
+
+/// Resolve a duration to a sample count; cycles count periods of the first
+/// channel's frequency and only apply to tonal waveforms above 0 Hz.
+fn spec_samples(spec: DurationSpec, config: &Config) -> Option<usize> {
+    match spec {
+        DurationSpec::Ms(ms) => {
+            Some((ms as f64 * config.sample_rate as f64 / 1000.0).round() as usize)
+        }
+        DurationSpec::Samples(samples) => Some(samples),
+        DurationSpec::Cycles(cycles) if config.waveform.uses_frequency() => {
+            let frequency = channel_frequency(config, 0) as f64;
+            (frequency.is_finite() && frequency > 0.0)
+                .then(|| (cycles as f64 * config.sample_rate as f64 / frequency).round() as usize)
+        }
+        DurationSpec::Cycles(_) => None,
+    }
+}
+
+/// Number of silent samples appended after the signal.
+fn tail_samples(config: &Config) -> usize {
+    ((config.tail_ms * config.sample_rate as f32) / 1000.0).round() as usize
+}
+
+/// Buffer length in frames after `--pad-to`: rounded up to the next multiple,
+/// or down with `--pad-truncate`.
+fn padded_length(config: &Config, frames: usize) -> usize {
+    match config.pad_to {
+        Some(block) if config.pad_truncate => frames - frames % block,
+        Some(block) => frames.div_ceil(block) * block,
+        None => frames,
+    }
+}
+
+/// Per-channel delays in samples, one entry per output channel.
+fn channel_delay_samples(config: &Config) -> Vec<usize> {
+    (0..config.channels as usize)
+        .map(|ch| {
+            config
+                .channel_delays
+                .get(ch)
+                .map_or(0, |delay| delay.samples(config.sample_rate))
+        })
+        .collect()
+}
+
+/// Delay each channel by its entry in `delays`, padding the end of every
+/// channel so they all grow by the largest delay.
+fn apply_channel_delays<T: Clone + Default>(channels: &mut [Vec<T>], delays: &[usize]) {
+    let max_delay = delays.iter().copied().max().unwrap_or(0);
+    if max_delay == 0 {
+        return;
+    }
+    for (channel, &delay) in channels.iter_mut().zip(delays) {
+        let len = channel.len();
+        channel.splice(0..0, std::iter::repeat_n(T::default(), delay));
+        channel.resize(len + max_delay, T::default());
+    }
+}
+
+/// Extend every channel with `count` samples of silence.
+fn append_silence<T: Clone + Default>(channels: &mut [Vec<T>], count: usize) {
+    for channel in channels.iter_mut() {
+        channel.resize(channel.len() + count, T::default());
+    }
+}
+
+/// Zero-pad or truncate every channel to exactly `len` samples.
+fn resize_channels<T: Clone + Default>(channels: &mut [Vec<T>], len: usize) {
+    for channel in channels.iter_mut() {
+        channel.resize(len, T::default());
+    }
+}
+
+/// Generate the float samples for the configured waveform.
+fn generate_samples(config: &Config) -> Vec<f32> {
+    let num_samples = signal_samples(config);
+    match config.waveform {
+        Waveform::Sine => generate_linear_chirp(
+            config.frequency,
+            config.frequency,
+            config.sample_rate as f32,
+            num_samples,
+            config.phase_deg.to_radians(),
+        ),
+        Waveform::Mls => generate_mls(config.mls_order, config.mls_seed, num_samples),
+        Waveform::Polarity => generate_polarity_pulse(
+            config.sample_rate as f32,
+            config.pulse_interval_ms / 1000.0,
+            num_samples,
+        ),
+        Waveform::Multitone => {
+            generate_multitone(&config.tones, config.sample_rate as f32, num_samples)
+        }
+        Waveform::Noise => {
+            let mut samples = generate_white_noise(config.seed, num_samples);
+            if let Some((low, high)) = config.noise_band {
+                band_limit(&mut samples, config.sample_rate as f32, low, high);
+            }
+            samples
+        }
+        Waveform::PinkNoise => {
+            let mut samples = generate_pink_noise(config.seed, num_samples);
+            if let Some((low, high)) = config.noise_band {
+                band_limit(&mut samples, config.sample_rate as f32, low, high);
+            }
+            let peak = measure_level(std::slice::from_ref(&samples), LevelMeasure::Peak);
+            if peak > 0.0 {
+                samples.iter_mut().for_each(|sample| *sample /= peak);
+            }
+            samples
+        }
+        Waveform::Pluck => generate_pluck(
+            config.frequency,
+            config.sample_rate as f32,
+            config.pluck_decay,
+            config.seed,
+            num_samples,
+        ),
+        Waveform::Wavetable => generate_wavetable(
+            &config.wavetable,
+            config.frequency,
+            config.sample_rate as f32,
+            config.phase_deg.to_radians(),
+            num_samples,
+        ),
+        Waveform::Expression => generate_expression(
+            config
+                .expression
+                .as_ref()
+                .expect("expression is parsed with --expr"),
+            config.frequency,
+            config.sample_rate as f32,
+            num_samples,
+        ),
+        Waveform::Glits | Waveform::Blits | Waveform::ChannelId | Waveform::CalNoise => {
+            unreachable!("multichannel idents are rendered by generate_channels")
+        }
+        Waveform::JTest | Waveform::BitPattern | Waveform::Staircase | Waveform::Ramp => {
+            unreachable!("code-exact waveforms are rendered by generate_channel_codes")
+        }
+    }
+}
+
+/// Generate one float stream per output channel.
+fn generate_channels(config: &Config) -> Vec<Vec<f32>> {
+    let mut channels = generate_default_channels(config);
+    for spec in &config.channel_specs {
+        let mut channel = config.clone();
+        channel.channel_specs.clear();
+        channel.channel_frequencies.clear();
+        channel.waveform = spec.waveform;
+        channel.frequency = spec.frequency.unwrap_or(config.frequency);
+        channel.phase_deg = spec
+            .phase_deg
+            .unwrap_or_else(|| channel_phase(config, spec.channel));
+        channel.phase_offset_deg = 0.0;
+        // Distinct seeds keep noise channels uncorrelated with each other.
+        channel.seed = config.seed.wrapping_add(spec.channel as u64);
+        let mut samples = generate_default_channels(&channel).swap_remove(spec.channel);
+        if let Some(level) = spec.level_dbfs {
+            let gain = db_to_linear(level - config.level_dbfs);
+            for sample in samples.iter_mut() {
+                *sample *= gain;
+            }
+        }
+        channels[spec.channel] = samples;
+    }
+    if let Some(pan) = config.pan {
+        let gains = pan_gains(pan);
+        for (ch, channel) in channels.iter_mut().enumerate() {
+            let gain = gains.get(ch).copied().unwrap_or(0.0);
+            for sample in channel.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+    channels
+}
+
+/// Constant-power (sin/cos) gains for the left and right channels at `pan`.
+/// Both sit at -3.01 dB in the centre so the total power stays constant.
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan + 1.0) * PI / 4.0;
+    [angle.cos(), angle.sin()]
+}
+
+/// Generate the channels of the main signal, before per-channel specs.
+fn generate_default_channels(config: &Config) -> Vec<Vec<f32>> {
+    let num_samples = signal_samples(config);
+    match config.waveform {
+        Waveform::Glits => generate_glits(config.sample_rate as f32, num_samples),
+        Waveform::Blits => generate_blits(config.sample_rate as f32, config.channels, num_samples),
+        Waveform::ChannelId => {
+            let frequencies: Vec<f32> = (0..config.channels as usize)
+                .map(|ch| channel_frequency(config, ch))
+                .collect();
+            generate_channel_id(
+                config.sample_rate as f32,
+                &frequencies,
+                config.id_tone_ms,
+                config.id_gap_ms,
+                num_samples,
+            )
+        }
+        Waveform::CalNoise => generate_cal_noise(config, num_samples),
+        _ if (!config.channel_frequencies.is_empty() && config.waveform.uses_frequency())
+            || (config.phase_offset_deg != 0.0 && config.waveform.uses_phase()) =>
+        {
+            (0..config.channels as usize)
+                .map(|ch| {
+                    let mut channel = config.clone();
+                    channel.frequency = channel_frequency(config, ch);
+                    channel.phase_deg = channel_phase(config, ch);
+                    generate_samples(&channel)
+                })
+                .collect()
+        }
+        _ => vec![generate_samples(config); config.channels as usize],
+    }
+}
+
+/// Frequency of channel `ch`. Channels past the end of a per-channel list
+/// reuse its last entry.
+fn channel_frequency(config: &Config, ch: usize) -> f32 {
+    match config.channel_frequencies.get(ch) {
+        Some(&frequency) => frequency,
+        None => config
+            .channel_frequencies
+            .last()
+            .copied()
+            .unwrap_or(config.frequency),
+    }
+}
+
+/// Start phase of channel `ch` in degrees: each channel leads the one
+/// before it by `--phase-offset`.
+fn channel_phase(config: &Config, ch: usize) -> f32 {
+    config.phase_deg + ch as f32 * config.phase_offset_deg
+}
+
+/// The configured frequency, or the per-channel list, for display.
+fn describe_frequencies(config: &Config) -> String {
+    if config.channel_frequencies.is_empty() {
+        format!("{} Hz", config.frequency)
+    } else {
+        let list: Vec<String> = config
+            .channel_frequencies
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        format!("{} Hz", list.join(", "))
+    }
+}
+
+/// Generate the configured channels, crossfading into the second source
+/// when one is configured.
+fn generate_sources(config: &Config) -> Vec<Vec<f32>> {
+    let mut channels = generate_channels(config);
+    let Some(crossfade) = config.crossfade else {
+        return channels;
+    };
+
+    let mut target = config.clone();
+    target.waveform = crossfade.waveform;
+    target.frequency = crossfade.frequency.unwrap_or(config.frequency);
+    // Offset the seed so noise into noise crossfades between distinct streams.
+    target.seed = config.seed.wrapping_add(1);
+    let target_channels = generate_channels(&target);
+
+    let len = signal_samples(config);
+    let fade = ((crossfade.length_ms * config.sample_rate as f32) / 1000.0).round() as usize;
+    let start = (len - fade.min(len)) / 2;
+    for (channel, target) in channels.iter_mut().zip(&target_channels) {
+        for (i, (sample, &next)) in channel.iter_mut().zip(target).enumerate() {
+            // Equal-power weights keep the level steady for uncorrelated sources.
+            let x = if i < start {
+                0.0
+            } else if i >= start + fade {
+                1.0
+            } else {
+                (i - start) as f32 / fade as f32
+            };
+            let angle = 0.5 * PI * x;
+            *sample = *sample * angle.cos() + next * angle.sin();
+        }
+    }
+    channels
+}
+
+/// Zero crossings of the resampling sinc kernel on each side of its centre.
+const RESAMPLE_ZERO_CROSSINGS: f64 = 24.0;
+/// Kaiser window shape; about 90 dB stopband rejection.
+const RESAMPLE_KAISER_BETA: f64 = 9.0;
+/// Passband edge as a fraction of the lower Nyquist frequency.
+const RESAMPLE_CUTOFF: f64 = 0.95;
+
+/// Synthesize the sources at the internal rate and resample them to the
+/// output rate, so the output only holds what fits below its Nyquist
+/// frequency. Clock drift is folded into the same step: a clock running
+/// `drift_ppm` fast takes its samples that much closer together.
+fn generate_resampled(config: &Config) -> Vec<Vec<f32>> {
+    let internal_rate = config.internal_rate.unwrap_or(config.sample_rate);
+    let step = internal_rate as f64 / config.sample_rate as f64 / (1.0 + config.drift_ppm / 1e6);
+    let len = signal_samples(config);
+    let mut internal = config.clone();
+    internal.sample_rate = internal_rate;
+    internal.duration_samples = Some((len as f64 * step).ceil() as usize);
+    generate_sources(&internal)
+        .iter()
+        .map(|channel| resample(channel, step, len))
+        .collect()
+}
+
+/// Band-limited resampling with a Kaiser-windowed sinc kernel. Output sample
+/// `n` is read at input position `n * step`; samples outside the input are zero.
+fn resample(input: &[f32], step: f64, len: usize) -> Vec<f32> {
+    // Downsampling narrows the kernel's passband to the output Nyquist.
+    let bandwidth = RESAMPLE_CUTOFF * (1.0 / step).min(1.0);
+    let half_width = RESAMPLE_ZERO_CROSSINGS / bandwidth;
+    let window_norm = bessel_i0(RESAMPLE_KAISER_BETA);
+    (0..len)
+        .map(|n| {
+            let position = n as f64 * step;
+            let first = (position - half_width).ceil().max(0.0) as usize;
+            let last =
+                ((position + half_width).floor() as usize).min(input.len().saturating_sub(1));
+            let mut sum = 0.0;
+            for (k, &sample) in input.iter().enumerate().take(last + 1).skip(first) {
+                let x = k as f64 - position;
+                let arg = std::f64::consts::PI * bandwidth * x;
+                let sinc = if x == 0.0 { 1.0 } else { arg.sin() / arg };
+                let r = x / half_width;
+                let window =
+                    bessel_i0(RESAMPLE_KAISER_BETA * (1.0 - r * r).max(0.0).sqrt()) / window_norm;
+                sum += sample as f64 * bandwidth * sinc * window;
+            }
+            sum as f32
+        })
+        .collect()
+}
+
+/// Zeroth-order modified Bessel function of the first kind, for the Kaiser window.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Apply the configured time-domain processing to the generated channels.
+fn process_channels(config: &Config, channels: &mut [Vec<f32>]) {
+    let ms_per_sample = 1000.0 / config.sample_rate as f64;
+    // The SNR reference is the signal before any envelope, so gated or faded
+    // signals keep the requested ratio against their steady-state level.
+    let signal_rms = measure_level(channels, LevelMeasure::Rms);
+
+    if let Some(adsr) = &config.adsr {
+        let duration_ms = (signal_samples(config) as f64 * ms_per_sample) as f32;
+        for channel in channels.iter_mut() {
+            for (i, sample) in channel.iter_mut().enumerate() {
+                *sample *= adsr.gain_at((i as f64 * ms_per_sample) as f32, duration_ms);
+            }
+        }
+    }
+
+    if let Some(tau_ms) = config.decay_tau_ms {
+        for channel in channels.iter_mut() {
+            for (i, sample) in channel.iter_mut().enumerate() {
+                *sample *= (-(i as f64 * ms_per_sample) / tau_ms as f64).exp() as f32;
+            }
+        }
+    }
+
+    if let Some(envelope) = &config.envelope {
+        for channel in channels.iter_mut() {
+            for (i, sample) in channel.iter_mut().enumerate() {
+                *sample *= db_to_linear(envelope.gain_db_at((i as f64 * ms_per_sample) as f32));
+            }
+        }
+    }
+
+    if config.fade_in_ms > 0.0 || config.fade_out_ms > 0.0 {
+        let fade_in = config.fade_in_ms as f64 / ms_per_sample;
+        let fade_out = config.fade_out_ms as f64 / ms_per_sample;
+        for channel in channels.iter_mut() {
+            let len = channel.len();
+            for (i, sample) in channel.iter_mut().enumerate() {
+                if (i as f64) < fade_in {
+                    *sample *= config.fade_curve.gain((i as f64 / fade_in) as f32);
+                }
+                let remaining = (len - 1 - i) as f64;
+                if remaining < fade_out {
+                    *sample *= config.fade_curve.gain((remaining / fade_out) as f32);
+                }
+            }
+        }
+    }
+
+    if let Some(burst) = &config.burst {
+        for channel in channels.iter_mut() {
+            for (i, sample) in channel.iter_mut().enumerate() {
+                *sample *= burst.gain_at((i as f64 * ms_per_sample) as f32);
+            }
+        }
+    }
+
+    // Noise is added after shaping so it stays continuous through gaps and fades.
+    if let Some(snr_db) = config.snr_db {
+        add_noise_at_snr(channels, signal_rms, snr_db, config.seed);
+    }
+
+    if let Some(mid_side) = config.mid_side
+        && let [left, right] = channels
+    {
+        mid_side.apply(left, right);
+    }
+
+    // Normalization runs last so it sees the fully shaped signal.
+    if let Some(normalize) = &config.normalize {
+        let level = measure_level(channels, normalize.measure);
+        if level > 0.0 {
+            scale_channels(channels, db_to_linear(normalize.target_dbfs) / level);
+        }
+    }
+
+    if let Some(target) = config.target_lufs {
+        let loudness = integrated_loudness(channels, config.sample_rate);
+        if loudness.is_finite() {
+            scale_channels(channels, db_to_linear(target - loudness as f32));
+        } else {
+            eprintln!("Warning: Signal is below the loudness gate, --target-lufs ignored");
+        }
+    }
+}
+
+/// Short human-readable description of the generated signal, used in codegen comments.
+fn describe_signal(config: &Config) -> String {
+    match config.waveform {
+        Waveform::Sine => format!("Sine wave: {}", describe_frequencies(config)),
+        Waveform::Mls => format!(
+            "MLS: order {}, seed {}, period {} samples",
+            config.mls_order,
+            config.mls_seed,
+            (1u32 << config.mls_order) - 1
+        ),
+        Waveform::Polarity => format!("Polarity pulse: every {} ms", config.pulse_interval_ms),
+        Waveform::Glits => format!("GLITS: {} Hz at {} dBFS", GLITS_FREQUENCY, IDENT_LEVEL_DBFS),
+        Waveform::Blits => format!(
+            "BLITS ident at {} dBFS, 2 kHz section at {} dBFS",
+            IDENT_LEVEL_DBFS, BLITS_ALL_LEVEL_DBFS
+        ),
+        Waveform::ChannelId => format!(
+            "Channel ID: {} tone, {} ms per channel, {} ms gaps",
+            describe_frequencies(config),
+            config.id_tone_ms,
+            config.id_gap_ms
+        ),
+        Waveform::JTest => format!(
+            "J-test: {} Hz square + LSB toggle at {} Hz",
+            config.sample_rate as f32 / 4.0,
+            config.sample_rate as f32 / JTEST_LSB_PERIOD as f32
+        ),
+        Waveform::BitPattern => format!("Bit pattern: {}", config.bit_pattern.describe()),
+        Waveform::Staircase => format!(
+            "Staircase: {} steps, {} samples per step",
+            config.staircase_steps, config.staircase_dwell
+        ),
+        Waveform::Ramp => "Full-scale ramp".to_string(),
+        Waveform::Pluck => format!(
+            "Plucked string: {} Hz, loop gain {}",
+            config.frequency, config.pluck_decay
+        ),
+        Waveform::Wavetable => format!(
+            "Wavetable: {} Hz, {} entries",
+            config.frequency,
+            config.wavetable.len()
+        ),
+        Waveform::Expression => format!("Expression: {}", config.expression_text),
+        Waveform::Noise => match config.noise_band {
+            Some((low, high)) => format!("White noise: {}-{} Hz, seed {}", low, high, config.seed),
+            None => format!("White noise: seed {}", config.seed),
+        },
+        Waveform::PinkNoise => match config.noise_band {
+            Some((low, high)) => format!("Pink noise: {}-{} Hz, seed {}", low, high, config.seed),
+            None => format!("Pink noise: seed {}", config.seed),
+        },
+        Waveform::CalNoise => format!(
+            "Calibration pink noise: {} dBFS RMS, {} ms per channel",
+            config.level_dbfs, config.id_tone_ms
+        ),
+        Waveform::Multitone => {
+            let tones: Vec<String> = config
+                .tones
+                .iter()
+                .map(|tone| format!("{} Hz x{}", tone.frequency, tone.amplitude))
+                .collect();
+            format!("Multitone: {}", tones.join(" + "))
+        }
+    }
+}
+
+/// Stage per-channel float streams for quantization: every sample is scaled
+/// by the output gain and its channel gain and shifted by the DC offset, and
+/// the limiter, if enabled, acts on the gained signal. Returns the staged
+/// channels with their peak and a count of samples past full scale, which
+/// quantization saturates instead of wrapping.
+fn stage_samples(channels: &[Vec<f32>], config: &Config) -> (Vec<Vec<f32>>, RenderStats) {
+    let gain = output_gain(config);
+
+    let mut staged: Vec<Vec<f32>> = channels
+        .iter()
+        .enumerate()
+        .map(|(ch, channel)| {
+            let gain = gain
+                * config
+                    .channel_gains_db
+                    .get(ch)
+                    .map_or(1.0, |&db| db_to_linear(db));
+            channel
+                .iter()
+                .map(|&sample| sample * gain + config.dc_offset)
+                .collect()
+        })
+        .collect();
+    match config.limiter {
+        Limiter::None => {}
+        Limiter::SoftClip => {
+            for sample in staged.iter_mut().flatten() {
+                *sample = soft_clip(*sample);
+            }
+        }
+        Limiter::Lookahead => lookahead_limit(
+            &mut staged,
+            config.sample_rate as f32,
+            db_to_linear(config.limit_ceiling_db),
+        ),
+    }
+
+    let mut stats = RenderStats::default();
+    for &sample in staged.iter().flatten() {
+        stats.peak = stats.peak.max(sample.abs());
+        if sample.abs() > 1.0 {
+            stats.clipped_samples += 1;
+        }
+    }
+    (staged, stats)
+}
+
+/// Quantize staged samples to integer codes at the configured bit depth.
+/// Dither, if enabled, is added at the target bit depth before rounding, and
+/// noise shaping feeds the filtered quantization error back into the input.
+fn quantize_samples(staged: &[Vec<f32>], config: &Config) -> Vec<Vec<i32>> {
+    let bits = code_bits(config);
+    let max_val = config.full_scale.scale(bits);
+    let (min_code, max_code) = code_limits(bits);
+    let shaping = config.noise_shaping.coefficients();
+    let mut rng = Rng::new(config.seed ^ DITHER_SEED_SALT);
+
+    staged
+        .iter()
+        .map(|channel| {
+            // Past quantization errors in LSBs, newest first.
+            let mut errors = vec![0.0f64; shaping.len()];
+            channel
+                .iter()
+                .map(|&sample| {
+                    let value = sample.clamp(-1.0, 1.0);
+                    // f64 keeps sub-LSB dither meaningful at 32-bit depth.
+                    let shaped = value as f64 * max_val
+                        - shaping.iter().zip(&errors).map(|(h, e)| h * e).sum::<f64>();
+                    let dithered = shaped + config.dither.sample(&mut rng) as f64;
+                    let code = (config.rounding.apply(dithered) as i64).clamp(min_code, max_code);
+                    if !errors.is_empty() {
+                        errors.rotate_right(1);
+                        errors[0] = (code as f64 - shaped)
+                            .clamp(-NOISE_SHAPING_MAX_ERROR, NOISE_SHAPING_MAX_ERROR);
+                    }
+                    code as i32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Slot packing summary, e.g. "left-justified, 24-bit data in 32-bit slots".
+fn describe_packing(config: &Config) -> String {
+    format!(
+        "{}, {}-bit data in {}-bit slots",
+        config.packing.to_str(),
+        config.sample_width.to_str(),
+        config.slot_bits
+    )
+}
+
+/// Human-readable sample format, e.g. "16-bit" or "32-bit float".
+fn depth_label(config: &Config) -> String {
+    match config.sample_format {
+        SampleFormat::Pcm => match config.data_bits {
+            Some(bits) => format!(
+                "{}-bit ({} in {} bits)",
+                bits,
+                config.justify.to_str(),
+                config.sample_width as u8 * 8
+            ),
+            None => format!("{}-bit", config.sample_width.to_str()),
+        },
+        SampleFormat::Float => format!("{}-bit float", config.sample_width as u8 * 8),
+        SampleFormat::ALaw => "8-bit A-law".to_string(),
+        SampleFormat::MuLaw => "8-bit μ-law".to_string(),
+    }
+}
+
+/// Sample format token used in generated array names, e.g. "16bit" or "f32".
+fn depth_token(config: &Config) -> String {
+    if let Some(q_format) = config.q_format {
+        return q_format.to_str().to_string();
+    }
+    match config.sample_format {
+        SampleFormat::Pcm => format!("{}bit", code_bits(config)),
+        SampleFormat::Float => format!("f{}", config.sample_width as u8 * 8),
+        SampleFormat::ALaw => "alaw".to_string(),
+        SampleFormat::MuLaw => "ulaw".to_string(),
+    }
+}
+
+/// Bytes occupied by one sample of one channel in the output.
+fn bytes_per_sample(config: &Config) -> usize {
+    match config.packing {
+        Packing::Packed => config.sample_width as usize,
+        _ => config.slot_bits as usize / 8,
+    }
+}
+
+/// Encode a 16-bit linear sample as G.711 A-law: the top 13 bits are split into
+/// a 3-bit segment and 4-bit mantissa, with even bits inverted for line coding.
+fn linear_to_alaw(pcm: i16) -> u8 {
+    const SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+    let mut value = pcm as i32 >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    let Some(segment) = SEGMENT_ENDS.iter().position(|&end| value <= end) else {
+        return 0x7F ^ mask;
+    };
+    let mantissa = if segment < 2 {
+        (value >> 1) & 0x0F
+    } else {
+        (value >> segment) & 0x0F
+    };
+    ((segment as i32) << 4 | mantissa) as u8 ^ mask
+}
+
+/// Encode a 16-bit linear sample as G.711 μ-law: the top 14 bits are biased by
+/// 33, split into a 3-bit segment and 4-bit mantissa, and inverted.
+fn linear_to_mulaw(pcm: i16) -> u8 {
+    const SEGMENT_ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+    const CLIP: i32 = 8159;
+    const BIAS: i32 = 0x84 >> 2;
+    let mut value = pcm as i32 >> 2;
+    let mask = if value < 0 {
+        value = -value;
+        0x7F
+    } else {
+        0xFF
+    };
+    value = value.min(CLIP) + BIAS;
+    let Some(segment) = SEGMENT_ENDS.iter().position(|&end| value <= end) else {
+        return 0x7F ^ mask;
+    };
+    ((segment as i32) << 4 | ((value >> (segment + 1)) & 0x0F)) as u8 ^ mask
+}
+
+/// Append one sample, given as little-endian bytes, in the configured byte order.
+fn push_sample_bytes(bytes: &mut Vec<u8>, le_bytes: &[u8], endian: Endian) {
+    match endian {
+        Endian::Little => bytes.extend_from_slice(le_bytes),
+        Endian::Big => bytes.extend(le_bytes.iter().rev()),
+    }
+}
+
+/// Resolution of the integer codes: the sample width, 16 bits for companded
+/// formats, or the reduced 12/20-bit depth that sits in a wider container.
+fn code_bits(config: &Config) -> u32 {
+    config
+        .data_bits
+        .unwrap_or(config.sample_format.quantize_width(config.sample_width) as u32 * 8)
+}
+
+/// Whether integer codes are written in offset-binary (unsigned) form, which
+/// 8-bit samples always use.
+fn uses_offset_binary(config: &Config) -> bool {
+    config.offset_binary || config.sample_width == SampleWidth::Width1Byte
+}
+
+/// Encode one channel of integer codes as sample bytes.
+/// Offset-binary codes are written unsigned, offset by half the code range.
+fn encode_codes(codes: &[i32], config: &Config) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(codes.len() * bytes_per_sample(config));
+    let bits = code_bits(config);
+    let container_bits = config.sample_width as u32 * 8;
+    let offset = if uses_offset_binary(config) {
+        1i64 << (bits - 1)
+    } else {
+        0
+    };
+    let justify_shift = match config.justify {
+        Justify::Left if config.data_bits.is_some() => container_bits - bits,
+        _ => 0,
+    };
+    for &code in codes {
+        // Offset binary adds half the code range, so right-justified slots
+        // are zero-extended rather than sign-extended.
+        let value = (code as i64 + offset) << justify_shift;
+        match (config.sample_width, config.packing) {
+            _ if config.sample_format == SampleFormat::ALaw => {
+                bytes.push(linear_to_alaw(code as i16))
+            }
+            _ if config.sample_format == SampleFormat::MuLaw => {
+                bytes.push(linear_to_mulaw(code as i16))
+            }
+            (width, Packing::Packed) => push_sample_bytes(
+                &mut bytes,
+                &value.to_le_bytes()[..width as usize],
+                config.endian,
+            ),
+            (_, packing) => {
+                let word = match packing {
+                    Packing::RightJustified => value,
+                    _ => value << (config.slot_bits - container_bits),
+                };
+                push_sample_bytes(
+                    &mut bytes,
+                    &word.to_le_bytes()[..config.slot_bits as usize / 8],
+                    config.endian,
+                )
+            }
+        }
+    }
+    bytes
+}
+
+/// Delay the serial bit stream of interleaved slot words by one bit clock, as
+/// in Philips I2S where each MSB follows the word-select edge by one bit. The
+/// final LSB wraps to the start so circular DMA stays aligned.
+fn delay_one_bit(buffer: &mut [u8], slot_bytes: usize, endian: Endian) {
+    let mut carry = 0u8;
+    for word in buffer.chunks_mut(slot_bytes) {
+        // Walk each word MSB first, in the order the bits leave the serializer.
+        if endian == Endian::Little {
+            word.reverse();
+        }
+        for byte in word.iter_mut() {
+            let lsb = *byte & 1;
+            *byte = (*byte >> 1) | (carry << 7);
+            carry = lsb;
+        }
+        if endian == Endian::Little {
+            word.reverse();
+        }
+    }
+    let msb_index = match endian {
+        Endian::Little => slot_bytes - 1,
+        Endian::Big => 0,
+    };
+    if let Some(byte) = buffer.get_mut(msb_index) {
+        *byte |= carry << 7;
+    }
+}
+
+/// Encode one channel of staged samples as IEEE floats.
+/// Values beyond ±1.0 are kept as they are; f64 output widens each sample
+/// exactly, without requantizing it.
+fn encode_floats(samples: &[f32], config: &Config) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * bytes_per_sample(config));
+    for &sample in samples {
+        match config.sample_width {
+            SampleWidth::Width8Byte => {
+                push_sample_bytes(&mut bytes, &(sample as f64).to_le_bytes(), config.endian)
+            }
+            _ => push_sample_bytes(&mut bytes, &sample.to_le_bytes(), config.endian),
+        }
+    }
+    bytes
+}
+
+/// Arrange per-channel sample bytes into one buffer, either interleaved by
+/// frame or one channel after another.
+fn interleave_bytes(channels: &[Vec<u8>], sample_bytes: usize, layout: Layout) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(channels.iter().map(Vec::len).sum());
+    match layout {
+        Layout::Interleaved => {
+            let frames = channels.first().map_or(0, |ch| ch.len() / sample_bytes);
+            for i in 0..frames {
+                for channel in channels {
+                    buffer.extend_from_slice(&channel[i * sample_bytes..(i + 1) * sample_bytes]);
+                }
+            }
+        }
+        Layout::Planar => {
+            for channel in channels {
+                buffer.extend_from_slice(channel);
+            }
+        }
+    }
+    buffer
+}
+
+fn print_buffer_info(
+    config: &Config,
+    total_samples: usize,
+    total_bytes: usize,
+    stats: &RenderStats,
+) {
+    println!("Sine Wave Generator - Configuration");
+    println!("=====================================");
+    if config.waveform != Waveform::Sine {
+        println!("Waveform:       {}", config.waveform.to_str());
+    }
+    if config.waveform.uses_frequency() {
+        println!("Frequency:      {}", describe_frequencies(config));
+    }
+    if config.tuning.a4_hz != A4_FREQUENCY || config.tuning.temperament != Temperament::Equal {
+        println!("Tuning:         {}", config.tuning.describe());
+    }
+    println!(
+        "Sample Rate:    {} Hz{} (Nyquist {} Hz)",
+        config.sample_rate,
+        if SUPPORTED_SAMPLE_RATES.contains(&config.sample_rate) {
+            ""
+        } else {
+            ", non-standard"
+        },
+        config.sample_rate as f32 / 2.0
+    );
+    if let Some(rate) = config.internal_rate {
+        println!("Internal rate:  {} Hz, windowed-sinc resampled", rate);
+    }
+    if config.drift_ppm != 0.0 {
+        println!("Clock drift:    {:+} ppm", config.drift_ppm);
+    }
+    println!(
+        "Channels:       {} ({})",
+        config.channels,
+        channel_layout_name(config.channels)
+    );
+    println!("Bit Depth:      {}", depth_label(config));
+    if config.layout == Layout::Planar && config.channels > 1 {
+        println!("Layout:         {}", config.layout.to_str());
+    }
+    if config.endian == Endian::Big {
+        println!("Byte order:     {}", config.endian.to_str());
+    }
+    if config.packing != Packing::Packed {
+        println!("Packing:        {}", describe_packing(config));
+    }
+    if config.offset_binary && config.sample_width != SampleWidth::Width1Byte {
+        println!("Encoding:       offset binary (unsigned)");
+    }
+    match config.duration_samples {
+        Some(samples) => println!(
+            "Duration:       {} ms ({} samples)",
+            config.duration_ms, samples
+        ),
+        None => println!("Duration:       {} ms", config.duration_ms),
+    }
+    let gain = output_gain(config);
+    if gain != 1.0 && !config.waveform.is_code_exact() {
+        println!(
+            "Level:          {:.2} dBFS (x{:.6} linear)",
+            linear_to_db(gain),
+            gain
+        );
+    }
+    if !config.channel_gains_db.is_empty() && !config.waveform.is_code_exact() {
+        let gains: Vec<String> = config
+            .channel_gains_db
+            .iter()
+            .map(|gain| format!("{} dB", gain))
+            .collect();
+        println!("Channel gains:  {}", gains.join(", "));
+    }
+    if !config.channel_delays.is_empty() {
+        let delays: Vec<String> = channel_delay_samples(config)
+            .iter()
+            .map(|delay| delay.to_string())
+            .collect();
+        println!("Channel delays: {} samples", delays.join(", "));
+    }
+    if config.loopable {
+        println!(
+            "Loopable:       whole cycles in {} samples",
+            signal_samples(config)
+        );
+    }
+    if config.tail_ms > 0.0 {
+        println!("Silent tail:    {} ms", config.tail_ms);
+    }
+    if let Some(block) = config.pad_to {
+        println!(
+            "Padding:        {} to a multiple of {} frames",
+            if config.pad_truncate {
+                "truncated"
+            } else {
+                "zero-padded"
+            },
+            block
+        );
+    }
+    if let Some(pan) = config.pan {
+        let [left, right] = pan_gains(pan);
+        println!(
+            "Pan:            {} (L {:.2} dB, R {:.2} dB)",
+            pan,
+            linear_to_db(left),
+            linear_to_db(right)
+        );
+    }
+    if let Some(mid_side) = config.mid_side {
+        println!("Matrix:         {}", mid_side.to_str());
+    }
+    if config.phase_deg != 0.0 && config.waveform.uses_phase() {
+        println!("Start phase:    {} deg", config.phase_deg);
+    }
+    if config.phase_offset_deg != 0.0 {
+        println!(
+            "Phase offset:   {} deg per channel",
+            config.phase_offset_deg
+        );
+    }
+    for spec in &config.channel_specs {
+        let label = format!("Channel {}:", spec.channel + 1);
+        println!("{:<16}{}", label, spec.describe(config));
+    }
+    if let Some(crossfade) = config.crossfade {
+        println!("Crossfade:      {}", crossfade.describe(config));
+    }
+    if let Some(snr_db) = config.snr_db {
+        println!("Added noise:    white, {} dB SNR", snr_db);
+    }
+    if config.fade_in_ms > 0.0 || config.fade_out_ms > 0.0 {
+        println!(
+            "Fades:          {} ms in / {} ms out ({})",
+            config.fade_in_ms,
+            config.fade_out_ms,
+            config.fade_curve.to_str()
+        );
+    }
+    if config.dc_offset != 0.0 && !config.waveform.is_code_exact() {
+        println!("DC offset:      {} FS", config.dc_offset);
+    }
+    let quantized = config.sample_format != SampleFormat::Float && !config.waveform.is_code_exact();
+    if (config.rounding != Rounding::Nearest || config.full_scale != FullScale::Symmetric)
+        && quantized
+    {
+        println!(
+            "Quantization:   {} rounding, {} full scale",
+            config.rounding.to_str(),
+            config.full_scale.to_str()
+        );
+    }
+    if config.dither != Dither::None && quantized {
+        println!("Dither:         {}", config.dither.to_str());
+    }
+    if config.noise_shaping != NoiseShaping::None && quantized {
+        println!("Noise shaping:  {}", config.noise_shaping.to_str());
+    }
+    if let Some(normalize) = &config.normalize {
+        println!("Normalized to:  {}", normalize.describe());
+    }
+    if config.limiter != Limiter::None && !config.waveform.is_code_exact() {
+        match config.limiter {
+            Limiter::Lookahead => println!(
+                "Limiter:        {} at {} dBFS",
+                config.limiter.to_str(),
+                config.limit_ceiling_db
+            ),
+            _ => println!("Limiter:        {}", config.limiter.to_str()),
+        }
+    }
+    if let Some(target) = config.target_lufs {
+        println!("Loudness:       {} LUFS (integrated, BS.1770)", target);
+    }
+    if let Some(adsr) = &config.adsr {
+        println!(
+            "Envelope:       A {} ms, D {} ms, S {}, R {} ms",
+            adsr.attack_ms, adsr.decay_ms, adsr.sustain, adsr.release_ms
+        );
+    }
+    if let Some(envelope) = &config.envelope {
+        println!(
+            "Automation:     {} ({} breakpoints)",
+            envelope.path,
+            envelope.points.len()
+        );
+    }
+    if let Some(tau_ms) = config.decay_tau_ms {
+        println!(
+            "Decay:          tau {} ms ({:.1} dB per second)",
+            tau_ms,
+            -20.0 * std::f32::consts::LOG10_E * 1000.0 / tau_ms
+        );
+    }
+    if let Some(burst) = &config.burst {
+        println!(
+            "Burst:          {} ms on / {} ms off ({} ms edges)",
+            burst.on_ms, burst.off_ms, burst.edge_ms
+        );
+    }
+    println!();
+    println!("Buffer Analysis:");
+    println!("  Samples:      {}", total_samples);
+    println!("  Total bytes:  {}", total_bytes);
+    if !config.waveform.is_code_exact() {
+        println!("  Peak level:   {:.2} dBFS", linear_to_db(stats.peak));
+        if stats.clipped_samples > 0 {
+            println!(
+                "  Clipped:      {} samples ({:.3}%)",
+                stats.clipped_samples,
+                100.0 * stats.clipped_samples as f64
+                    / (total_samples * config.channels as usize) as f64
+            );
+        } else {
+            println!("  Clipped:      none");
+        }
+    }
+
+    match config.waveform {
+        Waveform::Sine => {
+            // Calculate frequency info
+            let period_samples = config.sample_rate as f32 / config.frequency;
+            println!("\nFrequency Analysis:");
+            println!("  Period:       {:.2} samples", period_samples);
+            println!(
+                "  Full cycles:  {:.2}",
+                total_samples as f32 / period_samples
+            );
+        }
+        Waveform::Mls => {
+            let period_samples = (1u32 << config.mls_order) - 1;
+            println!("\nMLS Analysis:");
+            println!("  Order:        {}", config.mls_order);
+            println!("  Seed:         {}", config.mls_seed);
+            println!("  Period:       {} samples", period_samples);
+            println!(
+                "  Full periods: {:.2}",
+                total_samples as f32 / period_samples as f32
+            );
+        }
+        Waveform::JTest => {
+            println!("\nJ-test Analysis:");
+            println!(
+                "  Main tone:    {} Hz (fs/4)",
+                config.sample_rate as f32 / 4.0
+            );
+            println!(
+                "  LSB toggle:   {:.2} Hz (fs/{})",
+                config.sample_rate as f32 / JTEST_LSB_PERIOD as f32,
+                JTEST_LSB_PERIOD
+            );
+        }
+        Waveform::BitPattern => {
+            println!("\nPattern Analysis:");
+            println!("  Pattern:      {}", config.bit_pattern.describe());
+        }
+        Waveform::Staircase => {
+            let (min, max) = code_limits(code_bits(config));
+            println!("\nStaircase Analysis:");
+            println!("  Steps:        {}", config.staircase_steps);
+            println!("  Dwell:        {} samples", config.staircase_dwell);
+            println!(
+                "  Step size:    {:.2} codes",
+                (max - min) as f64 / (config.staircase_steps - 1) as f64
+            );
+            println!(
+                "  Full sweeps:  {:.2}",
+                total_samples as f32
+                    / (config.staircase_steps as usize * config.staircase_dwell) as f32
+            );
+        }
+        Waveform::Pluck => {
+            let period_samples = config.sample_rate as f32 / config.frequency;
+            println!("\nPluck Analysis:");
+            println!("  Period:       {:.2} samples", period_samples);
+            println!("  Loop gain:    {}", config.pluck_decay);
+            println!("  Seed:         {}", config.seed);
+        }
+        Waveform::Wavetable => {
+            let period_samples = config.sample_rate as f32 / config.frequency;
+            println!("\nWavetable Analysis:");
+            println!(
+                "  Table:        {}",
+                config.wavetable_path.as_deref().unwrap_or("")
+            );
+            println!("  Entries:      {}", config.wavetable.len());
+            println!("  Period:       {:.2} samples", period_samples);
+            println!(
+                "  Full cycles:  {:.2}",
+                total_samples as f32 / period_samples
+            );
+        }
+        Waveform::Expression => {
+            println!("\nExpression:");
+            println!("  {}", config.expression_text);
+        }
+        Waveform::Noise | Waveform::PinkNoise => {
+            println!("\nNoise Analysis:");
+            println!("  Seed:         {}", config.seed);
+            match config.noise_band {
+                Some((low, high)) => println!("  Band:         {} - {} Hz", low, high),
+                None => println!("  Band:         full (0 - {} Hz)", config.sample_rate / 2),
+            }
+        }
+        Waveform::Ramp => {
+            let (min, max) = code_limits(code_bits(config));
+            println!("\nRamp Analysis:");
+            println!("  Codes:        {} to {}", min, max);
+            println!(
+                "  Slope:        {:.2} codes/sample",
+                (max - min) as f64 / total_samples.saturating_sub(1).max(1) as f64
+            );
+        }
+        Waveform::Multitone => {
+            println!("\nTone Analysis:");
+            for tone in &config.tones {
+                println!(
+                    "  {:>8} Hz:  {:.3} ({:.2} dB)",
+                    tone.frequency,
+                    tone.amplitude,
+                    linear_to_db(tone.amplitude)
+                );
+            }
+        }
+        Waveform::Glits | Waveform::Blits => {
+            let cycle_ms = if config.waveform == Waveform::Glits {
+                GLITS_CYCLE_MS
+            } else {
+                BLITS_CYCLE_MS
+            };
+            println!("\nIdent Analysis:");
+            println!("  Level:        {} dBFS", IDENT_LEVEL_DBFS);
+            println!("  Cycle:        {} ms", cycle_ms);
+            println!("  Full cycles:  {:.2}", config.duration_ms / cycle_ms);
+        }
+        Waveform::CalNoise => {
+            let cycle_ms = (config.id_tone_ms + config.id_gap_ms) * config.channels as f32;
+            println!("\nCalibration Analysis:");
+            println!(
+                "  RMS level:    {} dBFS per channel",
+                config.level_dbfs + config.gain_db
+            );
+            match config.noise_band {
+                Some((low, high)) => println!("  Band:         {} - {} Hz", low, high),
+                None => println!("  Band:         full (0 - {} Hz)", config.sample_rate / 2),
+            }
+            println!("  Dwell:        {} ms per channel", config.id_tone_ms);
+            println!("  Gap:          {} ms", config.id_gap_ms);
+            println!("  Cycle:        {} ms", cycle_ms);
+        }
+        Waveform::ChannelId => {
+            let cycle_ms = (config.id_tone_ms + config.id_gap_ms) * config.channels as f32;
+            println!("\nChannel ID Analysis:");
+            println!("  Tone:         {} ms per channel", config.id_tone_ms);
+            println!("  Gap:          {} ms", config.id_gap_ms);
+            println!("  Cycle:        {} ms", cycle_ms);
+            println!("  Full cycles:  {:.2}", config.duration_ms / cycle_ms);
+        }
+        Waveform::Polarity => {
+            let interval_samples = config.pulse_interval_ms * config.sample_rate as f32 / 1000.0;
+            println!("\nPulse Analysis:");
+            println!("  Interval:     {:.2} samples", interval_samples);
+            println!(
+                "  Pulse width:  {:.2} samples",
+                (POLARITY_RISE_MS + POLARITY_FALL_MS) * config.sample_rate as f32 / 1000.0
+            );
+            println!(
+                "  Pulses:       {}",
+                (total_samples as f32 / interval_samples).ceil() as usize
+            );
+        }
+    }
+}
+
+fn print_buffer_hex(buffer: &[u8], bytes_per_line: usize) {
+    let mut i: usize = 0;
+    print!("[");
+    while i < buffer.len() {
+        if i > 0 {
+            print!("\n ");
+        }
+        for j in 0..bytes_per_line {
+            if i + j < buffer.len() {
+                print!("0x{:02X}", buffer[i + j]);
+                if i + j + 1 < buffer.len() && j < bytes_per_line - 1 {
+                    print!(", ");
+                }
+            }
+        }
+        i += bytes_per_line;
+    }
+    println!("]");
+}
+
+/// One emitted buffer: the whole render, or a single channel of it when
+/// `--split-channels` is set.
+struct OutputPart {
+    /// Configuration describing this buffer (mono for a split channel)
+    config: Config,
+    /// Appended to generated array names, e.g. `_ch2`
+    suffix: String,
+    /// Appended to section headings, e.g. ` (channel 2)`
+    label: String,
+    /// Index of the part's first channel in the full render
+    first_channel: usize,
+    buffer: Vec<u8>,
+}
+
+/// Split the render into the buffers to emit. Without `--split-channels`
+/// this is the interleaved (or planar) `buffer` as a single part.
+fn output_parts(config: &Config, channel_bytes: &[Vec<u8>], buffer: Vec<u8>) -> Vec<OutputPart> {
+    if !config.split_channels || config.channels == 1 {
+        return vec![OutputPart {
+            config: config.clone(),
+            suffix: String::new(),
+            label: String::new(),
+            first_channel: 0,
+            buffer,
+        }];
+    }
+
+    channel_bytes
+        .iter()
+        .enumerate()
+        .map(|(ch, bytes)| {
+            let mut mono = config.clone();
+            mono.channels = 1;
+            OutputPart {
+                config: mono,
+                suffix: format!("_ch{}", ch + 1),
+                label: format!(" (channel {})", ch + 1),
+                first_channel: ch,
+                buffer: bytes.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Note a planar layout in array comments, with the size of each channel block.
+fn print_layout_comment(buffer: &[u8], config: &Config, comment: &str) {
+    print!("{}", layout_comment(buffer, config, comment));
+}
+
+/// Comment lines, each ending in a newline, for buffer layouts other than
+/// interleaved, little-endian, packed two's complement.
+fn layout_comment(buffer: &[u8], config: &Config, comment: &str) -> String {
+    let mut text = String::new();
+    if config.layout == Layout::Planar && config.channels > 1 {
+        text.push_str(&format!(
+            "{} Layout: planar, {} bytes per channel\n",
+            comment,
+            buffer.len() / config.channels as usize
+        ));
+    }
+    if config.endian == Endian::Big {
+        text.push_str(&format!(
+            "{} Byte order: {}\n",
+            comment,
+            config.endian.to_str()
+        ));
+    }
+    if config.packing != Packing::Packed {
+        text.push_str(&format!(
+            "{} Packing: {}\n",
+            comment,
+            describe_packing(config)
+        ));
+    }
+    if config.offset_binary && config.sample_width != SampleWidth::Width1Byte {
+        text.push_str(&format!("{} Encoding: offset binary (unsigned)\n", comment));
+    }
+    text
+}
+
+/// Print one CSV row per sample and channel, in frame order. Integer output
+/// adds the signed code at the output bit depth (linear, before any A-law
+/// or μ-law companding).
+fn print_csv(values: &ChannelValues, config: &Config) {
+    use std::io::{self, Write};
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut write = || -> io::Result<()> {
+        write!(out, "index,time_s,channel,value")?;
+        writeln!(out, "{}", if values.codes.is_some() { ",code" } else { "" })?;
+        let frames = values.floats.first().map_or(0, Vec::len);
+        for i in 0..frames {
+            let time = i as f64 / config.sample_rate as f64;
+            for (ch, floats) in values.floats.iter().enumerate() {
+                write!(out, "{},{},{},{}", i, time, ch + 1, floats[i])?;
+                match &values.codes {
+                    Some(codes) => writeln!(out, ",{}", codes[ch][i])?,
+                    None => writeln!(out)?,
+                }
+            }
+        }
+        out.flush()
+    };
+    if let Err(e) = write() {
+        eprintln!("Error: Cannot write to stdout: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Print a MATLAB/Octave script setting `fs` and a frames x channels
+/// matrix of `config.channels` channels from `first_channel` on. Integer
+/// codes keep their class (int8/int16/int32); float output is single, or
+/// double for 64-bit.
+fn print_matlab(values: &ChannelValues, config: &Config, first_channel: usize, name_suffix: &str) {
+    use std::io::{self, Write};
+    let name = format!(
+        "{}_{}hz_{}ms_{}_{}ch{}",
+        config.waveform.to_str(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config),
+        config.channels,
+        name_suffix
+    );
+    let class = match &values.codes {
+        Some(_) if code_bits(config) <= 8 => "int8",
+        Some(_) if code_bits(config) <= 16 => "int16",
+        Some(_) => "int32",
+        None if config.sample_width == SampleWidth::Width8Byte => "double",
+        None => "single",
+    };
+    let channels = first_channel..first_channel + config.channels as usize;
+    let frames = values.floats.first().map_or(0, Vec::len);
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut write = || -> io::Result<()> {
+        writeln!(
+            out,
+            "% {}, {} ms, {}, {} channel{}",
+            describe_signal(config),
+            config.duration_ms,
+            depth_label(config),
+            config.channels,
+            if config.channels > 1 { "s" } else { "" }
+        )?;
+        writeln!(out, "% {}", config.command_line)?;
+        if values.codes.is_some() {
+            writeln!(
+                out,
+                "% Scale with double({}) / {} for a nominal range of +/-1",
+                name,
+                config.full_scale.scale(code_bits(config))
+            )?;
+        }
+        writeln!(out, "fs = {};", config.sample_rate)?;
+        writeln!(out, "{} = {}([", name, class)?;
+        for i in 0..frames {
+            let row: Vec<String> = channels
+                .clone()
+                .map(|ch| match &values.codes {
+                    Some(codes) => codes[ch][i].to_string(),
+                    None => values.floats[ch][i].to_string(),
+                })
+                .collect();
+            writeln!(out, "    {}", row.join(", "))?;
+        }
+        writeln!(out, "]);")?;
+        out.flush()
+    };
+    if let Err(e) = write() {
+        eprintln!("Error: Cannot write to stdout: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Quote and escape `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON number, or `null` where JSON has none (infinities, NaN).
+fn json_number<T: Into<f64> + std::fmt::Display + Copy>(value: T) -> String {
+    if value.into().is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Print the configuration and the samples as one JSON object. The command
+/// line reproduces the render exactly; the other members summarize it for
+/// consumers. `samples` holds one array per channel: integer codes at the
+/// output bit depth, or float values for float output.
+fn print_json(values: &ChannelValues, config: &Config, stats: &RenderStats) {
+    use std::io::{self, Write};
+    let frequencies = if config.waveform.uses_frequency() {
+        let list: Vec<String> = (0..config.channels as usize)
+            .map(|ch| json_number(channel_frequency(config, ch)))
+            .collect();
+        format!("[{}]", list.join(", "))
+    } else {
+        "null".to_string()
+    };
+    let sample_format = match config.sample_format {
+        SampleFormat::Pcm => "pcm",
+        SampleFormat::Float => "float",
+        SampleFormat::ALaw => "alaw",
+        SampleFormat::MuLaw => "mulaw",
+    };
+    let bits = match config.sample_format {
+        SampleFormat::Float => config.sample_width as u32 * 8,
+        _ => code_bits(config),
+    };
+    let members = [
+        ("generator", json_string("singen")),
+        ("command_line", json_string(&config.command_line)),
+        ("description", json_string(&describe_signal(config))),
+        ("waveform", json_string(config.waveform.to_str())),
+        ("frequencies_hz", frequencies),
+        ("tuning", json_string(&config.tuning.describe())),
+        ("sample_rate", config.sample_rate.to_string()),
+        ("channels", config.channels.to_string()),
+        (
+            "channel_layout",
+            json_string(channel_layout_name(config.channels)),
+        ),
+        ("sample_format", json_string(sample_format)),
+        ("bits", bits.to_string()),
+        (
+            "full_scale",
+            match values.codes {
+                Some(_) => json_number(config.full_scale.scale(code_bits(config))),
+                None => "1".to_string(),
+            },
+        ),
+        ("duration_ms", json_number(config.duration_ms)),
+        (
+            "frames",
+            values.floats.first().map_or(0, Vec::len).to_string(),
+        ),
+        ("level_dbfs", json_number(linear_to_db(output_gain(config)))),
+        ("dither", json_string(config.dither.to_str())),
+        ("noise_shaping", json_string(config.noise_shaping.to_str())),
+        ("seed", config.seed.to_string()),
+        ("peak_dbfs", json_number(linear_to_db(stats.peak))),
+        ("clipped_samples", stats.clipped_samples.to_string()),
+    ];
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut write = || -> io::Result<()> {
+        writeln!(out, "{{")?;
+        for (name, value) in &members {
+            writeln!(out, "  {}: {},", json_string(name), value)?;
+        }
+        writeln!(out, "  \"samples\": [")?;
+        for ch in 0..values.floats.len() {
+            let list: Vec<String> = match &values.codes {
+                Some(codes) => codes[ch].iter().map(|code| code.to_string()).collect(),
+                None => values.floats[ch]
+                    .iter()
+                    .map(|&value| json_number(value))
+                    .collect(),
+            };
+            let separator = if ch + 1 < values.floats.len() {
+                ","
+            } else {
+                ""
+            };
+            writeln!(out, "    [{}]{}", list.join(", "), separator)?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")?;
+        out.flush()
+    };
+    if let Err(e) = write() {
+        eprintln!("Error: Cannot write to stdout: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Decode little-endian signed sample codes back out of an output buffer.
+fn buffer_samples(buffer: &[u8], config: &Config) -> Vec<i64> {
+    let sample_bytes = bytes_per_sample(config);
+    buffer
+        .chunks(sample_bytes)
+        .map(|chunk| {
+            let fold = |value: i64, &byte: &u8| (value << 8) | byte as i64;
+            let value = match config.endian {
+                Endian::Little => chunk.iter().rev().fold(0, fold),
+                Endian::Big => chunk.iter().fold(0, fold),
+            };
+            let shift = 64 - 8 * sample_bytes as u32;
+            (value << shift) >> shift
+        })
+        .collect()
+}
+
+/// Split an output buffer into memory words of `word_bits`, each taking
+/// whole bytes in the buffer's endianness and masked to width. `None` when
+/// the buffer does not divide into whole words.
+fn memory_words(buffer: &[u8], config: &Config, word_bits: u32) -> Option<Vec<u64>> {
+    let word_bytes = word_bits.div_ceil(8) as usize;
+    if !buffer.len().is_multiple_of(word_bytes) {
+        return None;
+    }
+    let mask = u64::MAX >> (64 - word_bits);
+    let words = buffer
+        .chunks(word_bytes)
+        .map(|chunk| {
+            let fold = |value: u64, &byte: &u8| (value << 8) | byte as u64;
+            let value = match config.endian {
+                Endian::Little => chunk.iter().rev().fold(0, fold),
+                Endian::Big => chunk.iter().fold(0, fold),
+            };
+            value & mask
+        })
+        .collect();
+    Some(words)
+}
+
+/// Decode IEEE float samples back out of an output buffer.
+fn buffer_floats(buffer: &[u8], config: &Config) -> Vec<f32> {
+    buffer
+        .chunks(4)
+        .map(|chunk| {
+            let bytes = [chunk[0], chunk[1], chunk[2], chunk[3]];
+            match config.endian {
+                Endian::Little => f32::from_le_bytes(bytes),
+                Endian::Big => f32::from_be_bytes(bytes),
+            }
+        })
+        .collect()
+}
+
+/// Format integer sample values as array literals. C hex values are written
+/// as `bits`-wide two's complement; Rust ones keep their sign, since an
+/// unsigned literal out of range for the element type does not compile.
+fn format_int_values(values: &[i64], bits: u32, radix: Radix, signed_hex: bool) -> Vec<String> {
+    let mask = (1i64 << bits) - 1;
+    let width = bits as usize / 4;
+    values
+        .iter()
+        .map(|&value| match radix {
+            Radix::Decimal => value.to_string(),
+            Radix::Hex if signed_hex && value < 0 => format!("-0x{:0width$X}", -value),
+            Radix::Hex => format!("0x{:0width$X}", value & mask),
+        })
+        .collect()
+}
+
+/// Print literal values as the body of an array initializer, eight per line.
+fn print_sample_values(values: &[String]) {
+    for (i, chunk) in values.chunks(8).enumerate() {
+        print!("    ");
+        for (j, value) in chunk.iter().enumerate() {
+            print!("{}", value);
+            if i * 8 + j < values.len() - 1 {
+                print!(", ");
+            }
+        }
+        println!();
+    }
+}
+
+/// Largest single object avr-gcc accepts (`PTRDIFF_MAX` on AVR).
+const AVR_MAX_OBJECT_BYTES: usize = 32767;
+/// Flash left for sketches on an ATmega328P (Uno, Nano) after the bootloader.
+const UNO_SKETCH_FLASH_BYTES: usize = 32256;
+
+/// Bits per sample as firmware sees them: the integer code width, the float
+/// width, or 8 for A-law and μ-law.
+fn sample_bits(config: &Config) -> u32 {
+    match config.sample_format {
+        SampleFormat::Pcm => code_bits(config),
+        SampleFormat::Float => config.sample_width as u32 * 8,
+        SampleFormat::ALaw | SampleFormat::MuLaw => 8,
+    }
+}
+
+/// Frames in an output buffer of `config.channels` channels.
+fn buffer_frames(buffer: &[u8], config: &Config) -> usize {
+    buffer.len() / (bytes_per_sample(config) * config.channels as usize)
+}
+
+/// C descriptor type shared by every table, guarded so several generated
+/// headers can be included together.
+const C_DESCRIPTOR_TYPE: &str = "#ifndef SINGEN_TABLE_T_DEFINED
+#define SINGEN_TABLE_T_DEFINED
+typedef struct {
+    uint32_t sample_rate;
+    uint16_t channels;
+    uint16_t bits;
+    uint32_t frames;
+    const void *data;          /* the array, or its chunk pointer table */
+    uint32_t len;              /* elements in all chunks */
+    uint32_t chunk_count;      /* 0 when data is a single array */
+    const uint32_t *chunk_lens;
+} singen_table_t;
+#endif
+";
+
+/// Initializer rows of `per_line` values.
+fn array_rows(values: &[String], per_line: usize) -> String {
+    let rows: Vec<String> = values
+        .chunks(per_line)
+        .map(|chunk| format!("    {}", chunk.join(", ")))
+        .collect();
+    let mut text = rows.join(", \n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+/// `--name` with the split-channel suffix in matching case, or the
+/// generated name.
+fn array_name(config: &Config, generated: String, name_suffix: &str) -> String {
+    match &config.symbol_name {
+        Some(name) if name.chars().any(|c| c.is_ascii_lowercase()) => {
+            format!("{}{}", name, name_suffix)
+        }
+        Some(name) => format!("{}{}", name, name_suffix.to_uppercase()),
+        None => generated,
+    }
+}
+
+/// Warn about PROGMEM tables too large for one AVR array or an Uno's flash.
+/// With `--split-bytes`, each array holds at most that many bytes.
+fn warn_progmem_size(bytes: usize, split_bytes: Option<usize>, label: &str) {
+    if bytes.min(split_bytes.unwrap_or(bytes)) > AVR_MAX_OBJECT_BYTES {
+        eprintln!(
+            "Warning: {} bytes{} exceed the {}-byte limit for one AVR array; use --split-bytes or shorten the signal",
+            bytes, label, AVR_MAX_OBJECT_BYTES
+        );
+    } else if bytes > UNO_SKETCH_FLASH_BYTES {
+        eprintln!(
+            "Warning: {} bytes{} do not fit the {} bytes of sketch flash on an Uno/Nano",
+            bytes, label, UNO_SKETCH_FLASH_BYTES
+        );
+    }
+}
+
+/// C array declaration, kept in parts so it can be printed whole or split
+/// into a header and source file.
+struct CArray {
+    name: String,
+    c_type: &'static str,
+    values: Vec<String>,
+    per_line: usize,
+    /// Description comment lines, each ending in a newline
+    comments: String,
+    progmem: bool,
+    q_format: Option<QFormat>,
+    /// `const`, or `static`/`constexpr` qualifiers from the command line
+    storage: &'static str,
+    namespace: Option<String>,
+    align: Option<usize>,
+    section: Option<String>,
+    attribute_style: AttributeStyle,
+    /// Elements per array with `--split-bytes`
+    chunk_len: Option<usize>,
+    /// Sample rate, channels, bits and frames for `--descriptor`
+    descriptor: Option<(u32, u8, u32, usize)>,
+}
+
+impl CArray {
+    /// With `progmem`, the array is placed in AVR flash and the comments note
+    /// how to read it back.
+    fn new(buffer: &[u8], config: &Config, name_suffix: &str, progmem: bool) -> Self {
+        let name = array_name(
+            config,
+            format!(
+                "{}_{}hz_{}ms_{}_{}ch{}",
+                config.waveform.to_str(),
+                config.sample_rate,
+                config.duration_ms as u32,
+                depth_token(config),
+                config.channels,
+                name_suffix
+            )
+            .to_uppercase(),
+            name_suffix,
+        );
+
+        let mut comments = format!(
+            "// {}, {} ms, {}, {} channel{}\n",
+            describe_signal(config),
+            config.duration_ms,
+            depth_label(config),
+            config.channels,
+            if config.channels > 1 { "s" } else { "" }
+        );
+        comments.push_str(&format!("// Sample rate: {} Hz\n", config.sample_rate));
+        comments.push_str(&layout_comment(buffer, config, "//"));
+        comments.push_str(&format!("// Total bytes: {}\n", buffer.len()));
+        if progmem {
+            comments.push_str(&format!(
+                "// Flash: {} of {} bytes free for sketches on an Uno/Nano (ATmega328P)\n",
+                buffer.len(),
+                UNO_SKETCH_FLASH_BYTES
+            ));
+            // The word and dword macros read back unsigned
+            let cast = match config.element_type {
+                ElementType::Int16 | ElementType::Int32 => {
+                    format!("({})", config.element_type.c_type())
+                }
+                _ => String::new(),
+            };
+            comments.push_str(&format!(
+                "// Read elements with {}{}(&{}[i])\n",
+                cast,
+                config.element_type.pgm_read(),
+                name
+            ));
+        }
+
+        let (c_type, values, per_line) = if let Some(q_format) = config.q_format {
+            comments.push_str(&q_format.saturation_comment("//"));
+            comments.push('\n');
+            let bits = q_format.sample_width() as u32 * 8;
+            let values = buffer_samples(buffer, config);
+            (
+                q_format.c_type(),
+                format_int_values(&values, bits, config.radix, false),
+                8,
+            )
+        } else {
+            match config.element_type {
+                ElementType::UInt8 => (
+                    "uint8_t",
+                    buffer
+                        .iter()
+                        .map(|byte| format!("0x{:02X}", byte))
+                        .collect(),
+                    16,
+                ),
+                // {:?} prints the shortest form that reads back to the same f32.
+                ElementType::Float32 => (
+                    "float",
+                    buffer_floats(buffer, config)
+                        .iter()
+                        .map(|value| format!("{:?}f", value))
+                        .collect(),
+                    8,
+                ),
+                element_type => (
+                    element_type.c_type(),
+                    format_int_values(
+                        &buffer_samples(buffer, config),
+                        element_type.bits(),
+                        config.radix,
+                        false,
+                    ),
+                    8,
+                ),
+            }
+        };
+        let element_bytes = match config.q_format {
+            Some(q_format) => q_format.sample_width() as usize,
+            None => config.element_type.bits() as usize / 8,
+        };
+        let chunk_len = config
+            .split_bytes
+            .map(|bytes| bytes / element_bytes)
+            .filter(|&chunk_len| chunk_len < values.len());
+        let mut array = CArray {
+            name,
+            c_type,
+            values,
+            per_line,
+            comments,
+            progmem,
+            q_format: config.q_format,
+            storage: match (config.static_storage, config.constexpr) {
+                (false, false) => "const",
+                (true, false) => "static const",
+                (false, true) => "constexpr",
+                (true, true) => "static constexpr",
+            },
+            namespace: config.namespace.clone(),
+            align: config.align,
+            section: config.section.clone(),
+            attribute_style: config.attribute_style,
+            chunk_len,
+            descriptor: config.descriptor.then(|| {
+                (
+                    config.sample_rate,
+                    config.channels,
+                    sample_bits(config),
+                    buffer_frames(buffer, config),
+                )
+            }),
+        };
+        if let Some(chunk_len) = chunk_len {
+            let comment = format!(
+                "// Split into {} arrays of up to {} bytes, indexed by {}\n",
+                array.values.len().div_ceil(chunk_len),
+                chunk_len * element_bytes,
+                array.table_name("_CHUNKS")
+            );
+            array.comments.push_str(&comment);
+        }
+        array
+    }
+
+    /// `const type name[len]`, with the `PROGMEM` attribute for AVR flash.
+    /// Alignment and section attributes go on the definition only.
+    fn declaration(&self, name: &str, len: &str, definition: bool) -> String {
+        let mut prefix = String::new();
+        let mut suffix = String::new();
+        if self.progmem {
+            suffix.push_str(" PROGMEM");
+        }
+        if definition {
+            match self.attribute_style {
+                AttributeStyle::Gcc => {
+                    let mut attributes = Vec::new();
+                    if let Some(align) = self.align {
+                        attributes.push(format!("aligned({})", align));
+                    }
+                    if let Some(section) = &self.section {
+                        attributes.push(format!("section(\"{}\")", section));
+                    }
+                    if !attributes.is_empty() {
+                        suffix.push_str(&format!(" __attribute__(({}))", attributes.join(", ")));
+                    }
+                }
+                AttributeStyle::Msvc => {
+                    if let Some(align) = self.align {
+                        prefix.push_str(&format!("__declspec(align({})) ", align));
+                    }
+                    if let Some(section) = &self.section {
+                        prefix.push_str(&format!("__declspec(allocate(\"{}\")) ", section));
+                    }
+                }
+            }
+        }
+        format!(
+            "{}{} {} {}[{}]{}",
+            prefix, self.storage, self.c_type, name, len, suffix
+        )
+    }
+
+    /// The array, or with `--split-bytes` the `NAME_0`, `NAME_1`, ...
+    /// arrays, as names and values.
+    fn chunks(&self) -> Vec<(String, &[String])> {
+        match self.chunk_len {
+            Some(chunk_len) => self
+                .values
+                .chunks(chunk_len)
+                .enumerate()
+                .map(|(i, chunk)| (format!("{}_{}", self.name, i), chunk))
+                .collect(),
+            None => vec![(self.name.clone(), &self.values[..])],
+        }
+    }
+
+    /// Array definitions, followed by the chunk pointer and length tables
+    /// when split. `len` sizes an unsplit array.
+    fn definitions(&self, len: &str) -> String {
+        let mut text = String::new();
+        for (name, values) in self.chunks() {
+            let len = match self.chunk_len {
+                Some(_) => values.len().to_string(),
+                None => len.to_string(),
+            };
+            text.push_str(&format!("{} = {{\n", self.declaration(&name, &len, true)));
+            text.push_str(&array_rows(values, self.per_line));
+            text.push_str("};\n");
+        }
+        if self.chunk_len.is_some() {
+            let chunks = self.chunks();
+            let names: Vec<String> = chunks.iter().map(|(name, _)| name.clone()).collect();
+            let lens: Vec<String> = chunks
+                .iter()
+                .map(|(_, values)| values.len().to_string())
+                .collect();
+            text.push_str(&format!("{} = {{\n", self.chunk_table(true)));
+            text.push_str(&array_rows(&names, 4));
+            text.push_str("};\n");
+            text.push_str(&format!("{} = {{\n", self.chunk_table(false)));
+            text.push_str(&array_rows(&lens, 8));
+            text.push_str("};\n");
+        }
+        text.push_str(&self.descriptor(true));
+        text
+    }
+
+    /// `NAME_DESC` descriptor instance; `definition` adds its initializer.
+    fn descriptor(&self, definition: bool) -> String {
+        let Some((sample_rate, channels, bits, frames)) = self.descriptor else {
+            return String::new();
+        };
+        let declaration = format!(
+            "{} singen_table_t {}",
+            self.storage,
+            self.table_name("_DESC")
+        );
+        if !definition {
+            return format!("extern {};\n", declaration);
+        }
+        let (data, chunk_count, chunk_lens) = match self.chunk_len {
+            Some(chunk_len) => (
+                self.table_name("_CHUNKS"),
+                self.values.len().div_ceil(chunk_len),
+                self.table_name("_CHUNK_LENS"),
+            ),
+            None => (self.name.clone(), 0, "0".to_string()),
+        };
+        format!(
+            "{} = {{\n    {}, {}, {}, {}, {}, {}, {}, {}\n}};\n",
+            declaration,
+            sample_rate,
+            channels,
+            bits,
+            frames,
+            data,
+            self.values.len(),
+            chunk_count,
+            chunk_lens
+        )
+    }
+
+    /// `extern` declarations matching `definitions`.
+    fn externs(&self, len: &str) -> String {
+        let mut text = String::new();
+        for (name, values) in self.chunks() {
+            let len = match self.chunk_len {
+                Some(_) => values.len().to_string(),
+                None => len.to_string(),
+            };
+            text.push_str(&format!(
+                "extern {};\n",
+                self.declaration(&name, &len, false)
+            ));
+        }
+        if self.chunk_len.is_some() {
+            text.push_str(&format!("extern {};\n", self.chunk_table(true)));
+            text.push_str(&format!("extern {};\n", self.chunk_table(false)));
+        }
+        text.push_str(&self.descriptor(false));
+        text
+    }
+
+    /// Declarator of the `NAME_CHUNKS` pointer table or the
+    /// `NAME_CHUNK_LENS` element counts.
+    fn chunk_table(&self, pointers: bool) -> String {
+        let count = self.values.len().div_ceil(self.chunk_len.unwrap_or(1));
+        if pointers {
+            // constexpr makes the table const, not what it points to
+            let storage = if self.storage.ends_with("constexpr") {
+                format!("{} const", self.storage)
+            } else {
+                self.storage.to_string()
+            };
+            format!(
+                "{} {} *const {}[{}]",
+                storage,
+                self.c_type,
+                self.table_name("_CHUNKS"),
+                count
+            )
+        } else {
+            format!(
+                "{} uint32_t {}[{}]",
+                self.storage,
+                self.table_name("_CHUNK_LENS"),
+                count
+            )
+        }
+    }
+
+    /// Name of an index table, in the case of a lowercase `--name`.
+    fn table_name(&self, suffix: &str) -> String {
+        if self.name.chars().any(|c| c.is_ascii_lowercase()) {
+            format!("{}{}", self.name, suffix.to_lowercase())
+        } else {
+            format!("{}{}", self.name, suffix)
+        }
+    }
+
+    /// MSVC declares a section with a pragma before `allocate` can use it.
+    fn section_pragma(&self) -> String {
+        match (&self.section, self.attribute_style) {
+            (Some(section), AttributeStyle::Msvc) => {
+                format!("#pragma section(\"{}\", read)\n", section)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn namespace_open(&self) -> String {
+        self.namespace.as_ref().map_or(String::new(), |namespace| {
+            format!("namespace {} {{\n", namespace)
+        })
+    }
+
+    fn namespace_close(&self) -> String {
+        self.namespace.as_ref().map_or(String::new(), |namespace| {
+            format!("}}  // namespace {}\n", namespace)
+        })
+    }
+
+    /// Header with include guards, a `NAME_LEN` macro (and `NAME_CHUNK_COUNT`
+    /// when split) and `extern` declarations, and the source defining the
+    /// arrays.
+    fn file_pair(&self, header_name: &str) -> (String, String) {
+        let guard: String = header_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let len = format!("{}_LEN", self.name.to_uppercase());
+
+        let mut header = self.comments.clone();
+        header.push_str(&format!("#ifndef {0}\n#define {0}\n\n", guard));
+        header.push_str("#include <stdint.h>\n");
+        if self.progmem {
+            header.push_str("#include <avr/pgmspace.h>\n");
+        }
+        if self.q_format.is_some() {
+            header.push_str("#include \"arm_math.h\"\n");
+        }
+        header.push_str(&format!("\n#define {} {}\n", len, self.values.len()));
+        if let Some(chunk_len) = self.chunk_len {
+            header.push_str(&format!(
+                "#define {}_CHUNK_COUNT {}\n",
+                self.name.to_uppercase(),
+                self.values.len().div_ceil(chunk_len)
+            ));
+        }
+        header.push('\n');
+        if self.descriptor.is_some() {
+            header.push_str(C_DESCRIPTOR_TYPE);
+            header.push('\n');
+        }
+        header.push_str(&self.namespace_open());
+        header.push_str(&self.externs(&len));
+        header.push_str(&self.namespace_close());
+        header.push_str(&format!("\n#endif /* {} */\n", guard));
+
+        let mut source = format!("#include \"{}\"\n\n", header_name);
+        source.push_str(&self.section_pragma());
+        source.push_str(&self.namespace_open());
+        source.push_str(&self.definitions(&len));
+        source.push_str(&self.namespace_close());
+        (header, source)
+    }
+}
+
+fn print_c_array(buffer: &[u8], config: &Config, name_suffix: &str, progmem: bool) {
+    let array = CArray::new(buffer, config, name_suffix, progmem);
+    print!("{}", array.comments);
+    if progmem {
+        println!("#include <avr/pgmspace.h>");
+    }
+    if let Some(q_format) = array.q_format {
+        println!("// Requires arm_math.h for {}", q_format.c_type());
+    }
+    print!("{}", array.section_pragma());
+    if array.descriptor.is_some() {
+        print!("{}", C_DESCRIPTOR_TYPE);
+    }
+    print!("{}", array.namespace_open());
+    print!("{}", array.definitions(&array.values.len().to_string()));
+    print!("{}", array.namespace_close());
+}
+
+/// Write a C array as `path` with `.h` and `.c` extensions, the source
+/// including the header.
+fn write_c_files(buffer: &[u8], config: &Config, name_suffix: &str, progmem: bool, path: &str) {
+    let array = CArray::new(buffer, config, name_suffix, progmem);
+    let path = std::path::Path::new(path);
+    let header_path = path.with_extension("h");
+    let header_name = header_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let (header, source) = array.file_pair(&header_name);
+    write_output_file(&header_path.to_string_lossy(), header.as_bytes());
+    write_output_file(
+        &path.with_extension("c").to_string_lossy(),
+        source.as_bytes(),
+    );
+}
+
+fn print_rust_array(
+    buffer: &[u8],
+    config: &Config,
+    name_suffix: &str,
+    first_part: bool,
+    last_part: bool,
+) {
+    let name = array_name(
+        config,
+        format!(
+            "{}_{}HZ_{}MS_{}_{}CH{}",
+            config.waveform.to_str().to_uppercase(),
+            config.sample_rate,
+            config.duration_ms as u32,
+            depth_token(config).to_uppercase(),
+            config.channels,
+            name_suffix.to_uppercase()
+        ),
+        name_suffix,
+    );
+
+    println!(
+        "// {}, {} ms, {}, {} channel{}",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    println!("// Sample rate: {} Hz", config.sample_rate);
+    print_layout_comment(buffer, config, "//");
+    println!("// Total bytes: {}", buffer.len());
+    let (rust_type, values, per_line) = if let Some(q_format) = config.q_format {
+        println!("{}", q_format.saturation_comment("//"));
+        let bits = q_format.sample_width() as u32 * 8;
+        let values = buffer_samples(buffer, config);
+        (
+            q_format.rust_type(),
+            format_int_values(&values, bits, config.radix, true),
+            8,
+        )
+    } else {
+        match config.element_type {
+            ElementType::UInt8 => (
+                "u8",
+                buffer
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect(),
+                16,
+            ),
+            ElementType::Float32 => (
+                "f32",
+                buffer_floats(buffer, config)
+                    .iter()
+                    .map(|value| format!("{:?}", value))
+                    .collect(),
+                8,
+            ),
+            element_type => (
+                element_type.rust_type(),
+                format_int_values(
+                    &buffer_samples(buffer, config),
+                    element_type.bits(),
+                    config.radix,
+                    true,
+                ),
+                8,
+            ),
+        }
+    };
+
+    let modules: Vec<&str> = config
+        .namespace
+        .as_deref()
+        .map_or(Vec::new(), |path| path.split("::").collect());
+    // The descriptor type is shared by every part, so it is declared once,
+    // outside the namespace modules.
+    if config.descriptor && first_part {
+        println!("#[derive(Clone, Copy, Debug)]");
+        println!("pub struct SingenTable<T: 'static> {{");
+        println!("    pub sample_rate: u32,");
+        println!("    pub channels: u16,");
+        println!("    pub bits: u16,");
+        println!("    pub frames: u32,");
+        println!("    pub data: &'static [T],");
+        println!("}}");
+    }
+    // Split parts share one set of modules; Rust cannot reopen a module.
+    if first_part {
+        for module in &modules {
+            println!("pub mod {} {{", module);
+        }
+    }
+    let item = if config.static_storage {
+        "static"
+    } else {
+        "const"
+    };
+    println!(
+        "pub {} {}: [{}; {}] = [",
+        item,
+        name,
+        rust_type,
+        values.len()
+    );
+    print!("{}", array_rows(&values, per_line));
+    println!("];");
+    if config.descriptor {
+        let table_type = format!("{}SingenTable", "super::".repeat(modules.len()));
+        let desc = if name.chars().any(|c| c.is_ascii_lowercase()) {
+            "_desc"
+        } else {
+            "_DESC"
+        };
+        println!(
+            "pub {} {}{}: {}<{}> = {} {{ sample_rate: {}, channels: {}, bits: {}, frames: {}, data: &{} }};",
+            item,
+            name,
+            desc,
+            table_type,
+            rust_type,
+            table_type,
+            config.sample_rate,
+            config.channels,
+            sample_bits(config),
+            buffer_frames(buffer, config),
+            name
+        );
+    }
+    if last_part {
+        for _ in &modules {
+            println!("}}");
+        }
+    }
+}
+
+/// Python counterpart of the C and Rust arrays: a `bytes` literal of the
+/// buffer, or a list of sample values with `--element-type` or `--q-format`.
+fn print_python_array(buffer: &[u8], config: &Config, name_suffix: &str) {
+    let name = format!(
+        "{}_{}HZ_{}MS_{}_{}CH{}",
+        config.waveform.to_str().to_uppercase(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config).to_uppercase(),
+        config.channels,
+        name_suffix.to_uppercase()
+    );
+
+    println!(
+        "# {}, {} ms, {}, {} channel{}",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    println!("# Sample rate: {} Hz", config.sample_rate);
+    print_layout_comment(buffer, config, "#");
+    println!("# Total bytes: {}", buffer.len());
+    if let Some(q_format) = config.q_format {
+        let values = buffer_samples(buffer, config);
+        println!("{}", q_format.saturation_comment("#"));
+        println!("{} = [", name);
+        let bits = q_format.sample_width() as u32 * 8;
+        print_sample_values(&format_int_values(&values, bits, config.radix, true));
+        println!("]");
+        return;
+    }
+    if config.element_type != ElementType::UInt8 {
+        let values: Vec<String> = match config.element_type {
+            ElementType::Float32 => buffer_floats(buffer, config)
+                .iter()
+                .map(|value| format!("{:?}", value))
+                .collect(),
+            element_type => format_int_values(
+                &buffer_samples(buffer, config),
+                element_type.bits(),
+                config.radix,
+                true,
+            ),
+        };
+        println!(
+            "# {} values",
+            config.element_type.c_type().trim_end_matches("_t")
+        );
+        println!("{} = [", name);
+        print_sample_values(&values);
+        println!("]");
+        return;
+    }
+    println!("{} = (", name);
+    for chunk in buffer.chunks(16) {
+        let escaped: String = chunk
+            .iter()
+            .map(|byte| format!("\\x{:02x}", byte))
+            .collect();
+        println!("    b\"{}\"", escaped);
+    }
+    if buffer.is_empty() {
+        println!("    b\"\"");
+    }
+    println!(")");
+}
+
+/// ES module exporting the buffer as a typed array for Web Audio tests:
+/// `Uint8Array` bytes by default, or one element per sample with
+/// `--element-type` or `--q-format`. TypeScript output adds type
+/// annotations.
+fn print_js_array(buffer: &[u8], config: &Config, name_suffix: &str, typescript: bool) {
+    let name = format!(
+        "{}_{}HZ_{}MS_{}_{}CH{}",
+        config.waveform.to_str().to_uppercase(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config).to_uppercase(),
+        config.channels,
+        name_suffix.to_uppercase()
+    );
+    let annotation = |js_type: &str| {
+        if typescript {
+            format!(": {}", js_type)
+        } else {
+            String::new()
+        }
+    };
+
+    println!(
+        "// {}, {} ms, {}, {} channel{}",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    print_layout_comment(buffer, config, "//");
+    println!("// Total bytes: {}", buffer.len());
+    println!(
+        "export const {}_SAMPLE_RATE{} = {};",
+        name,
+        annotation("number"),
+        config.sample_rate
+    );
+    let (js_type, values) = if let Some(q_format) = config.q_format {
+        println!("{}", q_format.saturation_comment("//"));
+        let element_type = match q_format.sample_width() {
+            SampleWidth::Width2Byte => ElementType::Int16,
+            _ => ElementType::Int32,
+        };
+        let bits = q_format.sample_width() as u32 * 8;
+        let values = buffer_samples(buffer, config);
+        (
+            element_type.js_type(),
+            format_int_values(&values, bits, config.radix, true),
+        )
+    } else {
+        let values = match config.element_type {
+            ElementType::UInt8 => buffer
+                .iter()
+                .map(|byte| format!("0x{:02X}", byte))
+                .collect(),
+            ElementType::Float32 => buffer_floats(buffer, config)
+                .iter()
+                .map(|value| format!("{:?}", value))
+                .collect(),
+            element_type => format_int_values(
+                &buffer_samples(buffer, config),
+                element_type.bits(),
+                config.radix,
+                true,
+            ),
+        };
+        (config.element_type.js_type(), values)
+    };
+    println!(
+        "export const {}{} = new {}([",
+        name,
+        annotation(js_type),
+        js_type
+    );
+    print_sample_values(&values);
+    println!("]);");
+}
+
+/// Go identifier in lowerCamelCase built from `_`/`-` separated words.
+fn go_identifier(words: &str) -> String {
+    let mut name = String::new();
+    for word in words.split(['_', '-']).filter(|word| !word.is_empty()) {
+        let mut chars = word.chars();
+        if name.is_empty() {
+            name.extend(chars.map(|c| c.to_ascii_lowercase()));
+        } else if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    name
+}
+
+/// Go `[]byte` declaration, or a typed slice with `--element-type` or
+/// `--q-format`, laid out the way gofmt would print it.
+fn print_go_array(buffer: &[u8], config: &Config, name_suffix: &str) {
+    let name = go_identifier(&format!(
+        "{}_{}Hz_{}ms_{}_{}ch{}",
+        config.waveform.to_str(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config),
+        config.channels,
+        name_suffix
+    ));
+
+    println!(
+        "// {}, {} ms, {}, {} channel{}",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    print_layout_comment(buffer, config, "//");
+    println!("// Total bytes: {}", buffer.len());
+    println!("const {}SampleRate = {}", name, config.sample_rate);
+    println!();
+    let (go_type, values, per_line) = if let Some(q_format) = config.q_format {
+        println!("{}", q_format.saturation_comment("//"));
+        let element_type = match q_format.sample_width() {
+            SampleWidth::Width2Byte => ElementType::Int16,
+            _ => ElementType::Int32,
+        };
+        let bits = q_format.sample_width() as u32 * 8;
+        let values = buffer_samples(buffer, config);
+        (
+            element_type.go_type(),
+            format_int_values(&values, bits, config.radix, true),
+            8,
+        )
+    } else {
+        match config.element_type {
+            ElementType::UInt8 => (
+                "byte",
+                buffer
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect(),
+                16,
+            ),
+            ElementType::Float32 => (
+                "float32",
+                buffer_floats(buffer, config)
+                    .iter()
+                    .map(|value| format!("{:?}", value))
+                    .collect(),
+                8,
+            ),
+            element_type => (
+                element_type.go_type(),
+                format_int_values(
+                    &buffer_samples(buffer, config),
+                    element_type.bits(),
+                    config.radix,
+                    true,
+                ),
+                8,
+            ),
+        }
+    };
+    println!("var {} = []{}{{", name, go_type);
+    for chunk in values.chunks(per_line) {
+        println!("\t{},", chunk.join(", "));
+    }
+    println!("}}");
+}
+
+/// Intel HEX image of `bytes` loaded at `base`: data records of up to
+/// `record_length` bytes, an extended linear address record at every 64 KB
+/// boundary, and the end of file record. `None` when the image does not fit
+/// below 4 GB.
+fn intel_hex(bytes: &[u8], base: u32, record_length: usize) -> Option<String> {
+    let record = |text: &mut String, kind: u8, address: u16, data: &[u8]| {
+        let mut line = vec![data.len() as u8];
+        line.extend_from_slice(&address.to_be_bytes());
+        line.push(kind);
+        line.extend_from_slice(data);
+        let checksum = line
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+            .wrapping_neg();
+        line.push(checksum);
+        text.push(':');
+        for byte in line {
+            text.push_str(&format!("{:02X}", byte));
+        }
+        text.push('\n');
+    };
+
+    if base as u64 + bytes.len() as u64 > 1 << 32 {
+        return None;
+    }
+    let mut text = String::new();
+    let mut offset = 0;
+    let mut segment = None;
+    while offset < bytes.len() {
+        let address = base + offset as u32;
+        if segment != Some(address >> 16) {
+            segment = Some(address >> 16);
+            record(&mut text, 0x04, 0, &((address >> 16) as u16).to_be_bytes());
+        }
+        // Records stop at 64 KB boundaries so their 16-bit address cannot wrap
+        let len = record_length
+            .min(bytes.len() - offset)
+            .min(0x10000 - (address & 0xFFFF) as usize);
+        record(
+            &mut text,
+            0x00,
+            address as u16,
+            &bytes[offset..offset + len],
+        );
+        offset += len;
+    }
+    record(&mut text, 0x01, 0, &[]);
+    Some(text)
+}
+
+/// Motorola S-record image of `bytes` loaded at `base`: an S0 header, S1/S2/S3
+/// data records of up to `record_length` bytes with `address_bytes` (2, 3 or
+/// 4; the smallest that fits when `None`), an S5/S6 record count and the
+/// matching S9/S8/S7 termination. `None` when the image does not fit the
+/// address size.
+fn s_records(
+    bytes: &[u8],
+    base: u32,
+    address_bytes: Option<usize>,
+    record_length: usize,
+) -> Option<String> {
+    let record = |text: &mut String, kind: u8, address_bytes: usize, address: u32, data: &[u8]| {
+        let mut line = vec![(address_bytes + data.len() + 1) as u8];
+        line.extend_from_slice(&address.to_be_bytes()[4 - address_bytes..]);
+        line.extend_from_slice(data);
+        let checksum = !line.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        line.push(checksum);
+        text.push('S');
+        text.push((b'0' + kind) as char);
+        for byte in line {
+            text.push_str(&format!("{:02X}", byte));
+        }
+        text.push('\n');
+    };
+
+    let end = base as u64 + bytes.len() as u64;
+    let address_bytes = match address_bytes {
+        Some(address_bytes) => address_bytes,
+        None if end <= 1 << 16 => 2,
+        None if end <= 1 << 24 => 3,
+        None => 4,
+    };
+    if end > 1 << (8 * address_bytes) {
+        return None;
+    }
+    let mut text = String::new();
+    record(&mut text, 0, 2, 0, b"singen");
+    let chunks = bytes.chunks(record_length);
+    let count = chunks.len();
+    for (i, chunk) in chunks.enumerate() {
+        let address = base + (i * record_length) as u32;
+        record(
+            &mut text,
+            address_bytes as u8 - 1,
+            address_bytes,
+            address,
+            chunk,
+        );
+    }
+    // Record counts past 24 bits are left out; the count record is optional
+    if count < 1 << 16 {
+        record(&mut text, 5, 2, count as u32, &[]);
+    } else if count < 1 << 24 {
+        record(&mut text, 6, 3, count as u32, &[]);
+    }
+    record(&mut text, 11 - address_bytes as u8, address_bytes, 0, &[]);
+    Some(text)
+}
+
+/// `$readmemh` memory file: a comment line, then one zero-padded hex word
+/// per line from address 0.
+fn readmemh(words: &[u64], word_bits: u32, config: &Config) -> String {
+    let mut text = format!(
+        "// {}, {} words of {} bits\n",
+        describe_signal(config),
+        words.len(),
+        word_bits
+    );
+    let digits = word_bits.div_ceil(4) as usize;
+    for word in words {
+        text.push_str(&format!("{:0digits$X}\n", word));
+    }
+    text
+}
+
+/// Xilinx `.coe` file: hex radix and one comma-separated word per line,
+/// ending in a semicolon.
+fn coe_file(words: &[u64], word_bits: u32, config: &Config) -> String {
+    let mut text = format!(
+        "; {}, {} words of {} bits\n",
+        describe_signal(config),
+        words.len(),
+        word_bits
+    );
+    text.push_str("memory_initialization_radix=16;\n");
+    text.push_str("memory_initialization_vector=\n");
+    let digits = word_bits.div_ceil(4) as usize;
+    for (i, word) in words.iter().enumerate() {
+        let end = if i + 1 == words.len() { ';' } else { ',' };
+        text.push_str(&format!("{:0digits$X}{}\n", word, end));
+    }
+    text
+}
+
+/// Quartus `.mif` file: DEPTH, WIDTH and hex radix headers, then one
+/// `address : word;` line per word.
+fn mif_file(words: &[u64], word_bits: u32, config: &Config) -> String {
+    let mut text = format!("-- {}\n", describe_signal(config));
+    text.push_str(&format!("DEPTH = {};\n", words.len()));
+    text.push_str(&format!("WIDTH = {};\n", word_bits));
+    text.push_str("ADDRESS_RADIX = HEX;\nDATA_RADIX = HEX;\n\nCONTENT\nBEGIN\n");
+    let address_digits = format!("{:X}", words.len().saturating_sub(1)).len();
+    let digits = word_bits.div_ceil(4) as usize;
+    for (address, word) in words.iter().enumerate() {
+        text.push_str(&format!(
+            "    {:0address_digits$X} : {:0digits$X};\n",
+            address, word
+        ));
+    }
+    text.push_str("END;\n");
+    text
+}
+
+/// VHDL package declaring a constant ROM of `std_logic_vector` words,
+/// with its depth and width as constants. Words are `x"..."` literals when
+/// the width is a whole number of hex digits, bit strings otherwise.
+fn vhdl_package(words: &[u64], word_bits: u32, config: &Config, name_suffix: &str) -> String {
+    let name = format!(
+        "{}_{}hz_{}ms_{}_{}ch{}",
+        config.waveform.to_str(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config),
+        config.channels,
+        name_suffix
+    )
+    .replace('-', "_");
+    let literal = |word: &u64| {
+        if word_bits.is_multiple_of(4) {
+            format!("x\"{:0digits$X}\"", word, digits = word_bits as usize / 4)
+        } else {
+            format!("\"{:0bits$b}\"", word, bits = word_bits as usize)
+        }
+    };
+
+    let mut text = format!(
+        "-- {}, {} ms, {}, {} channel{}\n",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    text.push_str(&format!("-- {} words of {} bits\n", words.len(), word_bits));
+    text.push_str("library ieee;\nuse ieee.std_logic_1164.all;\n\n");
+    text.push_str(&format!("package {}_pkg is\n", name));
+    text.push_str(&format!(
+        "    constant {}_depth : natural := {};\n",
+        name,
+        words.len()
+    ));
+    text.push_str(&format!(
+        "    constant {}_width : natural := {};\n",
+        name, word_bits
+    ));
+    text.push_str(&format!(
+        "    type {0}_rom_t is array (0 to {0}_depth - 1) of std_logic_vector({0}_width - 1 downto 0);\n",
+        name
+    ));
+    text.push_str(&format!("    constant {0}_rom : {0}_rom_t := (\n", name));
+    if words.len() == 1 {
+        // A one-element aggregate needs a named association
+        text.push_str(&format!("        0 => {}\n", literal(&words[0])));
+    } else {
+        let lines: Vec<String> = words
+            .chunks(8)
+            .map(|chunk| chunk.iter().map(literal).collect::<Vec<_>>().join(", "))
+            .collect();
+        text.push_str(&format!("        {}\n", lines.join(",\n        ")));
+    }
+    text.push_str("    );\n");
+    text.push_str(&format!("end package {}_pkg;\n", name));
+    text
+}
+
+/// GNU assembler source defining the buffer as a global object in its own
+/// `.rodata` section (or `--section`): `.byte` rows by default, or one `.short`/`.long`/
+/// `.float` element per sample with `--element-type` or `--q-format`.
+fn asm_source(buffer: &[u8], config: &Config, name_suffix: &str) -> String {
+    let name = format!(
+        "{}_{}hz_{}ms_{}_{}ch{}",
+        config.waveform.to_str(),
+        config.sample_rate,
+        config.duration_ms as u32,
+        depth_token(config),
+        config.channels,
+        name_suffix
+    );
+    let (element_type, values, per_line) = if let Some(q_format) = config.q_format {
+        let element_type = match q_format.sample_width() {
+            SampleWidth::Width2Byte => ElementType::Int16,
+            _ => ElementType::Int32,
+        };
+        let bits = q_format.sample_width() as u32 * 8;
+        let values = buffer_samples(buffer, config);
+        (
+            element_type,
+            format_int_values(&values, bits, config.radix, false),
+            8,
+        )
+    } else {
+        match config.element_type {
+            ElementType::UInt8 => (
+                ElementType::UInt8,
+                buffer
+                    .iter()
+                    .map(|byte| format!("0x{:02X}", byte))
+                    .collect(),
+                16,
+            ),
+            ElementType::Float32 => (
+                ElementType::Float32,
+                buffer_floats(buffer, config)
+                    .iter()
+                    .map(|value| format!("{:?}", value))
+                    .collect(),
+                8,
+            ),
+            element_type => (
+                element_type,
+                format_int_values(
+                    &buffer_samples(buffer, config),
+                    element_type.bits(),
+                    config.radix,
+                    false,
+                ),
+                8,
+            ),
+        }
+    };
+
+    // /* */ comments work on every target; the line comment character varies
+    let mut text = format!(
+        "/* {}, {} ms, {}, {} channel{} */\n",
+        describe_signal(config),
+        config.duration_ms,
+        depth_label(config),
+        config.channels,
+        if config.channels > 1 { "s" } else { "" }
+    );
+    text.push_str(&format!(
+        "/* Sample rate: {} Hz, total bytes: {} */\n",
+        config.sample_rate,
+        buffer.len()
+    ));
+    if let Some(q_format) = config.q_format {
+        text.push_str(&format!(
+            "/* {} */\n",
+            q_format.saturation_comment("").trim_start()
+        ));
+    }
+    match &config.section {
+        Some(section) => text.push_str(&format!("    .section {},\"a\"\n", section)),
+        None => text.push_str(&format!("    .section .rodata.{},\"a\"\n", name)),
+    }
+    text.push_str(&format!("    .global {}\n", name));
+    text.push_str(&format!("    .type {}, %object\n", name));
+    let align = (element_type.bits() as usize / 8).max(config.align.unwrap_or(1));
+    text.push_str(&format!("    .balign {}\n", align));
+    text.push_str(&format!("{}:\n", name));
+    for chunk in values.chunks(per_line) {
+        text.push_str(&format!(
+            "    {} {}\n",
+            element_type.asm_directive(),
+            chunk.join(", ")
+        ));
+    }
+    text.push_str(&format!("    .size {0}, . - {0}\n", name));
+    text
+}
+
+/// Standard base64 (RFC 4648) with padding and no line breaks.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn print_raw_bytes(buffer: &[u8]) {
+    use std::io::{self, Write};
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(buffer).unwrap();
+}
+
+/// Insert `suffix` before the extension of `path`: `out.wav` + `_ch2` gives
+/// `out_ch2.wav`.
+fn suffixed_path(path: &str, suffix: &str) -> String {
+    let path = std::path::Path::new(path);
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Write an output file through a buffered writer, exiting with an error
+/// message if it cannot be created or written.
+fn write_output_file(path: &str, data: &[u8]) {
+    let result = std::fs::File::create(path).and_then(|file| {
+        let mut writer = std::io::BufWriter::new(file);
+        writer.write_all(data)?;
+        writer.flush()
+    });
+    if let Err(e) = result {
+        eprintln!("Error: Cannot write '{}': {}", path, e);
+        process::exit(1);
+    }
+}
+
+/// NumPy `.npy` (format 1.0) file holding `config.channels` channels of
+/// `values` from `first_channel` on, shaped frames x channels in C order:
+/// float output as `<f4` (`<f8` for 64-bit), integer codes as `<i2` up to
+/// 16 bits and `<i4` above.
+fn create_npy_file_array(values: &ChannelValues, config: &Config, first_channel: usize) -> Vec<u8> {
+    let channels = first_channel..first_channel + config.channels as usize;
+    let frames = values.floats.first().map_or(0, Vec::len);
+    let descr = match (&values.codes, config.sample_width) {
+        (None, SampleWidth::Width8Byte) => "<f8",
+        (None, _) => "<f4",
+        (Some(_), _) if code_bits(config) <= 16 => "<i2",
+        (Some(_), _) => "<i4",
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+        descr, frames, config.channels
+    );
+    // Magic, version and length take 10 bytes; the whole preamble pads to
+    // a multiple of 64 and ends in a newline.
+    let padded = (10 + header.len() + 1).div_ceil(64) * 64;
+    header.push_str(&" ".repeat(padded - 10 - header.len() - 1));
+    header.push('\n');
+
+    let mut file = Vec::with_capacity(padded + frames * channels.len() * 8);
+    file.extend_from_slice(b"\x93NUMPY\x01\x00");
+    file.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    file.extend_from_slice(header.as_bytes());
+    for i in 0..frames {
+        for ch in channels.clone() {
+            match (&values.codes, descr) {
+                (Some(codes), "<i2") => {
+                    file.extend_from_slice(&(codes[ch][i] as i16).to_le_bytes())
+                }
+                (Some(codes), _) => file.extend_from_slice(&codes[ch][i].to_le_bytes()),
+                (None, "<f8") => {
+                    file.extend_from_slice(&(values.floats[ch][i] as f64).to_le_bytes())
+                }
+                (None, _) => file.extend_from_slice(&values.floats[ch][i].to_le_bytes()),
+            }
+        }
+    }
+    file
+}
+
+/// `fact` chunk holding the frame count, required in non-PCM WAV files.
+fn wav_fact_chunk(buffer_len: usize, channels: u16, sample_width: SampleWidth) -> Vec<u8> {
+    let frames = buffer_len / (channels as usize * sample_width as usize);
+    let mut chunk = Vec::with_capacity(12);
+    chunk.extend_from_slice(b"fact");
+    chunk.extend_from_slice(&4u32.to_le_bytes());
+    chunk.extend_from_slice(&(frames as u32).to_le_bytes());
+    chunk
+}
+
+/// WAV header for `buffer_len` bytes of audio, up to and including the data
+/// chunk header; the data itself is streamed after it.
+fn create_wav_header(
+    buffer_len: usize,
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    format: SampleFormat,
+) -> Vec<u8> {
+    if channels > 2 {
+        return create_wav_extensible_header(
+            buffer_len,
+            sample_rate,
+            channels,
+            sample_width,
+            format,
+        );
+    }
+
+    // Non-PCM formats carry an empty fmt extension (cbSize = 0) and a fact chunk.
+    let extra = if format == SampleFormat::Pcm {
+        Vec::new()
+    } else {
+        let mut extra = 0u16.to_le_bytes().to_vec();
+        extra.extend(wav_fact_chunk(buffer_len, channels, sample_width));
+        extra
+    };
+
+    let mut wav_hdr = WavHeader::new();
+    wav_hdr.chunk_size = (36 + extra.len() + buffer_len + buffer_len % 2) as u32; // 4 + (24) + 8 + buffer_len + pad
+    wav_hdr.audio_format = format.wav_format_tag();
+    if format != SampleFormat::Pcm {
+        wav_hdr.subchunk_1_size = 18;
+    }
+    wav_hdr.num_channels = channels;
+    wav_hdr.sample_rate = sample_rate;
+    wav_hdr.byte_rate = sample_rate * channels as u32 * sample_width as u32;
+    wav_hdr.block_align = channels * sample_width as u16; // fixed formula
+    wav_hdr.bits_per_sample = sample_width as u16 * 8;
+    wav_hdr.subchunk_2_size = buffer_len as u32;
+
+    let mut file = wav_hdr.to_bytes();
+    // The fmt chunk ends at byte 36; any extension and fact chunk follow it.
+    file.splice(36..36, extra);
+    file
+}
+
+fn create_wav_extensible_header(
+    buffer_len: usize,
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    format: SampleFormat,
+) -> Vec<u8> {
+    let fact = if format == SampleFormat::Pcm {
+        Vec::new()
+    } else {
+        wav_fact_chunk(buffer_len, channels, sample_width)
+    };
+
+    let mut wav_hdr = WavExtensibleHeader::new();
+    wav_hdr.chunk_size = (60 + fact.len() + buffer_len + buffer_len % 2) as u32;
+    wav_hdr.sub_format[..2].copy_from_slice(&format.wav_format_tag().to_le_bytes());
+    wav_hdr.num_channels = channels;
+    wav_hdr.sample_rate = sample_rate;
+    wav_hdr.byte_rate = sample_rate * channels as u32 * sample_width as u32;
+    wav_hdr.block_align = channels * sample_width as u16;
+    wav_hdr.bits_per_sample = sample_width as u16 * 8;
+    wav_hdr.valid_bits_per_sample = sample_width as u16 * 8;
+    wav_hdr.channel_mask = channel_mask(channels as u8);
+    wav_hdr.subchunk_2_size = buffer_len as u32;
+
+    let mut file = wav_hdr.to_bytes();
+    // The fmt chunk ends at byte 60; the fact chunk goes before the data chunk.
+    file.splice(60..60, fact);
+    file
+}
+
+/// Samples per channel in each FLAC frame, the reference encoder's default.
+#[cfg(feature = "flac")]
+const FLAC_BLOCK_SIZE: usize = 4096;
+/// Highest Rice partition order tried for a residual.
+#[cfg(feature = "flac")]
+const FLAC_MAX_PARTITION_ORDER: u32 = 8;
+/// Largest plain Rice parameter; 15 is the escape code.
+#[cfg(feature = "flac")]
+const FLAC_MAX_RICE_PARAMETER: u32 = 14;
+/// Coefficients of the FLAC fixed predictors, orders 0 to 4.
+#[cfg(feature = "flac")]
+static FLAC_FIXED_COEFFICIENTS: [&[i64]; 5] = [&[], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]];
+
+/// MSB-first bit packer for the FLAC and MP3 bitstreams.
+#[cfg(any(feature = "flac", feature = "mp3"))]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+#[cfg(any(feature = "flac", feature = "mp3"))]
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Append the low `bits` bits of `value` (at most 32).
+    fn write(&mut self, value: u64, bits: u32) {
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    /// `zeros` zero bits followed by a one.
+    #[cfg(feature = "flac")]
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Pad with zero bits to the next byte boundary.
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+/// CRC-8 (polynomial 0x07) protecting each FLAC frame header.
+#[cfg(feature = "flac")]
+fn flac_crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16 (polynomial 0x8005) protecting each whole FLAC frame.
+#[cfg(feature = "flac")]
+fn flac_crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// MD5 digest, for the STREAMINFO signature of the decoded samples.
+#[cfg(feature = "flac")]
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect();
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let words: Vec<u32> = block
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            (a, d, c) = (d, c, b);
+            b = b.wrapping_add(rotated);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+    let mut digest = [0u8; 16];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// Choose the Rice partition order and parameters for a residual, estimating
+/// each partition's cost from its sum. Returns (bits, order, parameters).
+#[cfg(feature = "flac")]
+fn flac_rice_partitions(residual: &[u64], predictor_order: usize) -> (u64, u32, Vec<u32>) {
+    let block_size = residual.len() + predictor_order;
+    let mut best: Option<(u64, u32, Vec<u32>)> = None;
+    for order in 0..=FLAC_MAX_PARTITION_ORDER {
+        let partition_len = block_size >> order;
+        if !block_size.is_multiple_of(1 << order) || partition_len <= predictor_order {
+            break;
+        }
+        let mut start = 0;
+        let mut parameters = Vec::with_capacity(1 << order);
+        let mut bits = 0;
+        for partition in 0..1usize << order {
+            let len = if partition == 0 {
+                partition_len - predictor_order
+            } else {
+                partition_len
+            };
+            let sum: u64 = residual[start..start + len].iter().sum();
+            start += len;
+            let (parameter, cost) = (0..=FLAC_MAX_RICE_PARAMETER)
+                .map(|k| (k, len as u64 * (k as u64 + 1) + (sum >> k)))
+                .min_by_key(|&(_, cost)| cost)
+                .unwrap();
+            parameters.push(parameter);
+            bits += 4 + cost;
+        }
+        if best
+            .as_ref()
+            .is_none_or(|(best_bits, _, _)| bits < *best_bits)
+        {
+            best = Some((bits, order, parameters));
+        }
+    }
+    best.unwrap()
+}
+
+/// Zigzag-folded residual of a fixed predictor, for Rice coding.
+#[cfg(feature = "flac")]
+fn flac_fixed_residual(samples: &[i64], order: usize) -> Vec<u64> {
+    let coefficients = FLAC_FIXED_COEFFICIENTS[order];
+    (order..samples.len())
+        .map(|n| {
+            let prediction: i64 = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, &c)| c * samples[n - 1 - j])
+                .sum();
+            let r = samples[n] - prediction;
+            if r >= 0 {
+                (r as u64) << 1
+            } else {
+                ((-r as u64) << 1) - 1
+            }
+        })
+        .collect()
+}
+
+/// Encode one channel of a frame as the smallest of a constant, fixed
+/// predictor or verbatim subframe.
+#[cfg(feature = "flac")]
+fn write_flac_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        writer.write(0b0000_0000, 8);
+        writer.write(samples[0] as u64, bits);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * bits as u64;
+    let mut best: Option<(u64, usize)> = None;
+    for order in 0..FLAC_FIXED_COEFFICIENTS.len().min(samples.len()) {
+        let (rice_bits, ..) = flac_rice_partitions(&flac_fixed_residual(samples, order), order);
+        let cost = order as u64 * bits as u64 + 6 + rice_bits;
+        if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+            best = Some((cost, order));
+        }
+    }
+
+    match best {
+        Some((cost, order)) if cost < verbatim_bits => {
+            let residual = flac_fixed_residual(samples, order);
+            let (_, partition_order, parameters) = flac_rice_partitions(&residual, order);
+            writer.write(0b0001_0000 | (order as u64) << 1, 8);
+            for &sample in &samples[..order] {
+                writer.write(sample as u64, bits);
+            }
+            // Rice coding with 4-bit parameters
+            writer.write(0b00, 2);
+            writer.write(partition_order as u64, 4);
+            let partition_len = samples.len() >> partition_order;
+            let mut start = 0;
+            for (partition, &parameter) in parameters.iter().enumerate() {
+                let len = if partition == 0 {
+                    partition_len - order
+                } else {
+                    partition_len
+                };
+                writer.write(parameter as u64, 4);
+                for &value in &residual[start..start + len] {
+                    writer.write_unary(value >> parameter);
+                    writer.write(value, parameter);
+                }
+                start += len;
+            }
+        }
+        _ => {
+            writer.write(0b0000_0010, 8);
+            for &sample in samples {
+                writer.write(sample as u64, bits);
+            }
+        }
+    }
+}
+
+/// Build a FLAC file from interleaved signed samples (8-bit ones in
+/// offset binary, as rendered). Channels are coded independently with the
+/// fixed predictors; STREAMINFO carries the MD5 of the samples.
+#[cfg(feature = "flac")]
+fn create_flac_file_array(
+    samples: &[i64],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+) -> Vec<u8> {
+    let bits = sample_width as u32 * 8;
+    let samples: Vec<i64> = if sample_width == SampleWidth::Width1Byte {
+        samples.iter().map(|&sample| sample ^ (-1 << 7)).collect()
+    } else {
+        samples.to_vec()
+    };
+    let channels = channels as usize;
+    let total_frames = samples.len() / channels;
+    let size_code = match bits {
+        8 => 0b001,
+        16 => 0b100,
+        _ => 0b110,
+    };
+
+    let mut frames = Vec::new();
+    let (mut min_frame, mut max_frame) = (u32::MAX, 0);
+    for (index, block) in samples.chunks(FLAC_BLOCK_SIZE * channels).enumerate() {
+        let block_len = block.len() / channels;
+        let mut writer = BitWriter::new();
+        writer.write(0xFFF8, 16);
+        // Block size as a 16-bit value after the frame number; rate from STREAMINFO
+        writer.write(0b0111_0000, 8);
+        writer.write(((channels as u64 - 1) << 4) | size_code << 1, 8);
+        // Frame number, UTF-8 style
+        let number = index as u64;
+        if number < 0x80 {
+            writer.write(number, 8);
+        } else {
+            // n continuation bytes carry 6n bits, the lead byte 6 - n more
+            let continuation = (1..=6).find(|&n| number < 1u64 << (5 * n + 6)).unwrap();
+            let lead = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+            writer.write(lead | (number >> (6 * continuation)), 8);
+            for shift in (0..continuation).rev() {
+                writer.write(0x80 | ((number >> (6 * shift)) & 0x3F), 8);
+            }
+        }
+        writer.write(block_len as u64 - 1, 16);
+        writer.write(flac_crc8(&writer.bytes) as u64, 8);
+        for ch in 0..channels {
+            let channel: Vec<i64> = block.iter().skip(ch).step_by(channels).copied().collect();
+            write_flac_subframe(&mut writer, &channel, bits);
+        }
+        writer.align();
+        writer.write(flac_crc16(&writer.bytes) as u64, 16);
+        min_frame = min_frame.min(writer.bytes.len() as u32);
+        max_frame = max_frame.max(writer.bytes.len() as u32);
+        frames.extend(writer.bytes);
+    }
+
+    let mut pcm = Vec::with_capacity(samples.len() * sample_width as usize);
+    for &sample in &samples {
+        pcm.extend_from_slice(&sample.to_le_bytes()[..sample_width as usize]);
+    }
+
+    let mut header = BitWriter::new();
+    header.bytes.extend_from_slice(b"fLaC");
+    // Last metadata block, STREAMINFO, 34 bytes
+    header.write(0x80, 8);
+    header.write(34, 24);
+    header.write(FLAC_BLOCK_SIZE as u64, 16);
+    header.write(FLAC_BLOCK_SIZE as u64, 16);
+    header.write(if max_frame == 0 { 0 } else { min_frame as u64 }, 24);
+    header.write(max_frame as u64, 24);
+    header.write(sample_rate as u64, 20);
+    header.write(channels as u64 - 1, 3);
+    header.write(bits as u64 - 1, 5);
+    header.write((total_frames as u64) >> 32, 4);
+    header.write(total_frames as u64 & 0xFFFF_FFFF, 32);
+    header.bytes.extend_from_slice(&md5(&pcm));
+    header.bytes.extend(frames);
+    header.bytes
+}
+
+/// Samples in each Vorbis block; every packet is one long block.
+#[cfg(feature = "ogg")]
+const VORBIS_BLOCK_SIZE: usize = 2048;
+/// Floor 1 post positions besides 0 and 1024, coarse to fine so each post
+/// is predicted from its nearest coded neighbours.
+#[cfg(feature = "ogg")]
+const VORBIS_FLOOR_POSTS: [u16; 32] = [
+    56, 14, 224, 6, 28, 112, 448, 3, 10, 20, 40, 80, 160, 320, 640, 2, 4, 8, 12, 16, 24, 32, 48,
+    64, 96, 128, 192, 256, 384, 512, 768, 1,
+];
+/// Spectral lines per residue partition.
+#[cfg(feature = "ogg")]
+const VORBIS_PARTITION_SIZE: usize = 16;
+/// Residue partition classes: silent, ±1, then one to five balanced base-9
+/// digits (±4, ±40, ±364, ±3280, ±29524).
+#[cfg(feature = "ogg")]
+const VORBIS_RESIDUE_CLASSES: usize = 7;
+/// Natural log of the floor 1 step: the inverse-dB table rises by this much
+/// per entry from 1.0649863e-7 to 1.0.
+#[cfg(feature = "ogg")]
+const VORBIS_FLOOR_STEP: f64 = 0.06296131;
+
+/// LSB-first bit packer for Vorbis headers and audio packets.
+#[cfg(feature = "ogg")]
+struct VorbisPacker {
+    bytes: Vec<u8>,
+    bits: u32,
+}
+
+#[cfg(feature = "ogg")]
+impl VorbisPacker {
+    fn new() -> Self {
+        VorbisPacker {
+            bytes: Vec::new(),
+            bits: 0,
+        }
+    }
+
+    /// Append the low `bits` bits of `value`, least significant first.
+    fn write(&mut self, value: u64, bits: u32) {
+        for bit in 0..bits {
+            if self.bits == 0 {
+                self.bytes.push(0);
+            }
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= (((value >> bit) & 1) as u8) << self.bits;
+            self.bits = (self.bits + 1) % 8;
+        }
+    }
+
+    /// Append a Huffman codeword, most significant bit first.
+    fn write_codeword(&mut self, code: u32, len: u8) {
+        for bit in (0..len).rev() {
+            self.write(((code >> bit) & 1) as u64, 1);
+        }
+    }
+}
+
+/// One unit of an audio packet: raw bits, or a codebook entry whose codeword
+/// is only known once the whole stream's statistics are in.
+#[cfg(feature = "ogg")]
+enum VorbisToken {
+    Bits(u32, u32),
+    Entry(usize, u32),
+}
+
+/// CRC-32 (polynomial 0x04C11DB7, MSB first, no inversion) over an Ogg page.
+#[cfg(feature = "ogg")]
+fn ogg_crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u32) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Ogg page writer for a single logical stream.
+#[cfg(feature = "ogg")]
+struct OggStream {
+    out: Vec<u8>,
+    sequence: u32,
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+    /// Granule position of the last packet finished on the pending page
+    granule: i64,
+    continued: bool,
+}
+
+#[cfg(feature = "ogg")]
+impl OggStream {
+    /// Arbitrary but fixed, so the same settings give the same file.
+    const SERIAL: u32 = 0x5369_6E47;
+
+    fn new() -> Self {
+        OggStream {
+            out: Vec::new(),
+            sequence: 0,
+            lacing: Vec::new(),
+            body: Vec::new(),
+            granule: -1,
+            continued: false,
+        }
+    }
+
+    /// Queue a packet that ends at `granule`, breaking pages every 255
+    /// lacing values.
+    fn packet(&mut self, packet: &[u8], granule: i64) {
+        for segment in 0..=packet.len() / 255 {
+            if self.lacing.len() == 255 {
+                self.page(false);
+                self.continued = segment > 0;
+            }
+            let data = &packet[segment * 255..packet.len().min(segment * 255 + 255)];
+            self.lacing.push(data.len() as u8);
+            self.body.extend_from_slice(data);
+        }
+        self.granule = granule;
+    }
+
+    /// Write out the pending page.
+    fn page(&mut self, last: bool) {
+        let start = self.out.len();
+        let flags = self.continued as u8 | ((self.sequence == 0) as u8) << 1 | (last as u8) << 2;
+        self.out.extend_from_slice(b"OggS");
+        self.out.extend_from_slice(&[0, flags]);
+        self.out.extend_from_slice(&self.granule.to_le_bytes());
+        self.out.extend_from_slice(&Self::SERIAL.to_le_bytes());
+        self.out.extend_from_slice(&self.sequence.to_le_bytes());
+        self.out.extend_from_slice(&[0; 4]);
+        self.out.push(self.lacing.len() as u8);
+        self.out.append(&mut self.lacing);
+        self.out.append(&mut self.body);
+        let crc = ogg_crc32(&self.out[start..]);
+        self.out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+        self.granule = -1;
+        self.continued = false;
+    }
+}
+
+/// In-place radix-2 complex FFT (forward, unscaled); the length must be a
+/// power of two.
+#[cfg(feature = "ogg")]
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let step = -2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((step * k as f64).cos(), (step * k as f64).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let (xr, xi) = (re[b] * wr - im[b] * wi, re[b] * wi + im[b] * wr);
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Forward MDCT of `n` windowed samples to `n / 2` lines, scaled by 4/n so
+/// the Vorbis inverse transform and window overlap give the input back.
+/// Folds to a DCT-IV, computed with an n/4-point complex FFT.
+#[cfg(feature = "ogg")]
+fn vorbis_mdct(x: &[f64]) -> Vec<f64> {
+    use std::f64::consts::PI;
+    let (m, h) = (x.len() / 2, x.len() / 4);
+    let mut u = vec![0.0; m];
+    for j in 0..h {
+        u[j] = -x[m + h - 1 - j] - x[m + h + j];
+        u[h + j] = x[j] - x[m - 1 - j];
+    }
+    let (mut re, mut im) = (vec![0.0; h], vec![0.0; h]);
+    for j in 0..h {
+        let (a, b) = (u[2 * j], u[m - 1 - 2 * j]);
+        let angle = -PI * (4 * j + 1) as f64 / (4 * m) as f64;
+        re[j] = a * angle.cos() - b * angle.sin();
+        im[j] = a * angle.sin() + b * angle.cos();
+    }
+    fft(&mut re, &mut im);
+    let scale = 2.0 / m as f64;
+    let mut lines = vec![0.0; m];
+    for k in 0..h {
+        let angle = -PI * k as f64 / m as f64;
+        lines[2 * k] = (re[k] * angle.cos() - im[k] * angle.sin()) * scale;
+        lines[m - 1 - 2 * k] = -(re[k] * angle.sin() + im[k] * angle.cos()) * scale;
+    }
+    lines
+}
+
+/// Floor 1 line drawing, step for step as the decoder renders it.
+#[cfg(feature = "ogg")]
+fn vorbis_render_line(x0: i32, y0: i32, x1: i32, y1: i32, floor: &mut [i32]) {
+    let (dy, adx) = (y1 - y0, x1 - x0);
+    let base = dy / adx;
+    let ady = dy.abs() - base.abs() * adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let (mut y, mut err) = (y0, 0);
+    floor[x0 as usize] = y0;
+    for x in x0 + 1..x1 {
+        err += ady;
+        if err >= adx {
+            err -= adx;
+            y += step;
+        } else {
+            y += base;
+        }
+        floor[x as usize] = y;
+    }
+}
+
+/// Code one channel's floor 1 from the wanted value (0-127) at each post,
+/// in list order after the two end posts, and return the curve the decoder
+/// will draw, as amplitudes for each spectral line.
+#[cfg(feature = "ogg")]
+fn vorbis_floor(xs: &[i32], targets: &[i32], tokens: &mut Vec<VorbisToken>) -> Vec<f64> {
+    const RANGE: i32 = 128;
+    let mut final_y = targets.to_vec();
+    let mut used = vec![false; xs.len()];
+    used[0] = true;
+    used[1] = true;
+    tokens.push(VorbisToken::Bits(targets[0] as u32, 7));
+    tokens.push(VorbisToken::Bits(targets[1] as u32, 7));
+    for i in 2..xs.len() {
+        let (mut low, mut high) = (0, 1);
+        for j in 0..i {
+            if xs[j] < xs[i] && xs[j] > xs[low] {
+                low = j;
+            }
+            if xs[j] > xs[i] && xs[j] < xs[high] {
+                high = j;
+            }
+        }
+        let (dy, adx) = (final_y[high] - final_y[low], xs[high] - xs[low]);
+        let offset = dy.abs() * (xs[i] - xs[low]) / adx;
+        let predicted = final_y[low] + if dy < 0 { -offset } else { offset };
+        let (high_room, low_room) = (RANGE - predicted, predicted);
+        let room = 2 * high_room.min(low_room);
+        let decode = |val: i32| {
+            if val == 0 {
+                predicted
+            } else if val >= room {
+                if high_room > low_room {
+                    val - low_room + predicted
+                } else {
+                    predicted - val + high_room - 1
+                }
+            } else if val % 2 == 1 {
+                predicted - (val + 1) / 2
+            } else {
+                predicted + val / 2
+            }
+        };
+        let val = (0..RANGE).find(|&val| decode(val) == targets[i]).unwrap();
+        tokens.push(VorbisToken::Entry(0, val as u32));
+        if val != 0 {
+            used[low] = true;
+            used[high] = true;
+            used[i] = true;
+        }
+        final_y[i] = decode(val);
+    }
+
+    let mut order: Vec<usize> = (0..xs.len()).collect();
+    order.sort_by_key(|&i| xs[i]);
+    let mut curve = vec![0; xs[1] as usize];
+    let (mut lx, mut ly) = (0, final_y[0] * 2);
+    for &i in order.iter().skip(1).filter(|&&i| used[i]) {
+        vorbis_render_line(lx, ly, xs[i], final_y[i] * 2, &mut curve);
+        (lx, ly) = (xs[i], final_y[i] * 2);
+    }
+    curve
+        .iter()
+        .map(|&y| ((y - 255) as f64 * VORBIS_FLOOR_STEP).exp())
+        .collect()
+}
+
+/// Digit `place` of `value` in balanced base 9 (each digit -4 to 4).
+#[cfg(feature = "ogg")]
+fn balanced_nonary_digit(mut value: i32, place: usize) -> i32 {
+    for _ in 0..place {
+        value = (value - ((value + 4).rem_euclid(9) - 4)) / 9;
+    }
+    (value + 4).rem_euclid(9) - 4
+}
+
+/// Code residue type 1 for the channels that have a floor. Partitions are
+/// classed by their largest value; class 1 uses the ±1 book and class c >= 2
+/// one pass per balanced base-9 digit, c - 1 passes in all.
+#[cfg(feature = "ogg")]
+fn vorbis_residue(residues: &[Option<Vec<i32>>], tokens: &mut Vec<VorbisToken>) {
+    let classes: Vec<Option<Vec<usize>>> = residues
+        .iter()
+        .map(|lines| {
+            Some(
+                lines
+                    .as_ref()?
+                    .chunks(VORBIS_PARTITION_SIZE)
+                    .map(
+                        |part| match part.iter().map(|v| v.unsigned_abs()).max().unwrap_or(0) {
+                            0 => 0,
+                            1 => 1,
+                            peak => {
+                                (2..).find(|&c| peak <= (9u32.pow(c - 1) - 1) / 2).unwrap() as usize
+                            }
+                        },
+                    )
+                    .collect(),
+            )
+        })
+        .collect();
+    let partitions = VORBIS_BLOCK_SIZE / 2 / VORBIS_PARTITION_SIZE;
+    for pass in 0..VORBIS_RESIDUE_CLASSES - 2 {
+        for group in (0..partitions).step_by(2) {
+            if pass == 0 {
+                for classes in classes.iter().flatten() {
+                    let word = classes[group] * VORBIS_RESIDUE_CLASSES + classes[group + 1];
+                    tokens.push(VorbisToken::Entry(1, word as u32));
+                }
+            }
+            for partition in group..group + 2 {
+                for (lines, classes) in residues.iter().zip(&classes) {
+                    let (Some(lines), Some(classes)) = (lines, classes) else {
+                        continue;
+                    };
+                    let part = &lines[partition * VORBIS_PARTITION_SIZE..][..VORBIS_PARTITION_SIZE];
+                    match classes[partition] {
+                        1 if pass == 0 => {
+                            for quad in part.chunks(4) {
+                                let entry =
+                                    quad.iter().rev().fold(0, |entry, &v| entry * 3 + v + 1);
+                                tokens.push(VorbisToken::Entry(2, entry as u32));
+                            }
+                        }
+                        class if class >= 2 && pass <= class - 2 => {
+                            for pair in part.chunks(2) {
+                                let entry = balanced_nonary_digit(pair[0], pass)
+                                    + 4
+                                    + 9 * (balanced_nonary_digit(pair[1], pass) + 4);
+                                tokens.push(VorbisToken::Entry(3 + pass, entry as u32));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Huffman code lengths for symbol `counts`, giving every symbol a code and
+/// none longer than `max_len` bits.
+#[cfg(feature = "ogg")]
+fn huffman_lengths(counts: &[u64], max_len: u8) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    let mut weights: Vec<u64> = counts.iter().map(|&count| count + 1).collect();
+    loop {
+        let n = weights.len();
+        let mut parent = vec![0; 2 * n - 1];
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = weights
+            .iter()
+            .enumerate()
+            .map(|(node, &weight)| Reverse((weight, node)))
+            .collect();
+        let mut next = n;
+        while heap.len() > 1 {
+            let (Reverse((w1, a)), Reverse((w2, b))) = (heap.pop().unwrap(), heap.pop().unwrap());
+            parent[a] = next;
+            parent[b] = next;
+            heap.push(Reverse((w1 + w2, next)));
+            next += 1;
+        }
+        let lengths: Vec<u8> = (0..n)
+            .map(|mut node| {
+                let mut len = 0;
+                while node != next - 1 {
+                    node = parent[node];
+                    len += 1;
+                }
+                len
+            })
+            .collect();
+        if lengths.iter().all(|&len| len <= max_len) {
+            return lengths;
+        }
+        for weight in &mut weights {
+            *weight = *weight / 2 + 1;
+        }
+    }
+}
+
+/// Codewords the Vorbis decoder assigns to `lengths`: each entry in turn
+/// takes the lowest free codeword of its length.
+#[cfg(feature = "ogg")]
+fn vorbis_codewords(lengths: &[u8]) -> Vec<u32> {
+    let mut marker = [0u32; 33];
+    lengths
+        .iter()
+        .map(|&len| {
+            let len = len as usize;
+            let code = marker[len];
+            for j in (1..=len).rev() {
+                if marker[j] & 1 == 1 {
+                    marker[j] = if j == 1 {
+                        marker[1] + 1
+                    } else {
+                        marker[j - 1] << 1
+                    };
+                    break;
+                }
+                marker[j] += 1;
+            }
+            let mut entry = code;
+            for j in len + 1..33 {
+                if marker[j] >> 1 != entry {
+                    break;
+                }
+                entry = marker[j];
+                marker[j] = marker[j - 1] << 1;
+            }
+            code
+        })
+        .collect()
+}
+
+/// Vorbis float32_pack of a small integer.
+#[cfg(feature = "ogg")]
+fn vorbis_float(value: i64) -> u64 {
+    ((value < 0) as u64) << 31 | 788 << 21 | value.unsigned_abs()
+}
+
+/// Vorbis setup header: the codebooks with `lengths`, one floor 1, one
+/// residue type 1 and a single long-block mode.
+#[cfg(feature = "ogg")]
+fn vorbis_setup_header(xs: &[i32], lengths: &[Vec<u8>]) -> Vec<u8> {
+    let mut packer = VorbisPacker::new();
+    packer.write(5, 8);
+    for &byte in b"vorbis" {
+        packer.write(byte as u64, 8);
+    }
+    packer.write(lengths.len() as u64 - 1, 8);
+    for (book, lengths) in lengths.iter().enumerate() {
+        // Floor values and residue classwords are scalars; the ±1 book is
+        // four-dimensional and the digit books pairs of -4 to 4 times 9^place
+        let (dimensions, lookup) = match book {
+            0 => (1, None),
+            1 => (2, None),
+            2 => (4, Some((-1, 1, 3))),
+            _ => {
+                let place = 9i64.pow(book as u32 - 3);
+                (2, Some((-4 * place, place, 9)))
+            }
+        };
+        packer.write(0x56_4342, 24);
+        packer.write(dimensions, 16);
+        packer.write(lengths.len() as u64, 24);
+        // Not ordered, not sparse
+        packer.write(0, 2);
+        for &len in lengths {
+            packer.write(len as u64 - 1, 5);
+        }
+        match lookup {
+            None => packer.write(0, 4),
+            Some((minimum, delta, values)) => {
+                let value_bits = 64 - (values - 1u64).leading_zeros();
+                packer.write(1, 4);
+                packer.write(vorbis_float(minimum), 32);
+                packer.write(vorbis_float(delta), 32);
+                packer.write(value_bits as u64 - 1, 4);
+                packer.write(0, 1);
+                for value in 0..values {
+                    packer.write(value, value_bits);
+                }
+            }
+        }
+    }
+    // One unused time-domain transform
+    packer.write(0, 6);
+    packer.write(0, 16);
+
+    // Floor 1: eight partitions of four posts, all of class 0 with one
+    // book, multiplier 2 (values 0-127) and 10 range bits
+    packer.write(0, 6);
+    packer.write(1, 16);
+    packer.write((xs.len() as u64 - 2) / 4, 5);
+    for _ in 0..(xs.len() - 2) / 4 {
+        packer.write(0, 4);
+    }
+    packer.write(3, 3);
+    packer.write(0, 2);
+    packer.write(1, 8);
+    packer.write(1, 2);
+    packer.write(10, 4);
+    for &x in &xs[2..] {
+        packer.write(x as u64, 10);
+    }
+
+    // Residue type 1 over all 1024 lines with the classword book 1
+    packer.write(0, 6);
+    packer.write(1, 16);
+    packer.write(0, 24);
+    packer.write(VORBIS_BLOCK_SIZE as u64 / 2, 24);
+    packer.write(VORBIS_PARTITION_SIZE as u64 - 1, 24);
+    packer.write(VORBIS_RESIDUE_CLASSES as u64 - 1, 6);
+    packer.write(1, 8);
+    let cascade = |class: usize| match class {
+        0 => 0u64,
+        1 => 1,
+        _ => (1 << (class - 1)) - 1,
+    };
+    for class in 0..VORBIS_RESIDUE_CLASSES {
+        packer.write(cascade(class) & 7, 3);
+        packer.write((cascade(class) > 7) as u64, 1);
+        if cascade(class) > 7 {
+            packer.write(cascade(class) >> 3, 5);
+        }
+    }
+    for class in 1..VORBIS_RESIDUE_CLASSES {
+        for pass in 0..cascade(class).count_ones() as u64 {
+            packer.write(if class == 1 { 2 } else { 3 + pass }, 8);
+        }
+    }
+
+    // One mapping without coupling, submap 0 using floor 0 and residue 0
+    packer.write(0, 6);
+    packer.write(0, 16);
+    packer.write(0, 4);
+    packer.write(0, 24);
+    // One long-block mode
+    packer.write(0, 6);
+    packer.write(1, 1);
+    packer.write(0, 32);
+    packer.write(0, 8);
+    packer.write(1, 1);
+    packer.bytes
+}
+
+/// Build an Ogg Vorbis file from interleaved signed samples (8-bit ones in
+/// offset binary, as rendered). Each channel is coded alone in long blocks;
+/// `quality` (-1 to 10) sets how far below the spectral envelope the
+/// quantization noise sits, and the codebooks are fitted to the stream.
+#[cfg(feature = "ogg")]
+fn create_ogg_vorbis_file_array(
+    samples: &[i64],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    quality: f32,
+) -> Vec<u8> {
+    use std::f64::consts::{FRAC_PI_2, PI};
+    let channels = channels as usize;
+    let full_scale = (1i64 << (sample_width as u32 * 8 - 1)) as f64;
+    let frames = samples.len() / channels;
+    let hop = VORBIS_BLOCK_SIZE / 2;
+    // Block b spans frames (b - 1) * hop to (b + 1) * hop; the decoder
+    // returns nothing for the first and the last page's granule trims the end
+    let blocks = frames.div_ceil(hop) + 1;
+    let mut input = vec![vec![0.0; (blocks + 1) * hop]; channels];
+    for (i, &sample) in samples.iter().enumerate() {
+        let sample = if sample_width == SampleWidth::Width1Byte {
+            sample ^ (-1 << 7)
+        } else {
+            sample
+        };
+        input[i % channels][hop + i / channels] = sample as f64 / full_scale;
+    }
+    let window: Vec<f64> = (0..VORBIS_BLOCK_SIZE)
+        .map(|i| {
+            (FRAC_PI_2
+                * (PI * (i as f64 + 0.5) / VORBIS_BLOCK_SIZE as f64)
+                    .sin()
+                    .powi(2))
+            .sin()
+        })
+        .collect();
+    // Quantization step relative to the envelope, and the level below
+    // which nothing is coded
+    let step = 10f64.powf(-(25.0 + 5.0 * quality as f64) / 20.0);
+    let threshold = 10f64.powf(-(84.0 + 2.0 * quality as f64) / 20.0);
+
+    let xs: Vec<i32> = [0, hop as i32]
+        .into_iter()
+        .chain(VORBIS_FLOOR_POSTS.iter().map(|&x| x as i32))
+        .collect();
+    let mut sorted = xs.clone();
+    sorted.sort();
+    // Each post's envelope covers the lines halfway to its neighbours
+    let spans: Vec<(usize, usize)> = xs
+        .iter()
+        .map(|x| {
+            let at = sorted.binary_search(x).unwrap();
+            let start = if at == 0 { 0 } else { (sorted[at - 1] + x) / 2 };
+            let end = sorted.get(at + 1).map_or(*x, |next| (x + next) / 2);
+            (start as usize, end.max(start + 1) as usize)
+        })
+        .collect();
+
+    let mut packets = Vec::with_capacity(blocks);
+    for block in 0..blocks {
+        // Audio packet, long windows on both sides
+        let mut tokens = vec![VorbisToken::Bits(0, 1), VorbisToken::Bits(3, 2)];
+        let mut residues = Vec::with_capacity(channels);
+        for channel in &input {
+            let frame: Vec<f64> = channel[block * hop..][..VORBIS_BLOCK_SIZE]
+                .iter()
+                .zip(&window)
+                .map(|(sample, weight)| sample * weight)
+                .collect();
+            let lines = vorbis_mdct(&frame);
+            if lines.iter().all(|line| line.abs() < threshold) {
+                tokens.push(VorbisToken::Bits(0, 1));
+                residues.push(None);
+                continue;
+            }
+            tokens.push(VorbisToken::Bits(1, 1));
+            let targets: Vec<i32> = spans
+                .iter()
+                .map(|&(start, end)| {
+                    let envelope = lines[start..end.min(hop)]
+                        .iter()
+                        .fold(0.0f64, |peak, line| peak.max(line.abs()));
+                    let level = (envelope * step).max(threshold);
+                    (((255.0 + level.ln() / VORBIS_FLOOR_STEP) / 2.0).floor() as i32).clamp(0, 127)
+                })
+                .collect();
+            let floor = vorbis_floor(&xs, &targets, &mut tokens);
+            residues.push(Some(
+                lines
+                    .iter()
+                    .zip(&floor)
+                    .map(|(line, floor)| (line / floor).round().clamp(-29524.0, 29524.0) as i32)
+                    .collect(),
+            ));
+        }
+        vorbis_residue(&residues, &mut tokens);
+        packets.push(tokens);
+    }
+
+    // Fit each codebook to how often its entries occur
+    let mut counts = vec![vec![0u64; 81]; VORBIS_RESIDUE_CLASSES + 1];
+    counts[0] = vec![0; 128];
+    counts[1] = vec![0; VORBIS_RESIDUE_CLASSES.pow(2)];
+    for token in packets.iter().flatten() {
+        if let VorbisToken::Entry(book, entry) = *token {
+            counts[book][entry as usize] += 1;
+        }
+    }
+    let lengths: Vec<Vec<u8>> = counts
+        .iter()
+        .map(|counts| huffman_lengths(counts, 24))
+        .collect();
+    let codewords: Vec<Vec<u32>> = lengths
+        .iter()
+        .map(|lengths| vorbis_codewords(lengths))
+        .collect();
+
+    let mut identification = VorbisPacker::new();
+    identification.write(1, 8);
+    for &byte in b"vorbis" {
+        identification.write(byte as u64, 8);
+    }
+    identification.write(0, 32);
+    identification.write(channels as u64, 8);
+    identification.write(sample_rate as u64, 32);
+    // No bit rate hints
+    for _ in 0..3 {
+        identification.write(0, 32);
+    }
+    identification.write(8 | 11 << 4, 8);
+    identification.write(1, 1);
+
+    let mut comment = VorbisPacker::new();
+    comment.write(3, 8);
+    for &byte in b"vorbis" {
+        comment.write(byte as u64, 8);
+    }
+    comment.write(6, 32);
+    for &byte in b"singen" {
+        comment.write(byte as u64, 8);
+    }
+    comment.write(0, 32);
+    comment.write(1, 1);
+
+    let mut stream = OggStream::new();
+    stream.packet(&identification.bytes, 0);
+    stream.page(false);
+    stream.packet(&comment.bytes, 0);
+    stream.packet(&vorbis_setup_header(&xs, &lengths), 0);
+    stream.page(false);
+    for (block, tokens) in packets.iter().enumerate() {
+        if stream.body.len() >= 4096 {
+            stream.page(false);
+        }
+        let mut packer = VorbisPacker::new();
+        for token in tokens {
+            match *token {
+                VorbisToken::Bits(value, bits) => packer.write(value as u64, bits),
+                VorbisToken::Entry(book, entry) => packer.write_codeword(
+                    codewords[book][entry as usize],
+                    lengths[book][entry as usize],
+                ),
+            }
+        }
+        let granule = if block == blocks - 1 {
+            frames
+        } else {
+            block * hop
+        };
+        stream.packet(&packer.bytes, granule as i64);
+    }
+    stream.page(true);
+    stream.out
+}
+
+/// Samples per channel in each MP3 frame: two granules of 576.
+#[cfg(feature = "mp3")]
+const MP3_FRAME_SIZE: usize = 1152;
+/// Spectral lines in an MP3 granule, 18 for each of 32 subbands.
+#[cfg(feature = "mp3")]
+const MP3_GRANULE_SIZE: usize = 576;
+/// MPEG-1 Layer III bit rates (kbit/s) for header indexes 1 to 14.
+const MP3_BITRATES: [u32; 14] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+/// Long-block scale factor band edges at 44.1, 48 and 32 kHz, in header
+/// sample rate index order; the Huffman regions split on these.
+#[cfg(feature = "mp3")]
+const MP3_BAND_EDGES: [[u16; 23]; 3] = [
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 52, 62, 74, 90, 110, 134, 162, 196, 238, 288, 342,
+        418, 576,
+    ],
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 42, 50, 60, 72, 88, 106, 128, 156, 190, 230, 276, 330,
+        384, 576,
+    ],
+    [
+        0, 4, 8, 12, 16, 20, 24, 30, 36, 44, 54, 66, 82, 102, 126, 156, 194, 240, 296, 364, 448,
+        550, 576,
+    ],
+];
+/// Alias-reduction butterfly coefficients between adjacent subbands.
+#[cfg(feature = "mp3")]
+const MP3_ALIAS: [f64; 8] = [
+    -0.6, -0.535, -0.33, -0.185, -0.095, -0.041, -0.0142, -0.0037,
+];
+/// First half of the analysis window, in units of 2^-21; the window is
+/// symmetric about entry 256 and changes sign every 64 entries.
+#[cfg(feature = "mp3")]
+const MP3_WINDOW: [i32; 257] = [
+    0, -1, -1, -1, -1, -1, -1, -2, -2, -2, -2, -3, -3, -4, -4, -5, -5, -6, -7, -7, -8, -9, -10,
+    -11, -13, -14, -16, -17, -19, -21, -24, -26, -29, -31, -35, -38, -41, -45, -49, -53, -58, -63,
+    -68, -73, -79, -85, -91, -97, -104, -111, -117, -125, -132, -139, -147, -154, -161, -169, -176,
+    -183, -190, -196, -202, -208, -213, -218, -222, -225, -227, -228, -228, -227, -224, -221, -215,
+    -208, -200, -189, -177, -163, -146, -127, -106, -83, -57, -29, 2, 36, 72, 111, 153, 197, 244,
+    294, 347, 401, 459, 519, 581, 645, 711, 779, 848, 919, 991, 1064, 1137, 1210, 1283, 1356, 1428,
+    1498, 1567, 1634, 1698, 1759, 1817, 1870, 1919, 1962, 2001, 2032, 2057, 2075, 2085, 2087, 2080,
+    2063, 2037, 2000, 1952, 1893, 1822, 1739, 1644, 1535, 1414, 1280, 1131, 970, 794, 605, 402,
+    185, -45, -288, -545, -814, -1095, -1388, -1692, -2006, -2330, -2663, -3004, -3351, -3705,
+    -4063, -4425, -4788, -5153, -5517, -5879, -6237, -6589, -6935, -7271, -7597, -7910, -8209,
+    -8491, -8755, -8998, -9219, -9416, -9585, -9727, -9838, -9916, -9959, -9966, -9935, -9863,
+    -9750, -9592, -9389, -9139, -8840, -8492, -8092, -7640, -7134, -6574, -5959, -5288, -4561,
+    -3776, -2935, -2037, -1082, -70, 998, 2122, 3300, 4533, 5818, 7154, 8540, 9975, 11455, 12980,
+    14548, 16155, 17799, 19478, 21189, 22929, 24694, 26482, 28289, 30112, 31947, 33791, 35640,
+    37489, 39336, 41176, 43006, 44821, 46617, 48390, 50137, 51853, 53534, 55178, 56778, 58333,
+    59838, 61289, 62684, 64019, 65290, 66494, 67629, 68692, 69679, 70590, 71420, 72169, 72835,
+    73415, 73908, 74313, 74630, 74856, 74992, 75038,
+];
+/// Big-value Huffman tables 1 to 3, 5 to 12 and 16 (shared by 17 to 23,
+/// which add linbits): number, codes and lengths indexed by x * width + y.
+#[cfg(feature = "mp3")]
+static MP3_HUFFMAN: [(u32, &[u16], &[u8]); 12] = [
+    (1, &[1, 1, 1, 0], &[1, 3, 2, 3]),
+    (
+        2,
+        &[1, 2, 1, 3, 1, 1, 3, 2, 0],
+        &[1, 3, 6, 3, 3, 5, 5, 5, 6],
+    ),
+    (
+        3,
+        &[3, 2, 1, 1, 1, 1, 3, 2, 0],
+        &[2, 2, 6, 3, 2, 5, 5, 5, 6],
+    ),
+    (
+        5,
+        &[1, 2, 6, 5, 3, 1, 4, 4, 7, 5, 7, 1, 6, 1, 1, 0],
+        &[1, 3, 6, 7, 3, 3, 6, 7, 6, 6, 7, 8, 7, 6, 7, 8],
+    ),
+    (
+        6,
+        &[7, 3, 5, 1, 6, 2, 3, 2, 5, 4, 4, 1, 3, 3, 2, 0],
+        &[3, 3, 5, 7, 3, 2, 4, 5, 4, 4, 5, 6, 6, 5, 6, 7],
+    ),
+    (
+        7,
+        &[
+            1, 2, 10, 19, 16, 10, 3, 3, 7, 10, 5, 3, 11, 4, 13, 17, 8, 4, 12, 11, 18, 15, 11, 2, 7,
+            6, 9, 14, 3, 1, 6, 4, 5, 3, 2, 0,
+        ],
+        &[
+            1, 3, 6, 8, 8, 9, 3, 4, 6, 7, 7, 8, 6, 5, 7, 8, 8, 9, 7, 7, 8, 9, 9, 9, 7, 7, 8, 9, 9,
+            10, 8, 8, 9, 10, 10, 10,
+        ],
+    ),
+    (
+        8,
+        &[
+            3, 4, 6, 18, 12, 5, 5, 1, 2, 16, 9, 3, 7, 3, 5, 14, 7, 3, 19, 17, 15, 13, 10, 4, 13, 5,
+            8, 11, 5, 1, 12, 4, 4, 1, 1, 0,
+        ],
+        &[
+            2, 3, 6, 8, 8, 9, 3, 2, 4, 8, 8, 8, 6, 4, 6, 8, 8, 9, 8, 8, 8, 9, 9, 10, 8, 7, 8, 9,
+            10, 10, 9, 8, 9, 9, 11, 11,
+        ],
+    ),
+    (
+        9,
+        &[
+            7, 5, 9, 14, 15, 7, 6, 4, 5, 5, 6, 7, 7, 6, 8, 8, 8, 5, 15, 6, 9, 10, 5, 1, 11, 7, 9,
+            6, 4, 1, 14, 4, 6, 2, 6, 0,
+        ],
+        &[
+            3, 3, 5, 6, 8, 9, 3, 3, 4, 5, 6, 8, 4, 4, 5, 6, 7, 8, 6, 5, 6, 7, 7, 8, 7, 6, 7, 7, 8,
+            9, 8, 7, 8, 8, 9, 9,
+        ],
+    ),
+    (
+        10,
+        &[
+            1, 2, 10, 23, 35, 30, 12, 17, 3, 3, 8, 12, 18, 21, 12, 7, 11, 9, 15, 21, 32, 40, 19, 6,
+            14, 13, 22, 34, 46, 23, 18, 7, 20, 19, 33, 47, 27, 22, 9, 3, 31, 22, 41, 26, 21, 20, 5,
+            3, 14, 13, 10, 11, 16, 6, 5, 1, 9, 8, 7, 8, 4, 4, 2, 0,
+        ],
+        &[
+            1, 3, 6, 8, 9, 9, 9, 10, 3, 4, 6, 7, 8, 9, 8, 8, 6, 6, 7, 8, 9, 10, 9, 9, 7, 7, 8, 9,
+            10, 10, 9, 10, 8, 8, 9, 10, 10, 10, 10, 10, 9, 9, 10, 10, 11, 11, 10, 11, 8, 8, 9, 10,
+            10, 10, 11, 11, 9, 8, 9, 10, 10, 11, 11, 11,
+        ],
+    ),
+    (
+        11,
+        &[
+            3, 4, 10, 24, 34, 33, 21, 15, 5, 3, 4, 10, 32, 17, 11, 10, 11, 7, 13, 18, 30, 31, 20,
+            5, 25, 11, 19, 59, 27, 18, 12, 5, 35, 33, 31, 58, 30, 16, 7, 5, 28, 26, 32, 19, 17, 15,
+            8, 14, 14, 12, 9, 13, 14, 9, 4, 1, 11, 4, 6, 6, 6, 3, 2, 0,
+        ],
+        &[
+            2, 3, 5, 7, 8, 9, 8, 9, 3, 3, 4, 6, 8, 8, 7, 8, 5, 5, 6, 7, 8, 9, 8, 8, 7, 6, 7, 9, 8,
+            10, 8, 9, 8, 8, 8, 9, 9, 10, 9, 10, 8, 8, 9, 10, 10, 11, 10, 11, 8, 7, 7, 8, 9, 10, 10,
+            10, 8, 7, 8, 9, 10, 10, 10, 10,
+        ],
+    ),
+    (
+        12,
+        &[
+            9, 6, 16, 33, 41, 39, 38, 26, 7, 5, 6, 9, 23, 16, 26, 11, 17, 7, 11, 14, 21, 30, 10, 7,
+            17, 10, 15, 12, 18, 28, 14, 5, 32, 13, 22, 19, 18, 16, 9, 5, 40, 17, 31, 29, 17, 13, 4,
+            2, 27, 12, 11, 15, 10, 7, 4, 1, 27, 12, 8, 12, 6, 3, 1, 0,
+        ],
+        &[
+            4, 3, 5, 7, 8, 9, 9, 9, 3, 3, 4, 5, 7, 7, 8, 8, 5, 4, 5, 6, 7, 8, 7, 8, 6, 5, 6, 6, 7,
+            8, 8, 8, 7, 6, 7, 7, 8, 8, 8, 9, 8, 7, 8, 8, 8, 9, 8, 9, 8, 7, 7, 8, 8, 9, 9, 10, 9, 8,
+            8, 9, 9, 9, 9, 10,
+        ],
+    ),
+    (
+        16,
+        &[
+            1, 5, 14, 44, 74, 63, 110, 93, 172, 149, 138, 242, 225, 195, 376, 17, 3, 4, 12, 20, 35,
+            62, 53, 47, 83, 75, 68, 119, 201, 107, 207, 9, 15, 13, 23, 38, 67, 58, 103, 90, 161,
+            72, 127, 117, 110, 209, 206, 16, 45, 21, 39, 69, 64, 114, 99, 87, 158, 140, 252, 212,
+            199, 387, 365, 26, 75, 36, 68, 65, 115, 101, 179, 164, 155, 264, 246, 226, 395, 382,
+            362, 9, 66, 30, 59, 56, 102, 185, 173, 265, 142, 253, 232, 400, 388, 378, 445, 16, 111,
+            54, 52, 100, 184, 178, 160, 133, 257, 244, 228, 217, 385, 366, 715, 10, 98, 48, 91, 88,
+            165, 157, 148, 261, 248, 407, 397, 372, 380, 889, 884, 8, 85, 84, 81, 159, 156, 143,
+            260, 249, 427, 401, 392, 383, 727, 713, 708, 7, 154, 76, 73, 141, 131, 256, 245, 426,
+            406, 394, 384, 735, 359, 710, 352, 11, 139, 129, 67, 125, 247, 233, 229, 219, 393, 743,
+            737, 720, 885, 882, 439, 4, 243, 120, 118, 115, 227, 223, 396, 746, 742, 736, 721, 712,
+            706, 223, 436, 6, 202, 224, 222, 218, 216, 389, 386, 381, 364, 888, 443, 707, 440, 437,
+            1728, 4, 747, 211, 210, 208, 370, 379, 734, 723, 714, 1735, 883, 877, 876, 3459, 865,
+            2, 377, 369, 102, 187, 726, 722, 358, 711, 709, 866, 1734, 871, 3458, 870, 434, 0, 12,
+            10, 7, 11, 10, 17, 11, 9, 13, 12, 10, 7, 5, 3, 1, 3,
+        ],
+        &[
+            1, 4, 6, 8, 9, 9, 10, 10, 11, 11, 11, 12, 12, 12, 13, 9, 3, 4, 6, 7, 8, 9, 9, 9, 10,
+            10, 10, 11, 12, 11, 12, 8, 6, 6, 7, 8, 9, 9, 10, 10, 11, 10, 11, 11, 11, 12, 12, 9, 8,
+            7, 8, 9, 9, 10, 10, 10, 11, 11, 12, 12, 12, 13, 13, 10, 9, 8, 9, 9, 10, 10, 11, 11, 11,
+            12, 12, 12, 13, 13, 13, 9, 9, 8, 9, 9, 10, 11, 11, 12, 11, 12, 12, 13, 13, 13, 14, 10,
+            10, 9, 9, 10, 11, 11, 11, 11, 12, 12, 12, 12, 13, 13, 14, 10, 10, 9, 10, 10, 11, 11,
+            11, 12, 12, 13, 13, 13, 13, 15, 15, 10, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 13,
+            14, 14, 14, 10, 11, 10, 10, 11, 11, 12, 12, 13, 13, 13, 13, 14, 13, 14, 13, 11, 11, 11,
+            10, 11, 12, 12, 12, 12, 13, 14, 14, 14, 15, 15, 14, 10, 12, 11, 11, 11, 12, 12, 13, 14,
+            14, 14, 14, 14, 14, 13, 14, 11, 12, 12, 12, 12, 12, 13, 13, 13, 13, 15, 14, 14, 14, 14,
+            16, 11, 14, 12, 12, 12, 13, 13, 14, 14, 14, 16, 15, 15, 15, 17, 15, 11, 13, 13, 11, 12,
+            14, 14, 13, 14, 14, 15, 16, 15, 17, 15, 14, 11, 9, 8, 8, 9, 9, 10, 10, 10, 11, 11, 11,
+            11, 11, 11, 11, 8,
+        ],
+    ),
+];
+/// Linbits of tables 16 to 23, which extend table 16's escape value 15.
+#[cfg(feature = "mp3")]
+const MP3_LINBITS: [u32; 8] = [1, 2, 3, 4, 6, 8, 10, 13];
+/// Count1 table A codes and lengths, indexed by the quadruple's bits vwxy;
+/// table B codes each quadruple as its 4-bit complement.
+#[cfg(feature = "mp3")]
+const MP3_COUNT1_CODES: [u16; 16] = [1, 5, 4, 5, 6, 5, 4, 4, 7, 3, 6, 0, 7, 2, 3, 1];
+#[cfg(feature = "mp3")]
+const MP3_COUNT1_LENGTHS: [u8; 16] = [1, 4, 4, 5, 4, 6, 5, 6, 4, 5, 5, 6, 5, 6, 6, 6];
+
+/// Quantized spectrum of one granule of one channel, with the side
+/// information describing how it is coded.
+#[cfg(feature = "mp3")]
+struct Mp3Granule {
+    values: Vec<i32>,
+    global_gain: u32,
+    big_values: usize,
+    /// Index into the table list of each big-value region
+    tables: [usize; 3],
+    region0_count: usize,
+    region1_count: usize,
+    count1_table: u32,
+    /// Huffman-coded bits (part2_3_length; no scale factors are sent)
+    bits: u32,
+}
+
+/// Huffman table selections: (table_select, index into MP3_HUFFMAN, linbits).
+#[cfg(feature = "mp3")]
+fn mp3_tables() -> Vec<(u32, usize, u32)> {
+    (0..11)
+        .map(|table| (MP3_HUFFMAN[table].0, table, 0))
+        .chain(
+            MP3_LINBITS
+                .iter()
+                .enumerate()
+                .map(|(i, &linbits)| (16 + i as u32, 11, linbits)),
+        )
+        .collect()
+}
+
+/// Largest magnitude a table can code.
+#[cfg(feature = "mp3")]
+fn mp3_table_limit((_, table, linbits): (u32, usize, u32)) -> i32 {
+    let width = MP3_HUFFMAN[table].2.len().isqrt() as i32;
+    if linbits > 0 {
+        15 + (1 << linbits) - 1
+    } else {
+        width - 1
+    }
+}
+
+/// Bits to code the pair (x, y) with a table, magnitudes within its limit.
+#[cfg(feature = "mp3")]
+fn mp3_pair_bits((_, table, linbits): (u32, usize, u32), x: i32, y: i32) -> u32 {
+    let (_, _, lengths) = MP3_HUFFMAN[table];
+    let width = lengths.len().isqrt();
+    let (x, y) = (x.unsigned_abs() as usize, y.unsigned_abs() as usize);
+    let escape = |v: usize| if linbits > 0 && v >= 15 { linbits } else { 0 };
+    lengths[x.min(15) * width + y.min(15)] as u32
+        + escape(x)
+        + escape(y)
+        + (x != 0) as u32
+        + (y != 0) as u32
+}
+
+/// Quantize a granule's spectrum with the given global gain and find the
+/// cheapest region split and tables; `None` if a value is out of range.
+#[cfg(feature = "mp3")]
+fn mp3_quantize(
+    lines: &[f64],
+    global_gain: u32,
+    edges: &[u16; 23],
+    tables: &[(u32, usize, u32)],
+) -> Option<Mp3Granule> {
+    let scale = 2f64.powf(-(global_gain as f64 - 210.0) / 4.0);
+    let mut values = Vec::with_capacity(MP3_GRANULE_SIZE);
+    for line in lines {
+        let value = ((line.abs() * scale).powf(0.75) + 0.4054).floor();
+        if value > 8206.0 {
+            return None;
+        }
+        values.push(if *line < 0.0 {
+            -(value as i32)
+        } else {
+            value as i32
+        });
+    }
+
+    // Zeros at the top are implied; below them quadruples of magnitude at
+    // most 1 go in the count1 region, and the rest in pairs
+    let mut end = MP3_GRANULE_SIZE;
+    while end >= 2 && values[end - 2..end] == [0, 0] {
+        end -= 2;
+    }
+    let mut count1_bits = [0, 0];
+    let mut big = end;
+    while big >= 4 && values[big - 4..big].iter().all(|value| value.abs() <= 1) {
+        let quad = values[big - 4..big]
+            .iter()
+            .fold(0, |acc, value| acc << 1 | value.unsigned_abs() as usize);
+        count1_bits[0] += MP3_COUNT1_LENGTHS[quad] as u32 + quad.count_ones();
+        count1_bits[1] += 4 + quad.count_ones();
+        big -= 4;
+    }
+    let count1_table = (count1_bits[1] < count1_bits[0]) as u32;
+
+    // Running cost of each table over the pairs, and each band's peak
+    let pairs = big / 2;
+    let costs: Vec<Vec<u32>> = tables
+        .iter()
+        .map(|&table| {
+            let limit = mp3_table_limit(table);
+            let mut sum = 0;
+            let mut running = vec![0];
+            for pair in values[..big].chunks(2) {
+                if pair[0].abs() <= limit && pair[1].abs() <= limit {
+                    sum += mp3_pair_bits(table, pair[0], pair[1]);
+                }
+                running.push(sum);
+            }
+            running
+        })
+        .collect();
+    let peaks: Vec<i32> = edges
+        .windows(2)
+        .map(|band| {
+            values[(band[0] as usize).min(big)..(band[1] as usize).min(big)]
+                .iter()
+                .fold(0, |peak, value| peak.max(value.abs()))
+        })
+        .collect();
+    // Cheapest table for the pairs between band edges `from` and `to`
+    let region = |from: usize, to: usize| -> (usize, u32) {
+        let (start, stop) = (
+            (edges[from] as usize / 2).min(pairs),
+            (edges[to] as usize / 2).min(pairs),
+        );
+        let peak = peaks[from..to].iter().copied().max().unwrap_or(0);
+        if peak == 0 {
+            return (usize::MAX, 0);
+        }
+        tables
+            .iter()
+            .enumerate()
+            .filter(|&(_, &table)| mp3_table_limit(table) >= peak)
+            .map(|(i, _)| (i, costs[i][stop] - costs[i][start]))
+            .min_by_key(|&(_, bits)| bits)
+            .unwrap()
+    };
+    let mut best: Option<(u32, usize, usize, [usize; 3])> = None;
+    for region0_count in 0..16 {
+        for region1_count in 0..8.min(21 - region0_count) {
+            let split = [0, region0_count + 1, region0_count + region1_count + 2, 22];
+            let choices: Vec<(usize, u32)> = split.windows(2).map(|r| region(r[0], r[1])).collect();
+            let bits: u32 = choices.iter().map(|choice| choice.1).sum();
+            if best.is_none_or(|(least, ..)| bits < least) {
+                best = Some((
+                    bits,
+                    region0_count,
+                    region1_count,
+                    [choices[0].0, choices[1].0, choices[2].0],
+                ));
+            }
+        }
+    }
+    let (bits, region0_count, region1_count, regions) = best.unwrap();
+    Some(Mp3Granule {
+        values,
+        global_gain,
+        big_values: pairs,
+        tables: regions,
+        region0_count,
+        region1_count,
+        count1_table,
+        bits: bits + count1_bits[count1_table as usize],
+    })
+}
+
+/// Append a granule's Huffman-coded spectrum.
+#[cfg(feature = "mp3")]
+fn mp3_write_granule(
+    writer: &mut BitWriter,
+    granule: &Mp3Granule,
+    edges: &[u16; 23],
+    tables: &[(u32, usize, u32)],
+) {
+    let region1 = edges[granule.region0_count + 1] as usize;
+    let region2 = edges[granule.region0_count + granule.region1_count + 2] as usize;
+    let values = &granule.values;
+    for i in (0..granule.big_values * 2).step_by(2) {
+        let region = if i < region1 {
+            0
+        } else if i < region2 {
+            1
+        } else {
+            2
+        };
+        let Some(&(_, table, linbits)) = tables.get(granule.tables[region]) else {
+            continue;
+        };
+        let (_, codes, lengths) = MP3_HUFFMAN[table];
+        let width = lengths.len().isqrt();
+        let (x, y) = (
+            values[i].unsigned_abs() as usize,
+            values[i + 1].unsigned_abs() as usize,
+        );
+        let entry = x.min(15) * width + y.min(15);
+        writer.write(codes[entry] as u64, lengths[entry] as u32);
+        for (magnitude, value) in [(x, values[i]), (y, values[i + 1])] {
+            if linbits > 0 && magnitude >= 15 {
+                writer.write(magnitude as u64 - 15, linbits);
+            }
+            if value != 0 {
+                writer.write((value < 0) as u64, 1);
+            }
+        }
+    }
+    let mut i = granule.big_values * 2;
+    while i < MP3_GRANULE_SIZE && values[i..].iter().any(|&value| value != 0) {
+        let quad = &values[i..i + 4];
+        let entry = quad
+            .iter()
+            .fold(0, |acc, value| acc << 1 | value.unsigned_abs() as usize);
+        if granule.count1_table == 0 {
+            writer.write(
+                MP3_COUNT1_CODES[entry] as u64,
+                MP3_COUNT1_LENGTHS[entry] as u32,
+            );
+        } else {
+            writer.write(15 - entry as u64, 4);
+        }
+        for &value in quad.iter().filter(|&&value| value != 0) {
+            writer.write((value < 0) as u64, 1);
+        }
+        i += 4;
+    }
+}
+
+/// Build an MPEG-1 Layer III file from interleaved signed samples (8-bit
+/// ones in offset binary, as rendered). One or two channels, coded
+/// independently at a constant `bitrate` (kbit/s) in long blocks; each
+/// granule takes the finest global gain that fits its share of the frame.
+#[cfg(feature = "mp3")]
+fn create_mp3_file_array(
+    samples: &[i64],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    bitrate: u32,
+) -> Vec<u8> {
+    use std::f64::consts::PI;
+    let channels = channels as usize;
+    let full_scale = (1i64 << (sample_width as u32 * 8 - 1)) as f64;
+    let frames = samples.len() / channels;
+    // The filter banks delay the signal by 1057 samples; one more frame
+    // flushes them
+    let frame_count = frames.div_ceil(MP3_FRAME_SIZE) + 1;
+    let mut input = vec![vec![0.0; frame_count * MP3_FRAME_SIZE]; channels];
+    for (i, &sample) in samples.iter().enumerate() {
+        let sample = if sample_width == SampleWidth::Width1Byte {
+            sample ^ (-1 << 7)
+        } else {
+            sample
+        };
+        input[i % channels][i / channels] = sample as f64 / full_scale;
+    }
+
+    let rate_index = [44_100, 48_000, 32_000]
+        .iter()
+        .position(|&rate| rate == sample_rate)
+        .unwrap();
+    let bitrate_index = MP3_BITRATES
+        .iter()
+        .position(|&rate| rate == bitrate)
+        .unwrap()
+        + 1;
+    let edges = &MP3_BAND_EDGES[rate_index];
+    let tables = mp3_tables();
+    let window: Vec<f64> = (0..512)
+        .map(|i| {
+            let tap = MP3_WINDOW[if i <= 256 { i } else { 512 - i }] as f64 / (1 << 21) as f64;
+            if i / 64 % 2 == 1 { -tap } else { tap }
+        })
+        .collect();
+    let matrix: Vec<f64> = (0..32 * 64)
+        .map(|i| ((2 * (i / 64) + 1) as f64 * (i as f64 % 64.0 - 16.0) * PI / 64.0).cos())
+        .collect();
+    // 36-point MDCT with the sine window, scaled so the decoder's inverse
+    // restores the level
+    let mdct: Vec<f64> = (0..18 * 36)
+        .map(|i| {
+            let (k, n) = ((i / 36) as f64, (i % 36) as f64);
+            (PI / 36.0 * (n + 0.5)).sin() * (PI / 72.0 * (2.0 * n + 19.0) * (2.0 * k + 1.0)).cos()
+                / 9.0
+        })
+        .collect();
+    let (cs, ca): (Vec<f64>, Vec<f64>) = MP3_ALIAS
+        .iter()
+        .map(|c| (1.0 / (1.0 + c * c).sqrt(), c / (1.0 + c * c).sqrt()))
+        .unzip();
+
+    let side_bytes = if channels == 1 { 17 } else { 32 };
+    let mut history = vec![[0.0; 512]; channels];
+    let mut previous = vec![[[0.0; 18]; 32]; channels];
+    let mut out = Vec::new();
+    for frame in 0..frame_count {
+        let bytes = |frame: usize| frame as u64 * 144_000 * bitrate as u64 / sample_rate as u64;
+        let frame_bytes = (bytes(frame + 1) - bytes(frame)) as usize;
+        let padding = frame_bytes > (144_000 * bitrate / sample_rate) as usize;
+        let budget = (((frame_bytes - 4 - side_bytes) * 8 / (2 * channels)) as u32).min(4095);
+
+        let mut granules = Vec::with_capacity(2 * channels);
+        for granule in 0..2 {
+            for channel in 0..channels {
+                // Polyphase analysis into 18 samples of each of 32 subbands
+                let mut subbands = [[0.0; 18]; 32];
+                let start = frame * MP3_FRAME_SIZE + granule * MP3_GRANULE_SIZE;
+                let x = &mut history[channel];
+                for t in 0..18 {
+                    x.copy_within(0..480, 32);
+                    for i in 0..32 {
+                        x[31 - i] = input[channel][start + 32 * t + i];
+                    }
+                    let mut y = [0.0; 64];
+                    for (i, tap) in window.iter().enumerate() {
+                        y[i % 64] += tap * x[i];
+                    }
+                    for (sb, row) in matrix.chunks(64).enumerate() {
+                        let value: f64 = row.iter().zip(&y).map(|(m, y)| m * y).sum();
+                        // Odd subbands are spectrally inverted at odd times
+                        subbands[sb][t] = if sb % 2 == 1 && t % 2 == 1 {
+                            -value
+                        } else {
+                            value
+                        };
+                    }
+                }
+                let mut lines = vec![0.0; MP3_GRANULE_SIZE];
+                for sb in 0..32 {
+                    let block: Vec<f64> = previous[channel][sb]
+                        .iter()
+                        .chain(&subbands[sb])
+                        .copied()
+                        .collect();
+                    for (k, row) in mdct.chunks(36).enumerate() {
+                        lines[18 * sb + k] = row.iter().zip(&block).map(|(m, z)| m * z).sum();
+                    }
+                }
+                previous[channel] = subbands;
+                for sb in 1..32 {
+                    for i in 0..8 {
+                        let (upper, lower) = (lines[18 * sb - 1 - i], lines[18 * sb + i]);
+                        lines[18 * sb - 1 - i] = upper * cs[i] + lower * ca[i];
+                        lines[18 * sb + i] = lower * cs[i] - upper * ca[i];
+                    }
+                }
+                // Smallest global gain (finest step) whose coding fits
+                let (mut low, mut high) = (0, 255);
+                while low < high {
+                    let gain = (low + high) / 2;
+                    match mp3_quantize(&lines, gain, edges, &tables) {
+                        Some(coded) if coded.bits <= budget => high = gain,
+                        _ => low = gain + 1,
+                    }
+                }
+                granules.push(mp3_quantize(&lines, low, edges, &tables).unwrap());
+            }
+        }
+
+        // Header: sync, MPEG-1 Layer III without CRC, stereo or mono
+        let mut writer = BitWriter::new();
+        writer.write(0xFFFB, 16);
+        writer.write(bitrate_index as u64, 4);
+        writer.write(rate_index as u64, 2);
+        writer.write(padding as u64, 1);
+        writer.write(0, 1);
+        writer.write(if channels == 1 { 3 } else { 0 }, 2);
+        writer.write(0, 6);
+        // Side information: no bit reservoir, no scale factors
+        writer.write(0, 9);
+        writer.write(0, if channels == 1 { 5 } else { 3 });
+        writer.write(0, 4 * channels as u32);
+        for granule in &granules {
+            writer.write(granule.bits as u64, 12);
+            writer.write(granule.big_values as u64, 9);
+            writer.write(granule.global_gain as u64, 8);
+            // scalefac_compress 0, long blocks
+            writer.write(0, 5);
+            for table in granule.tables {
+                writer.write(tables.get(table).map_or(0, |table| table.0) as u64, 5);
+            }
+            writer.write(granule.region0_count as u64, 4);
+            writer.write(granule.region1_count as u64, 3);
+            // No preflag, scale factor step 2^0.5
+            writer.write(granule.count1_table as u64, 3);
+        }
+        for granule in &granules {
+            mp3_write_granule(&mut writer, granule, edges, &tables);
+        }
+        writer.align();
+        writer.bytes.resize(frame_bytes, 0);
+        out.extend(writer.bytes);
+    }
+    out
+}
+
+/// Insert a RIFF chunk at byte `pos` of a WAV file, padding it to an even
+/// length, and update the RIFF size.
+fn insert_wav_chunk(file: &mut Vec<u8>, pos: usize, id: &[u8; 4], data: &[u8]) {
+    let mut chunk = Vec::with_capacity(9 + data.len());
+    push_riff_chunk(&mut chunk, id, data);
+    file.splice(pos..pos, chunk);
+    let riff_size = (file.len() - 8) as u32;
+    file[4..8].copy_from_slice(&riff_size.to_le_bytes());
+}
+
+/// Append a RIFF chunk, padded to an even length.
+fn push_riff_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// NUL-terminated text for RIFF string chunks.
+fn zstring(text: &str) -> Vec<u8> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Contents of a `LIST`/`INFO` chunk.
+fn info_list(info: &Info, config: &Config) -> Vec<u8> {
+    let (date, _) = utc_date_time();
+    let mut list = b"INFO".to_vec();
+    push_riff_chunk(
+        &mut list,
+        b"INAM",
+        &zstring(info.title.as_deref().unwrap_or(&describe_signal(config))),
+    );
+    push_riff_chunk(
+        &mut list,
+        b"ICMT",
+        &zstring(info.comment.as_deref().unwrap_or("")),
+    );
+    push_riff_chunk(&mut list, b"ISFT", &zstring("singen"));
+    push_riff_chunk(&mut list, b"ICRD", &zstring(&date));
+    list
+}
+
+/// Labelled segment boundaries as frame positions, in order: the start of
+/// the signal, each channel slot of channel ID and calibration noise, each
+/// ident cycle, each burst, the crossfade and the tail. `frames` is the
+/// length of the rendered output.
+fn segment_boundaries(config: &Config, frames: usize) -> Vec<(usize, String)> {
+    let len = signal_samples(config);
+    let duration_ms = len as f64 * 1000.0 / config.sample_rate as f64;
+    let at = |ms: f64| (ms * config.sample_rate as f64 / 1000.0).round() as usize;
+    let mut cues = vec![(0, describe_signal(config))];
+    let mut repeat = |period_ms: f64, label: &dyn Fn(usize) -> String| {
+        if period_ms > 0.0 {
+            let mut n = 0;
+            while (n as f64 * period_ms) < duration_ms {
+                cues.push((at(n as f64 * period_ms), label(n)));
+                n += 1;
+            }
+        }
+    };
+    match config.waveform {
+        Waveform::ChannelId | Waveform::CalNoise => {
+            let channels = config.channels as usize;
+            repeat((config.id_tone_ms + config.id_gap_ms) as f64, &|n| {
+                format!("Channel {}", n % channels + 1)
+            });
+        }
+        Waveform::Glits => repeat(GLITS_CYCLE_MS as f64, &|n| format!("GLITS cycle {}", n + 1)),
+        Waveform::Blits => repeat(BLITS_CYCLE_MS as f64, &|n| format!("BLITS cycle {}", n + 1)),
+        _ => {}
+    }
+    if let Some(burst) = &config.burst {
+        repeat((burst.on_ms + burst.off_ms) as f64, &|n| {
+            format!("Burst {}", n + 1)
+        });
+    }
+    if let Some(crossfade) = &config.crossfade {
+        let fade = at(crossfade.length_ms as f64).min(len);
+        cues.push(((len - fade) / 2, "Crossfade start".to_string()));
+        cues.push(((len - fade) / 2 + fade, "Crossfade end".to_string()));
+    }
+    if config.tail_ms > 0.0 {
+        cues.push((
+            frames.saturating_sub(tail_samples(config)),
+            "Tail".to_string(),
+        ));
+    }
+    // Merge labels that land on the same frame
+    cues.sort_by_key(|&(position, _)| position);
+    let mut merged: Vec<(usize, String)> = Vec::with_capacity(cues.len());
+    for (position, label) in cues {
+        match merged.last_mut() {
+            Some((last, text)) if *last == position => *text = format!("{} / {}", text, label),
+            _ => merged.push((position, label)),
+        }
+    }
+    merged.retain(|&(position, _)| position < frames.max(1));
+    merged
+}
+
+/// Contents of a `cue ` chunk and its `LIST`/`adtl` labels for the
+/// given points.
+fn cue_chunks(points: &[(usize, String)]) -> (Vec<u8>, Vec<u8>) {
+    let mut cue = (points.len() as u32).to_le_bytes().to_vec();
+    let mut labels = b"adtl".to_vec();
+    for (id, (position, label)) in (1u32..).zip(points) {
+        cue.extend_from_slice(&id.to_le_bytes());
+        cue.extend_from_slice(&(*position as u32).to_le_bytes());
+        cue.extend_from_slice(b"data");
+        cue.extend_from_slice(&0u32.to_le_bytes()); // Chunk start
+        cue.extend_from_slice(&0u32.to_le_bytes()); // Block start
+        cue.extend_from_slice(&(*position as u32).to_le_bytes());
+        let mut labl = id.to_le_bytes().to_vec();
+        labl.extend_from_slice(&zstring(label));
+        push_riff_chunk(&mut labels, b"labl", &labl);
+    }
+    (cue, labels)
+}
+
+/// Contents of a `smpl` chunk with one forward loop over frames
+/// `start..end`. The unity note follows the first channel's frequency so
+/// samplers play the tone back at pitch.
+fn smpl_chunk(start: usize, end: usize, config: &Config) -> Vec<u8> {
+    let (unity_note, pitch_fraction) = if config.waveform.uses_frequency() {
+        let note = A4_MIDI_NOTE
+            + 12.0 * (channel_frequency(config, 0) as f64 / config.tuning.a4_hz).log2();
+        let unity = note.floor().clamp(0.0, 127.0);
+        let fraction = ((note - unity).clamp(0.0, 1.0) * 4294967296.0).min(u32::MAX as f64);
+        (unity as u32, fraction as u32)
+    } else {
+        (60, 0)
+    };
+    let mut chunk = Vec::with_capacity(60);
+    for value in [
+        0, // Manufacturer
+        0, // Product
+        (1e9 / config.sample_rate as f64).round() as u32,
+        unity_note,
+        pitch_fraction,
+        0, // SMPTE format
+        0, // SMPTE offset
+        1, // Sample loops
+        0, // Sampler data
+        0, // Cue point ID
+        0, // Forward loop
+        start as u32,
+        (end - 1) as u32, // Last frame played
+        0,                // Fraction
+        0,                // Play count: endless
+    ] {
+        chunk.extend_from_slice(&value.to_le_bytes());
+    }
+    chunk
+}
+
+/// Contents of a version 1 `bext` chunk, with a coding history line
+/// describing the render.
+fn bext_chunk(bext: &Bext, config: &Config) -> Vec<u8> {
+    let (date, time) = utc_date_time();
+    let mut chunk = Vec::with_capacity(640);
+    let mut field = |text: &str, len: usize| {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(len, 0);
+        chunk.extend_from_slice(&bytes);
+    };
+    field(
+        bext.description
+            .as_deref()
+            .unwrap_or(&describe_signal(config)),
+        256,
+    );
+    field(bext.originator.as_deref().unwrap_or("singen"), 32);
+    // Originator reference
+    field("", 32);
+    field(bext.date.as_deref().unwrap_or(&date), 10);
+    field(bext.time.as_deref().unwrap_or(&time), 8);
+    let time_reference = (bext.time_reference_secs * config.sample_rate as f64).round() as u64;
+    chunk.extend_from_slice(&time_reference.to_le_bytes());
+    chunk.extend_from_slice(&1u16.to_le_bytes());
+    // UMID and reserved bytes
+    chunk.resize(chunk.len() + 64 + 190, 0);
+    chunk.extend_from_slice(
+        format!(
+            "A=PCM,F={},W={},M={},T=singen\r\n",
+            config.sample_rate,
+            config.sample_width as u32 * 8,
+            channel_layout_name(config.channels)
+        )
+        .as_bytes(),
+    );
+    chunk
+}
+
+/// File container the output bytes take: the output format itself, or WAV
+/// for base64 output with `--base64-wav`.
+fn container_format(config: &Config) -> OutputFormat {
+    if config.output_format == OutputFormat::Base64 && config.base64_wav {
+        OutputFormat::WavFile
+    } else {
+        config.output_format
+    }
+}
+
+/// Header and trailer of a WAV file around `data_len` bytes of audio: the
+/// chunks up to and including the data chunk header, and the chunks that
+/// follow the data.
+fn wav_layout(part: &OutputPart, config: &Config, data_len: usize) -> (Vec<u8>, Vec<u8>) {
+    let frames = data_len / (part.config.channels as usize * config.sample_width as usize);
+    let mut header = create_wav_header(
+        data_len,
+        config.sample_rate,
+        part.config.channels as u16,
+        config.sample_width,
+        config.sample_format,
+    );
+    if let Some(bext) = &config.bext {
+        // Ahead of fmt, where ingest systems look first
+        insert_wav_chunk(&mut header, 12, b"bext", &bext_chunk(bext, &part.config));
+    }
+
+    let mut trailer = Vec::new();
+    if let Some((start, end)) = config.loop_points {
+        push_riff_chunk(&mut trailer, b"smpl", &smpl_chunk(start, end, &part.config));
+    }
+    if config.cues {
+        let (cue, labels) = cue_chunks(&segment_boundaries(&part.config, frames));
+        push_riff_chunk(&mut trailer, b"cue ", &cue);
+        push_riff_chunk(&mut trailer, b"LIST", &labels);
+    }
+    if let Some(info) = &config.info {
+        push_riff_chunk(&mut trailer, b"LIST", &info_list(info, &part.config));
+    }
+    // RIFF pads odd-length chunks to an even size (24-bit mono, 8-bit mono);
+    // the data chunk size itself stays the true length.
+    if data_len % 2 == 1 {
+        trailer.insert(0, 0);
+    }
+
+    let riff_len = (header.len() - 8 + data_len + trailer.len()) as u64;
+    // RIFF sizes are 32-bit; larger files switch to RF64.
+    if config.rf64 || riff_len > u32::MAX as u64 {
+        header = wav_to_rf64(&header, riff_len, data_len, frames as u64);
+    } else {
+        header[4..8].copy_from_slice(&(riff_len as u32).to_le_bytes());
+    }
+    (header, trailer)
+}
+
+/// Stdout as a file, so a redirect to a regular file can be seeked.
+#[cfg(unix)]
+fn stdout_file() -> Option<std::fs::File> {
+    use std::os::fd::AsFd;
+    let fd = std::io::stdout().as_fd().try_clone_to_owned().ok()?;
+    Some(std::fs::File::from(fd))
+}
+
+#[cfg(not(unix))]
+fn stdout_file() -> Option<std::fs::File> {
+    None
+}
+
+/// Write a WAV part to the output file or stdout without assembling the
+/// file in memory. A seekable destination first gets the header with zero
+/// sizes, so an interrupted write reads as incomplete, and the sizes are
+/// patched once the data and trailer are out. Pipes cannot be patched and
+/// get the final header up front; the data length is known before writing.
+fn write_wav_stream(part: &OutputPart, config: &Config) {
+    let path = config
+        .outfile
+        .as_ref()
+        .map(|path| suffixed_path(path, &part.suffix));
+    let name = path
+        .as_deref()
+        .map_or("stdout".to_string(), |path| format!("'{}'", path));
+    let result = (|| -> std::io::Result<()> {
+        let mut file = match &path {
+            Some(path) => Some(std::fs::File::create(path)?),
+            None => stdout_file(),
+        };
+        // Stdout may already be partway into a file; patch relative to here.
+        let start = file.as_mut().and_then(|file| file.stream_position().ok());
+        let seekable = start.is_some();
+        let (header, trailer) = wav_layout(part, config, part.buffer.len());
+        let mut placeholder = header.clone();
+        if seekable {
+            let data_size = placeholder.len() - 4;
+            placeholder[data_size..].fill(0);
+            if placeholder.starts_with(b"RF64") {
+                // ds64 RIFF, data and sample counts
+                placeholder[20..44].fill(0);
+            } else {
+                placeholder[4..8].fill(0);
+            }
+        }
+
+        let mut out: Box<dyn Write> = match &file {
+            Some(file) => Box::new(std::io::BufWriter::new(file)),
+            None => Box::new(std::io::stdout().lock()),
+        };
+        out.write_all(&placeholder)?;
+        out.write_all(&part.buffer)?;
+        out.write_all(&trailer)?;
+        out.flush()?;
+        drop(out);
+
+        if let (Some(file), Some(start)) = (file.as_mut(), start) {
+            let end = file.stream_position()?;
+            file.seek(SeekFrom::Start(start))?;
+            file.write_all(&header)?;
+            file.seek(SeekFrom::Start(end))?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        eprintln!("Error: Cannot write {}: {}", name, e);
+        process::exit(1);
+    }
+}
+
+/// Rewrite a WAV header as RF64 (EBU Tech 3306): `RF64` in place of
+/// `RIFF`, a `ds64` chunk with the 64-bit sizes after `WAVE`, and -1 in the
+/// 32-bit RIFF and data size fields. `riff_len` and `data_len` are the true
+/// sizes, as the 32-bit fields in `file` may have wrapped.
+fn wav_to_rf64(file: &[u8], riff_len: u64, data_len: usize, frames: u64) -> Vec<u8> {
+    let mut rf64 = Vec::with_capacity(file.len() + 36);
+    rf64.extend_from_slice(b"RF64");
+    rf64.extend_from_slice(&u32::MAX.to_le_bytes());
+    rf64.extend_from_slice(b"WAVE");
+    rf64.extend_from_slice(b"ds64");
+    rf64.extend_from_slice(&28u32.to_le_bytes());
+    rf64.extend_from_slice(&(riff_len + 36).to_le_bytes());
+    rf64.extend_from_slice(&(data_len as u64).to_le_bytes());
+    rf64.extend_from_slice(&frames.to_le_bytes());
+    // No table entries for other oversized chunks
+    rf64.extend_from_slice(&0u32.to_le_bytes());
+
+    let mut pos = 12;
+    while pos + 8 <= file.len() {
+        let id = &file[pos..pos + 4];
+        let len = if id == b"data" {
+            data_len
+        } else {
+            u32::from_le_bytes(file[pos + 4..pos + 8].try_into().unwrap()) as usize
+        };
+        rf64.extend_from_slice(id);
+        rf64.extend_from_slice(&(if id == b"data" { u32::MAX } else { len as u32 }).to_le_bytes());
+        let end = (pos + 8 + len + len % 2).min(file.len());
+        if id == b"fact" && frames > u32::MAX as u64 {
+            rf64.extend_from_slice(&u32::MAX.to_le_bytes());
+        } else {
+            rf64.extend_from_slice(&file[pos + 8..end]);
+        }
+        pos = end;
+    }
+    rf64
+}
+
+/// Build an Apple Core Audio Format file (`desc` and `data` chunks). CAF
+/// flags the byte order, so samples are written as rendered; 8-bit linear
+/// samples are signed and 64-bit chunk sizes lift the 4 GB limit.
+fn create_caf_file_array(
+    buffer: &[u8],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    format: SampleFormat,
+    endian: Endian,
+) -> Vec<u8> {
+    let format_id = match format {
+        SampleFormat::Pcm | SampleFormat::Float => b"lpcm",
+        SampleFormat::ALaw => b"alaw",
+        SampleFormat::MuLaw => b"ulaw",
+    };
+    // kCAFLinearPCMFormatFlagIsFloat and kCAFLinearPCMFormatFlagIsLittleEndian
+    let mut flags = 0u32;
+    if format == SampleFormat::Float {
+        flags |= 1;
+    }
+    if endian == Endian::Little && sample_width != SampleWidth::Width1Byte {
+        flags |= 2;
+    }
+
+    let mut file = Vec::with_capacity(68 + buffer.len());
+    file.extend_from_slice(b"caff");
+    // Version 1, no flags
+    file.extend_from_slice(&1u16.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes());
+    file.extend_from_slice(b"desc");
+    file.extend_from_slice(&32i64.to_be_bytes());
+    file.extend_from_slice(&(sample_rate as f64).to_be_bytes());
+    file.extend_from_slice(format_id);
+    file.extend_from_slice(&flags.to_be_bytes());
+    // Bytes per packet, frames per packet, channels, bits per channel
+    file.extend_from_slice(&(channels as u32 * sample_width as u32).to_be_bytes());
+    file.extend_from_slice(&1u32.to_be_bytes());
+    file.extend_from_slice(&(channels as u32).to_be_bytes());
+    file.extend_from_slice(&(sample_width as u32 * 8).to_be_bytes());
+    file.extend_from_slice(b"data");
+    // The data chunk opens with a 4-byte edit count
+    file.extend_from_slice(&(4 + buffer.len() as i64).to_be_bytes());
+    file.extend_from_slice(&0u32.to_be_bytes());
+    if format == SampleFormat::Pcm && sample_width == SampleWidth::Width1Byte {
+        file.extend(buffer.iter().map(|&byte| byte ^ 0x80));
+    } else {
+        file.extend_from_slice(buffer);
+    }
+    file
+}
+
+/// 80-bit IEEE 754 extended value of a sample rate, as stored in the AIFF
+/// `COMM` chunk.
+fn aiff_extended_rate(sample_rate: u32) -> [u8; 10] {
+    let shift = sample_rate.leading_zeros();
+    let exponent = 16383 + 31 - shift as u16;
+    let mantissa = (sample_rate as u64) << (32 + shift);
+    let mut bytes = [0u8; 10];
+    bytes[..2].copy_from_slice(&exponent.to_be_bytes());
+    bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+/// Build an AIFF file (`FORM`/`COMM`/`SSND`). AIFF samples are big-endian
+/// two's complement, so a little-endian buffer is byte-swapped and 8-bit
+/// offset-binary samples are converted to signed.
+fn create_aiff_file_array(
+    buffer: &[u8],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    endian: Endian,
+) -> Vec<u8> {
+    let sample_bytes = sample_width as usize;
+    let frames = buffer.len() / (channels as usize * sample_bytes);
+    let ssnd_len = 8 + buffer.len();
+    // Chunks are padded to an even length.
+    let pad = ssnd_len % 2;
+
+    let mut file = Vec::with_capacity(54 + ssnd_len + pad);
+    file.extend_from_slice(b"FORM");
+    file.extend_from_slice(&((4 + 26 + 8 + ssnd_len + pad) as u32).to_be_bytes());
+    file.extend_from_slice(b"AIFF");
+    file.extend_from_slice(b"COMM");
+    file.extend_from_slice(&18u32.to_be_bytes());
+    file.extend_from_slice(&channels.to_be_bytes());
+    file.extend_from_slice(&(frames as u32).to_be_bytes());
+    file.extend_from_slice(&(sample_width as u16 * 8).to_be_bytes());
+    file.extend_from_slice(&aiff_extended_rate(sample_rate));
+    file.extend_from_slice(b"SSND");
+    file.extend_from_slice(&(ssnd_len as u32).to_be_bytes());
+    // Offset and block size, both unused
+    file.extend_from_slice(&[0u8; 8]);
+    push_big_endian_samples(
+        &mut file,
+        buffer,
+        sample_bytes,
+        endian,
+        sample_width == SampleWidth::Width1Byte,
+    );
+    file.resize(file.len() + pad, 0);
+    file
+}
+
+/// Append rendered samples in big-endian byte order, turning 8-bit
+/// offset-binary samples into two's complement when `signed_8bit` is set.
+fn push_big_endian_samples(
+    file: &mut Vec<u8>,
+    buffer: &[u8],
+    sample_bytes: usize,
+    endian: Endian,
+    signed_8bit: bool,
+) {
+    for sample in buffer.chunks(sample_bytes) {
+        if sample_bytes == 1 && signed_8bit {
+            file.push(sample[0] ^ 0x80);
+        } else if endian == Endian::Little {
+            file.extend(sample.iter().rev());
+        } else {
+            file.extend_from_slice(sample);
+        }
+    }
+}
+
+/// Build a Sun AU (`.snd`) file: a 24-byte big-endian header followed by
+/// big-endian samples. 8-bit linear AU samples are signed.
+fn create_au_file_array(
+    buffer: &[u8],
+    sample_rate: u32,
+    channels: u16,
+    sample_width: SampleWidth,
+    format: SampleFormat,
+    endian: Endian,
+) -> Vec<u8> {
+    let mut file = Vec::with_capacity(24 + buffer.len());
+    file.extend_from_slice(b".snd");
+    // Data offset: the header with no annotation
+    file.extend_from_slice(&24u32.to_be_bytes());
+    file.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+    file.extend_from_slice(&format.au_encoding(sample_width).to_be_bytes());
+    file.extend_from_slice(&sample_rate.to_be_bytes());
+    file.extend_from_slice(&(channels as u32).to_be_bytes());
+    push_big_endian_samples(
+        &mut file,
+        buffer,
+        sample_width as usize,
+        endian,
+        format == SampleFormat::Pcm,
+    );
+    file
+}
+
+fn main() {
+    let config = parse_args();
+
+    let delays = channel_delay_samples(&config);
+    let max_delay = delays.iter().copied().max().unwrap_or(0);
+    let too_long = || {
+        eprintln!("Error: Duration too long to render");
+        process::exit(1);
+    };
+    let rendered_samples = signal_samples(&config)
+        .checked_add(max_delay + tail_samples(&config))
+        .unwrap_or_else(too_long);
+    let total_samples = padded_length(&config, rendered_samples);
+    if total_samples < rendered_samples {
+        eprintln!(
+            "Warning: Truncating {} frames to {} to fit a multiple of {} frames",
+            rendered_samples,
+            total_samples,
+            config.pad_to.unwrap_or(1)
+        );
+    }
+    let total_bytes = total_samples
+        .checked_mul(bytes_per_sample(&config) * config.channels as usize)
+        .unwrap_or_else(too_long);
+
+    let keep_values = config.output_format.uses_values();
+    let (channel_bytes, stats, values): (Vec<Vec<u8>>, _, _) = if config.waveform.is_code_exact() {
+        let mut channel_codes = generate_channel_codes(&config);
+        apply_channel_delays(&mut channel_codes, &delays);
+        append_silence(&mut channel_codes, tail_samples(&config));
+        resize_channels(&mut channel_codes, total_samples);
+        let channel_bytes = channel_codes
+            .iter()
+            .map(|codes| encode_codes(codes, &config))
+            .collect();
+        let values = keep_values.then(|| {
+            let max_val = config.full_scale.scale(code_bits(&config));
+            ChannelValues {
+                floats: channel_codes
+                    .iter()
+                    .map(|codes| {
+                        codes
+                            .iter()
+                            .map(|&code| (code as f64 / max_val) as f32)
+                            .collect()
+                    })
+                    .collect(),
+                codes: Some(channel_codes),
+            }
+        });
+        (channel_bytes, RenderStats::default(), values)
+    } else {
+        let mut channel_samples = if config
+            .internal_rate
+            .is_some_and(|rate| rate != config.sample_rate)
+            || config.drift_ppm != 0.0
+        {
+            generate_resampled(&config)
+        } else {
+            generate_sources(&config)
+        };
+        process_channels(&config, &mut channel_samples);
+        apply_channel_delays(&mut channel_samples, &delays);
+        append_silence(&mut channel_samples, tail_samples(&config));
+        resize_channels(&mut channel_samples, total_samples);
+        let (staged, stats) = stage_samples(&channel_samples, &config);
+        let (channel_bytes, codes) = match config.sample_format {
+            SampleFormat::Pcm | SampleFormat::ALaw | SampleFormat::MuLaw => {
+                let codes = quantize_samples(&staged, &config);
+                let channel_bytes = codes
+                    .iter()
+                    .map(|codes| encode_codes(codes, &config))
+                    .collect();
+                (channel_bytes, Some(codes))
+            }
+            SampleFormat::Float => (
+                staged
+                    .iter()
+                    .map(|samples| encode_floats(samples, &config))
+                    .collect(),
+                None,
+            ),
+        };
+        let values = keep_values.then_some(ChannelValues {
+            floats: staged,
+            codes,
+        });
+        (channel_bytes, stats, values)
+    };
+    let mut buffer = interleave_bytes(&channel_bytes, bytes_per_sample(&config), config.layout);
+    if config.packing == Packing::I2s {
+        delay_one_bit(&mut buffer, bytes_per_sample(&config), config.endian);
+    }
+
+    if stats.clipped_samples > 0 {
+        if config.strict {
+            eprintln!(
+                "Error: {} samples clipped (peak {:.2} dBFS)",
+                stats.clipped_samples,
+                linear_to_db(stats.peak)
+            );
+            process::exit(1);
+        }
+        if config.output_format.is_binary() || config.output_format == OutputFormat::Base64 {
+            eprintln!(
+                "Warning: {} samples clipped (peak {:.2} dBFS)",
+                stats.clipped_samples,
+                linear_to_db(stats.peak)
+            );
+        }
+    }
+
+    match config.output_format {
+        OutputFormat::Info => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+        }
+        OutputFormat::Hex => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                println!("\nBuffer data (hexadecimal){}:", part.label);
+                print_buffer_hex(&part.buffer, 16);
+            }
+        }
+        OutputFormat::CArray | OutputFormat::Progmem if config.outfile.is_some() => {
+            let progmem = config.output_format == OutputFormat::Progmem;
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                if progmem {
+                    warn_progmem_size(part.buffer.len(), config.split_bytes, &part.label);
+                }
+                let path =
+                    suffixed_path(config.outfile.as_deref().unwrap_or_default(), &part.suffix);
+                write_c_files(&part.buffer, &part.config, &part.suffix, progmem, &path);
+            }
+        }
+        OutputFormat::CArray => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                println!("\nC array declaration{}:", part.label);
+                print_c_array(&part.buffer, &part.config, &part.suffix, false);
+            }
+        }
+        OutputFormat::Progmem => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                warn_progmem_size(part.buffer.len(), config.split_bytes, &part.label);
+                println!("\nArduino PROGMEM declaration{}:", part.label);
+                print_c_array(&part.buffer, &part.config, &part.suffix, true);
+            }
+        }
+        OutputFormat::RustArray => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            let parts = output_parts(&config, &channel_bytes, buffer);
+            for (index, part) in parts.iter().enumerate() {
+                println!("\nRust array declaration{}:", part.label);
+                print_rust_array(
+                    &part.buffer,
+                    &part.config,
+                    &part.suffix,
+                    index == 0,
+                    index + 1 == parts.len(),
+                );
+            }
+        }
+        OutputFormat::PythonArray => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                println!("\nPython literal{}:", part.label);
+                print_python_array(&part.buffer, &part.config, &part.suffix);
+            }
+        }
+        OutputFormat::JsArray | OutputFormat::TsArray => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            let typescript = config.output_format == OutputFormat::TsArray;
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                println!(
+                    "\n{} module{}:",
+                    if typescript {
+                        "TypeScript"
+                    } else {
+                        "JavaScript"
+                    },
+                    part.label
+                );
+                print_js_array(&part.buffer, &part.config, &part.suffix, typescript);
+            }
+        }
+        OutputFormat::GoArray => {
+            print_buffer_info(&config, total_samples, total_bytes, &stats);
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                println!("\nGo declaration{}:", part.label);
+                print_go_array(&part.buffer, &part.config, &part.suffix);
+            }
+        }
+        OutputFormat::Csv => {
+            if let Some(values) = &values {
+                print_csv(values, &config);
+            }
+        }
+        OutputFormat::Json => {
+            if let Some(values) = &values {
+                print_json(values, &config, &stats);
+            }
+        }
+        OutputFormat::Matlab => {
+            if let Some(values) = &values {
+                for part in output_parts(&config, &channel_bytes, buffer) {
+                    print_matlab(values, &part.config, part.first_channel, &part.suffix);
+                }
+            }
+        }
+        OutputFormat::Base64 => {
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                let data = if config.base64_wav {
+                    let (header, trailer) = wav_layout(&part, &config, part.buffer.len());
+                    [header, part.buffer, trailer].concat()
+                } else {
+                    part.buffer
+                };
+                if config.data_uri {
+                    print!(
+                        "data:{};base64,",
+                        if config.base64_wav {
+                            "audio/wav"
+                        } else {
+                            "application/octet-stream"
+                        }
+                    );
+                }
+                println!("{}", base64_encode(&data));
+            }
+        }
+        OutputFormat::RawBytes
+        | OutputFormat::RawFloat
+        | OutputFormat::Npy
+        | OutputFormat::IntelHex
+        | OutputFormat::SRecord(_)
+        | OutputFormat::ReadMemH
+        | OutputFormat::Vhdl
+        | OutputFormat::Coe
+        | OutputFormat::Mif
+        | OutputFormat::Asm
+        | OutputFormat::WavFile
+        | OutputFormat::AiffFile
+        | OutputFormat::FlacFile
+        | OutputFormat::OggFile
+        | OutputFormat::Mp3File
+        | OutputFormat::AuFile
+        | OutputFormat::CafFile => {
+            for part in output_parts(&config, &channel_bytes, buffer) {
+                let data = match config.output_format {
+                    OutputFormat::WavFile => {
+                        write_wav_stream(&part, &config);
+                        continue;
+                    }
+                    OutputFormat::AiffFile => create_aiff_file_array(
+                        &part.buffer,
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                        config.endian,
+                    ),
+                    OutputFormat::AuFile => create_au_file_array(
+                        &part.buffer,
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                        config.sample_format,
+                        config.endian,
+                    ),
+                    OutputFormat::CafFile => create_caf_file_array(
+                        &part.buffer,
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                        config.sample_format,
+                        config.endian,
+                    ),
+                    OutputFormat::IntelHex | OutputFormat::SRecord(_) => {
+                        let base = config.base_address.unwrap_or(0);
+                        let record_length = config.record_length.unwrap_or(16);
+                        let image = match config.output_format {
+                            OutputFormat::SRecord(address_bytes) => {
+                                s_records(&part.buffer, base, address_bytes, record_length)
+                            }
+                            _ => intel_hex(&part.buffer, base, record_length),
+                        };
+                        match image {
+                            Some(text) => text.into_bytes(),
+                            None => {
+                                eprintln!(
+                                    "Error: {} bytes at 0x{:08X} run past the {} address space",
+                                    part.buffer.len(),
+                                    base,
+                                    match config.output_format {
+                                        OutputFormat::SRecord(Some(2)) => "S19 16-bit",
+                                        OutputFormat::SRecord(Some(3)) => "S28 24-bit",
+                                        _ => "32-bit",
+                                    }
+                                );
+                                process::exit(1);
+                            }
+                        }
+                    }
+                    format if format.uses_words() => {
+                        let word_bits = config
+                            .word_bits
+                            .unwrap_or(bytes_per_sample(&part.config) as u32 * 8);
+                        match memory_words(&part.buffer, &part.config, word_bits) {
+                            Some(words) => match config.output_format {
+                                OutputFormat::Vhdl => {
+                                    vhdl_package(&words, word_bits, &part.config, &part.suffix)
+                                }
+                                OutputFormat::Coe => coe_file(&words, word_bits, &part.config),
+                                OutputFormat::Mif => mif_file(&words, word_bits, &part.config),
+                                _ => readmemh(&words, word_bits, &part.config),
+                            }
+                            .into_bytes(),
+                            None => {
+                                eprintln!(
+                                    "Error: {} bytes do not fill whole {}-bit words",
+                                    part.buffer.len(),
+                                    word_bits
+                                );
+                                process::exit(1);
+                            }
+                        }
+                    }
+                    OutputFormat::Asm => {
+                        asm_source(&part.buffer, &part.config, &part.suffix).into_bytes()
+                    }
+                    OutputFormat::Npy => match &values {
+                        Some(values) => {
+                            create_npy_file_array(values, &part.config, part.first_channel)
+                        }
+                        None => continue,
+                    },
+                    #[cfg(feature = "flac")]
+                    OutputFormat::FlacFile => create_flac_file_array(
+                        &buffer_samples(&part.buffer, &part.config),
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                    ),
+                    #[cfg(feature = "ogg")]
+                    OutputFormat::OggFile => create_ogg_vorbis_file_array(
+                        &buffer_samples(&part.buffer, &part.config),
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                        config.quality.unwrap_or(3.0),
+                    ),
+                    #[cfg(feature = "mp3")]
+                    OutputFormat::Mp3File => create_mp3_file_array(
+                        &buffer_samples(&part.buffer, &part.config),
+                        config.sample_rate,
+                        part.config.channels as u16,
+                        config.sample_width,
+                        config.bitrate.unwrap_or(128),
+                    ),
+                    _ => part.buffer,
+                };
+                match &config.outfile {
+                    Some(path) => write_output_file(&suffixed_path(path, &part.suffix), &data),
+                    None => print_raw_bytes(&data),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_for(args: &str) -> Config {
+        let args: Vec<String> = std::iter::once("singen")
+            .chain(args.split_whitespace())
+            .map(String::from)
+            .collect();
+        config_from_args(&args)
+    }
+
+    fn le16(bytes: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+    }
+
+    fn le32(bytes: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+    }
+
+    /// MSB-first bit reader for decoding FLAC and MP3 frames back.
+    #[cfg(any(feature = "flac", feature = "mp3"))]
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    #[cfg(any(feature = "flac", feature = "mp3"))]
+    impl BitReader<'_> {
+        fn read(&mut self, bits: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..bits {
+                let bit = (self.bytes[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+                value = (value << 1) | bit as u64;
+                self.pos += 1;
+            }
+            value
+        }
+
+        #[cfg(feature = "flac")]
+        fn read_signed(&mut self, bits: u32) -> i64 {
+            let value = self.read(bits) as i64;
+            (value << (64 - bits)) >> (64 - bits)
+        }
+
+        #[cfg(feature = "flac")]
+        fn align(&mut self) {
+            self.pos = self.pos.div_ceil(8) * 8;
+        }
+    }
+
+    /// Decode one subframe of `block_len` samples.
+    #[cfg(feature = "flac")]
+    fn decode_flac_subframe(reader: &mut BitReader, block_len: usize, bits: u32) -> Vec<i64> {
+        let kind = reader.read(8);
+        match kind >> 1 {
+            0 => vec![reader.read_signed(bits); block_len],
+            1 => (0..block_len).map(|_| reader.read_signed(bits)).collect(),
+            kind if kind & 0b111000 == 0b001000 => {
+                let order = (kind & 0b111) as usize;
+                let mut samples: Vec<i64> = (0..order).map(|_| reader.read_signed(bits)).collect();
+                assert_eq!(reader.read(2), 0, "4-bit Rice parameters");
+                let partition_order = reader.read(4);
+                let partition_len = block_len >> partition_order;
+                for partition in 0..1 << partition_order {
+                    let parameter = reader.read(4) as u32;
+                    let len = partition_len - if partition == 0 { order } else { 0 };
+                    for _ in 0..len {
+                        let mut quotient = 0;
+                        while reader.read(1) == 0 {
+                            quotient += 1;
+                        }
+                        let folded = (quotient << parameter) | reader.read(parameter);
+                        let residual = (folded >> 1) as i64 ^ -((folded & 1) as i64);
+                        let n = samples.len();
+                        let prediction: i64 = FLAC_FIXED_COEFFICIENTS[order]
+                            .iter()
+                            .enumerate()
+                            .map(|(j, &c)| c * samples[n - 1 - j])
+                            .sum();
+                        samples.push(prediction + residual);
+                    }
+                }
+                samples
+            }
+            kind => panic!("unexpected subframe type {:#x}", kind),
+        }
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_crcs_match_check_values() {
+        // CRC-8/SMBUS and CRC-16/UMTS check values of "123456789"
+        assert_eq!(flac_crc8(b"123456789"), 0xF4);
+        assert_eq!(flac_crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn md5_matches_rfc_1321_vectors() {
+        let hex = |digest: [u8; 16]| {
+            digest
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(md5(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_frames_decode_to_the_input() {
+        let frames = FLAC_BLOCK_SIZE + 1000;
+        let mut seed = 1u32;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for n in 0..frames {
+            samples.push((20000.0 * (n as f64 * 0.01).sin()) as i64);
+            // Constant over the first block, then full-scale noise
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            samples.push(if n < FLAC_BLOCK_SIZE {
+                -1234
+            } else {
+                (seed >> 16) as i16 as i64
+            });
+        }
+        let file = create_flac_file_array(&samples, 48000, 2, SampleWidth::Width2Byte);
+        assert_eq!(&file[..4], b"fLaC");
+
+        let mut reader = BitReader {
+            bytes: &file,
+            pos: 8 * 8,
+        };
+        assert_eq!(reader.read(16), FLAC_BLOCK_SIZE as u64);
+        assert_eq!(reader.read(16), FLAC_BLOCK_SIZE as u64);
+        reader.read(48);
+        assert_eq!(reader.read(20), 48000);
+        assert_eq!(reader.read(3), 1);
+        assert_eq!(reader.read(5), 15);
+        assert_eq!(reader.read(36), frames as u64);
+        let pcm: Vec<u8> = samples
+            .iter()
+            .flat_map(|&sample| (sample as i16).to_le_bytes())
+            .collect();
+        assert_eq!(&file[26..42], &md5(&pcm));
+
+        let mut decoded = Vec::new();
+        reader.pos = 42 * 8;
+        while reader.pos < file.len() * 8 {
+            let start = reader.pos / 8;
+            assert_eq!(reader.read(16), 0xFFF8);
+            reader.read(16);
+            while reader.read(8) & 0xC0 == 0x80 {}
+            reader.pos -= 8;
+            reader.read(8);
+            let block_len = reader.read(16) as usize + 1;
+            let header_end = reader.pos / 8;
+            assert_eq!(reader.read(8) as u8, flac_crc8(&file[start..header_end]));
+            let left = decode_flac_subframe(&mut reader, block_len, 16);
+            let right = decode_flac_subframe(&mut reader, block_len, 16);
+            reader.align();
+            let frame_end = reader.pos / 8;
+            assert_eq!(reader.read(16) as u16, flac_crc16(&file[start..frame_end]));
+            decoded.extend(left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]));
+        }
+        assert_eq!(decoded, samples);
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn ogg_crc_and_vorbis_codewords_match_the_spec() {
+        assert_eq!(ogg_crc32(b"123456789"), 0x89A1_897F);
+        // The worked example in the Vorbis I specification
+        assert_eq!(
+            vorbis_codewords(&[2, 4, 4, 4, 4, 2, 3, 3]),
+            [0b00, 0b0100, 0b0101, 0b0110, 0b0111, 0b10, 0b110, 0b111]
+        );
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn ogg_pages_split_long_packets() {
+        let mut stream = OggStream::new();
+        stream.packet(&[7; 70000], 5);
+        stream.packet(&[1; 10], 9);
+        stream.page(true);
+
+        let mut pages = Vec::new();
+        let mut pos = 0;
+        while pos < stream.out.len() {
+            let page = &stream.out[pos..];
+            assert_eq!(&page[..4], b"OggS");
+            let segments = page[26] as usize;
+            let len = 27
+                + segments
+                + page[27..27 + segments]
+                    .iter()
+                    .map(|&s| s as usize)
+                    .sum::<usize>();
+            let mut unsigned = page[..len].to_vec();
+            unsigned[22..26].fill(0);
+            assert_eq!(le32(page, 22), ogg_crc32(&unsigned));
+            pages.push((
+                page[5],
+                i64::from_le_bytes(page[6..14].try_into().unwrap()),
+                page[27..27 + segments].to_vec(),
+            ));
+            pos += len;
+        }
+        assert_eq!(pages.len(), 2);
+        // 255 full segments and no packet end on the first page
+        assert_eq!(pages[0], (2, -1, vec![255; 255]));
+        let mut lacing = vec![255; 274 - 255];
+        lacing.extend([70000 % 255, 10].map(|s| s as u8));
+        assert_eq!(pages[1], (5, 9, lacing));
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn vorbis_mdct_matches_the_direct_sum() {
+        let n = 64;
+        let input: Vec<f64> = (0..n)
+            .map(|i| ((i * 7919) % 101) as f64 / 50.0 - 1.0)
+            .collect();
+        for (k, line) in vorbis_mdct(&input).iter().enumerate() {
+            let direct: f64 = input
+                .iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    x * (std::f64::consts::TAU / n as f64
+                        * (i as f64 + 0.5 + n as f64 / 4.0)
+                        * (k as f64 + 0.5))
+                        .cos()
+                })
+                .sum();
+            assert!((line - direct * 4.0 / n as f64).abs() < 1e-12, "line {}", k);
+        }
+    }
+
+    /// LSB-first bit reader for decoding Vorbis packets back.
+    #[cfg(feature = "ogg")]
+    struct VorbisReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    #[cfg(feature = "ogg")]
+    impl VorbisReader<'_> {
+        fn read(&mut self, bits: u32) -> u32 {
+            let mut value = 0;
+            for bit in 0..bits {
+                value |= ((self.bytes[self.pos / 8] >> (self.pos % 8)) as u32 & 1) << bit;
+                self.pos += 1;
+            }
+            value
+        }
+
+        fn float(&mut self) -> f64 {
+            let mantissa = self.read(21) as f64 * 2f64.powi(self.read(10) as i32 - 788);
+            if self.read(1) == 1 {
+                -mantissa
+            } else {
+                mantissa
+            }
+        }
+    }
+
+    #[cfg(feature = "ogg")]
+    fn ilog(value: usize) -> u32 {
+        usize::BITS - value.leading_zeros()
+    }
+
+    /// A codebook read back from a setup header: codewords by length and
+    /// value, and the vector behind each entry.
+    #[cfg(feature = "ogg")]
+    struct VorbisBook {
+        dimensions: usize,
+        codes: std::collections::HashMap<(u32, u32), usize>,
+        vectors: Vec<Vec<f64>>,
+    }
+
+    #[cfg(feature = "ogg")]
+    impl VorbisBook {
+        fn read(reader: &mut VorbisReader) -> VorbisBook {
+            assert_eq!(reader.read(24), 0x564342);
+            let dimensions = reader.read(16) as usize;
+            let entries = reader.read(24) as usize;
+            assert_eq!(reader.read(1), 0, "ordered codebook");
+            let sparse = reader.read(1) == 1;
+            let lengths: Vec<u8> = (0..entries)
+                .map(|_| {
+                    if sparse && reader.read(1) == 0 {
+                        0
+                    } else {
+                        reader.read(5) as u8 + 1
+                    }
+                })
+                .collect();
+            let used: Vec<usize> = (0..entries).filter(|&e| lengths[e] > 0).collect();
+            let used_lengths: Vec<u8> = used.iter().map(|&e| lengths[e]).collect();
+            let codes = vorbis_codewords(&used_lengths)
+                .into_iter()
+                .zip(&used)
+                .map(|(code, &entry)| ((lengths[entry] as u32, code), entry))
+                .collect();
+            let vectors = match reader.read(4) {
+                0 => Vec::new(),
+                1 => {
+                    let minimum = reader.float();
+                    let delta = reader.float();
+                    let value_bits = reader.read(4) + 1;
+                    let sequence = reader.read(1) == 1;
+                    let count = (1..)
+                        .take_while(|&r: &usize| r.pow(dimensions as u32) <= entries)
+                        .last()
+                        .unwrap();
+                    let multiplicands: Vec<f64> =
+                        (0..count).map(|_| reader.read(value_bits) as f64).collect();
+                    (0..entries)
+                        .map(|entry| {
+                            let mut last = 0.0;
+                            let mut divisor = 1;
+                            (0..dimensions)
+                                .map(|_| {
+                                    let value = multiplicands[entry / divisor % count] * delta
+                                        + minimum
+                                        + last;
+                                    if sequence {
+                                        last = value;
+                                    }
+                                    divisor *= count;
+                                    value
+                                })
+                                .collect()
+                        })
+                        .collect()
+                }
+                kind => panic!("unexpected lookup type {}", kind),
+            };
+            VorbisBook {
+                dimensions,
+                codes,
+                vectors,
+            }
+        }
+
+        fn decode(&self, reader: &mut VorbisReader) -> usize {
+            let mut code = 0;
+            for len in 1..=32 {
+                code = code << 1 | reader.read(1);
+                if let Some(&entry) = self.codes.get(&(len, code)) {
+                    return entry;
+                }
+            }
+            panic!("no codeword at bit {}", reader.pos);
+        }
+    }
+
+    /// Decode an Ogg Vorbis stream with one floor 1, one residue 1 and one
+    /// uncoupled mapping, as laid out in the Vorbis I specification, to
+    /// per-channel samples trimmed at the last granule position.
+    #[cfg(feature = "ogg")]
+    fn decode_ogg_vorbis(file: &[u8]) -> (u32, Vec<Vec<f64>>) {
+        use std::f64::consts::{FRAC_PI_2, PI};
+        let mut packets = vec![Vec::new()];
+        let mut granule = 0;
+        let mut pos = 0;
+        while pos < file.len() {
+            assert_eq!(&file[pos..pos + 4], b"OggS");
+            granule = i64::from_le_bytes(file[pos + 6..pos + 14].try_into().unwrap());
+            let segments = file[pos + 26] as usize;
+            let mut data = pos + 27 + segments;
+            for &lacing in &file[pos + 27..pos + 27 + segments] {
+                packets
+                    .last_mut()
+                    .unwrap()
+                    .extend(&file[data..data + lacing as usize]);
+                data += lacing as usize;
+                if lacing < 255 {
+                    packets.push(Vec::new());
+                }
+            }
+            pos = data;
+        }
+        packets.pop();
+
+        let identification = &packets[0];
+        assert_eq!(&identification[..7], b"\x01vorbis");
+        let channels = identification[11] as usize;
+        let sample_rate = le32(identification, 12);
+        let n = 1 << (identification[28] >> 4);
+        let hop = n / 2;
+
+        let mut reader = VorbisReader {
+            bytes: &packets[2],
+            pos: 56,
+        };
+        let books: Vec<VorbisBook> = (0..reader.read(8) + 1)
+            .map(|_| VorbisBook::read(&mut reader))
+            .collect();
+        assert_eq!((reader.read(6), reader.read(16)), (0, 0), "time domain");
+        assert_eq!((reader.read(6), reader.read(16)), (0, 1), "one floor 1");
+        let partitions: Vec<usize> = {
+            let count = reader.read(5);
+            (0..count).map(|_| reader.read(4) as usize).collect()
+        };
+        let classes: Vec<(usize, u32, usize, Vec<i32>)> =
+            (0..partitions.iter().max().map_or(0, |class| class + 1))
+                .map(|_| {
+                    let dimensions = reader.read(3) as usize + 1;
+                    let subclasses = reader.read(2);
+                    let master = if subclasses > 0 {
+                        reader.read(8) as usize
+                    } else {
+                        0
+                    };
+                    let books = (0..1 << subclasses)
+                        .map(|_| reader.read(8) as i32 - 1)
+                        .collect();
+                    (dimensions, subclasses, master, books)
+                })
+                .collect();
+        let multiplier = reader.read(2) as i32 + 1;
+        let range = [256, 128, 86, 64][multiplier as usize - 1];
+        let range_bits = reader.read(4);
+        let mut xs = vec![0, 1 << range_bits];
+        for &class in &partitions {
+            xs.extend((0..classes[class].0).map(|_| reader.read(range_bits) as i32));
+        }
+        assert_eq!((reader.read(6), reader.read(16)), (0, 1), "one residue 1");
+        let begin = reader.read(24) as usize;
+        let end = reader.read(24) as usize;
+        let partition_size = reader.read(24) as usize + 1;
+        let classifications = reader.read(6) as usize + 1;
+        let classbook = reader.read(8) as usize;
+        let cascades: Vec<u32> = (0..classifications)
+            .map(|_| {
+                let low = reader.read(3);
+                if reader.read(1) == 1 {
+                    reader.read(5) << 3 | low
+                } else {
+                    low
+                }
+            })
+            .collect();
+        let residue_books: Vec<Vec<Option<usize>>> = cascades
+            .iter()
+            .map(|cascade| {
+                (0..8)
+                    .map(|pass| (cascade >> pass & 1 == 1).then(|| reader.read(8) as usize))
+                    .collect()
+            })
+            .collect();
+        assert_eq!((reader.read(6), reader.read(16)), (0, 0), "one mapping");
+        assert_eq!(reader.read(1), 0, "one submap");
+        assert_eq!(reader.read(1), 0, "no coupling");
+        assert_eq!(reader.read(2), 0);
+        assert_eq!((reader.read(8), reader.read(8), reader.read(8)), (0, 0, 0));
+        let modes: Vec<u32> = (0..reader.read(6) + 1)
+            .map(|_| {
+                let blockflag = reader.read(1);
+                assert_eq!(
+                    (reader.read(16), reader.read(16), reader.read(8)),
+                    (0, 0, 0)
+                );
+                blockflag
+            })
+            .collect();
+        assert_eq!(reader.read(1), 1, "framing bit");
+
+        // The spec's inverse dB table, 1.0649863e-07 up to 1 in equal ratios
+        let inverse_db = |y: i32| 1.0649863e-07f64.powf(1.0 - y as f64 / 255.0);
+        let window: Vec<f64> = (0..n)
+            .map(|i| (FRAC_PI_2 * (PI * (i as f64 + 0.5) / n as f64).sin().powi(2)).sin())
+            .collect();
+        let cosines: Vec<f64> = (0..4 * n)
+            .map(|m| (PI / 2.0 / n as f64 * m as f64).cos())
+            .collect();
+        let mut output = vec![vec![0.0; (packets.len() - 2) * hop]; channels];
+        for (block, packet) in packets[3..].iter().enumerate() {
+            let mut reader = VorbisReader {
+                bytes: packet,
+                pos: 0,
+            };
+            assert_eq!(reader.read(1), 0, "audio packet");
+            assert_eq!(
+                modes[reader.read(ilog(modes.len() - 1)) as usize],
+                1,
+                "long block"
+            );
+            assert_eq!((reader.read(1), reader.read(1)), (1, 1), "long windows");
+            let floors: Vec<Option<Vec<f64>>> = (0..channels)
+                .map(|_| {
+                    if reader.read(1) == 0 {
+                        return None;
+                    }
+                    let bits = ilog(range - 1);
+                    let mut ys = vec![reader.read(bits) as i32, reader.read(bits) as i32];
+                    for &class in &partitions {
+                        let (dimensions, subclasses, master, ref subbooks) = classes[class];
+                        let mut value = if subclasses > 0 {
+                            books[master].decode(&mut reader)
+                        } else {
+                            0
+                        };
+                        for _ in 0..dimensions {
+                            let book = subbooks[value & ((1 << subclasses) - 1)];
+                            value >>= subclasses;
+                            ys.push(if book >= 0 {
+                                books[book as usize].decode(&mut reader) as i32
+                            } else {
+                                0
+                            });
+                        }
+                    }
+                    // Amplitude value synthesis
+                    let mut used = vec![true; xs.len()];
+                    for i in 2..xs.len() {
+                        let low = (0..i)
+                            .filter(|&j| xs[j] < xs[i])
+                            .max_by_key(|&j| xs[j])
+                            .unwrap();
+                        let high = (0..i)
+                            .filter(|&j| xs[j] > xs[i])
+                            .min_by_key(|&j| xs[j])
+                            .unwrap();
+                        let dy = ys[high] - ys[low];
+                        let offset = dy.abs() * (xs[i] - xs[low]) / (xs[high] - xs[low]);
+                        let predicted = ys[low] + if dy < 0 { -offset } else { offset };
+                        let value = ys[i];
+                        let high_room = range as i32 - predicted;
+                        let low_room = predicted;
+                        let room = 2 * high_room.min(low_room);
+                        if value != 0 {
+                            used[low] = true;
+                            used[high] = true;
+                        }
+                        ys[i] = if value == 0 {
+                            used[i] = false;
+                            predicted
+                        } else if value >= room {
+                            if high_room > low_room {
+                                value - low_room + predicted
+                            } else {
+                                predicted - value + high_room - 1
+                            }
+                        } else if value % 2 == 1 {
+                            predicted - (value + 1) / 2
+                        } else {
+                            predicted + value / 2
+                        };
+                    }
+                    // Curve synthesis between the posts in order of position
+                    let mut order: Vec<usize> = (0..xs.len()).filter(|&i| used[i]).collect();
+                    order.sort_by_key(|&i| xs[i]);
+                    let mut curve = vec![0; hop];
+                    for pair in order.windows(2) {
+                        let (x0, y0) = (xs[pair[0]], ys[pair[0]] * multiplier);
+                        let (x1, y1) = (xs[pair[1]], ys[pair[1]] * multiplier);
+                        let dy = y1 - y0;
+                        let dx = x1 - x0;
+                        let base = dy / dx;
+                        let step = if dy < 0 { base - 1 } else { base + 1 };
+                        let remainder = dy.abs() - base.abs() * dx;
+                        let (mut y, mut error) = (y0, 0);
+                        for x in x0..x1.min(hop as i32) {
+                            curve[x as usize] = y;
+                            error += remainder;
+                            if error >= dx {
+                                error -= dx;
+                                y += step;
+                            } else {
+                                y += base;
+                            }
+                        }
+                    }
+                    let last = *order.last().unwrap();
+                    if (xs[last] as usize) < hop {
+                        curve[xs[last] as usize..].fill(ys[last] * multiplier);
+                    }
+                    Some(curve.into_iter().map(inverse_db).collect())
+                })
+                .collect();
+
+            let mut residues = vec![vec![0.0; hop]; channels];
+            let coded: Vec<usize> = (0..channels).filter(|&c| floors[c].is_some()).collect();
+            let per_word = books[classbook].dimensions;
+            let partition_count = (end.min(hop) - begin.min(hop)) / partition_size;
+            let mut classes_of = vec![vec![0; partition_count + per_word]; channels];
+            for pass in 0..8 {
+                let mut partition = 0;
+                while partition < partition_count {
+                    if pass == 0 {
+                        for &channel in &coded {
+                            let mut word = books[classbook].decode(&mut reader);
+                            for i in (0..per_word).rev() {
+                                classes_of[channel][partition + i] = word % classifications;
+                                word /= classifications;
+                            }
+                        }
+                    }
+                    for _ in 0..per_word {
+                        if partition == partition_count {
+                            break;
+                        }
+                        for &channel in &coded {
+                            let class = classes_of[channel][partition];
+                            if let Some(&Some(book)) = residue_books[class].get(pass) {
+                                let mut at = begin + partition * partition_size;
+                                while at < begin + (partition + 1) * partition_size {
+                                    let entry = books[book].decode(&mut reader);
+                                    for value in &books[book].vectors[entry] {
+                                        residues[channel][at] += value;
+                                        at += 1;
+                                    }
+                                }
+                            }
+                        }
+                        partition += 1;
+                    }
+                }
+            }
+
+            for (channel, floor) in floors.iter().enumerate() {
+                let Some(floor) = floor else { continue };
+                let spectrum: Vec<(usize, f64)> = (0..hop)
+                    .map(|k| (k, floor[k] * residues[channel][k]))
+                    .filter(|&(_, line)| line != 0.0)
+                    .collect();
+                for i in 0..n {
+                    let at = (block * hop + i).wrapping_sub(hop);
+                    if at >= output[channel].len() {
+                        continue;
+                    }
+                    let sample: f64 = spectrum
+                        .iter()
+                        .map(|&(k, line)| line * cosines[(2 * i + 1 + hop) * (2 * k + 1) % (4 * n)])
+                        .sum();
+                    output[channel][at] += sample * window[i];
+                }
+            }
+        }
+        for channel in &mut output {
+            channel.truncate(granule as usize);
+        }
+        (sample_rate, output)
+    }
+
+    #[cfg(feature = "ogg")]
+    #[test]
+    fn ogg_vorbis_decodes_to_the_input_level_and_frequency() {
+        use std::f64::consts::TAU;
+        let frames = 12_000;
+        // 1 kHz at -6 dBFS on the left, 3 kHz at -20 dBFS on the right
+        let input: Vec<[f64; 2]> = (0..frames)
+            .map(|i| {
+                let t = i as f64 / 48_000.0;
+                [
+                    0.5 * (TAU * 1000.0 * t).sin(),
+                    0.1 * (TAU * 3000.0 * t).sin(),
+                ]
+            })
+            .collect();
+        let samples: Vec<i64> = input
+            .iter()
+            .flatten()
+            .map(|&x| (x * 32767.0).round() as i64)
+            .collect();
+        let file = create_ogg_vorbis_file_array(&samples, 48_000, 2, SampleWidth::Width2Byte, 3.0);
+        let (sample_rate, decoded) = decode_ogg_vorbis(&file);
+        assert_eq!(sample_rate, 48_000);
+        assert_eq!(decoded.len(), 2);
+        for (channel, frequency) in [1000, 3000].into_iter().enumerate() {
+            let decoded = &decoded[channel];
+            assert_eq!(decoded.len(), frames);
+            let rms = |x: &mut dyn Iterator<Item = f64>| {
+                let (sum, count) = x.fold((0.0, 0), |(sum, count), v| (sum + v * v, count + 1));
+                (sum / count as f64).sqrt()
+            };
+            let level = 20.0 * rms(&mut decoded.iter().copied()).log10();
+            let expected = 20.0 * rms(&mut input.iter().map(|x| x[channel])).log10();
+            assert!(
+                (level - expected).abs() < 0.1,
+                "channel {} at {} dB",
+                channel,
+                level
+            );
+            let error = rms(&mut decoded
+                .iter()
+                .zip(&input)
+                .map(|(y, x)| y - x[channel] * 32767.0 / 32768.0));
+            assert!(
+                20.0 * error.log10() - expected < -35.0,
+                "channel {} noise",
+                channel
+            );
+            let rising = decoded
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+                .count();
+            assert!(
+                rising.abs_diff(frequency / 4) <= 1,
+                "{} Hz: {}",
+                frequency,
+                rising
+            );
+        }
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_huffman_tables_are_complete_prefix_codes() {
+        let count1: (u32, &[u16], &[u8]) = (32, &MP3_COUNT1_CODES, &MP3_COUNT1_LENGTHS);
+        for (table, codes, lengths) in MP3_HUFFMAN.into_iter().chain([count1]) {
+            let kraft: u64 = lengths.iter().map(|&len| 1 << (19 - len)).sum();
+            assert_eq!(kraft, 1 << 19, "table {}", table);
+            for (i, (&code, &len)) in codes.iter().zip(lengths).enumerate() {
+                for (&other, &other_len) in codes[i + 1..].iter().zip(&lengths[i + 1..]) {
+                    let shared = len.min(other_len);
+                    assert_ne!(
+                        code as u32 >> (len - shared),
+                        other as u32 >> (other_len - shared),
+                        "table {}",
+                        table
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_frames_fit_their_bit_rate() {
+        let samples: Vec<i64> = (0..2 * 44_100)
+            .map(|i| (((i / 2) as f64 * 0.1).sin() * 20_000.0) as i64)
+            .collect();
+        let file = create_mp3_file_array(&samples, 44_100, 2, SampleWidth::Width2Byte, 128);
+        let mut pos = 0;
+        let mut frames = 0;
+        while pos < file.len() {
+            let frame = &file[pos..];
+            assert_eq!(frame[..2], [0xFF, 0xFB]);
+            // 128 kbit/s at 44.1 kHz, stereo
+            assert_eq!(frame[2] & 0xFC, 9 << 4);
+            assert_eq!(frame[3] >> 6, 0);
+            let len = 417 + (frame[2] as usize >> 1 & 1);
+            // part2_3_length of each granule and channel, 59 bits apart in
+            // the side information
+            let bit = |n: usize| (frame[4 + n / 8] >> (7 - n % 8)) as usize & 1;
+            let bits: usize = (0..4)
+                .map(|block| (0..12).fold(0, |acc, n| acc << 1 | bit(20 + 59 * block + n)))
+                .sum();
+            assert!(bits <= (len - 36) * 8);
+            pos += len;
+            frames += 1;
+        }
+        assert_eq!(pos, file.len());
+        assert_eq!(frames, 44_100usize.div_ceil(MP3_FRAME_SIZE) + 1);
+    }
+
+    /// Decode an MPEG-1 Layer III stream in long blocks without joint
+    /// stereo or a bit reservoir, following ISO/IEC 11172-3's decoding
+    /// process, to per-channel samples.
+    #[cfg(feature = "mp3")]
+    fn decode_mp3(file: &[u8]) -> (u32, Vec<Vec<f64>>) {
+        use std::collections::HashMap;
+        use std::f64::consts::PI;
+        // Huffman entries by codeword length and value
+        type Codes = HashMap<(u8, u16), usize>;
+        // Scale factor bits of bands 0-10 and 11-20 by scalefac_compress
+        const SLEN: [(u64, u64); 16] = [
+            (0, 0),
+            (0, 1),
+            (0, 2),
+            (0, 3),
+            (3, 0),
+            (1, 1),
+            (1, 2),
+            (1, 3),
+            (2, 1),
+            (2, 2),
+            (2, 3),
+            (3, 1),
+            (3, 2),
+            (3, 3),
+            (4, 2),
+            (4, 3),
+        ];
+        const PRETAB: [i32; 21] = [
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 3, 2,
+        ];
+        let code_map = |codes: &[u16], lengths: &[u8]| -> Codes {
+            (0..codes.len())
+                .map(|i| ((lengths[i], codes[i]), i))
+                .collect()
+        };
+        let tables: HashMap<u64, (usize, Codes)> = MP3_HUFFMAN
+            .iter()
+            .map(|&(table, codes, lengths)| {
+                (
+                    table as u64,
+                    (lengths.len().isqrt(), code_map(codes, lengths)),
+                )
+            })
+            .collect();
+        let count1_codes = code_map(&MP3_COUNT1_CODES, &MP3_COUNT1_LENGTHS);
+        let decode = |reader: &mut BitReader, codes: &Codes| {
+            let mut code = 0;
+            for len in 1..=19 {
+                code = code << 1 | reader.read(1) as u16;
+                if let Some(&entry) = codes.get(&(len, code)) {
+                    return entry;
+                }
+            }
+            panic!("no codeword at bit {}", reader.pos);
+        };
+        let (cs, ca): (Vec<f64>, Vec<f64>) = MP3_ALIAS
+            .iter()
+            .map(|c| (1.0 / (1.0 + c * c).sqrt(), c / (1.0 + c * c).sqrt()))
+            .unzip();
+        // Synthesis window D, 32 times the analysis window C
+        let window: Vec<f64> = (0..512)
+            .map(|i| {
+                let tap = MP3_WINDOW[if i <= 256 { i } else { 512 - i }] as f64 / (1 << 16) as f64;
+                if i / 64 % 2 == 1 { -tap } else { tap }
+            })
+            .collect();
+
+        let mut sample_rate = 0;
+        let mut output = vec![Vec::new(); 2];
+        let mut overlap = [[[0.0; 18]; 32]; 2];
+        let mut fifo = [[0.0; 1024]; 2];
+        let mut pos = 0;
+        while pos < file.len() {
+            let mut reader = BitReader {
+                bytes: &file[pos..],
+                pos: 0,
+            };
+            assert_eq!(reader.read(16), 0xFFFB, "MPEG-1 Layer III without CRC");
+            let bitrate = MP3_BITRATES[reader.read(4) as usize - 1] as usize;
+            let rate_index = reader.read(2) as usize;
+            sample_rate = [44_100, 48_000, 32_000][rate_index];
+            let len = 144_000 * bitrate / sample_rate as usize + reader.read(1) as usize;
+            reader.read(1);
+            let mode = reader.read(2);
+            assert_ne!(mode, 1, "joint stereo");
+            let channels = if mode == 3 { 1 } else { 2 };
+            output.truncate(channels);
+            reader.read(6);
+            let edges = MP3_BAND_EDGES[rate_index].map(|edge| edge as usize);
+
+            assert_eq!(reader.read(9), 0, "bit reservoir");
+            reader.read(if channels == 1 { 5 } else { 3 });
+            let scfsi: Vec<u64> = (0..channels).map(|_| reader.read(4)).collect();
+            let sides: Vec<[u64; 13]> = (0..2 * channels)
+                .map(|_| [12, 9, 8, 4, 1, 5, 5, 5, 4, 3, 1, 1, 1].map(|bits| reader.read(bits)))
+                .collect();
+            let mut scalefactors = vec![[0; 21]; channels];
+            for granule in 0..2 {
+                let mut spectra = vec![[0.0; 576]; channels];
+                for (channel, lines) in spectra.iter_mut().enumerate() {
+                    let [
+                        part2_3_length,
+                        big_values,
+                        global_gain,
+                        compress,
+                        switching,
+                        table0,
+                        table1,
+                        table2,
+                        region0,
+                        region1,
+                        preflag,
+                        scale,
+                        count1_table,
+                    ] = sides[granule * channels + channel];
+                    assert_eq!(switching, 0, "long blocks");
+                    let end = reader.pos + part2_3_length as usize;
+                    let (slen1, slen2) = SLEN[compress as usize];
+                    for (group, bands) in [0..6, 6..11, 11..16, 16..21].into_iter().enumerate() {
+                        if granule == 1 && scfsi[channel] >> (3 - group) & 1 == 1 {
+                            continue;
+                        }
+                        for band in bands {
+                            scalefactors[channel][band] =
+                                reader.read(if group < 2 { slen1 } else { slen2 } as u32) as i32;
+                        }
+                    }
+
+                    let mut values = [0; 576];
+                    let region1_start = edges[region0 as usize + 1];
+                    let region2_start = edges[(region0 + region1) as usize + 2];
+                    for i in (0..2 * big_values as usize).step_by(2) {
+                        let table = match i {
+                            i if i < region1_start => table0,
+                            i if i < region2_start => table1,
+                            _ => table2,
+                        };
+                        if table == 0 {
+                            continue;
+                        }
+                        let (width, codes) = &tables[&table.min(16)];
+                        let linbits = if table >= 16 {
+                            MP3_LINBITS[table as usize - 16]
+                        } else {
+                            0
+                        };
+                        let entry = decode(&mut reader, codes);
+                        for (j, magnitude) in [entry / width, entry % width].into_iter().enumerate()
+                        {
+                            let mut value = magnitude as i32;
+                            if linbits > 0 && value == 15 {
+                                value += reader.read(linbits) as i32;
+                            }
+                            if value != 0 && reader.read(1) == 1 {
+                                value = -value;
+                            }
+                            values[i + j] = value;
+                        }
+                    }
+                    let mut i = 2 * big_values as usize;
+                    while reader.pos < end && i < 576 {
+                        let quad = if count1_table == 0 {
+                            decode(&mut reader, &count1_codes)
+                        } else {
+                            15 - reader.read(4) as usize
+                        };
+                        for j in 0..4 {
+                            let mut value = (quad >> (3 - j) & 1) as i32;
+                            if value != 0 && reader.read(1) == 1 {
+                                value = -value;
+                            }
+                            values[i + j] = value;
+                        }
+                        i += 4;
+                    }
+                    assert_eq!(reader.pos, end, "part2_3_length");
+
+                    // Requantization
+                    let mut band = 0;
+                    for (k, &value) in values.iter().enumerate() {
+                        while k >= edges[band + 1] {
+                            band += 1;
+                        }
+                        let scalefactor = if band < 21 {
+                            scalefactors[channel][band] + preflag as i32 * PRETAB[band]
+                        } else {
+                            0
+                        };
+                        let exponent = (global_gain as f64 - 210.0) / 4.0
+                            - 0.5 * (1.0 + scale as f64) * scalefactor as f64;
+                        lines[k] = value.signum() as f64
+                            * (value.abs() as f64).powf(4.0 / 3.0)
+                            * 2f64.powf(exponent);
+                    }
+                }
+
+                for (channel, lines) in spectra.iter_mut().enumerate() {
+                    for sb in 1..32 {
+                        for i in 0..8 {
+                            let (upper, lower) = (lines[18 * sb - 1 - i], lines[18 * sb + i]);
+                            lines[18 * sb - 1 - i] = upper * cs[i] - lower * ca[i];
+                            lines[18 * sb + i] = lower * cs[i] + upper * ca[i];
+                        }
+                    }
+                    // IMDCT with the sine window and overlap-add, then
+                    // undo the inversion of odd subbands at odd times
+                    let mut subbands = [[0.0; 32]; 18];
+                    for sb in 0..32 {
+                        for n in 0..36 {
+                            let y = (0..18)
+                                .map(|k| {
+                                    lines[18 * sb + k]
+                                        * (PI / 72.0 * (2 * n + 19) as f64 * (2 * k + 1) as f64)
+                                            .cos()
+                                })
+                                .sum::<f64>()
+                                * (PI / 36.0 * (n as f64 + 0.5)).sin();
+                            if n < 18 {
+                                let value = y + overlap[channel][sb][n];
+                                subbands[n][sb] = if sb % 2 == 1 && n % 2 == 1 {
+                                    -value
+                                } else {
+                                    value
+                                };
+                            } else {
+                                overlap[channel][sb][n - 18] = y;
+                            }
+                        }
+                    }
+                    // Polyphase synthesis
+                    for subband in &subbands {
+                        let v = &mut fifo[channel];
+                        v.copy_within(0..960, 64);
+                        for (i, slot) in v[..64].iter_mut().enumerate() {
+                            *slot = (0..32)
+                                .map(|k| {
+                                    ((16 + i) as f64 * (2 * k + 1) as f64 * PI / 64.0).cos()
+                                        * subband[k]
+                                })
+                                .sum();
+                        }
+                        for j in 0..32 {
+                            output[channel].push(
+                                (0..16)
+                                    .map(|i| {
+                                        let at = j + 32 * i;
+                                        let u = v[at / 64 * 128 + at % 64 + at % 64 / 32 * 64];
+                                        u * window[at]
+                                    })
+                                    .sum(),
+                            );
+                        }
+                    }
+                }
+            }
+            pos += len;
+        }
+        (sample_rate, output)
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_decodes_to_the_input_level_and_frequency() {
+        use std::f64::consts::TAU;
+        let frames = 11_025;
+        // 1 kHz at -6 dBFS on the left, 3 kHz at -20 dBFS on the right
+        let input: Vec<[f64; 2]> = (0..frames)
+            .map(|i| {
+                let t = i as f64 / 44_100.0;
+                [
+                    0.5 * (TAU * 1000.0 * t).sin(),
+                    0.1 * (TAU * 3000.0 * t).sin(),
+                ]
+            })
+            .collect();
+        let samples: Vec<i64> = input
+            .iter()
+            .flatten()
+            .map(|&x| (x * 32767.0).round() as i64)
+            .collect();
+        let file = create_mp3_file_array(&samples, 44_100, 2, SampleWidth::Width2Byte, 128);
+        let (sample_rate, decoded) = decode_mp3(&file);
+        assert_eq!(sample_rate, 44_100);
+        assert_eq!(decoded.len(), 2);
+        for (channel, frequency) in [1000, 3000].into_iter().enumerate() {
+            // Decoded audio starts 1057 samples late
+            let decoded = &decoded[channel][1057..1057 + frames];
+            let rms = |x: &mut dyn Iterator<Item = f64>| {
+                let (sum, count) = x.fold((0.0, 0), |(sum, count), v| (sum + v * v, count + 1));
+                (sum / count as f64).sqrt()
+            };
+            let level = 20.0 * rms(&mut decoded.iter().copied()).log10();
+            let expected = 20.0 * rms(&mut input.iter().map(|x| x[channel])).log10();
+            assert!(
+                (level - expected).abs() < 0.1,
+                "channel {} at {} dB",
+                channel,
+                level
+            );
+            let error = rms(&mut decoded
+                .iter()
+                .zip(&input)
+                .map(|(y, x)| y - x[channel] * 32767.0 / 32768.0));
+            assert!(
+                20.0 * error.log10() - expected < -35.0,
+                "channel {} noise",
+                channel
+            );
+            let rising = decoded
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+                .count();
+            assert!(
+                rising.abs_diff(frequency / 4) <= 1,
+                "{} Hz: {}",
+                frequency,
+                rising
+            );
+        }
+    }
+
+    #[test]
+    fn durations_resolve_in_each_unit() {
+        let mut config = config_for("-r 48000 -f 1000");
+        let samples = |spec: &str, config: &Config| {
+            DurationSpec::from_str(spec).and_then(|spec| spec_samples(spec, config))
+        };
+        assert_eq!(samples("250", &config), Some(12000));
+        assert_eq!(samples("250ms", &config), Some(12000));
+        assert_eq!(samples("1.5s", &config), Some(72000));
+        assert_eq!(samples("2sec", &config), Some(96000));
+        assert_eq!(samples("4800samples", &config), Some(4800));
+        assert_eq!(samples("48ksamples", &config), Some(48000));
+        assert_eq!(samples("10cycles", &config), Some(480));
+        for bad in [
+            "-5",
+            "nan",
+            "inf",
+            "-1s",
+            "-5samples",
+            "-3cycles",
+            "infcycles",
+        ] {
+            assert!(DurationSpec::from_str(bad).is_none(), "{}", bad);
+        }
+        // Cycles of 0 Hz or a negative frequency have no length
+        for frequency in [0.0, -440.0, f32::NAN] {
+            config.frequency = frequency;
+            config.channel_frequencies = vec![frequency];
+            assert_eq!(samples("10cycles", &config), None, "{} Hz", frequency);
+        }
+    }
+
+    #[test]
+    fn si_values_take_prefixes_and_stay_finite() {
+        assert_eq!(parse_si("2.5kHz", "hz"), Some(2500.0));
+        assert_eq!(parse_si("48k", "hz"), Some(48000.0));
+        assert_eq!(parse_si("1.5M", ""), Some(1.5e6));
+        assert_eq!(parse_si("250m", ""), Some(0.25));
+        for bad in ["nan", "inf", "-inf", "NaNk", "infHz", "1e308k", "12x"] {
+            assert_eq!(parse_si(bad, "hz"), None, "{}", bad);
+        }
+        let tuning = config_for("").tuning;
+        assert_eq!(parse_hz("A4", &tuning), Some(440.0));
+        assert_eq!(parse_hz("midi:nan", &tuning), None);
+        // -f takes only positive frequencies, one per channel
+        let config = config_for("-f 1k,2.5kHz");
+        assert_eq!(config.channel_frequencies, [1000.0, 2500.0]);
+    }
+
+    #[test]
+    fn gain_and_amplitude_combine_into_the_output_level() {
+        assert_eq!(output_gain(&config_for("")), 1.0);
+        let gain = output_gain(&config_for("-g -6.0206"));
+        assert!((gain - 0.5).abs() < 1e-5, "{}", gain);
+        let gain = output_gain(&config_for("-g -6.0206 --amplitude 0.5"));
+        assert!((gain - 0.25).abs() < 1e-5, "{}", gain);
+        assert!((linear_to_db(0.1) + 20.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn normalize_targets_are_finite_levels_at_or_below_full_scale() {
+        let target = Normalize::from_str("peak:-1dBFS").unwrap();
+        assert!(matches!(target.measure, LevelMeasure::Peak) && target.target_dbfs == -1.0);
+        let target = Normalize::from_str("RMS: -20 dB").unwrap();
+        assert!(matches!(target.measure, LevelMeasure::Rms) && target.target_dbfs == -20.0);
+        for bad in ["peak:nan", "peak:-inf", "rms:inf", "peak:0.5", "loud:-1"] {
+            assert!(Normalize::from_str(bad).is_none(), "{}", bad);
+        }
+        let channels = [vec![0.5, -1.0], vec![0.25, 0.0]];
+        assert_eq!(measure_level(&channels, LevelMeasure::Peak), 1.0);
+    }
+
+    #[test]
+    fn added_noise_sits_at_the_requested_snr() {
+        let sine: Vec<f32> = (0..48000).map(|n| (n as f32 * 0.1).sin()).collect();
+        let mut channels = vec![sine.clone()];
+        let signal_rms = measure_level(&channels, LevelMeasure::Rms);
+        add_noise_at_snr(&mut channels, signal_rms, 20.0, 1);
+        let noise: Vec<f32> = channels[0].iter().zip(&sine).map(|(a, b)| a - b).collect();
+        let snr = linear_to_db(signal_rms / measure_level(&[noise], LevelMeasure::Rms));
+        assert!((snr - 20.0).abs() < 0.01, "{} dB", snr);
+    }
+
+    #[test]
+    fn burst_cadence_takes_time_units() {
+        let burst = Burst::from_str("10ms:0.005s").unwrap();
+        assert_eq!((burst.on_ms, burst.off_ms), (10.0, 5.0));
+        assert_eq!(burst.gain_at(9.0), 1.0);
+        assert_eq!(burst.gain_at(12.0), 0.0);
+        assert_eq!(burst.gain_at(16.0), 1.0);
+        for bad in ["nan:5", "5:inf", "0:5", "5:-1", "10ms"] {
+            assert!(Burst::from_str(bad).is_none(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn ident_cadences_and_levels_follow_tech_3304() {
+        let peak = |samples: &[f32], from_ms: usize, to_ms: usize| {
+            samples[from_ms * 48..to_ms * 48]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+        let glits = generate_glits(48000.0, 4000 * 48);
+        // Left breaks once at the start, right twice from 500 ms
+        assert_eq!(peak(&glits[0], 0, 250), 0.0);
+        assert_eq!(peak(&glits[1], 500, 750), 0.0);
+        assert_eq!(peak(&glits[1], 1000, 1250), 0.0);
+        let level = linear_to_db(peak(&glits[1], 0, 500));
+        assert!((level - IDENT_LEVEL_DBFS).abs() < 0.01, "{} dBFS", level);
+
+        let blits = generate_blits(48000.0, 6, BLITS_CYCLE_MS as usize * 48);
+        let level = linear_to_db(peak(&blits[2], 1600, 2200));
+        assert!((level - IDENT_LEVEL_DBFS).abs() < 0.01, "{} dBFS", level);
+        assert_eq!(peak(&blits[2], 0, 1600), 0.0);
+        for channel in &blits {
+            let level = linear_to_db(peak(channel, 4800, 6800));
+            assert!(
+                (level - BLITS_ALL_LEVEL_DBFS).abs() < 0.01,
+                "{} dBFS",
+                level
+            );
+        }
+        assert_eq!(peak(&blits[2], 7000, 10000), 0.0);
+    }
+
+    #[test]
+    fn mls_has_full_period_and_balance() {
+        for order in MLS_MIN_ORDER..=16 {
+            let period = (1usize << order) - 1;
+            let mls = generate_mls(order, 1, 2 * period);
+            assert_eq!(mls[..period], mls[period..], "order {}", order);
+            // One more +1 than -1 in each period
+            let sum: f32 = mls[..period].iter().sum();
+            assert_eq!(sum, 1.0, "order {}", order);
+            // Circular autocorrelation of -1 at every non-zero lag, so no
+            // shorter period
+            if order <= 9 {
+                for lag in 1..period {
+                    let correlation: f32 = (0..period).map(|i| mls[i] * mls[i + lag]).sum();
+                    assert_eq!(correlation, -1.0, "order {} lag {}", order, lag);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jtest_codes_follow_the_dunn_pattern() {
+        let codes = generate_jtest(16, 2 * JTEST_LSB_PERIOD);
+        assert_eq!(codes[..6], [16384, 16384, -16384, -16384, 16384, 16384]);
+        // The LSB drops for the second half of every 192 samples
+        assert_eq!(codes[96..100], [16383, 16383, -16385, -16385]);
+        assert_eq!(
+            codes[188..196],
+            [16383, 16383, -16385, -16385, 16384, 16384, -16384, -16384]
+        );
+        assert_eq!(codes[..192], codes[192..]);
+        let codes = generate_jtest(24, JTEST_LSB_PERIOD);
+        assert_eq!(codes[..4], [1 << 22, 1 << 22, -(1 << 22), -(1 << 22)]);
+        assert_eq!(codes[190..], [-(1 << 22) - 1, -(1 << 22) - 1]);
+    }
+
+    #[test]
+    fn expressions_follow_precedence() {
+        let eval = |text: &str, vars: [f64; 4]| Expr::parse(text).unwrap().eval(&vars);
+        assert_eq!(eval("2 + 3 * 4 ^ 2", [0.0; 4]), 50.0);
+        assert_eq!(eval("2 ^ 3 ^ 2", [0.0; 4]), 512.0);
+        assert_eq!(eval("(1 - 4) / 2", [0.0; 4]), -1.5);
+        assert_eq!(
+            eval("max(n, fs) - min(2, 3)", [0.0, 0.0, 48000.0, 7.0]),
+            47998.0
+        );
+        assert!((eval("sin(2*pi*f*t)", [0.25, 1.0, 0.0, 0.0]) - 1.0).abs() < 1e-12);
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("2 3").is_err());
+        assert!(Expr::parse("foo(1)").is_err());
+    }
+
+    #[test]
+    fn full_scale_sine_reads_minus_3_lufs() {
+        // BS.1770: a 0 dBFS 1 kHz sine in one channel reads -3.01 LKFS
+        let sine: Vec<f32> = (0..96000)
+            .map(|n| (TAU * 1000.0 * n as f32 / 48000.0).sin())
+            .collect();
+        let loudness = integrated_loudness(std::slice::from_ref(&sine), 48000);
+        assert!((loudness + 3.01).abs() < 0.05, "{} LUFS", loudness);
+        let stereo = integrated_loudness(&[sine.clone(), sine], 48000);
+        assert!(stereo.abs() < 0.05, "{} LUFS", stereo);
+    }
+
+    #[test]
+    fn rounding_modes_and_full_scale_mappings() {
+        let staged = vec![
+            [2.5, 3.5, -2.5, 1.75, -1.75]
+                .iter()
+                .map(|lsbs| lsbs / 32768.0)
+                .chain([-1.0, 1.0])
+                .collect::<Vec<f32>>(),
+        ];
+        let codes = |args: &str| quantize_samples(&staged, &config_for(args)).remove(0);
+        assert_eq!(
+            codes("-b 16 --full-scale asym"),
+            [3, 4, -3, 2, -2, -32768, 32767]
+        );
+        assert_eq!(
+            codes("-b 16 --full-scale asym --rounding even"),
+            [2, 4, -2, 2, -2, -32768, 32767]
+        );
+        assert_eq!(
+            codes("-b 16 --full-scale asym --rounding truncate"),
+            [2, 3, -2, 1, -1, -32768, 32767]
+        );
+        // Symmetric mapping never reaches the most negative code
+        assert_eq!(codes("-b 16")[5..], [-32767, 32767]);
+        assert_eq!(codes("-b 24")[5..], [-8388607, 8388607]);
+    }
+
+    #[test]
+    fn envelope_json_accepts_pairs_and_objects() {
+        assert_eq!(
+            parse_envelope_json(r#" [[0, -6], {"time_ms": 100, "gain_db": 0.5}] "#).unwrap(),
+            [(0.0, -6.0), (100.0, 0.5)]
+        );
+        assert_eq!(
+            parse_envelope_json(r#"[{"gain_db": -3, "note": "x", "time_ms": 1e3}]"#).unwrap(),
+            [(1000.0, -3.0)]
+        );
+        assert!(parse_envelope_json("[[0, -6]] x").is_err());
+        assert!(parse_envelope_json("{\"time_ms\": 0}").is_err());
+        assert!(parse_envelope_json("[[0, -6]").is_err());
+    }
+
+    #[test]
+    fn more_than_two_channels_use_wave_format_extensible() {
+        let header = create_wav_header(600, 44100, 6, SampleWidth::Width2Byte, SampleFormat::Pcm);
+        assert_eq!(header.len(), 68);
+        assert_eq!(le32(&header, 4) as usize, header.len() - 8 + 600);
+        assert_eq!(le32(&header, 16), 40);
+        assert_eq!(le16(&header, 20), 0xFFFE);
+        assert_eq!(le16(&header, 22), 6);
+        assert_eq!(le32(&header, 28), 44100 * 12);
+        assert_eq!(le16(&header, 32), 12);
+        assert_eq!(le16(&header, 36), 22);
+        assert_eq!(le16(&header, 38), 16);
+        // 5.1: FL FR FC LFE BL BR
+        assert_eq!(le32(&header, 40), 0x3F);
+        // KSDATAFORMAT_SUBTYPE_PCM
+        assert_eq!(
+            header[44..60],
+            [
+                1, 0, 0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71
+            ]
+        );
+        assert_eq!(&header[60..64], b"data");
+        assert_eq!(le32(&header, 64), 600);
+
+        let mask = |channels| {
+            let header = create_wav_header(
+                0,
+                48000,
+                channels,
+                SampleWidth::Width3Byte,
+                SampleFormat::Pcm,
+            );
+            le32(&header, 40)
+        };
+        assert_eq!(mask(8), 0x63F);
+        // No standard layout: the speakers are left unassigned
+        assert_eq!(mask(12), 0);
+    }
+
+    #[test]
+    fn eight_bit_samples_are_offset_by_128() {
+        let config = config_for("-b 8");
+        assert_eq!(
+            encode_codes(&[-128, -1, 0, 1, 127], &config),
+            [0x00, 0x7F, 0x80, 0x81, 0xFF]
+        );
+        // Silence and full scale, through quantization
+        let codes = quantize_samples(&[vec![0.0, 1.0, -1.0]], &config);
+        assert_eq!(encode_codes(&codes[0], &config), [0x80, 0xFF, 0x01]);
+        let header = create_wav_header(3, 8000, 1, SampleWidth::Width1Byte, SampleFormat::Pcm);
+        assert_eq!(le16(&header, 34), 8);
+    }
+
+    #[test]
+    fn big_endian_reverses_each_sample() {
+        let codes = [0x123456, -2];
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 24")),
+            [0x56, 0x34, 0x12, 0xFE, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 24 --endian big")),
+            [0x12, 0x34, 0x56, 0xFF, 0xFF, 0xFE]
+        );
+        assert_eq!(
+            encode_codes(&[0x1234], &config_for("-b 32 --endian big")),
+            [0, 0, 0x12, 0x34]
+        );
+        assert_eq!(
+            encode_floats(&[1.0, -2.0], &config_for("-b f32 --endian big")),
+            [0x3F, 0x80, 0, 0, 0xC0, 0, 0, 0]
+        );
+        // 8-bit samples have no byte order
+        assert_eq!(encode_codes(&[1], &config_for("-b 8 --endian big")), [0x81]);
+    }
+
+    #[test]
+    fn slots_justify_24_bit_samples() {
+        let codes = [0x123456, -1];
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 24 --packing left")),
+            [0x00, 0x56, 0x34, 0x12, 0x00, 0xFF, 0xFF, 0xFF]
+        );
+        // Right-justified slots are sign-extended
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 24 --packing right")),
+            [0x56, 0x34, 0x12, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 24 --packing left --endian big")),
+            [0x12, 0x34, 0x56, 0x00, 0xFF, 0xFF, 0xFF, 0x00]
+        );
+        assert_eq!(
+            encode_codes(
+                &[0x1234],
+                &config_for("-b 16 --packing left --slot-bits 24")
+            ),
+            [0x00, 0x34, 0x12]
+        );
+    }
+
+    #[test]
+    fn alaw_codes_match_the_reference_encoder() {
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(linear_to_alaw(-1), 0x55);
+        assert_eq!(linear_to_alaw(1000), 0xFA);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xAA);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+        assert_eq!(encode_codes(&[0, -1], &config_for("-b alaw")), [0xD5, 0x55]);
+    }
+
+    #[test]
+    fn mulaw_codes_match_the_reference_encoder() {
+        assert_eq!(linear_to_mulaw(0), 0xFF);
+        assert_eq!(linear_to_mulaw(-1), 0x7E);
+        assert_eq!(linear_to_mulaw(1000), 0xCE);
+        assert_eq!(linear_to_mulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_mulaw(i16::MIN), 0x00);
+        assert_eq!(encode_codes(&[0, -1], &config_for("-b ulaw")), [0xFF, 0x7E]);
+    }
+
+    #[test]
+    fn i2s_delays_each_word_by_one_bit() {
+        // Left 0x4001, right 0x8001: the right LSB wraps round to the
+        // first bit and the left LSB becomes the right MSB
+        let i2s = |args: &str| {
+            let config = config_for(args);
+            let channels: Vec<Vec<u8>> = [[0x4001], [-0x7FFF]]
+                .iter()
+                .map(|codes| encode_codes(codes, &config))
+                .collect();
+            let mut buffer = interleave_bytes(&channels, bytes_per_sample(&config), config.layout);
+            delay_one_bit(&mut buffer, bytes_per_sample(&config), config.endian);
+            buffer
+        };
+        assert_eq!(
+            i2s("-b 16 -c 2 --packing i2s --slot-bits 16 --endian big"),
+            [0xA0, 0x00, 0xC0, 0x00]
+        );
+        assert_eq!(
+            i2s("-b 16 -c 2 --packing i2s --slot-bits 16"),
+            [0x00, 0xA0, 0x00, 0xC0]
+        );
+        // Left-justified in 32-bit slots, then delayed
+        assert_eq!(
+            i2s("-b 16 -c 2 --packing i2s --endian big"),
+            [0x20, 0x00, 0x80, 0x00, 0x40, 0x00, 0x80, 0x00]
+        );
+    }
+
+    #[test]
+    fn offset_binary_adds_half_the_code_range() {
+        let codes = [-32768, -1, 0, 32767];
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 16 --offset-binary")),
+            [0x00, 0x00, 0xFF, 0x7F, 0x00, 0x80, 0xFF, 0xFF]
+        );
+        // Unsigned 12-bit codes stay inside their 12 bits when right-justified
+        assert_eq!(
+            encode_codes(&[-2048, -1, 2047], &config_for("-b 12 --offset-binary")),
+            [0x00, 0x00, 0xFF, 0x07, 0xFF, 0x0F]
+        );
+        assert_eq!(
+            encode_codes(&[-1], &config_for("-b 24 --offset-binary --endian big")),
+            [0x7F, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn reduced_depths_justify_within_their_container() {
+        // 12-bit codes span ±2047, in 16-bit words by default
+        let config = config_for("-b 12");
+        assert_eq!(code_bits(&config), 12);
+        let codes = quantize_samples(&[vec![1.0, -1.0]], &config).remove(0);
+        assert_eq!(codes, [2047, -2047]);
+        assert_eq!(encode_codes(&codes, &config), [0xFF, 0x07, 0x01, 0xF8]);
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 12 --justify left")),
+            [0xF0, 0x7F, 0x10, 0x80]
+        );
+        // 20-bit codes in 24- or 32-bit containers
+        let codes = [0x12345, -1];
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 20")),
+            [0x45, 0x23, 0x01, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 20 --justify left")),
+            [0x50, 0x34, 0x12, 0xF0, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_codes(&codes, &config_for("-b 20 --container 32 --justify left")),
+            [0x00, 0x50, 0x34, 0x12, 0x00, 0xF0, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn resampling_preserves_an_in_band_sine() {
+        let frequency = 0.05;
+        let input: Vec<f32> = (0..4000)
+            .map(|n| (TAU as f64 * frequency * n as f64).sin() as f32)
+            .collect();
+        for step in [0.5, 0.75, 1.25] {
+            let len = (input.len() as f64 / step) as usize;
+            let output = resample(&input, step, len);
+            // Away from the edges, where the kernel runs past the input
+            for (n, &sample) in output.iter().enumerate().take(len - 200).skip(200) {
+                let expected = (TAU as f64 * frequency * n as f64 * step).sin();
+                assert!(
+                    (sample as f64 - expected).abs() < 1e-3,
+                    "step {} sample {}: {} vs {}",
+                    step,
+                    n,
+                    sample,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rf64_carries_64_bit_sizes_in_ds64() {
+        let le64 =
+            |bytes: &[u8], pos: usize| u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+        let part = |config: &Config| OutputPart {
+            config: config.clone(),
+            suffix: String::new(),
+            label: String::new(),
+            first_channel: 0,
+            buffer: Vec::new(),
+        };
+        let config = config_for("-c 2 -b 16 -o wav");
+        let data_len = 6_000_000_000;
+        let (header, trailer) = wav_layout(&part(&config), &config, data_len);
+        assert!(trailer.is_empty());
+        assert_eq!(&header[..4], b"RF64");
+        assert_eq!(le32(&header, 4), u32::MAX);
+        assert_eq!(&header[8..16], b"WAVEds64");
+        assert_eq!(le32(&header, 16), 28);
+        assert_eq!(le64(&header, 20), (header.len() - 8 + data_len) as u64);
+        assert_eq!(le64(&header, 28), data_len as u64);
+        assert_eq!(le64(&header, 36), data_len as u64 / 4);
+        assert_eq!(le32(&header, 44), 0);
+        assert_eq!(&header[48..52], b"fmt ");
+        assert_eq!(le16(&header, 70), 16);
+        assert_eq!(&header[header.len() - 8..header.len() - 4], b"data");
+        assert_eq!(le32(&header, header.len() - 4), u32::MAX);
+
+        // Smaller files stay RIFF unless asked
+        let (header, _) = wav_layout(&part(&config), &config, 4000);
+        assert_eq!(&header[..4], b"RIFF");
+        let config = config_for("-c 2 -b 16 -o wav --rf64");
+        let (header, _) = wav_layout(&part(&config), &config, 4000);
+        assert_eq!(&header[..4], b"RF64");
+        assert_eq!(le64(&header, 20), (header.len() - 8 + 4000) as u64);
+        assert_eq!(le64(&header, 28), 4000);
+        assert_eq!(le64(&header, 36), 1000);
+    }
+
+    #[test]
+    fn streamed_wav_files_get_their_sizes_patched() {
+        for rf64 in ["", " --rf64"] {
+            let path = std::env::temp_dir().join(format!("singen-test-{}.wav", process::id()));
+            let config = config_for(&format!("-c 1 -b 24 -o wav -O {}{}", path.display(), rf64));
+            let part = OutputPart {
+                config: config.clone(),
+                suffix: String::new(),
+                label: String::new(),
+                first_channel: 0,
+                buffer: (0..3 * 99).map(|i| i as u8).collect(),
+            };
+            write_wav_stream(&part, &config);
+            let file = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let (header, trailer) = wav_layout(&part, &config, part.buffer.len());
+            // Odd-length data is padded
+            assert_eq!(trailer, [0]);
+            assert_eq!(file.len(), header.len() + part.buffer.len() + trailer.len());
+            assert_eq!(file[..header.len()], header);
+            assert_eq!(file[header.len()..][..part.buffer.len()], part.buffer);
+            if rf64.is_empty() {
+                assert_eq!(le32(&file, 4) as usize, file.len() - 8);
+                assert_eq!(le32(&file, header.len() - 4) as usize, part.buffer.len());
+            } else {
+                assert_eq!(le32(&file, 20) as usize, file.len() - 8);
+                assert_eq!(le32(&file, 28) as usize, part.buffer.len());
+            }
+        }
+    }
+
+    #[test]
+    fn wav_header_fields_round_trip() {
+        let header = create_wav_header(1000, 48000, 2, SampleWidth::Width3Byte, SampleFormat::Pcm);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(le32(&header, 4) as usize, header.len() - 8 + 1000);
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        assert_eq!(le32(&header, 16), 16);
+        assert_eq!(le16(&header, 20), 1);
+        assert_eq!(le16(&header, 22), 2);
+        assert_eq!(le32(&header, 24), 48000);
+        assert_eq!(le32(&header, 28), 48000 * 6);
+        assert_eq!(le16(&header, 32), 6);
+        assert_eq!(le16(&header, 34), 24);
+        assert_eq!(&header[header.len() - 8..header.len() - 4], b"data");
+        assert_eq!(le32(&header, header.len() - 4), 1000);
+    }
+
+    #[test]
+    fn wav_pads_odd_length_data() {
+        let config = config_for("-c 1 -b 24 -o wav");
+        let part = OutputPart {
+            config: config.clone(),
+            suffix: String::new(),
+            label: String::new(),
+            first_channel: 0,
+            buffer: vec![0; 3],
+        };
+        let (header, trailer) = wav_layout(&part, &config, 3);
+        assert_eq!(trailer, [0]);
+        assert_eq!(le32(&header, header.len() - 4), 3);
+        let riff_len = le32(&header, 4) as usize;
+        assert_eq!(riff_len, header.len() - 8 + 4);
+        assert!(riff_len.is_multiple_of(2));
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, output) in cases {
+            assert_eq!(base64_encode(input.as_bytes()), output);
+        }
+    }
+
+    #[test]
+    fn intel_hex_records_carry_checksums() {
+        let text = intel_hex(b"address gap", 0x10, 16).unwrap();
+        assert_eq!(
+            text,
+            ":020000040000FA\n:0B0010006164647265737320676170A7\n:00000001FF\n"
+        );
+        // A record never crosses a 64 KB segment
+        let text = intel_hex(&[0xAB; 4], 0x1FFFE, 16).unwrap();
+        assert_eq!(
+            text,
+            ":020000040001F9\n:02FFFE00ABABAB\n:020000040002F8\n:02000000ABABA8\n:00000001FF\n"
+        );
+        assert!(intel_hex(&[0; 2], u32::MAX, 16).is_none());
+    }
+
+    #[test]
+    fn s_records_carry_checksums_and_counts() {
+        let mut data = vec![0x0A, 0x0A, 0x0D];
+        data.resize(16, 0);
+        let text = s_records(&data, 0x7AF0, None, 16).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "S009000073696E67656E72",
+                "S1137AF00A0A0D0000000000000000000000000061",
+                "S5030001FB",
+                "S9030000FC",
+            ]
+        );
+        assert!(s_records(&data, 0xFFFF, Some(2), 16).is_none());
+        assert!(
+            s_records(&data, 0x10000, None, 16)
+                .unwrap()
+                .lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("S214010000")
+        );
+    }
+
+    #[test]
+    fn readmemh_words_group_and_mask() {
+        let config = config_for("-b 16");
+        let buffer = [0x34, 0x12, 0xCD, 0xAB];
+        assert_eq!(
+            memory_words(&buffer, &config, 16).unwrap(),
+            [0x1234, 0xABCD]
+        );
+        assert_eq!(memory_words(&buffer, &config, 12).unwrap(), [0x234, 0xBCD]);
+        assert_eq!(memory_words(&buffer, &config, 32).unwrap(), [0xABCD1234]);
+        assert!(memory_words(&buffer[..3], &config, 16).is_none());
+        assert_eq!(
+            memory_words(&buffer, &config_for("-b 16 --endian big"), 16).unwrap(),
+            [0x3412, 0xCDAB]
+        );
+
+        let mem = readmemh(&[0x234, 0xBCD], 12, &config);
+        assert!(mem.starts_with("//"));
+        assert_eq!(mem.lines().skip(1).collect::<Vec<_>>(), ["234", "BCD"]);
+    }
+
+    #[test]
+    fn vhdl_packages_hold_hex_or_binary_words() {
+        let config = config_for("-b 16");
+        let words = [0x234, 0xBCD];
+        let vhdl = vhdl_package(&words, 12, &config, "");
+        assert!(vhdl.contains("x\"234\""));
+        assert!(vhdl.contains("x\"BCD\""));
+        // Widths that are not whole nibbles are written in binary
+        assert!(vhdl_package(&words, 10, &config, "").contains("\"1000110100\""));
+    }
+
+    #[test]
+    fn coe_files_end_the_vector_with_a_semicolon() {
+        let coe = coe_file(&[0x234, 0xBCD], 12, &config_for("-b 16"));
+        assert!(coe.ends_with(
+            "memory_initialization_radix=16;\nmemory_initialization_vector=\n234,\nBCD;\n"
+        ));
+    }
+
+    #[test]
+    fn mif_files_give_depth_width_and_addresses() {
+        let mif = mif_file(&[0x234, 0xBCD], 12, &config_for("-b 16"));
+        assert!(mif.contains("DEPTH = 2;\nWIDTH = 12;\n"));
+        assert!(mif.ends_with("BEGIN\n    0 : 234;\n    1 : BCD;\nEND;\n"));
+    }
+
+    #[test]
+    fn c_pairs_declare_in_the_header_and_define_in_the_source() {
+        let config = config_for("-o carray --name tone -b 16 --element-type int16");
+        let array = CArray::new(&[1, 0, 2, 0, 0xFF, 0xFF], &config, "", false);
+        let (header, source) = array.file_pair("tone-1k.h");
+        assert!(header.contains("#ifndef TONE_1K_H\n#define TONE_1K_H\n"));
+        assert!(header.ends_with("#endif /* TONE_1K_H */\n"));
+        assert!(header.contains("#include <stdint.h>\n"));
+        assert!(header.contains("#define TONE_LEN 3\n"));
+        assert!(header.contains("extern const int16_t tone[TONE_LEN];\n"));
+        assert!(!header.contains('{'));
+        assert!(source.starts_with("#include \"tone-1k.h\"\n"));
+        assert!(source.contains("const int16_t tone[TONE_LEN] = {\n"));
+        assert!(!source.contains("extern"));
+    }
+
+    #[test]
+    fn split_arrays_are_indexed_by_chunk_tables() {
+        let config = config_for("-o carray --name tone -b 16 --element-type int16 --split-bytes 4");
+        let array = CArray::new(&[1, 0, 2, 0, 3, 0], &config, "", false);
+        let definitions = array.definitions("TONE_LEN");
+        assert!(definitions.contains("const int16_t tone_0[2] = {\n"));
+        assert!(definitions.contains("const int16_t tone_1[1] = {\n"));
+        assert!(
+            definitions
+                .contains("const int16_t *const tone_chunks[2] = {\n    tone_0, tone_1\n};\n")
+        );
+        assert!(definitions.contains("const uint32_t tone_chunk_lens[2] = {\n    2, 1\n};\n"));
+        let (header, _) = array.file_pair("tone.h");
+        assert!(header.contains("#define TONE_CHUNK_COUNT 2\n"));
+        assert!(header.contains("extern const int16_t tone_1[1];\n"));
+        assert!(header.contains("extern const uint32_t tone_chunk_lens[2];\n"));
+
+        // A split size that holds the whole array leaves it in one piece
+        let config = config_for("-o carray --name tone -b 16 --split-bytes 6");
+        let array = CArray::new(&[1, 0, 2, 0, 3, 0], &config, "", false);
+        assert!(array.chunk_len.is_none());
+        assert!(!array.definitions("6").contains("tone_0"));
+    }
+}